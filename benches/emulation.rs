@@ -0,0 +1,183 @@
+// Throughput of the emulator's hot paths, to catch performance regressions:
+//
+//     cargo bench --bench emulation
+//
+// Most run a small built-in NROM program that keeps the CPU busy with a mix
+// of addressing modes while rendering and OAM DMA run every frame. Whole
+// frames of real games are timed too for each .nes file in the directory
+// $NESRS_BENCH_ROMS names, if set.
+
+use std::{env, fs, hint::black_box, path::PathBuf};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nesrs::{console::Accuracy, ppu::Renderer, Cartridge, Console};
+
+const INSTRUCTIONS: u64 = 10_000;
+// One NTSC frame
+const DOTS: u64 = 341 * 262;
+
+#[rustfmt::skip]
+const PROGRAM: &[u8] = &[
+    // $8000 reset
+    0x78,                   // SEI
+    0xD8,                   // CLD
+    0xA2, 0xFF,             // LDX #$FF
+    0x9A,                   // TXS
+    0xA9, 0x80,             // LDA #$80
+    0x8D, 0x00, 0x20,       // STA $2000
+    0xA9, 0x1E,             // LDA #$1E
+    0x8D, 0x01, 0x20,       // STA $2001
+    // $800F loop
+    0xA2, 0x00,             // LDX #0
+    // $8011 inner
+    0xB5, 0x00,             // LDA $00,X
+    0x69, 0x03,             // ADC #3
+    0x9D, 0x00, 0x02,       // STA $0200,X
+    0x45, 0x10,             // EOR $10
+    0x2A,                   // ROL A
+    0x85, 0x10,             // STA $10
+    0x20, 0x2B, 0x80,       // JSR $802B
+    0xE8,                   // INX
+    0xD0, 0xEE,             // BNE inner
+    0xB1, 0x20,             // LDA ($20),Y
+    0xC8,                   // INY
+    0x4C, 0x0F, 0x80,       // JMP loop
+    0xEA, 0xEA,
+    // $802B
+    0xA5, 0x11,             // LDA $11
+    0x18,                   // CLC
+    0x69, 0x01,             // ADC #1
+    0x85, 0x11,             // STA $11
+    0x60,                   // RTS
+    // $8033 NMI, copying the page written above to OAM
+    0x48,                   // PHA
+    0xA9, 0x02,             // LDA #2
+    0x8D, 0x14, 0x40,       // STA $4014
+    0x68,                   // PLA
+    0x40,                   // RTI
+    // $803B IRQ
+    0x40,                   // RTI
+];
+
+// 32KB of PRG with the program at $8000, and 8KB of CHR with a different
+// pattern in every tile
+fn rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x8000];
+    prg[..PROGRAM.len()].copy_from_slice(PROGRAM);
+    prg[0x7FFA..].copy_from_slice(&[0x33, 0x80, 0x00, 0x80, 0x3B, 0x80]);
+    let chr = (0..0x2000u32).map(|i| (i.wrapping_mul(37) >> 3) as u8);
+
+    let mut rom = b"NES\x1a\x02\x01\x01\x00".to_vec();
+    rom.resize(16, 0);
+    rom.extend_from_slice(&prg);
+    rom.extend(chr);
+    rom
+}
+
+fn console() -> Console {
+    let cartridge = Cartridge::from_bytes(&rom()).expect("benchmark ROM is valid");
+    let mut console = Console::new(cartridge).expect("benchmark ROM has a supported mapper");
+    // Past the warm-up frames, with rendering on
+    for _ in 0..4 {
+        console.step_frame();
+    }
+    console
+}
+
+fn cpu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    let mut console = console();
+    group.bench_function("instructions", |b| {
+        b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                black_box(console.cpu.step());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn ppu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ppu");
+    group.throughput(Throughput::Elements(DOTS));
+    let mut console = console();
+    group.bench_function("dots", |b| {
+        b.iter(|| {
+            let ppu = console.ppu_mut();
+            for _ in 0..DOTS {
+                ppu.step();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    group.throughput(Throughput::Elements(1));
+    for (name, accuracy, renderer) in [
+        ("fast", Accuracy::Fast, Renderer::Dot),
+        ("scanline", Accuracy::Fast, Renderer::Scanline),
+        ("cycle_accurate", Accuracy::CycleAccurate, Renderer::Dot),
+    ] {
+        let mut console = console();
+        console.set_accuracy(accuracy);
+        console.set_renderer(renderer);
+        group.bench_function(name, |b| b.iter(|| black_box(console.step_frame())));
+    }
+    group.finish();
+}
+
+// Each game's own frames, when $NESRS_BENCH_ROMS is set
+fn roms(c: &mut Criterion) {
+    let Some(dir) = env::var_os("NESRS_BENCH_ROMS").map(PathBuf::from) else {
+        return;
+    };
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .expect("NESRS_BENCH_ROMS is a directory")
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("nes"))
+        })
+        .collect();
+    paths.sort();
+
+    let mut group = c.benchmark_group("rom");
+    group.throughput(Throughput::Elements(1));
+    for path in paths {
+        let mut console = match Cartridge::from_path(&path).and_then(Console::new) {
+            Ok(console) => console,
+            Err(err) => {
+                eprintln!("skipping {}: {}", path.display(), err);
+                continue;
+            }
+        };
+        // Through the title screen's setup to something more typical
+        for _ in 0..120 {
+            console.step_frame();
+        }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        group.bench_function(name.as_ref(), |b| {
+            b.iter(|| black_box(console.step_frame()))
+        });
+    }
+    group.finish();
+}
+
+fn savestate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("savestate");
+    let mut console = console();
+    let state = console.save_state();
+    group.throughput(Throughput::Bytes(state.len() as u64));
+    group.bench_function("save", |b| b.iter(|| black_box(console.save_state())));
+    group.bench_function("load", |b| {
+        b.iter(|| console.load_state(&state).expect("state loads"))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, cpu, ppu, frame, roms, savestate);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+use std::env;
+
+// Regenerates include/nesrs.h from the exported functions
+fn main() {
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir))
+        .expect("cbindgen.toml is valid");
+    cbindgen::Builder::new()
+        .with_crate(&dir)
+        .with_config(config)
+        .generate()
+        .expect("C bindings generate")
+        .write_to_file(format!("{}/include/nesrs.h", dir));
+}
@@ -0,0 +1,174 @@
+/* Generated by cbindgen from capi/src/lib.rs; do not edit. */
+
+#ifndef NESRS_H
+#define NESRS_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of a call; nesrs_last_error describes anything but NESRS_STATUS_OK.
+ */
+typedef enum NesrsStatus {
+  NESRS_STATUS_OK = 0,
+  /**
+   * A null or out-of-range argument
+   */
+  NESRS_STATUS_INVALID_ARGUMENT,
+  /**
+   * The ROM image couldn't be loaded
+   */
+  NESRS_STATUS_INVALID_ROM,
+  /**
+   * The call needs a ROM loaded first
+   */
+  NESRS_STATUS_NO_ROM,
+  /**
+   * The save state couldn't be restored
+   */
+  NESRS_STATUS_INVALID_STATE,
+} NesrsStatus;
+
+/**
+ * Joypad buttons, as passed to nesrs_set_button.
+ */
+typedef enum NesrsButton {
+  NESRS_BUTTON_A = 0,
+  NESRS_BUTTON_B,
+  NESRS_BUTTON_SELECT,
+  NESRS_BUTTON_START,
+  NESRS_BUTTON_UP,
+  NESRS_BUTTON_DOWN,
+  NESRS_BUTTON_LEFT,
+  NESRS_BUTTON_RIGHT,
+} NesrsButton;
+
+/**
+ * An emulated console, created by nesrs_create and freed by nesrs_destroy.
+ */
+typedef struct NesrsConsole NesrsConsole;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a console with no ROM loaded.
+ */
+struct NesrsConsole *nesrs_create(void);
+
+/**
+ * Frees a console from nesrs_create. Null is ignored.
+ *
+ * # Safety
+ * `nes` must be null or a handle from nesrs_create not already destroyed.
+ */
+void nesrs_destroy(struct NesrsConsole *nes);
+
+/**
+ * Describes why the last failed call failed, as a NUL-terminated string
+ * that lives until the next call on `nes`.
+ *
+ * # Safety
+ * `nes` must be a valid handle.
+ */
+const char *nesrs_last_error(const struct NesrsConsole *nes);
+
+/**
+ * Loads an iNES or NES 2.0 image of `len` bytes, replacing any running
+ * game. The data is copied.
+ *
+ * # Safety
+ * `nes` must be a valid handle and `data` point to `len` readable bytes.
+ */
+enum NesrsStatus nesrs_load_rom(struct NesrsConsole *nes, const uint8_t *data, size_t len);
+
+/**
+ * Runs the console until the PPU finishes a frame.
+ *
+ * # Safety
+ * `nes` must be a valid handle.
+ */
+enum NesrsStatus nesrs_run_frame(struct NesrsConsole *nes);
+
+/**
+ * Returns the last completed frame as RGBA bytes, row by row, and stores
+ * its size in `width` and `height` when they aren't null. The pixels stay
+ * valid until the next call on `nes`. Null when no ROM is loaded.
+ *
+ * # Safety
+ * `nes` must be a valid handle; `width` and `height` null or writable.
+ */
+const uint8_t *nesrs_get_framebuffer(struct NesrsConsole *nes, uint32_t *width, uint32_t *height);
+
+/**
+ * Presses or releases a button on joypad `player` (0-3, 2 and 3 being the
+ * Four Score's).
+ *
+ * # Safety
+ * `nes` must be a valid handle and `button` one of the NesrsButton values.
+ */
+enum NesrsStatus nesrs_set_button(struct NesrsConsole *nes,
+                                  uint32_t player,
+                                  enum NesrsButton button,
+                                  bool pressed);
+
+/**
+ * Sets the rate, in Hz, of the audio nesrs_drain_audio returns.
+ *
+ * # Safety
+ * `nes` must be a valid handle.
+ */
+enum NesrsStatus nesrs_set_sample_rate(struct NesrsConsole *nes, double sample_rate);
+
+/**
+ * Moves up to `len` mono samples of buffered audio into `out`, returning
+ * how many were written.
+ *
+ * # Safety
+ * `nes` must be a valid handle and `out` point to `len` writable floats.
+ */
+size_t nesrs_drain_audio(struct NesrsConsole *nes, float *out, size_t len);
+
+/**
+ * Snapshots the console, returning a buffer of `*len` bytes to be freed
+ * with nesrs_free_state, or null when no ROM is loaded.
+ *
+ * # Safety
+ * `nes` must be a valid handle and `len` writable.
+ */
+uint8_t *nesrs_save_state(struct NesrsConsole *nes, size_t *len);
+
+/**
+ * Frees a buffer from nesrs_save_state. Null is ignored.
+ *
+ * # Safety
+ * `state` and `len` must be exactly as nesrs_save_state returned them.
+ */
+void nesrs_free_state(uint8_t *state, size_t len);
+
+/**
+ * Restores a state from nesrs_save_state, possibly from an earlier run of
+ * the same ROM.
+ *
+ * # Safety
+ * `nes` must be a valid handle and `data` point to `len` readable bytes.
+ */
+enum NesrsStatus nesrs_load_state(struct NesrsConsole *nes, const uint8_t *data, size_t len);
+
+/**
+ * Presses the console's reset button.
+ *
+ * # Safety
+ * `nes` must be a valid handle.
+ */
+enum NesrsStatus nesrs_reset(struct NesrsConsole *nes);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NESRS_H */
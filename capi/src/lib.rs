@@ -0,0 +1,309 @@
+// C bindings for embedding the emulator. Doc comments here are copied into
+// include/nesrs.h by cbindgen, so they are written for C callers.
+//
+// Every function takes the handle from nesrs_create, which must not be used
+// from two threads at once. Null handles are tolerated and reported.
+
+use std::{
+    ffi::{c_char, CString},
+    ptr, slice,
+};
+
+use nesrs::{
+    controller::Button,
+    ppu::{HEIGHT, WIDTH},
+    Cartridge, Console,
+};
+
+/// Result of a call; nesrs_last_error describes anything but NESRS_STATUS_OK.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NesrsStatus {
+    Ok = 0,
+    /// A null or out-of-range argument
+    InvalidArgument,
+    /// The ROM image couldn't be loaded
+    InvalidRom,
+    /// The call needs a ROM loaded first
+    NoRom,
+    /// The save state couldn't be restored
+    InvalidState,
+}
+
+/// Joypad buttons, as passed to nesrs_set_button.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NesrsButton {
+    A = 0,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// An emulated console, created by nesrs_create and freed by nesrs_destroy.
+pub struct NesrsConsole {
+    console: Option<Console>,
+    error: CString,
+}
+
+impl NesrsConsole {
+    fn fail(&mut self, status: NesrsStatus, message: impl ToString) -> NesrsStatus {
+        let message = message.to_string().replace('\0', " ");
+        self.error = CString::new(message).unwrap_or_default();
+        status
+    }
+
+    fn console(&mut self) -> Result<&mut Console, NesrsStatus> {
+        if self.console.is_none() {
+            return Err(self.fail(NesrsStatus::NoRom, "no ROM is loaded"));
+        }
+        Ok(self.console.as_mut().unwrap())
+    }
+}
+
+/// Creates a console with no ROM loaded.
+#[no_mangle]
+pub extern "C" fn nesrs_create() -> *mut NesrsConsole {
+    Box::into_raw(Box::new(NesrsConsole {
+        console: None,
+        error: CString::default(),
+    }))
+}
+
+/// Frees a console from nesrs_create. Null is ignored.
+///
+/// # Safety
+/// `nes` must be null or a handle from nesrs_create not already destroyed.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_destroy(nes: *mut NesrsConsole) {
+    if !nes.is_null() {
+        drop(Box::from_raw(nes));
+    }
+}
+
+/// Describes why the last failed call failed, as a NUL-terminated string
+/// that lives until the next call on `nes`.
+///
+/// # Safety
+/// `nes` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_last_error(nes: *const NesrsConsole) -> *const c_char {
+    match nes.as_ref() {
+        Some(nes) => nes.error.as_ptr(),
+        None => c"null console".as_ptr(),
+    }
+}
+
+/// Loads an iNES or NES 2.0 image of `len` bytes, replacing any running
+/// game. The data is copied.
+///
+/// # Safety
+/// `nes` must be a valid handle and `data` point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_load_rom(
+    nes: *mut NesrsConsole,
+    data: *const u8,
+    len: usize,
+) -> NesrsStatus {
+    let Some(nes) = nes.as_mut() else {
+        return NesrsStatus::InvalidArgument;
+    };
+    if data.is_null() {
+        return nes.fail(NesrsStatus::InvalidArgument, "ROM data is null");
+    }
+    let console = Cartridge::from_bytes(slice::from_raw_parts(data, len))
+        .and_then(Console::new)
+        .map_err(|err| nes.fail(NesrsStatus::InvalidRom, err));
+    match console {
+        Ok(console) => {
+            nes.console = Some(console);
+            NesrsStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
+
+/// Runs the console until the PPU finishes a frame.
+///
+/// # Safety
+/// `nes` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_run_frame(nes: *mut NesrsConsole) -> NesrsStatus {
+    let Some(nes) = nes.as_mut() else {
+        return NesrsStatus::InvalidArgument;
+    };
+    match nes.console() {
+        Ok(console) => {
+            console.step_frame();
+            NesrsStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
+
+/// Returns the last completed frame as RGBA bytes, row by row, and stores
+/// its size in `width` and `height` when they aren't null. The pixels stay
+/// valid until the next call on `nes`. Null when no ROM is loaded.
+///
+/// # Safety
+/// `nes` must be a valid handle; `width` and `height` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_get_framebuffer(
+    nes: *mut NesrsConsole,
+    width: *mut u32,
+    height: *mut u32,
+) -> *const u8 {
+    if let Some(width) = width.as_mut() {
+        *width = WIDTH;
+    }
+    if let Some(height) = height.as_mut() {
+        *height = HEIGHT;
+    }
+    match nes.as_mut().map(|nes| nes.console()) {
+        Some(Ok(console)) => console.framebuffer().as_raw().as_ptr(),
+        _ => ptr::null(),
+    }
+}
+
+/// Presses or releases a button on joypad `player` (0-3, 2 and 3 being the
+/// Four Score's).
+///
+/// # Safety
+/// `nes` must be a valid handle and `button` one of the NesrsButton values.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_set_button(
+    nes: *mut NesrsConsole,
+    player: u32,
+    button: NesrsButton,
+    pressed: bool,
+) -> NesrsStatus {
+    let Some(nes) = nes.as_mut() else {
+        return NesrsStatus::InvalidArgument;
+    };
+    if player > 3 {
+        return nes.fail(NesrsStatus::InvalidArgument, "player must be 0-3");
+    }
+    match nes.console() {
+        Ok(console) => {
+            console.set_button(player as usize, Button::ALL[button as usize], pressed);
+            NesrsStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
+
+/// Sets the rate, in Hz, of the audio nesrs_drain_audio returns.
+///
+/// # Safety
+/// `nes` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_set_sample_rate(
+    nes: *mut NesrsConsole,
+    sample_rate: f64,
+) -> NesrsStatus {
+    let Some(nes) = nes.as_mut() else {
+        return NesrsStatus::InvalidArgument;
+    };
+    match nes.console() {
+        Ok(console) => {
+            console.set_sample_rate(sample_rate);
+            NesrsStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
+
+/// Moves up to `len` mono samples of buffered audio into `out`, returning
+/// how many were written.
+///
+/// # Safety
+/// `nes` must be a valid handle and `out` point to `len` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_drain_audio(
+    nes: *mut NesrsConsole,
+    out: *mut f32,
+    len: usize,
+) -> usize {
+    match (nes.as_mut().map(|nes| nes.console()), out.is_null()) {
+        (Some(Ok(console)), false) => console.drain_samples(slice::from_raw_parts_mut(out, len)),
+        _ => 0,
+    }
+}
+
+/// Snapshots the console, returning a buffer of `*len` bytes to be freed
+/// with nesrs_free_state, or null when no ROM is loaded.
+///
+/// # Safety
+/// `nes` must be a valid handle and `len` writable.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_save_state(nes: *mut NesrsConsole, len: *mut usize) -> *mut u8 {
+    let (Some(nes), Some(len)) = (nes.as_mut(), len.as_mut()) else {
+        return ptr::null_mut();
+    };
+    let Ok(console) = nes.console() else {
+        return ptr::null_mut();
+    };
+    let state = console.save_state().into_boxed_slice();
+    *len = state.len();
+    Box::into_raw(state) as *mut u8
+}
+
+/// Frees a buffer from nesrs_save_state. Null is ignored.
+///
+/// # Safety
+/// `state` and `len` must be exactly as nesrs_save_state returned them.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_free_state(state: *mut u8, len: usize) {
+    if !state.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(state, len)));
+    }
+}
+
+/// Restores a state from nesrs_save_state, possibly from an earlier run of
+/// the same ROM.
+///
+/// # Safety
+/// `nes` must be a valid handle and `data` point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_load_state(
+    nes: *mut NesrsConsole,
+    data: *const u8,
+    len: usize,
+) -> NesrsStatus {
+    let Some(nes) = nes.as_mut() else {
+        return NesrsStatus::InvalidArgument;
+    };
+    if data.is_null() {
+        return nes.fail(NesrsStatus::InvalidArgument, "state data is null");
+    }
+    let result = match nes.console() {
+        Ok(console) => console.load_state(slice::from_raw_parts(data, len)),
+        Err(status) => return status,
+    };
+    match result {
+        Ok(()) => NesrsStatus::Ok,
+        Err(err) => nes.fail(NesrsStatus::InvalidState, err),
+    }
+}
+
+/// Presses the console's reset button.
+///
+/// # Safety
+/// `nes` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn nesrs_reset(nes: *mut NesrsConsole) -> NesrsStatus {
+    let Some(nes) = nes.as_mut() else {
+        return NesrsStatus::InvalidArgument;
+    };
+    match nes.console() {
+        Ok(console) => {
+            console.reset();
+            NesrsStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
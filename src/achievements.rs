@@ -0,0 +1,54 @@
+use crate::memory::CPUMemory;
+
+// Size of the address space RetroAchievements gives the NES: the CPU's
+pub const ADDRESS_SPACE: u32 = 0x10000;
+
+// Called once a frame with the console's memory, for evaluating
+// achievements where rcheevos expects to run
+pub type AchievementCallback = Box<dyn FnMut(&AchievementMemory) + Send>;
+
+// NES memory laid out the way RetroAchievements addresses it, so achievement
+// sets written against other emulators read the same bytes here: the CPU's
+// $0000-$FFFF with RAM and its mirrors, cartridge RAM at $6000-$7FFF and
+// PRG ROM above. Registers and the expansion area at $2000-$5FFF read as
+// 0, as nothing is mapped there for them. Reads have no side effects.
+pub struct AchievementMemory<'a> {
+    memory: &'a CPUMemory,
+}
+
+impl<'a> AchievementMemory<'a> {
+    pub fn new(memory: &'a CPUMemory) -> Self {
+        Self { memory }
+    }
+
+    // Byte at `addr`, 0 past the end of the address space
+    pub fn peek(&self, addr: u32) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.memory.ram[addr as usize % 0x0800],
+            0x2000..=0x5FFF => 0,
+            0x6000..=0xFFFF => self.memory.mapper().prg_peek(addr as u16),
+            _ => 0,
+        }
+    }
+
+    // `bytes` bytes (up to 4) from `addr` as a little-endian value, as
+    // rcheevos's peek callback returns them
+    pub fn peek_value(&self, addr: u32, bytes: u32) -> u32 {
+        (0..bytes.min(4)).fold(0, |value, offset| {
+            value | (self.peek(addr.wrapping_add(offset)) as u32) << (offset * 8)
+        })
+    }
+
+    // Fills `buf` from `addr` on
+    pub fn read_into(&self, addr: u32, buf: &mut [u8]) {
+        for (offset, byte) in buf.iter_mut().enumerate() {
+            *byte = self.peek(addr.wrapping_add(offset as u32));
+        }
+    }
+
+    // All of the cartridge's RAM, for boards with more than the 8KB bank
+    // $6000-$7FFF shows
+    pub fn sram(&self) -> &[u8] {
+        &self.memory.mapper().cartridge().sram
+    }
+}
@@ -0,0 +1,1174 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::{FilterChain, WavCapture},
+    region::Region,
+    savestate::{StateError, StateReader, StateWriter},
+    utils::RingBuffer,
+};
+
+const SAMPLE_BUFFER_SIZE: usize = 16384;
+
+static LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+static DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+static TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+static NOISE_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+static PAL_NOISE_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+// DMC rates in APU cycles (two CPU cycles each)
+static DMC_TABLE: [u8; 16] = [
+    214, 190, 170, 160, 143, 127, 113, 107, 95, 80, 71, 64, 53, 42, 36, 27,
+];
+
+static PAL_DMC_TABLE: [u8; 16] = [
+    199, 177, 158, 149, 138, 118, 105, 99, 88, 74, 66, 59, 49, 39, 33, 25,
+];
+
+// A mixer input: one of the 2A03's channels, or the cartridge's expansion
+// channel at an index into Console::expansion_channels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DMC,
+    Expansion(usize),
+}
+
+impl Channel {
+    // The 2A03's own channels, in mixer order
+    pub const APU: [Channel; 5] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::DMC,
+    ];
+
+    // Slot in the mixer settings and channel taps: expansion channels
+    // share the last one
+    fn index(self) -> usize {
+        match self {
+            Channel::Pulse1 => 0,
+            Channel::Pulse2 => 1,
+            Channel::Triangle => 2,
+            Channel::Noise => 3,
+            Channel::DMC => 4,
+            Channel::Expansion(_) => 5,
+        }
+    }
+}
+
+// How one channel is mixed. While any channel is soloed only soloed ones
+// are heard.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelMix {
+    pub volume: f32,
+    pub muted: bool,
+    pub solo: bool,
+}
+
+impl Default for ChannelMix {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+            solo: false,
+        }
+    }
+}
+
+// Each 2A03 channel's level alone through the mixer, plus the expansion
+// audio, averaged per output sample and left unfiltered. Boards only
+// report their channels summed, so expansion is a single stream.
+struct ChannelTaps {
+    sums: [f32; 6],
+    samples: [RingBuffer<f32>; 6],
+}
+
+pub struct APU {
+    region: Region,
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: DMC,
+    cycle: u64,
+    // CPU cycles since the frame counter's sequence last restarted
+    frame_cycle: u64,
+    frame_period: u8,
+    frame_irq_enabled: bool,
+    frame_irq: bool,
+    // A $4017 write still waiting to restart the sequence: the new period
+    // and the CPU cycles left until it does
+    frame_write: Option<(u8, u8)>,
+
+    // Nonlinear mixer lookup tables
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+    mix: [ChannelMix; 5],
+    expansion_mix: Vec<ChannelMix>,
+    // Whether any mix differs from the default, which takes the mixer off
+    // its lookup tables
+    custom_mix: bool,
+    taps: Option<Box<ChannelTaps>>,
+    captures: Vec<WavCapture>,
+
+    // Output is the mixer averaged over each sample period, then run
+    // through the console's analog filters
+    sample_rate: f64,
+    // Speed correction from the audio backend, see `set_rate_adjust`
+    rate_adjust: f64,
+    // CPU cycles per output sample
+    sample_period: f64,
+    sample_clock: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    filters: FilterChain,
+    samples: RingBuffer<f32>,
+    // Set while emulating frames that won't be heard, see `set_silent`
+    silent: bool,
+}
+
+// The mixer's two nonlinear stages, the formulas behind its lookup tables,
+// for sums that aren't whole numbers
+fn pulse_level(sum: f32) -> f32 {
+    if sum <= 0.0 {
+        0.0
+    } else {
+        95.52 / (8128.0 / sum + 100.0)
+    }
+}
+
+fn tnd_level(sum: f32) -> f32 {
+    if sum <= 0.0 {
+        0.0
+    } else {
+        163.67 / (24329.0 / sum + 100.0)
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (i, value) in pulse_table.iter_mut().enumerate().skip(1) {
+            *value = 95.52 / (8128.0 / i as f32 + 100.0);
+        }
+        let mut tnd_table = [0.0; 203];
+        for (i, value) in tnd_table.iter_mut().enumerate().skip(1) {
+            *value = 163.67 / (24329.0 / i as f32 + 100.0);
+        }
+
+        let region = Region::NTSC;
+        Self {
+            region,
+            pulse1: Pulse::new(1),
+            pulse2: Pulse::new(2),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: DMC::default(),
+            cycle: 0,
+            frame_cycle: 0,
+            frame_period: 4,
+            frame_irq_enabled: true,
+            frame_irq: false,
+            frame_write: None,
+            pulse_table,
+            tnd_table,
+            mix: [ChannelMix::default(); 5],
+            expansion_mix: Vec::new(),
+            custom_mix: false,
+            taps: None,
+            captures: Vec::new(),
+            sample_rate: 44100.0,
+            rate_adjust: 1.0,
+            sample_period: region.cpu_frequency() as f64 / 44100.0,
+            sample_clock: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filters: FilterChain::nes(44100.0),
+            samples: RingBuffer::new(SAMPLE_BUFFER_SIZE),
+            silent: false,
+        }
+    }
+
+    // Power-on state: every channel cleared and silent, and the frame
+    // counter in 4-step mode. Mixer, output and capture settings are kept.
+    pub fn power_on(&mut self) {
+        self.pulse1 = Pulse::new(1);
+        self.pulse2 = Pulse::new(2);
+        self.triangle = Triangle::default();
+        self.noise = Noise::default();
+        self.dmc = DMC::default();
+        self.frame_cycle = 0;
+        self.frame_period = 4;
+        self.frame_irq_enabled = true;
+        self.frame_irq = false;
+        self.frame_write = None;
+    }
+
+    // The reset button: the channels are silenced as by a $4015 write of 0,
+    // the DMC's output drops to its low bit, and the frame counter restarts
+    // in the mode last written to $4017
+    pub fn reset(&mut self) {
+        self.write_control(0);
+        self.dmc.value &= 1;
+        self.frame_irq = false;
+        let mode = if self.frame_period == 5 { 0x80 } else { 0 };
+        let inhibit = if self.frame_irq_enabled { 0 } else { 0x40 };
+        self.write_frame_counter(mode | inhibit);
+    }
+
+    // Switches the clock rate and the frame counter and period tables
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.set_sample_rate(self.sample_rate);
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.set_rate_adjust(self.rate_adjust);
+        self.filters = FilterChain::nes(sample_rate as f32);
+        self.samples.clear();
+    }
+
+    // Scales the number of samples produced per emulated second by `ratio`,
+    // e.g. 1.002 for 0.2% more. Audio backends use it to track the sound
+    // card's real clock without resetting the stream.
+    pub fn set_rate_adjust(&mut self, ratio: f64) {
+        self.rate_adjust = ratio;
+        self.sample_period = self.region.cpu_frequency() as f64 / (self.sample_rate * ratio);
+    }
+
+    // Moves buffered samples into `out`, returning how many were written
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        self.samples.drain_into(out)
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    // Stops the APU producing output, to samples, taps or captures, while
+    // its channels keep running. For frames emulated and then thrown away,
+    // like run-ahead's, which would otherwise be heard twice.
+    pub fn set_silent(&mut self, silent: bool) {
+        self.silent = silent;
+    }
+
+    pub fn channel_mix(&self, channel: Channel) -> ChannelMix {
+        match channel {
+            Channel::Expansion(index) => self.expansion_mix.get(index).copied().unwrap_or_default(),
+            channel => self.mix[channel.index()],
+        }
+    }
+
+    // Expansion channels only take effect once their gain is passed on to
+    // the board, as Console does
+    pub fn set_channel_mix(&mut self, channel: Channel, mix: ChannelMix) {
+        match channel {
+            Channel::Expansion(index) => {
+                if self.expansion_mix.len() <= index {
+                    self.expansion_mix.resize(index + 1, ChannelMix::default());
+                }
+                self.expansion_mix[index] = mix;
+            }
+            channel => self.mix[channel.index()] = mix,
+        }
+        let default = ChannelMix::default();
+        self.custom_mix = self
+            .mix
+            .iter()
+            .chain(&self.expansion_mix)
+            .any(|&mix| mix != default);
+    }
+
+    // The volume `channel` is heard at once muting and soloing are applied
+    pub fn channel_gain(&self, channel: Channel) -> f32 {
+        let solo = self
+            .mix
+            .iter()
+            .chain(&self.expansion_mix)
+            .any(|mix| mix.solo);
+        let mix = self.channel_mix(channel);
+        if mix.muted || (solo && !mix.solo) {
+            0.0
+        } else {
+            mix.volume
+        }
+    }
+
+    // Starts or stops collecting each channel's output separately, for
+    // visualizers and ripping channels to their own files
+    pub fn set_channel_taps(&mut self, enabled: bool) {
+        self.taps = enabled.then(|| {
+            Box::new(ChannelTaps {
+                sums: [0.0; 6],
+                samples: std::array::from_fn(|_| RingBuffer::new(SAMPLE_BUFFER_SIZE)),
+            })
+        });
+    }
+
+    // Moves `channel`'s tapped samples into `out`, returning how many were
+    // written. Every expansion channel drains the same, combined stream.
+    pub fn drain_channel_samples(&mut self, channel: Channel, out: &mut [f32]) -> usize {
+        match &mut self.taps {
+            Some(taps) => taps.samples[channel.index()].drain_into(out),
+            None => 0,
+        }
+    }
+
+    // Starts writing the APU's output to `capture`, at the rate it was
+    // created for, alongside any other captures until `stop_capture`
+    pub fn start_capture(&mut self, capture: WavCapture) {
+        self.captures.push(capture);
+    }
+
+    // Removes the capture writing to `path`, to be finished
+    pub fn stop_capture(&mut self, path: &Path) -> Option<WavCapture> {
+        let index = self
+            .captures
+            .iter()
+            .position(|capture| capture.path() == path)?;
+        Some(self.captures.remove(index))
+    }
+
+    // Buffered samples and the output rate are frontend settings and are
+    // left alone
+    pub fn save(&self, state: &mut StateWriter) {
+        state.write(&self.pulse1);
+        state.write(&self.pulse2);
+        state.write(&self.triangle);
+        state.write(&self.noise);
+        state.write(&self.dmc);
+        state.write(&self.cycle);
+        state.write(&self.frame_cycle);
+        state.write(&self.frame_period);
+        state.write(&self.frame_irq_enabled);
+        state.write(&self.frame_irq);
+        state.write(&self.frame_write);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse1 = state.read()?;
+        self.pulse2 = state.read()?;
+        self.triangle = state.read()?;
+        self.noise = state.read()?;
+        self.dmc = state.read()?;
+        self.cycle = state.read()?;
+        self.frame_cycle = state.read()?;
+        self.frame_period = state.read()?;
+        self.frame_irq_enabled = state.read()?;
+        self.frame_irq = state.read()?;
+        self.frame_write = state.read()?;
+        Ok(())
+    }
+
+    // Advances the APU by one CPU cycle, mixing in `expansion`: the level of
+    // the cartridge's own sound channels, on the same 0-1 scale as the APU
+    pub fn step(&mut self, expansion: f32) {
+        self.cycle += 1;
+        self.step_timer();
+        self.step_frame_counter();
+        if self.silent {
+            return;
+        }
+
+        let output = self.output() + expansion;
+        self.sample_sum += output;
+        self.sample_count += 1;
+        let stems = self.captures.iter().any(WavCapture::stems);
+        let channels = if self.taps.is_some() || stems {
+            self.channel_outputs(expansion)
+        } else {
+            [0.0; 6]
+        };
+        if let Some(taps) = &mut self.taps {
+            for (sum, level) in taps.sums.iter_mut().zip(channels) {
+                *sum += level;
+            }
+        }
+        for capture in &mut self.captures {
+            capture.step(output, &channels);
+        }
+        self.sample_clock += 1.0;
+        if self.sample_clock >= self.sample_period {
+            self.sample_clock -= self.sample_period;
+            let count = self.sample_count as f32;
+            let sample = self.sample_sum / count;
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+            let sample = self.filters.step(sample);
+            self.samples.push(sample);
+            if let Some(taps) = &mut self.taps {
+                for (sum, samples) in taps.sums.iter_mut().zip(&mut taps.samples) {
+                    samples.push(*sum / count);
+                    *sum = 0.0;
+                }
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        if self.custom_mix {
+            let [p1, p2, t, n, d] = self.channel_levels();
+            return pulse_level(p1 + p2) + tnd_level(t + n + d);
+        }
+        let p1 = self.pulse1.output();
+        let p2 = self.pulse2.output();
+        let t = self.triangle.output();
+        let n = self.noise.output();
+        let d = self.dmc.output();
+        let pulse_out = self.pulse_table[(p1 + p2) as usize];
+        let tnd_out = self.tnd_table[3 * t as usize + 2 * n as usize + d as usize];
+        pulse_out + tnd_out
+    }
+
+    // Each channel's output with its gain applied, weighted as the mixer
+    // sums them
+    fn channel_levels(&self) -> [f32; 5] {
+        let outputs = [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            3 * self.triangle.output(),
+            2 * self.noise.output(),
+            self.dmc.output(),
+        ];
+        let mut levels = [0.0; 5];
+        for ((level, output), channel) in levels.iter_mut().zip(outputs).zip(Channel::APU) {
+            *level = output as f32 * self.channel_gain(channel);
+        }
+        levels
+    }
+
+    // Each channel alone through the mixer, in Channel::index order
+    fn channel_outputs(&self, expansion: f32) -> [f32; 6] {
+        let [p1, p2, t, n, d] = self.channel_levels();
+        [
+            pulse_level(p1),
+            pulse_level(p2),
+            tnd_level(t),
+            tnd_level(n),
+            tnd_level(d),
+            expansion,
+        ]
+    }
+
+    // mode 0:    mode 1:       function
+    // ---------  -----------  -----------------------------
+    //  - - - f    - - - - -    IRQ (if bit 6 is clear)
+    //  - l - l    - l - - l    Length counter and sweep
+    //  e e e e    e e e - e    Envelope and linear counter
+    //
+    // The 4-step sequence raises the IRQ flag on the cycle before its last
+    // step and the cycle after too, restarting on the latter, so a $4015
+    // read on either of the first two doesn't keep it clear.
+    fn step_frame_counter(&mut self) {
+        if let Some((period, delay)) = self.frame_write {
+            if delay > 1 {
+                self.frame_write = Some((period, delay - 1));
+            } else {
+                // Restarting in 5-step mode clocks everything at once
+                self.frame_write = None;
+                self.frame_period = period;
+                self.frame_cycle = 0;
+                if period == 5 {
+                    self.step_half_frame();
+                }
+                return;
+            }
+        }
+
+        self.frame_cycle += 1;
+        let [step1, step2, step3, step4, step5] = self.region.frame_counter_steps();
+        match (self.frame_period, self.frame_cycle) {
+            (_, cycle) if cycle == step1 || cycle == step3 => self.step_envelope(),
+            (_, cycle) if cycle == step2 => self.step_half_frame(),
+            (4, cycle) if cycle == step4 - 1 => self.fire_irq(),
+            (4, cycle) if cycle == step4 => {
+                self.step_half_frame();
+                self.fire_irq();
+            }
+            (4, cycle) if cycle == step4 + 1 => {
+                self.fire_irq();
+                self.frame_cycle = 0;
+            }
+            (5, cycle) if cycle == step5 => self.step_half_frame(),
+            (5, cycle) if cycle == step5 + 1 => self.frame_cycle = 0,
+            _ => {}
+        }
+    }
+
+    // Envelopes and linear counter, then length counters and sweeps
+    fn step_half_frame(&mut self) {
+        self.step_envelope();
+        self.step_sweep();
+        self.step_length();
+    }
+
+    fn step_timer(&mut self) {
+        if self.cycle & 1 == 0 {
+            self.pulse1.step_timer();
+            self.pulse2.step_timer();
+            self.noise.step_timer();
+            self.dmc.step_timer();
+        }
+        self.triangle.step_timer();
+    }
+
+    fn step_envelope(&mut self) {
+        self.pulse1.step_envelope();
+        self.pulse2.step_envelope();
+        self.triangle.step_counter();
+        self.noise.step_envelope();
+    }
+
+    fn step_sweep(&mut self) {
+        self.pulse1.step_sweep();
+        self.pulse2.step_sweep();
+    }
+
+    fn step_length(&mut self) {
+        self.pulse1.step_length();
+        self.pulse2.step_length();
+        self.triangle.step_length();
+        self.noise.step_length();
+    }
+
+    fn fire_irq(&mut self) {
+        if self.frame_irq_enabled {
+            self.frame_irq = true;
+        }
+    }
+
+    // State of the APU's IRQ output: frame counter or DMC sample end
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq_pending
+    }
+
+    // Address the DMC wants its next sample byte read from, if any
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    // Delivers the byte read for `dmc_fetch_address`
+    pub fn dmc_fill(&mut self, value: u8) {
+        self.dmc.fill(value);
+    }
+
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => self.read_status(),
+            _ => 0,
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000 => self.pulse1.write_control(value),
+            0x4001 => self.pulse1.write_sweep(value),
+            0x4002 => self.pulse1.write_timer_low(value),
+            0x4003 => self.pulse1.write_timer_high(value),
+            0x4004 => self.pulse2.write_control(value),
+            0x4005 => self.pulse2.write_sweep(value),
+            0x4006 => self.pulse2.write_timer_low(value),
+            0x4007 => self.pulse2.write_timer_high(value),
+            0x4008 => self.triangle.write_control(value),
+            0x400A => self.triangle.write_timer_low(value),
+            0x400B => self.triangle.write_timer_high(value),
+            0x400C => self.noise.write_control(value),
+            0x400E => self.noise.write_period(value, self.region),
+            0x400F => self.noise.write_length(value),
+            0x4010 => self.dmc.write_control(value, self.region),
+            0x4011 => self.dmc.write_value(value),
+            0x4012 => self.dmc.write_address(value),
+            0x4013 => self.dmc.write_length(value),
+            0x4015 => self.write_control(value),
+            0x4017 => self.write_frame_counter(value),
+            _ => {}
+        }
+    }
+
+    // $4015: status (read)
+    fn read_status(&mut self) -> u8 {
+        let mut result = 0;
+        if self.pulse1.length_value > 0 {
+            result |= 1;
+        }
+        if self.pulse2.length_value > 0 {
+            result |= 2;
+        }
+        if self.triangle.length_value > 0 {
+            result |= 4;
+        }
+        if self.noise.length_value > 0 {
+            result |= 8;
+        }
+        if self.dmc.current_length > 0 {
+            result |= 16;
+        }
+        if self.frame_irq {
+            result |= 64;
+        }
+        if self.dmc.irq_pending {
+            result |= 128;
+        }
+        self.frame_irq = false;
+        result
+    }
+
+    // $4015: channel enable (write)
+    fn write_control(&mut self, value: u8) {
+        self.pulse1.enabled = value & 1 == 1;
+        self.pulse2.enabled = value & 2 == 2;
+        self.triangle.enabled = value & 4 == 4;
+        self.noise.enabled = value & 8 == 8;
+        self.dmc.enabled = value & 16 == 16;
+        self.dmc.irq_pending = false;
+        if !self.pulse1.enabled {
+            self.pulse1.length_value = 0;
+        }
+        if !self.pulse2.enabled {
+            self.pulse2.length_value = 0;
+        }
+        if !self.triangle.enabled {
+            self.triangle.length_value = 0;
+        }
+        if !self.noise.enabled {
+            self.noise.length_value = 0;
+        }
+        if !self.dmc.enabled {
+            self.dmc.current_length = 0;
+        } else if self.dmc.current_length == 0 {
+            self.dmc.restart();
+        }
+    }
+
+    // $4017: frame counter. The IRQ inhibit takes hold at once, but the
+    // sequence only restarts in the new mode 3 CPU cycles after a write that
+    // lands on an APU cycle, or 4 after one between them. `cycle` is the
+    // last cycle run, so the write is on the next.
+    fn write_frame_counter(&mut self, value: u8) {
+        self.frame_irq_enabled = (value >> 6) & 1 == 0;
+        if !self.frame_irq_enabled {
+            self.frame_irq = false;
+        }
+        let period = 4 + ((value >> 7) & 1);
+        let delay = if (self.cycle + 1) & 1 == 0 { 3 } else { 4 };
+        self.frame_write = Some((period, delay));
+    }
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Envelope {
+    enabled: bool,
+    looping: bool,
+    start: bool,
+    period: u8,
+    value: u8,
+    volume: u8,
+    constant_volume: u8,
+}
+
+impl Envelope {
+    fn write_control(&mut self, value: u8) {
+        self.looping = (value >> 5) & 1 == 1;
+        self.enabled = (value >> 4) & 1 == 0;
+        self.period = value & 15;
+        self.constant_volume = value & 15;
+        self.start = true;
+    }
+
+    fn step(&mut self) {
+        if self.start {
+            self.volume = 15;
+            self.value = self.period;
+            self.start = false;
+        } else if self.value > 0 {
+            self.value -= 1;
+        } else {
+            if self.volume > 0 {
+                self.volume -= 1;
+            } else if self.looping {
+                self.volume = 15;
+            }
+            self.value = self.period;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled {
+            self.volume
+        } else {
+            self.constant_volume
+        }
+    }
+}
+
+// Also used for the MMC5's two extra pulse channels, which lack the sweep
+// unit
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Pulse {
+    enabled: bool,
+    // Pulse 1 negates its sweep with one's complement
+    channel: u8,
+    // Without a sweep unit nothing mutes low or overflowing periods
+    sweep: bool,
+    length_enabled: bool,
+    length_value: u8,
+    timer_period: u16,
+    timer_value: u16,
+    duty_mode: u8,
+    duty_value: u8,
+    sweep_reload: bool,
+    sweep_enabled: bool,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_period: u8,
+    sweep_value: u8,
+    envelope: Envelope,
+}
+
+impl Pulse {
+    fn new(channel: u8) -> Self {
+        Self {
+            enabled: false,
+            channel,
+            sweep: true,
+            length_enabled: false,
+            length_value: 0,
+            timer_period: 0,
+            timer_value: 0,
+            duty_mode: 0,
+            duty_value: 0,
+            sweep_reload: false,
+            sweep_enabled: false,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_period: 0,
+            sweep_value: 0,
+            envelope: Envelope::default(),
+        }
+    }
+
+    pub(crate) fn without_sweep() -> Self {
+        Self {
+            sweep: false,
+            ..Self::new(0)
+        }
+    }
+
+    // Clears the length counter when disabled, silencing the channel
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_value = 0;
+        }
+    }
+
+    // Whether the length counter is still running, as status registers
+    // report it
+    pub(crate) fn active(&self) -> bool {
+        self.length_value > 0
+    }
+
+    // $4000/$4004: duty, length counter halt, envelope
+    pub(crate) fn write_control(&mut self, value: u8) {
+        self.duty_mode = (value >> 6) & 3;
+        self.length_enabled = (value >> 5) & 1 == 0;
+        self.envelope.write_control(value);
+    }
+
+    // $4001/$4005: sweep unit
+    fn write_sweep(&mut self, value: u8) {
+        self.sweep_enabled = (value >> 7) & 1 == 1;
+        self.sweep_period = ((value >> 4) & 7) + 1;
+        self.sweep_negate = (value >> 3) & 1 == 1;
+        self.sweep_shift = value & 7;
+        self.sweep_reload = true;
+    }
+
+    pub(crate) fn write_timer_low(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0xFF00) | value as u16;
+    }
+
+    pub(crate) fn write_timer_high(&mut self, value: u8) {
+        if self.enabled {
+            self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        }
+        self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 7) << 8);
+        self.envelope.start = true;
+        self.duty_value = 0;
+    }
+
+    pub(crate) fn step_timer(&mut self) {
+        if self.timer_value == 0 {
+            self.timer_value = self.timer_period;
+            self.duty_value = (self.duty_value + 1) % 8;
+        } else {
+            self.timer_value -= 1;
+        }
+    }
+
+    pub(crate) fn step_envelope(&mut self) {
+        self.envelope.step();
+    }
+
+    fn step_sweep(&mut self) {
+        if self.sweep_reload {
+            if self.sweep_enabled && self.sweep_value == 0 {
+                self.sweep();
+            }
+            self.sweep_value = self.sweep_period;
+            self.sweep_reload = false;
+        } else if self.sweep_value > 0 {
+            self.sweep_value -= 1;
+        } else {
+            if self.sweep_enabled {
+                self.sweep();
+            }
+            self.sweep_value = self.sweep_period;
+        }
+    }
+
+    pub(crate) fn step_length(&mut self) {
+        if self.length_enabled && self.length_value > 0 {
+            self.length_value -= 1;
+        }
+    }
+
+    // Only a shift above 0 moves the period, and never while muted
+    fn sweep(&mut self) {
+        if self.sweep_shift > 0 && !self.muted() {
+            self.timer_period = self.target_period();
+        }
+    }
+
+    // The period the sweep unit is heading for, which it works out
+    // continuously whether or not it's enabled
+    fn target_period(&self) -> u16 {
+        let delta = self.timer_period >> self.sweep_shift;
+        if !self.sweep_negate {
+            return self.timer_period + delta;
+        }
+        // Pulse 1 subtracts one more
+        let delta = delta + (self.channel == 1) as u16;
+        self.timer_period.saturating_sub(delta)
+    }
+
+    // Periods too short to hear, or heading past 11 bits, silence the
+    // channel
+    fn muted(&self) -> bool {
+        self.sweep && (self.timer_period < 8 || self.target_period() > 0x7FF)
+    }
+
+    pub(crate) fn output(&self) -> u8 {
+        if !self.enabled
+            || self.length_value == 0
+            || DUTY_TABLE[self.duty_mode as usize][self.duty_value as usize] == 0
+            || self.muted()
+        {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Triangle {
+    enabled: bool,
+    length_enabled: bool,
+    length_value: u8,
+    timer_period: u16,
+    timer_value: u16,
+    duty_value: u8,
+    counter_period: u8,
+    counter_value: u8,
+    counter_reload: bool,
+}
+
+impl Triangle {
+    // $4008: length counter halt, linear counter load
+    fn write_control(&mut self, value: u8) {
+        self.length_enabled = (value >> 7) & 1 == 0;
+        self.counter_period = value & 0x7F;
+    }
+
+    fn write_timer_low(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0xFF00) | value as u16;
+    }
+
+    fn write_timer_high(&mut self, value: u8) {
+        if self.enabled {
+            self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        }
+        self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 7) << 8);
+        self.timer_value = self.timer_period;
+        self.counter_reload = true;
+    }
+
+    fn step_timer(&mut self) {
+        if self.timer_value == 0 {
+            self.timer_value = self.timer_period;
+            if self.length_value > 0 && self.counter_value > 0 {
+                self.duty_value = (self.duty_value + 1) % 32;
+            }
+        } else {
+            self.timer_value -= 1;
+        }
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enabled && self.length_value > 0 {
+            self.length_value -= 1;
+        }
+    }
+
+    fn step_counter(&mut self) {
+        if self.counter_reload {
+            self.counter_value = self.counter_period;
+        } else if self.counter_value > 0 {
+            self.counter_value -= 1;
+        }
+        if self.length_enabled {
+            self.counter_reload = false;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        // Ultrasonic periods are silenced rather than aliased
+        if !self.enabled
+            || self.timer_period < 3
+            || self.length_value == 0
+            || self.counter_value == 0
+        {
+            return 0;
+        }
+        TRIANGLE_TABLE[self.duty_value as usize]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Noise {
+    enabled: bool,
+    mode: bool,
+    shift_register: u16,
+    length_enabled: bool,
+    length_value: u8,
+    timer_period: u16,
+    timer_value: u16,
+    envelope: Envelope,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: false,
+            shift_register: 1,
+            length_enabled: false,
+            length_value: 0,
+            timer_period: 0,
+            timer_value: 0,
+            envelope: Envelope::default(),
+        }
+    }
+}
+
+impl Noise {
+    // $400C: length counter halt, envelope
+    fn write_control(&mut self, value: u8) {
+        self.length_enabled = (value >> 5) & 1 == 0;
+        self.envelope.write_control(value);
+    }
+
+    // $400E: mode and period
+    fn write_period(&mut self, value: u8, region: Region) {
+        let table = match region {
+            Region::PAL => &PAL_NOISE_TABLE,
+            Region::NTSC | Region::Dendy => &NOISE_TABLE,
+        };
+        self.mode = value & 0x80 == 0x80;
+        self.timer_period = table[(value & 0x0F) as usize];
+    }
+
+    // $400F: length counter load
+    fn write_length(&mut self, value: u8) {
+        if self.enabled {
+            self.length_value = LENGTH_TABLE[(value >> 3) as usize];
+        }
+        self.envelope.start = true;
+    }
+
+    fn step_timer(&mut self) {
+        if self.timer_value == 0 {
+            self.timer_value = self.timer_period;
+            let shift = if self.mode { 6 } else { 1 };
+            let b1 = self.shift_register & 1;
+            let b2 = (self.shift_register >> shift) & 1;
+            self.shift_register >>= 1;
+            self.shift_register |= (b1 ^ b2) << 14;
+        } else {
+            self.timer_value -= 1;
+        }
+    }
+
+    fn step_envelope(&mut self) {
+        self.envelope.step();
+    }
+
+    fn step_length(&mut self) {
+        if self.length_enabled && self.length_value > 0 {
+            self.length_value -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.length_value == 0 || self.shift_register & 1 == 1 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct DMC {
+    enabled: bool,
+    value: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    current_length: u16,
+    shift_register: u8,
+    bit_count: u8,
+    tick_period: u8,
+    tick_value: u8,
+    looping: bool,
+    irq: bool,
+    irq_pending: bool,
+}
+
+impl DMC {
+    // $4010: IRQ enable, loop, rate
+    fn write_control(&mut self, value: u8, region: Region) {
+        let table = match region {
+            Region::PAL => &PAL_DMC_TABLE,
+            Region::NTSC | Region::Dendy => &DMC_TABLE,
+        };
+        self.irq = value & 0x80 == 0x80;
+        if !self.irq {
+            self.irq_pending = false;
+        }
+        self.looping = value & 0x40 == 0x40;
+        self.tick_period = table[(value & 0x0F) as usize];
+    }
+
+    // $4011: direct load
+    fn write_value(&mut self, value: u8) {
+        self.value = value & 0x7F;
+    }
+
+    // $4012: sample address = %11AAAAAA.AA000000
+    fn write_address(&mut self, value: u8) {
+        self.sample_address = 0xC000 | ((value as u16) << 6);
+    }
+
+    // $4013: sample length = %0000LLLL.LLLL0001
+    fn write_length(&mut self, value: u8) {
+        self.sample_length = ((value as u16) << 4) | 1;
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.current_length = self.sample_length;
+    }
+
+    fn fetch_address(&self) -> Option<u16> {
+        if self.enabled && self.current_length > 0 && self.bit_count == 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    fn fill(&mut self, value: u8) {
+        self.shift_register = value;
+        self.bit_count = 8;
+        // Sample reads wrap from $FFFF around to $8000
+        self.current_address = self.current_address.wrapping_add(1);
+        if self.current_address == 0 {
+            self.current_address = 0x8000;
+        }
+        self.current_length -= 1;
+        if self.current_length == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn step_timer(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.tick_value == 0 {
+            self.tick_value = self.tick_period;
+            self.step_shifter();
+        } else {
+            self.tick_value -= 1;
+        }
+    }
+
+    fn step_shifter(&mut self) {
+        if self.bit_count == 0 {
+            return;
+        }
+        if self.shift_register & 1 == 1 {
+            if self.value <= 125 {
+                self.value += 2;
+            }
+        } else if self.value >= 2 {
+            self.value -= 2;
+        }
+        self.shift_register >>= 1;
+        self.bit_count -= 1;
+    }
+
+    fn output(&self) -> u8 {
+        self.value
+    }
+}
@@ -0,0 +1,65 @@
+use std::f32::consts::PI;
+
+// First-order IIR filter
+pub struct Filter {
+    b0: f32,
+    b1: f32,
+    a1: f32,
+    prev_x: f32,
+    prev_y: f32,
+}
+
+impl Filter {
+    pub fn low_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let c = sample_rate / PI / cutoff;
+        let a0i = 1.0 / (1.0 + c);
+        Self {
+            b0: a0i,
+            b1: a0i,
+            a1: (1.0 - c) * a0i,
+            prev_x: 0.0,
+            prev_y: 0.0,
+        }
+    }
+
+    pub fn high_pass(sample_rate: f32, cutoff: f32) -> Self {
+        let c = sample_rate / PI / cutoff;
+        let a0i = 1.0 / (1.0 + c);
+        Self {
+            b0: c * a0i,
+            b1: -c * a0i,
+            a1: (1.0 - c) * a0i,
+            prev_x: 0.0,
+            prev_y: 0.0,
+        }
+    }
+
+    pub fn step(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.prev_x - self.a1 * self.prev_y;
+        self.prev_x = x;
+        self.prev_y = y;
+        y
+    }
+}
+
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    // The filters between the NES's DAC and its audio output: two high-pass
+    // stages at 90Hz and 440Hz and a 14kHz low-pass
+    pub fn nes(sample_rate: f32) -> Self {
+        Self {
+            filters: vec![
+                Filter::high_pass(sample_rate, 90.0),
+                Filter::high_pass(sample_rate, 440.0),
+                Filter::low_pass(sample_rate, 14000.0),
+            ],
+        }
+    }
+
+    pub fn step(&mut self, x: f32) -> f32 {
+        self.filters.iter_mut().fold(x, |x, filter| filter.step(x))
+    }
+}
@@ -0,0 +1,9 @@
+mod filter;
+#[cfg(feature = "audio-cpal")]
+mod output;
+mod wav;
+
+pub use filter::{Filter, FilterChain};
+#[cfg(feature = "audio-cpal")]
+pub use output::{AudioError, AudioOutput};
+pub use wav::{WavCapture, WavWriter};
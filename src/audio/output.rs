@@ -0,0 +1,133 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+};
+
+use crate::console::Console;
+
+// Latency the drift compensation steers the device buffer towards
+const TARGET_LATENCY: f64 = 0.05;
+// Largest speed correction applied, small enough to be inaudible
+const MAX_ADJUST: f64 = 0.005;
+
+#[derive(Debug)]
+pub enum AudioError {
+    NoDevice,
+    UnsupportedFormat(SampleFormat),
+    Config(cpal::DefaultStreamConfigError),
+    Build(cpal::BuildStreamError),
+    Play(cpal::PlayStreamError),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioError::NoDevice => write!(f, "no audio output device"),
+            AudioError::UnsupportedFormat(format) => {
+                write!(f, "unsupported sample format {:?}", format)
+            }
+            AudioError::Config(err) => write!(f, "{}", err),
+            AudioError::Build(err) => write!(f, "{}", err),
+            AudioError::Play(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+// Plays a Console's audio on the default output device
+pub struct AudioOutput {
+    _stream: Stream,
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+    scratch: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn new() -> Result<Self, AudioError> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or(AudioError::NoDevice)?;
+        let supported = device.default_output_config().map_err(AudioError::Config)?;
+        let format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+
+        let stream = match format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, buffer.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, buffer.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, buffer.clone()),
+            format => return Err(AudioError::UnsupportedFormat(format)),
+        }?;
+        stream.play().map_err(AudioError::Play)?;
+
+        Ok(Self {
+            _stream: stream,
+            buffer,
+            sample_rate: config.sample_rate.0,
+            scratch: vec![0.0; config.sample_rate.0 as usize],
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Sets the console's APU to produce samples at the device rate
+    pub fn attach(&self, console: &mut Console) {
+        console.set_sample_rate(self.sample_rate as f64);
+    }
+
+    // Moves the console's buffered samples to the device, then nudges the
+    // APU's output rate so the device buffer hovers around TARGET_LATENCY.
+    // This absorbs the drift between the emulation clock and the sound
+    // card's, which otherwise ends in underruns (crackle) or growing lag.
+    pub fn queue(&mut self, console: &mut Console) {
+        let count = console.drain_samples(&mut self.scratch);
+        let queued = {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.extend(&self.scratch[..count]);
+            buffer.len()
+        };
+
+        let target = self.sample_rate as f64 * TARGET_LATENCY;
+        let error = (target - queued as f64) / target;
+        let adjust = (error * MAX_ADJUST).clamp(-MAX_ADJUST, MAX_ADJUST);
+        console.apu_mut().set_rate_adjust(1.0 + adjust);
+    }
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<Stream, AudioError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut buffer = buffer.lock().unwrap();
+                // The NES is mono: the same sample goes to every channel, and
+                // silence fills in if emulation falls behind
+                for frame in data.chunks_mut(channels) {
+                    let sample = buffer.pop_front().unwrap_or(0.0);
+                    for out in frame {
+                        *out = T::from_sample(sample);
+                    }
+                }
+            },
+            |err| eprintln!("audio stream error: {}", err),
+            None,
+        )
+        .map_err(AudioError::Build)
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::FilterChain;
+
+// Stem file suffixes, in Channel::index order
+const STEMS: [&str; 6] = ["pulse1", "pulse2", "triangle", "noise", "dmc", "expansion"];
+
+// A 16-bit mono PCM WAV file. The RIFF sizes are filled in by `finish`.
+pub struct WavWriter {
+    out: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM, one channel
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        // Byte rate and block size
+        out.write_all(&(sample_rate * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { out, samples: 0 })
+    }
+
+    // Writes a sample, clipping it to -1..1
+    pub fn write(&mut self, sample: f32) -> io::Result<()> {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.out.write_all(&value.to_le_bytes())?;
+        self.samples += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        let data = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.flush()
+    }
+}
+
+struct Track {
+    writer: WavWriter,
+    filters: FilterChain,
+    sum: f32,
+}
+
+impl Track {
+    fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+        Ok(Self {
+            writer: WavWriter::create(path, sample_rate)?,
+            filters: FilterChain::nes(sample_rate as f32),
+            sum: 0.0,
+        })
+    }
+}
+
+// Records the mixer output, and optionally each channel as its own stem,
+// from the APU's cycles rather than the frontend's sample stream, so the
+// files stay in step with emulated time whatever the audio device does.
+// Stems are the channel alone through the mixer, filtered like the mix.
+pub struct WavCapture {
+    path: PathBuf,
+    mix: Track,
+    stems: Vec<Track>,
+    // CPU cycles per sample
+    period: f64,
+    clock: f64,
+    count: u32,
+    // The first write error, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl WavCapture {
+    // Stems go next to `path` as name.pulse1.wav, name.triangle.wav and so
+    // on, expansion audio together in name.expansion.wav
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        sample_rate: u32,
+        cpu_frequency: u64,
+        stems: bool,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mix = Track::create(path, sample_rate)?;
+        let stems = if stems {
+            STEMS
+                .iter()
+                .map(|stem| Track::create(&stem_path(path, stem), sample_rate))
+                .collect::<io::Result<_>>()?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            mix,
+            stems,
+            period: cpu_frequency as f64 / sample_rate as f64,
+            clock: 0.0,
+            count: 0,
+            error: None,
+        })
+    }
+
+    // The mix's file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn stems(&self) -> bool {
+        !self.stems.is_empty()
+    }
+
+    // Adds one CPU cycle's mixer level and, with stems, each channel's
+    pub fn step(&mut self, mix: f32, channels: &[f32; 6]) {
+        self.mix.sum += mix;
+        for (track, level) in self.stems.iter_mut().zip(channels) {
+            track.sum += level;
+        }
+        self.count += 1;
+        self.clock += 1.0;
+        if self.clock < self.period {
+            return;
+        }
+        self.clock -= self.period;
+        let count = self.count as f32;
+        self.count = 0;
+        for track in std::iter::once(&mut self.mix).chain(&mut self.stems) {
+            let sample = track.filters.step(track.sum / count);
+            track.sum = 0.0;
+            if let Err(err) = track.writer.write(sample) {
+                self.error.get_or_insert(err);
+            }
+        }
+    }
+
+    // Completes the files, returning the first error met while writing them
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        for track in std::iter::once(self.mix).chain(self.stems) {
+            track.writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+fn stem_path(path: &Path, stem: &str) -> PathBuf {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.wav", name, stem))
+}
@@ -0,0 +1,322 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use nesrs::{
+    config::{Config, Input},
+    ppu::{HEIGHT, WIDTH},
+    timing::FrameLimiter,
+    video::ScreenshotOptions,
+    Button, Cartridge, Console,
+};
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    controller::{Button as PadButton, GameController},
+    event::Event,
+    keyboard::Keycode,
+    pixels::PixelFormatEnum,
+    rect::Rect,
+};
+
+const USAGE: &str = "usage: nesrs-sdl [--config PATH] <rom>
+
+Settings are read from --config, or else the user's nesrs/config.toml if
+there is one. Disk System images (.fds) need disksys.rom next to them; F
+flips the disk. F1 and F2 insert coins in VS. System games, and holding M
+blows into the Famicom microphone. F5 saves a state, F7 loads it and F12
+takes a screenshot.";
+
+// Save state slot for the F5 and F7 keys
+const STATE_SLOT: u8 = 1;
+
+// The player and button each configured key presses, by SDL key name
+fn key_bindings(input: &Input) -> Result<HashMap<Keycode, (usize, Button)>, String> {
+    let mut bindings = HashMap::new();
+    for (player, keys) in input.players().into_iter().enumerate() {
+        for button in Button::ALL {
+            for name in keys.keys(button) {
+                let key = Keycode::from_name(name)
+                    .ok_or_else(|| format!("unknown key {:?} in config", name))?;
+                bindings.entry(key).or_insert((player, button));
+            }
+        }
+    }
+    Ok(bindings)
+}
+
+// Face buttons follow position rather than label: the bottom button is B
+// and the right one A, as on the NES pad
+fn pad_button(button: PadButton) -> Option<Button> {
+    match button {
+        PadButton::B => Some(Button::A),
+        PadButton::A => Some(Button::B),
+        PadButton::Back => Some(Button::Select),
+        PadButton::Start => Some(Button::Start),
+        PadButton::DPadUp => Some(Button::Up),
+        PadButton::DPadDown => Some(Button::Down),
+        PadButton::DPadLeft => Some(Button::Left),
+        PadButton::DPadRight => Some(Button::Right),
+        _ => None,
+    }
+}
+
+// Frames the drive stays empty while flipping the disk, long enough for
+// games to notice
+const DISK_SWAP_FRAMES: u32 = 60;
+
+fn run(path: &Path, config: &Config) -> Result<(), String> {
+    let disk = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("fds"));
+    let cartridge = if disk {
+        Cartridge::from_disk_path(path, path.with_file_name("disksys.rom"))
+    } else {
+        Cartridge::from_path(path)
+    };
+    let cartridge = cartridge.map_err(|err| err.to_string())?;
+    for diagnostic in &cartridge.diagnostics {
+        eprintln!("nesrs-sdl: warning: {}", diagnostic);
+    }
+    let mut console = Console::new(cartridge).map_err(|err| err.to_string())?;
+    config.apply(&mut console).map_err(|err| err.to_string())?;
+    if config.paths.saves.is_some() {
+        let sram_path = config
+            .paths
+            .sram_path(path)
+            .map_err(|err| err.to_string())?;
+        console
+            .set_sram_path(sram_path)
+            .map_err(|err| err.to_string())?;
+    }
+    let bindings = key_bindings(&config.input)?;
+    let sample_rate = config.audio.sample_rate;
+    // Audio queued beyond this is dropped so latency cannot build up
+    let max_queued_samples = sample_rate / 10;
+
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let audio = sdl.audio()?;
+    let controllers = sdl.game_controller()?;
+
+    // Framed as the console's video config says
+    let framing = console.video_config();
+    let (x, y, width, height) = framing.visible();
+    let (window_width, window_height) = framing.display_size(config.video.scale.max(1));
+    let window = video
+        .window("nesrs", window_width, window_height)
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut canvas = window
+        .into_canvas()
+        .accelerated()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let (logical_width, logical_height) = framing.display_size(1);
+    canvas
+        .set_logical_size(logical_width, logical_height)
+        .map_err(|err| err.to_string())?;
+    let visible = Rect::new(x as i32, y as i32, width, height);
+    let texture_creator = canvas.texture_creator();
+    // ABGR8888 is R, G, B, A in memory on little-endian hosts, matching the
+    // framebuffer's byte order
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::ABGR8888, WIDTH, HEIGHT)
+        .map_err(|err| err.to_string())?;
+
+    let spec = AudioSpecDesired {
+        freq: Some(sample_rate as i32),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    let queue: AudioQueue<f32> = audio.open_queue(None, &spec)?;
+    queue.resume();
+    let mut samples = vec![0.0; sample_rate as usize / 10];
+
+    // Gamepads drive player 1 alongside the keyboard
+    let mut pads: Vec<GameController> = Vec::new();
+
+    // F flips Disk System disks: the side to insert and frames until then
+    let mut disk_swap: Option<(usize, u32)> = None;
+
+    let mut events = sdl.event_pump()?;
+    let mut limiter = FrameLimiter::new(console.region());
+    loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return Ok(()),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F),
+                    ..
+                } if console.disk_sides() > 0 && disk_swap.is_none() => {
+                    let side = console.disk_side().map_or(0, |side| side + 1);
+                    console.insert_disk(None);
+                    disk_swap = Some((side % console.disk_sides(), DISK_SWAP_FRAMES));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => console.insert_coin(0),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => console.insert_coin(1),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    let state_path = config
+                        .paths
+                        .state_path(path, STATE_SLOT)
+                        .map_err(|err| err.to_string())?;
+                    fs::write(state_path, console.save_state()).map_err(|err| err.to_string())?;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    let state_path = config
+                        .paths
+                        .state_path(path, STATE_SLOT)
+                        .map_err(|err| err.to_string())?;
+                    // Nothing saved yet is no reason to quit
+                    if let Ok(state) = fs::read(state_path) {
+                        console.load_state(&state).map_err(|err| err.to_string())?;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    let screenshot_path = config
+                        .paths
+                        .screenshot_path(path)
+                        .map_err(|err| err.to_string())?;
+                    console
+                        .screenshot_to_file(screenshot_path, ScreenshotOptions::default())
+                        .map_err(|err| err.to_string())?;
+                }
+                // Tab fast-forwards while held
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => limiter.set_fast_forward(true),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => limiter.set_fast_forward(false),
+                // M blows into the Famicom microphone while held
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => console.set_microphone(true),
+                Event::KeyUp {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => console.set_microphone(false),
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } => {
+                    if let Some(&(player, button)) = bindings.get(&key) {
+                        console.set_button(player, button, true);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => {
+                    if let Some(&(player, button)) = bindings.get(&key) {
+                        console.set_button(player, button, false);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(pad) = controllers.open(which) {
+                        pads.push(pad);
+                    }
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(button) = pad_button(button) {
+                        console.set_button(0, button, true);
+                    }
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    if let Some(button) = pad_button(button) {
+                        console.set_button(0, button, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        disk_swap = match disk_swap {
+            Some((side, 0)) => {
+                console.insert_disk(Some(side));
+                None
+            }
+            Some((side, frames)) => Some((side, frames - 1)),
+            None => None,
+        };
+        console.step_frame();
+
+        let count = console.drain_samples(&mut samples);
+        for sample in &mut samples[..count] {
+            *sample *= config.audio.volume;
+        }
+        if queue.size() / 4 < max_queued_samples {
+            queue.queue_audio(&samples[..count])?;
+        }
+
+        texture
+            .update(None, console.framebuffer().as_raw(), WIDTH as usize * 4)
+            .map_err(|err| err.to_string())?;
+        canvas.clear();
+        canvas.copy(&texture, visible, None)?;
+        canvas.present();
+
+        limiter.wait();
+    }
+}
+
+fn parse_args() -> Result<(PathBuf, Option<PathBuf>), String> {
+    let mut args = env::args().skip(1);
+    let mut rom = None;
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = Some(args.next().ok_or(USAGE)?.into()),
+            _ if arg.starts_with('-') || rom.is_some() => return Err(USAGE.to_string()),
+            _ => rom = Some(PathBuf::from(arg)),
+        }
+    }
+    Ok((rom.ok_or(USAGE)?, config))
+}
+
+fn load_config(path: Option<PathBuf>) -> Result<Config, String> {
+    let config = match (path, Config::default_path()) {
+        (Some(path), _) => Config::load(path),
+        (None, Some(path)) => Config::load_or_default(path),
+        (None, None) => Ok(Config::default()),
+    };
+    config.map_err(|err| err.to_string())
+}
+
+fn main() {
+    let (path, config) = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+    if let Err(message) = load_config(config).and_then(|config| run(&path, &config)) {
+        eprintln!("nesrs-sdl: {}", message);
+        process::exit(1);
+    }
+}
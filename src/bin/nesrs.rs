@@ -0,0 +1,373 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant},
+};
+
+use nesrs::{
+    capture::{Clip, PngSequence},
+    config::Config,
+    console::Accuracy,
+    ppu::Renderer,
+    profiler::Profiler,
+    recorder::RecordConfig,
+    symbols::Symbols,
+    test_rom::{self, TestRun},
+    Cartridge, Console,
+};
+
+const USAGE: &str =
+    "usage: nesrs <rom> [--frames N] [--timeout SECONDS] [--png PATH] [--trace PATH]
+             [--palette PATH] [--bios PATH] [--cycle-accurate] [--accurate-oam]
+             [--scanline] [--script PATH] [--wav PATH [--stems]] [--capture DIR]
+             [--gif PATH] [--apng PATH] [--every N] [--record PATH]
+             [--symbols PATH]... [--profile PATH] [--gdb ADDR]
+             [--config PATH]
+
+Runs a ROM headlessly. Disk System images (.fds) need the BIOS, looked for
+as disksys.rom next to the image unless --bios gives it. --cycle-accurate
+runs the PPU in step with every CPU bus access, and --accurate-oam lets
+OAM decay while rendering is off as on a real 2C02. --scanline draws a line
+at a time instead of a dot, which is faster but misses effects timed within
+a line; it has no effect with --cycle-accurate. --script runs a Rhai script
+around each frame, its drawing included in --png, in builds with the
+scripting feature. --wav records the audio, and with --stems each channel
+beside it. --capture saves frames to DIR as numbered PNGs, and --gif and
+--apng save them as an animation; --every keeps only every Nth frame.
+--record records video and audio to PATH (.mp4, .mkv, ...) with ffmpeg.
+--symbols names addresses in the --trace log from a ca65 debug file (.dbg)
+or an FCEUX .nl file, and may be given once for each file to load.
+--profile writes the CPU cycles each subroutine and interrupt handler took
+per frame to PATH, named from --symbols where they can be.
+--gdb waits for gdb to connect at ADDR (e.g. 127.0.0.1:9001) and runs the
+ROM under it until it detaches, in builds with the gdb feature.
+--config loads settings from a TOML file, which the other options override.
+ROMs following the blargg test convention ($6000 status, $6001-$6003 =
+DE B0 61) stop as soon as they report a result, and the exit status is that
+result (0 = passed).";
+
+// Exit status when the ROM has not finished in time, as timeout(1) uses
+const EXIT_TIMEOUT: i32 = 124;
+
+struct Options {
+    rom: PathBuf,
+    frames: u64,
+    timeout: Option<Duration>,
+    png: Option<PathBuf>,
+    trace: Option<PathBuf>,
+    palette: Option<PathBuf>,
+    bios: Option<PathBuf>,
+    accuracy: Accuracy,
+    accurate_oam: bool,
+    scanline: bool,
+    script: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    stems: bool,
+    capture: Option<PathBuf>,
+    gif: Option<PathBuf>,
+    apng: Option<PathBuf>,
+    every: u64,
+    record: Option<PathBuf>,
+    symbols: Vec<PathBuf>,
+    profile: Option<PathBuf>,
+    gdb: Option<String>,
+    config: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+    let mut rom = None;
+    let mut options = Options {
+        rom: PathBuf::new(),
+        frames: 60 * 60,
+        timeout: None,
+        png: None,
+        trace: None,
+        palette: None,
+        bios: None,
+        accuracy: Accuracy::Fast,
+        accurate_oam: false,
+        scanline: false,
+        script: None,
+        wav: None,
+        stems: false,
+        capture: None,
+        gif: None,
+        apng: None,
+        every: 1,
+        record: None,
+        symbols: Vec::new(),
+        profile: None,
+        gdb: None,
+        config: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--frames" => {
+                options.frames = value("--frames")?
+                    .parse()
+                    .map_err(|_| "--frames must be a number".to_string())?
+            }
+            "--timeout" => {
+                let seconds: f64 = value("--timeout")?
+                    .parse()
+                    .map_err(|_| "--timeout must be a number of seconds".to_string())?;
+                options.timeout = Some(Duration::from_secs_f64(seconds));
+            }
+            "--png" => options.png = Some(value("--png")?.into()),
+            "--trace" => options.trace = Some(value("--trace")?.into()),
+            "--palette" => options.palette = Some(value("--palette")?.into()),
+            "--bios" => options.bios = Some(value("--bios")?.into()),
+            "--cycle-accurate" => options.accuracy = Accuracy::CycleAccurate,
+            "--accurate-oam" => options.accurate_oam = true,
+            "--scanline" => options.scanline = true,
+            "--script" => options.script = Some(value("--script")?.into()),
+            "--wav" => options.wav = Some(value("--wav")?.into()),
+            "--stems" => options.stems = true,
+            "--capture" => options.capture = Some(value("--capture")?.into()),
+            "--gif" => options.gif = Some(value("--gif")?.into()),
+            "--apng" => options.apng = Some(value("--apng")?.into()),
+            "--record" => options.record = Some(value("--record")?.into()),
+            "--symbols" => options.symbols.push(value("--symbols")?.into()),
+            "--profile" => options.profile = Some(value("--profile")?.into()),
+            "--gdb" => options.gdb = Some(value("--gdb")?),
+            "--config" => options.config = Some(value("--config")?.into()),
+            "--every" => {
+                options.every = value("--every")?
+                    .parse()
+                    .map_err(|_| "--every must be a number".to_string())?
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {}\n\n{}", arg, USAGE))
+            }
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    options.rom = rom.ok_or(USAGE)?;
+    Ok(options)
+}
+
+fn load(rom: &Path, bios: Option<&Path>) -> nesrs::Result<Cartridge> {
+    let disk = rom
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("fds"));
+    let cartridge = if disk {
+        let bios = bios.map_or_else(|| rom.with_file_name("disksys.rom"), Path::to_path_buf);
+        Cartridge::from_disk_path(rom, bios)
+    } else {
+        Cartridge::from_path(rom)
+    };
+    Ok(cartridge?)
+}
+
+// The --gdb session, before the frames run
+#[cfg(feature = "gdb")]
+fn serve_gdb(console: &mut Console, addr: &str) -> nesrs::Result<()> {
+    eprintln!("nesrs: waiting for gdb on {}", addr);
+    Ok(nesrs::gdb::serve(console, addr)?)
+}
+
+#[cfg(not(feature = "gdb"))]
+fn serve_gdb(_console: &mut Console, _addr: &str) -> nesrs::Result<()> {
+    Err(nesrs::Error::MissingFeature("gdb"))
+}
+
+// The --script file, run around each frame
+#[cfg(feature = "scripting")]
+struct Hooks(Option<nesrs::script::Script>);
+
+#[cfg(feature = "scripting")]
+impl Hooks {
+    fn new(path: Option<&Path>, console: &mut Console) -> nesrs::Result<Self> {
+        let script = path.map(|path| nesrs::script::Script::from_path(path, console));
+        Ok(Self(script.transpose()?))
+    }
+
+    fn frame_start(&mut self, console: &mut Console) -> nesrs::Result<()> {
+        match &mut self.0 {
+            Some(script) => Ok(script.frame_start(console)?),
+            None => Ok(()),
+        }
+    }
+
+    fn frame_end(&mut self, console: &mut Console) -> nesrs::Result<()> {
+        match &mut self.0 {
+            Some(script) => Ok(script.frame_end(console)?),
+            None => Ok(()),
+        }
+    }
+
+    fn draw(&self, image: &mut image::RgbaImage) {
+        if let Some(script) = &self.0 {
+            script.draw(image);
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+struct Hooks;
+
+#[cfg(not(feature = "scripting"))]
+impl Hooks {
+    fn new(path: Option<&Path>, _console: &mut Console) -> nesrs::Result<Self> {
+        match path {
+            Some(_) => Err(nesrs::Error::MissingFeature("scripting")),
+            None => Ok(Self),
+        }
+    }
+
+    fn frame_start(&mut self, _console: &mut Console) -> nesrs::Result<()> {
+        Ok(())
+    }
+
+    fn frame_end(&mut self, _console: &mut Console) -> nesrs::Result<()> {
+        Ok(())
+    }
+
+    fn draw(&self, _image: &mut image::RgbaImage) {}
+}
+
+fn run(options: &Options) -> nesrs::Result<i32> {
+    let cartridge = load(&options.rom, options.bios.as_deref())?;
+    for diagnostic in &cartridge.diagnostics {
+        eprintln!("nesrs: warning: {}", diagnostic);
+    }
+    let mut console = Console::new(cartridge)?;
+    let mut config = match &options.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    if options.accuracy != Accuracy::Fast {
+        config.emulation.accuracy = options.accuracy;
+    }
+    config.emulation.accurate_oam |= options.accurate_oam;
+    if options.scanline {
+        config.emulation.renderer = Renderer::Scanline;
+    }
+    if let Some(path) = &options.palette {
+        config.video.palette = Some(path.clone());
+    }
+    config.apply(&mut console)?;
+    if config.paths.saves.is_some() {
+        let path = config.paths.sram_path(&options.rom)?;
+        console.set_sram_path(path)?;
+    }
+    let mut symbols = Symbols::new();
+    for path in &options.symbols {
+        symbols.extend(Symbols::load(path)?);
+    }
+    if let Some(path) = &options.trace {
+        let file = fs::File::create(path)?;
+        console.cpu.set_trace(std::io::BufWriter::new(file));
+        if !symbols.is_empty() {
+            console.cpu.set_trace_symbols(symbols.clone());
+        }
+    }
+    if options.profile.is_some() {
+        console.start_profiling(Profiler::with_symbols(symbols));
+    }
+    if let Some(path) = &options.wav {
+        let started = if options.stems {
+            console.start_wav_capture_with_stems(path)
+        } else {
+            console.start_wav_capture(path)
+        };
+        started?;
+    }
+    if let Some(path) = &options.record {
+        console.start_recording(path, RecordConfig::default())?;
+    }
+    let mut hooks = Hooks::new(options.script.as_deref(), &mut console)?;
+    if let Some(addr) = &options.gdb {
+        serve_gdb(&mut console, addr)?;
+    }
+    let mut sequence = options
+        .capture
+        .as_ref()
+        .map(|dir| PngSequence::new(dir, options.every))
+        .transpose()?;
+    let animated = options.gif.is_some() || options.apng.is_some();
+    let mut clip = animated.then(|| Clip::new(console.region(), options.every));
+
+    let start = Instant::now();
+    let mut status = None;
+    let mut test_run = TestRun::new();
+    for _ in 0..options.frames {
+        hooks.frame_start(&mut console)?;
+        let result = test_run.step_frame(&mut console);
+        hooks.frame_end(&mut console)?;
+        if let Some(sequence) = &mut sequence {
+            sequence.capture(console.framebuffer())?;
+        }
+        if let Some(clip) = &mut clip {
+            clip.capture(console.framebuffer());
+        }
+        if let Some(result) = result {
+            status = Some(result as i32);
+            break;
+        }
+
+        if options
+            .timeout
+            .is_some_and(|timeout| start.elapsed() >= timeout)
+        {
+            break;
+        }
+    }
+    console.cpu.clear_trace();
+    if let (Some(path), Some(profiler)) = (&options.profile, console.stop_profiling()) {
+        fs::write(path, profiler.report().to_string())?;
+    }
+    console.stop_wav_capture()?;
+    console.stop_recording()?;
+    if let Some(clip) = &clip {
+        if let Some(path) = &options.gif {
+            clip.save_gif(path)?;
+        }
+        if let Some(path) = &options.apng {
+            clip.save_apng(path)?;
+        }
+    }
+
+    if let Some(path) = &options.png {
+        let mut image = console.framebuffer().clone();
+        hooks.draw(&mut image);
+        image.save(path)?;
+    }
+
+    let signed = test_rom::signed(&console);
+    if signed {
+        let message = test_rom::message(&console);
+        if !message.is_empty() {
+            println!("{}", message);
+        }
+    }
+    Ok(match status {
+        Some(result) => result,
+        None if signed => EXIT_TIMEOUT,
+        None => 0,
+    })
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+    match run(&options) {
+        Ok(code) => process::exit(code),
+        Err(message) => {
+            eprintln!("nesrs: {}", message);
+            process::exit(1);
+        }
+    }
+}
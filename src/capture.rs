@@ -0,0 +1,243 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, ImageError, RgbaImage,
+};
+
+use crate::region::Region;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    Image(ImageError),
+    Png(png::EncodingError),
+    // A clip needs at least one frame
+    Empty,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CaptureError::Io(err) => write!(f, "failed to write capture: {}", err),
+            CaptureError::Image(err) => write!(f, "failed to encode capture: {}", err),
+            CaptureError::Png(err) => write!(f, "failed to encode capture: {}", err),
+            CaptureError::Empty => write!(f, "no frames captured"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<io::Error> for CaptureError {
+    fn from(err: io::Error) -> Self {
+        CaptureError::Io(err)
+    }
+}
+
+impl From<ImageError> for CaptureError {
+    fn from(err: ImageError) -> Self {
+        CaptureError::Image(err)
+    }
+}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(err: png::EncodingError) -> Self {
+        CaptureError::Png(err)
+    }
+}
+
+// Saves every `every`th frame passed to `capture` into `directory` as
+// frame_000000.png, frame_000001.png, ..., numbered by the frames kept
+pub struct PngSequence {
+    directory: PathBuf,
+    every: u64,
+    frames: u64,
+    saved: u64,
+}
+
+impl PngSequence {
+    // Creates `directory` if it doesn't exist
+    pub fn new<P: AsRef<Path>>(directory: P, every: u64) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            every: every.max(1),
+            frames: 0,
+            saved: 0,
+        })
+    }
+
+    // Call once per frame with the finished picture, e.g.
+    // Console::framebuffer after step_frame
+    pub fn capture(&mut self, image: &RgbaImage) -> Result<(), CaptureError> {
+        let keep = self.frames.is_multiple_of(self.every);
+        self.frames += 1;
+        if keep {
+            let path = self.directory.join(format!("frame_{:06}.png", self.saved));
+            image.save(path)?;
+            self.saved += 1;
+        }
+        Ok(())
+    }
+
+    // Number of PNGs written
+    pub fn saved(&self) -> u64 {
+        self.saved
+    }
+}
+
+// Every `every`th frame passed to `capture`, held in memory until saved as
+// an animated GIF or APNG. Each kept frame lasts `every` frames of the
+// console's frame rate, so clips play back at the speed the game ran.
+// Frames are a quarter megabyte each, so clips should stay short.
+pub struct Clip {
+    frame_rate: f64,
+    every: u64,
+    frames: u64,
+    images: Vec<RgbaImage>,
+}
+
+impl Clip {
+    pub fn new(region: Region, every: u64) -> Self {
+        Self {
+            frame_rate: region.frame_rate(),
+            every: every.max(1),
+            frames: 0,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn capture(&mut self, image: &RgbaImage) {
+        if self.frames.is_multiple_of(self.every) {
+            self.images.push(image.clone());
+        }
+        self.frames += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.images.clear();
+        self.frames = 0;
+    }
+
+    // Milliseconds from the start of the clip to kept frame `index`
+    fn start_ms(&self, index: usize) -> f64 {
+        index as f64 * self.every as f64 * 1000.0 / self.frame_rate
+    }
+
+    // GIF delays are whole hundredths of a second, so each frame's is
+    // rounded from its exact start time to keep the clip from drifting
+    pub fn save_gif<P: AsRef<Path>>(&self, path: P) -> Result<(), CaptureError> {
+        if self.images.is_empty() {
+            return Err(CaptureError::Empty);
+        }
+        let centiseconds = |index| (self.start_ms(index) / 10.0).round() as u32;
+        let mut encoder = GifEncoder::new(fs::File::create(path)?);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for (index, image) in self.images.iter().enumerate() {
+            let delay = (centiseconds(index + 1) - centiseconds(index)) * 10;
+            let frame =
+                image::Frame::from_parts(image.clone(), 0, 0, Delay::from_numer_denom_ms(delay, 1));
+            encoder.encode_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    // APNG delays are fractions, so every frame gets the exact frame time:
+    // `every` thousand over the frame rate in thousandths of a hertz
+    pub fn save_apng<P: AsRef<Path>>(&self, path: P) -> Result<(), CaptureError> {
+        let Some(first) = self.images.first() else {
+            return Err(CaptureError::Empty);
+        };
+        let (width, height) = first.dimensions();
+        let mut delay_num = self.every.min(65) as u16 * 1000;
+        let mut delay_den = (self.frame_rate * 1000.0).round() as u16;
+        if self.every > 65 {
+            // Beyond what fits, whole milliseconds will do
+            delay_num = self.start_ms(1).round().min(u16::MAX as f64) as u16;
+            delay_den = 1000;
+        }
+
+        let mut file = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut file, width, height);
+            encoder.set_color(png::ColorType::RGBA);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+
+            // acTL: frame count, then 0 plays to loop forever
+            let mut actl = Vec::with_capacity(8);
+            actl.extend((self.images.len() as u32).to_be_bytes());
+            actl.extend(0u32.to_be_bytes());
+            writer.write_chunk(png::chunk::acTL, &actl)?;
+
+            // fcTL and fdAT chunks share one sequence
+            let mut sequence = 0u32;
+            for (index, image) in self.images.iter().enumerate() {
+                let mut fctl = Vec::with_capacity(26);
+                fctl.extend(sequence.to_be_bytes());
+                fctl.extend(width.to_be_bytes());
+                fctl.extend(height.to_be_bytes());
+                // At offset 0,0, and each frame replaces the last outright
+                fctl.extend([0; 8]);
+                fctl.extend(delay_num.to_be_bytes());
+                fctl.extend(delay_den.to_be_bytes());
+                fctl.extend([0, 0]);
+                writer.write_chunk(png::chunk::fcTL, &fctl)?;
+                sequence += 1;
+
+                let data = compressed_image(image)?;
+                if index == 0 {
+                    writer.write_chunk(png::chunk::IDAT, &data)?;
+                } else {
+                    let mut fdat = Vec::with_capacity(data.len() + 4);
+                    fdat.extend(sequence.to_be_bytes());
+                    fdat.extend(data);
+                    writer.write_chunk(png::chunk::fdAT, &fdat)?;
+                    sequence += 1;
+                }
+            }
+            // Dropping the writer ends the file with IEND
+        }
+        fs::write(path, file)?;
+        Ok(())
+    }
+}
+
+// The zlib stream of `image` as a PNG, taken from the IDAT chunks of a
+// standalone encoding
+fn compressed_image(image: &RgbaImage) -> Result<Vec<u8>, CaptureError> {
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, image.width(), image.height());
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(image.as_raw())?;
+    }
+
+    // Chunks after the 8-byte signature: length, type, data, CRC
+    let mut data = Vec::new();
+    let mut offset = 8;
+    while offset + 8 <= png.len() {
+        let length = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+        let kind = &png[offset + 4..offset + 8];
+        let body = &png[offset + 8..offset + 8 + length];
+        if kind == png::chunk::IDAT {
+            data.extend_from_slice(body);
+        }
+        offset += 12 + length;
+    }
+    Ok(data)
+}
@@ -0,0 +1,688 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fds::Disk,
+    region::Region,
+    romdb::{GameInfo, Hashes, RomDatabase},
+    vs::{VSProtection, VSPPU},
+};
+
+const INES_MAGIC: [u8; 4] = *b"NES\x1A";
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const SRAM_SIZE: usize = 0x2000;
+// The Disk System's BIOS ROM and its RAM adapter's work RAM
+const FDS_BIOS_SIZE: usize = 0x2000;
+const FDS_RAM_SIZE: usize = 0x8000;
+
+// NES 2.0 default expansion devices the console can set up by itself
+pub const EXPANSION_FOUR_SCORE: u8 = 0x02;
+pub const EXPANSION_ZAPPER: u8 = 0x08;
+pub const EXPANSION_POWER_PAD_A: u8 = 0x0B;
+pub const EXPANSION_POWER_PAD_B: u8 = 0x0C;
+pub const EXPANSION_VAUS_NES: u8 = 0x0F;
+pub const EXPANSION_VAUS_FAMICOM: u8 = 0x10;
+pub const EXPANSION_FAMILY_KEYBOARD: u8 = 0x23;
+pub const EXPANSION_SNES_MOUSE: u8 = 0x29;
+
+// PlayChoice-10 hint screen ROM and the PROM keying it
+const INST_ROM_SIZE: usize = 0x2000;
+const PROM_SIZE: usize = 32;
+
+// Mapper number given to Disk System games, as iNES once reserved it
+pub const MAPPER_FDS: u16 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
+// The machine a ROM was made for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConsoleType {
+    #[default]
+    NES,
+    // A VS. System arcade board, with the PPU it carries and its copy
+    // protection
+    VS(VSPPU, VSProtection),
+    // A PlayChoice-10 arcade cartridge, a console game on an RGB PPU
+    PlayChoice10,
+}
+
+impl ConsoleType {
+    // Whether the game was made for a PPU putting out RGB, with its own
+    // colors, rather than composite video
+    pub fn rgb_ppu(self) -> bool {
+        self != ConsoleType::NES
+    }
+}
+
+// What a PlayChoice-10 cartridge carries beyond the game: the instructions
+// screens the cabinet's own processor shows beside it, and the PROM its
+// BIOS checks them against. The cabinet isn't emulated; these are kept for
+// frontends that want to show the screens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlayChoice {
+    // 8KB INST-ROM
+    pub inst_rom: Vec<u8>,
+    // 16 bytes of PROM data then 16 of CounterOut, empty in dumps without
+    // them
+    pub prom: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    INes,
+    Nes2,
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    InvalidMagic,
+    EmptyPrgRom,
+    // The header declares more ROM than can be addressed
+    RomTooLarge,
+    Truncated { expected: usize, actual: usize },
+    UnsupportedMapper(u16),
+    InvalidBios(usize),
+    InvalidDisk,
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(err) => write!(f, "failed to read ROM: {}", err),
+            CartridgeError::InvalidMagic => write!(f, "missing iNES header magic"),
+            CartridgeError::EmptyPrgRom => write!(f, "header declares no PRG ROM"),
+            CartridgeError::RomTooLarge => write!(f, "header declares too much ROM to load"),
+            CartridgeError::Truncated { expected, actual } => write!(
+                f,
+                "ROM is truncated: header needs {} bytes, file has {}",
+                expected, actual
+            ),
+            CartridgeError::UnsupportedMapper(mapper) => {
+                write!(f, "mapper {} is not supported", mapper)
+            }
+            CartridgeError::InvalidBios(size) => {
+                write!(f, "Disk System BIOS must be 8KB, not {} bytes", size)
+            }
+            CartridgeError::InvalidDisk => write!(f, "disk side has no disk info block"),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        CartridgeError::Io(err)
+    }
+}
+
+// A fault found in a ROM as it loaded, and what was done about it. The ROM
+// database's corrections and the repairs made by heuristics are all listed
+// here, so frontends can warn about them instead of the game failing to
+// load or misbooting without a word.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Diagnostic {
+    // The mapper number was replaced, from the database or because the
+    // header's board can't hold a ROM this size
+    Mapper { header: u16, fixed: u16 },
+    Submapper { header: u8, fixed: u8 },
+    Mirroring { header: Mirroring, fixed: Mirroring },
+    Battery { fixed: bool },
+    Region { header: Region, fixed: Region },
+    // Bytes 12-15 hold junk left by an old dumping tool, e.g. "DiskDude!",
+    // so the upper nibble of the mapper number was ignored
+    DirtyHeader,
+    // The file ends partway into the CHR ROM the header declares, so only
+    // the whole banks present are used
+    TruncatedChr { header: usize, actual: usize },
+    // The database knows the dump to be corrupt or modified
+    BadDump { title: String },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::Mapper { header, fixed } => {
+                write!(f, "header says mapper {}, using mapper {}", header, fixed)
+            }
+            Diagnostic::Submapper { header, fixed } => {
+                write!(f, "header says submapper {}, using {}", header, fixed)
+            }
+            Diagnostic::Mirroring { header, fixed } => {
+                write!(f, "header says {:?} mirroring, using {:?}", header, fixed)
+            }
+            Diagnostic::Battery { fixed: true } => {
+                write!(f, "header is missing the battery flag, saving work RAM")
+            }
+            Diagnostic::Battery { fixed: false } => {
+                write!(f, "header sets the battery flag, but the board has none")
+            }
+            Diagnostic::Region { header, fixed } => {
+                write!(f, "header says {:?}, using {:?}", header, fixed)
+            }
+            Diagnostic::DirtyHeader => {
+                write!(
+                    f,
+                    "header has junk in bytes 12-15, ignoring the mapper's upper bits"
+                )
+            }
+            Diagnostic::TruncatedChr { header, actual } => write!(
+                f,
+                "header declares {}KB of CHR ROM, file has {}KB",
+                header / 1024,
+                actual / 1024
+            ),
+            Diagnostic::BadDump { title } => write!(f, "{} is a known bad dump", title),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub format: Format,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub region: Region,
+    // Work RAM at $6000, volatile and battery-backed
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    // CHR RAM, used when there is no CHR ROM
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    // NES 2.0 default expansion device, see EXPANSION_*
+    pub expansion_device: u8,
+    pub console_type: ConsoleType,
+    // An iNES header with junk where zeros belong, see Diagnostic::DirtyHeader
+    pub dirty: bool,
+}
+
+impl Header {
+    pub fn parse(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(CartridgeError::Truncated {
+                expected: HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        if bytes[0..4] != INES_MAGIC {
+            return Err(CartridgeError::InvalidMagic);
+        }
+
+        let flags6 = bytes[6];
+        let flags7 = bytes[7];
+        let format = if flags7 & 0x0C == 0x08 {
+            Format::Nes2
+        } else {
+            Format::INes
+        };
+
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mut mapper = (flags6 >> 4) as u16;
+        let mut submapper = 0;
+        let mut region = Region::NTSC;
+        let mut expansion_device = 0;
+        let mut console_type = ConsoleType::NES;
+        let mut dirty = false;
+        let prg_rom_size;
+        let chr_rom_size;
+        let prg_ram_size;
+        let prg_nvram_size;
+        let chr_ram_size;
+        let chr_nvram_size;
+        let battery = flags6 & 0x02 != 0;
+        match format {
+            Format::Nes2 => {
+                mapper |= (flags7 & 0xF0) as u16;
+                mapper |= ((bytes[8] & 0x0F) as u16) << 8;
+                submapper = bytes[8] >> 4;
+                prg_rom_size = nes2_rom_size(bytes[4], bytes[9] & 0x0F, PRG_BANK_SIZE)
+                    .ok_or(CartridgeError::RomTooLarge)?;
+                chr_rom_size = nes2_rom_size(bytes[5], bytes[9] >> 4, CHR_BANK_SIZE)
+                    .ok_or(CartridgeError::RomTooLarge)?;
+                // Multi-region games (2) run as NTSC
+                region = match bytes[12] & 0x03 {
+                    1 => Region::PAL,
+                    3 => Region::Dendy,
+                    _ => Region::NTSC,
+                };
+                prg_ram_size = nes2_ram_size(bytes[10] & 0x0F);
+                prg_nvram_size = nes2_ram_size(bytes[10] >> 4);
+                chr_ram_size = nes2_ram_size(bytes[11] & 0x0F);
+                chr_nvram_size = nes2_ram_size(bytes[11] >> 4);
+                expansion_device = bytes[15] & 0x3F;
+                console_type = match flags7 & 0x03 {
+                    1 => ConsoleType::VS(
+                        VSPPU::from_header(bytes[13]),
+                        VSProtection::from_header(bytes[13]),
+                    ),
+                    2 => ConsoleType::PlayChoice10,
+                    // Extended console types run as an NES
+                    _ => ConsoleType::NES,
+                };
+            }
+            Format::INes => {
+                // Old dumping tools wrote signatures like "DiskDude!" into
+                // bytes 7-15, so the upper mapper nibble is only trusted when
+                // the padding is clean.
+                let clean = bytes[12..16].iter().all(|&b| b == 0);
+                if clean {
+                    mapper |= (flags7 & 0xF0) as u16;
+                }
+                dirty = !clean;
+                // iNES leaves the PPU unsaid; most VS. System games have a
+                // 2C03 or a chip with its colors
+                if clean && flags7 & 0x01 != 0 {
+                    console_type = ConsoleType::VS(VSPPU::RP2C03, VSProtection::None);
+                } else if clean && flags7 & 0x02 != 0 {
+                    console_type = ConsoleType::PlayChoice10;
+                }
+                prg_rom_size = bytes[4] as usize * PRG_BANK_SIZE;
+                chr_rom_size = bytes[5] as usize * CHR_BANK_SIZE;
+                // Byte 8 counts 8KB units of work RAM, 0 meaning one
+                let prg_ram = match bytes[8] {
+                    units @ 1.. if clean => units as usize * SRAM_SIZE,
+                    _ => SRAM_SIZE,
+                };
+                (prg_ram_size, prg_nvram_size) = if battery { (0, prg_ram) } else { (prg_ram, 0) };
+                chr_ram_size = if chr_rom_size == 0 { CHR_BANK_SIZE } else { 0 };
+                chr_nvram_size = 0;
+            }
+        }
+
+        if prg_rom_size == 0 {
+            return Err(CartridgeError::EmptyPrgRom);
+        }
+
+        Ok(Self {
+            format,
+            prg_rom_size,
+            chr_rom_size,
+            mapper,
+            submapper,
+            mirroring,
+            battery,
+            trainer: flags6 & 0x04 != 0,
+            region,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            chr_nvram_size,
+            expansion_device,
+            console_type,
+            dirty,
+        })
+    }
+}
+
+// NES 2.0 RAM sizes are shift counts: 64 << n bytes, or none for 0
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+// NES 2.0 sizes use the header's MSB nibble; 0xF switches the LSB byte to
+// exponent-multiplier notation (2^E * (MM * 2 + 1) bytes). None if the
+// size doesn't fit in a usize.
+fn nes2_rom_size(lsb: u8, msb: u8, bank_size: usize) -> Option<usize> {
+    if msb == 0x0F {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        1usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+    } else {
+        Some((((msb as usize) << 8) | lsb as usize) * bank_size)
+    }
+}
+
+// Fills out a ROM that ends partway into a bank, as exponent-notation sizes
+// can, by repeating it the way a chip with unconnected address lines
+// mirrors, so mappers only ever see whole banks
+fn whole_banks(mut rom: Vec<u8>, bank_size: usize) -> Vec<u8> {
+    let len = rom.len();
+    let target = len.div_ceil(bank_size) * bank_size;
+    while rom.len() < target {
+        let count = (target - rom.len()).min(len);
+        rom.extend_from_within(..count);
+    }
+    rom
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    pub prg: Vec<u8>,
+    // CHR ROM, or 8KB of CHR RAM when the header declares no CHR ROM
+    pub chr: Vec<u8>,
+    pub chr_ram: bool,
+    pub trainer: Option<Vec<u8>>,
+    // Work RAM mapped at $6000-$7FFF, possibly banked; empty on boards
+    // without any
+    pub sram: Vec<u8>,
+    // Disk System disk, taken by the mapper
+    pub disk: Option<Disk>,
+    // The hint screens of PlayChoice-10 dumps that include them
+    pub playchoice: Option<PlayChoice>,
+    // File the ROM was loaded from, if any
+    pub path: Option<PathBuf>,
+    // Checksums of the ROM, for cartridges rather than disks
+    pub hashes: Option<Hashes>,
+    // What the ROM database knows of the game, if it is listed
+    pub game: Option<GameInfo>,
+    // Faults found in the ROM and worked around
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Cartridge {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        let mut header = Header::parse(bytes)?;
+        let mut diagnostics = Vec::new();
+        if header.dirty {
+            diagnostics.push(Diagnostic::DirtyHeader);
+        }
+
+        let trainer_size = if header.trainer { TRAINER_SIZE } else { 0 };
+        // Dumps cut short in the CHR ROM run with the banks they have
+        let prg_end = (HEADER_SIZE + trainer_size)
+            .checked_add(header.prg_rom_size)
+            .ok_or(CartridgeError::RomTooLarge)?;
+        let chr_available = bytes.len().saturating_sub(prg_end);
+        // Only a short CHR ROM is cut to whole banks; a complete one keeps
+        // the odd size NES 2.0 exponent notation can give it
+        let chr_present = chr_available / CHR_BANK_SIZE * CHR_BANK_SIZE;
+        if chr_available < header.chr_rom_size && chr_present > 0 {
+            diagnostics.push(Diagnostic::TruncatedChr {
+                header: header.chr_rom_size,
+                actual: chr_present,
+            });
+            header.chr_rom_size = chr_present;
+        }
+        let expected = prg_end
+            .checked_add(header.chr_rom_size)
+            .ok_or(CartridgeError::RomTooLarge)?;
+        if bytes.len() < expected {
+            return Err(CartridgeError::Truncated {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let mut offset = HEADER_SIZE;
+        let trainer = if header.trainer {
+            offset += TRAINER_SIZE;
+            Some(bytes[HEADER_SIZE..offset].to_vec())
+        } else {
+            None
+        };
+
+        let prg = bytes[offset..offset + header.prg_rom_size].to_vec();
+        offset += header.prg_rom_size;
+
+        // Boards declaring less than 8KB of CHR RAM get 8KB anyway, so
+        // mappers can rely on a full pattern table
+        let chr_ram = header.chr_rom_size == 0;
+        let chr = if chr_ram {
+            vec![0; (header.chr_ram_size + header.chr_nvram_size).max(CHR_BANK_SIZE)]
+        } else {
+            bytes[offset..offset + header.chr_rom_size].to_vec()
+        };
+        offset += header.chr_rom_size;
+
+        // PlayChoice-10 dumps follow the CHR ROM with the INST-ROM and
+        // PROM, which are no part of the game
+        let rest = &bytes[offset..];
+        let playchoice = (header.console_type == ConsoleType::PlayChoice10
+            && rest.len() >= INST_ROM_SIZE)
+            .then(|| {
+                let prom = &rest[INST_ROM_SIZE..];
+                PlayChoice {
+                    inst_rom: rest[..INST_ROM_SIZE].to_vec(),
+                    prom: prom[..prom.len().min(PROM_SIZE)].to_vec(),
+                }
+            });
+
+        // Trainers are loaded into work RAM at $7000, which needs the RAM to
+        // be there
+        let mut sram_size = header.prg_ram_size + header.prg_nvram_size;
+        if header.trainer {
+            sram_size = sram_size.max(SRAM_SIZE);
+        }
+        let mut sram = vec![0; sram_size];
+        if let Some(trainer) = &trainer {
+            sram[0x1000..0x1000 + TRAINER_SIZE].copy_from_slice(trainer);
+        }
+
+        // The database knows dumps by what's in the file
+        let hashes = Hashes::new(&prg, if chr_ram { &[] } else { &chr });
+        let prg = whole_banks(prg, PRG_BANK_SIZE);
+        let chr = if chr_ram {
+            chr
+        } else {
+            whole_banks(chr, CHR_BANK_SIZE)
+        };
+        let mut cartridge = Self {
+            header,
+            prg,
+            chr,
+            chr_ram,
+            trainer,
+            sram,
+            disk: None,
+            playchoice,
+            path: None,
+            hashes: Some(hashes),
+            game: None,
+            diagnostics,
+        };
+        if cartridge.identify(RomDatabase::embedded()).is_none() {
+            cartridge.check_size();
+        }
+        Ok(cartridge)
+    }
+
+    // NROM boards hold at most 32KB of PRG and 8KB of CHR. Bigger ROMs
+    // labeled mapper 0 have lost their mapper number, and are most often
+    // MMC1 boards, or MMC3 with more CHR than MMC1 can bank.
+    fn check_size(&mut self) {
+        let header = &self.header;
+        if header.mapper != 0 || (header.prg_rom_size <= 0x8000 && header.chr_rom_size <= 0x2000) {
+            return;
+        }
+        let fixed = if header.chr_rom_size > 0x20000 { 4 } else { 1 };
+        self.diagnostics
+            .push(Diagnostic::Mapper { header: 0, fixed });
+        self.header.mapper = fixed;
+    }
+
+    // Looks the ROM up in `database` and, if it is listed, corrects the
+    // header to match: dumps often carry the wrong mapper, mirroring or
+    // battery flag. Done with the embedded database on load; call again
+    // with a fuller one before creating the console.
+    // Each correction is recorded in `diagnostics`.
+    pub fn identify(&mut self, database: &RomDatabase) -> Option<&GameInfo> {
+        let game = database.lookup(self.hashes.as_ref()?)?.clone();
+        let header = &mut self.header;
+        let diagnostics = &mut self.diagnostics;
+        if header.mapper != game.mapper {
+            diagnostics.push(Diagnostic::Mapper {
+                header: header.mapper,
+                fixed: game.mapper,
+            });
+            header.mapper = game.mapper;
+        }
+        // iNES headers have no submapper or region to be wrong about
+        let nes2 = header.format == Format::Nes2;
+        if header.submapper != game.submapper && nes2 {
+            diagnostics.push(Diagnostic::Submapper {
+                header: header.submapper,
+                fixed: game.submapper,
+            });
+        }
+        header.submapper = game.submapper;
+        if let Some(mirroring) = game.mirroring.filter(|&m| m != header.mirroring) {
+            diagnostics.push(Diagnostic::Mirroring {
+                header: header.mirroring,
+                fixed: mirroring,
+            });
+            header.mirroring = mirroring;
+        }
+        if header.region != game.region && nes2 {
+            diagnostics.push(Diagnostic::Region {
+                header: header.region,
+                fixed: game.region,
+            });
+        }
+        header.region = game.region;
+        if header.battery != game.battery {
+            diagnostics.push(Diagnostic::Battery {
+                fixed: game.battery,
+            });
+            // The same work RAM, now kept or not
+            let ram = header.prg_ram_size + header.prg_nvram_size;
+            (header.prg_ram_size, header.prg_nvram_size) =
+                if game.battery { (0, ram) } else { (ram, 0) };
+            header.battery = game.battery;
+        }
+        if game.bad_dump {
+            diagnostics.push(Diagnostic::BadDump {
+                title: game.title.clone(),
+            });
+        }
+        self.game = Some(game);
+        self.game.as_ref()
+    }
+
+    // Builds a Disk System "cartridge": the RAM adapter with `bios` at
+    // $E000, 32KB of work RAM, 8KB of CHR RAM and `disk` in the drive
+    pub fn from_disk(bios: &[u8], disk: Disk) -> Result<Self, CartridgeError> {
+        if bios.len() != FDS_BIOS_SIZE {
+            return Err(CartridgeError::InvalidBios(bios.len()));
+        }
+        let header = Header {
+            format: Format::INes,
+            prg_rom_size: FDS_BIOS_SIZE,
+            chr_rom_size: 0,
+            mapper: MAPPER_FDS,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: false,
+            region: Region::NTSC,
+            prg_ram_size: FDS_RAM_SIZE,
+            prg_nvram_size: 0,
+            chr_ram_size: CHR_BANK_SIZE,
+            chr_nvram_size: 0,
+            expansion_device: 0,
+            console_type: ConsoleType::NES,
+            dirty: false,
+        };
+        Ok(Self {
+            header,
+            prg: bios.to_vec(),
+            chr: vec![0; CHR_BANK_SIZE],
+            chr_ram: true,
+            trainer: None,
+            sram: vec![0; FDS_RAM_SIZE],
+            disk: Some(disk),
+            playchoice: None,
+            path: None,
+            hashes: None,
+            game: None,
+            diagnostics: Vec::new(),
+        })
+    }
+
+    // Loads the .fds image at `path` with the BIOS at `bios`
+    pub fn from_disk_path<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        bios: Q,
+    ) -> Result<Self, CartridgeError> {
+        let disk = Disk::from_path(&path)?;
+        let mut cartridge = Self::from_disk(&fs::read(bios)?, disk)?;
+        cartridge.path = Some(path.as_ref().to_path_buf());
+        Ok(cartridge)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, CartridgeError> {
+        let bytes = fs::read(&path)?;
+        let mut cartridge = Self::from_bytes(&bytes)?;
+        cartridge.path = Some(path.as_ref().to_path_buf());
+        Ok(cartridge)
+    }
+
+    pub fn mapper(&self) -> u16 {
+        self.header.mapper
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.header.mirroring
+    }
+
+    pub fn battery(&self) -> bool {
+        self.header.battery
+    }
+
+    pub fn sram(&self) -> &[u8] {
+        &self.sram
+    }
+
+    // Work RAM at `offset` into the board's RAM, mirrored when there is
+    // less of it. Boards without any read 0 and ignore writes.
+    pub fn read_sram(&self, offset: usize) -> u8 {
+        match self.sram.len() {
+            0 => 0,
+            len => self.sram[offset % len],
+        }
+    }
+
+    pub fn write_sram(&mut self, offset: usize, value: u8) {
+        let len = self.sram.len();
+        if len > 0 {
+            self.sram[offset % len] = value;
+        }
+    }
+
+    // Restores work RAM from a save file. Files of another size, e.g. from
+    // emulators that pad or trim them, are copied as far as they overlap.
+    pub fn load_sram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.sram.len());
+        self.sram[..len].copy_from_slice(&data[..len]);
+    }
+
+    // Where battery-backed RAM is kept by default: next to the ROM, with a
+    // .sav extension
+    pub fn sav_path(&self) -> Option<PathBuf> {
+        match &self.path {
+            Some(path) if self.battery() => Some(path.with_extension("sav")),
+            _ => None,
+        }
+    }
+}
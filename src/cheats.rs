@@ -0,0 +1,244 @@
+use std::fmt;
+
+// Game Genie letters, in order of the nibble they stand for
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheatError {
+    InvalidLength(usize),
+    InvalidLetter(char),
+    InvalidRaw(String),
+    // Only RAM can be frozen, internal or on the cartridge
+    NotRAM(u16),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::InvalidLength(len) => {
+                write!(f, "Game Genie codes have 6 or 8 letters, not {}", len)
+            }
+            CheatError::InvalidLetter(letter) => {
+                write!(f, "{:?} is not a Game Genie letter", letter)
+            }
+            CheatError::InvalidRaw(code) => write!(
+                f,
+                "{:?} is not a cheat: expected address:value or address:value:compare in hex",
+                code
+            ),
+            CheatError::NotRAM(addr) => write!(
+                f,
+                "${:04X} can't be frozen: only RAM at $0000-$1FFF or $6000-$7FFF can",
+                addr
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+// Makes CPU reads of `address` return `value`, when what is really there
+// equals `compare` if one is given
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String,
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    // Accepts either a Game Genie code or a raw cheat
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        if code.contains(':') {
+            Self::raw(code)
+        } else {
+            Self::game_genie(code)
+        }
+    }
+
+    // Decodes a 6-letter (address, value) or 8-letter (address, value,
+    // compare) Game Genie code. The bits are scrambled across the letters;
+    // see the nesdev wiki's Game Genie page for the layout.
+    pub fn game_genie(code: &str) -> Result<Self, CheatError> {
+        let code = code.trim().to_ascii_uppercase();
+        let n = code
+            .chars()
+            .map(|letter| {
+                LETTERS
+                    .iter()
+                    .position(|&l| l as char == letter)
+                    .map(|nibble| nibble as u16)
+                    .ok_or(CheatError::InvalidLetter(letter))
+            })
+            .collect::<Result<Vec<u16>, _>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(CheatError::InvalidLength(n.len()));
+        }
+
+        let address = 0x8000
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7);
+        let (value, compare) = if n.len() == 6 {
+            (value | (n[5] & 8), None)
+        } else {
+            let compare = (n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8);
+            (value | (n[7] & 8), Some(compare as u8))
+        };
+
+        Ok(Self {
+            code,
+            address,
+            value: value as u8,
+            compare,
+            enabled: true,
+        })
+    }
+
+    // Parses "AAAA:VV" or "AAAA:VV:CC", all hex
+    pub fn raw(code: &str) -> Result<Self, CheatError> {
+        let code = code.trim();
+        let invalid = || CheatError::InvalidRaw(code.to_string());
+        let parts: Vec<&str> = code.split(':').collect();
+        if parts.len() != 2 && parts.len() != 3 {
+            return Err(invalid());
+        }
+        let address = u16::from_str_radix(parts[0], 16).map_err(|_| invalid())?;
+        let value = u8::from_str_radix(parts[1], 16).map_err(|_| invalid())?;
+        let compare = match parts.get(2) {
+            Some(compare) => Some(u8::from_str_radix(compare, 16).map_err(|_| invalid())?),
+            None => None,
+        };
+        Ok(Self {
+            code: code.to_ascii_uppercase(),
+            address,
+            value,
+            compare,
+            enabled: true,
+        })
+    }
+}
+
+// Holds a byte of RAM at `value`: the game's writes to it are dropped, so
+// unlike a cheat, the value is really in memory, where save states and RAM
+// viewers see it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Freeze {
+    pub address: u16,
+    pub value: u8,
+}
+
+// Internal RAM by its address in the first 2KB, which its mirrors share
+fn unmirror(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x1FFF => addr % 0x0800,
+        _ => addr,
+    }
+}
+
+// The cheats and freezes in effect on the CPU bus
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    freezes: Vec<Freeze>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds and enables a cheat, replacing any with the same code
+    pub fn add(&mut self, code: &str) -> Result<(), CheatError> {
+        let cheat = Cheat::parse(code)?;
+        self.cheats.retain(|c| c.code != cheat.code);
+        self.cheats.push(cheat);
+        Ok(())
+    }
+
+    // Returns false if there was no such cheat
+    pub fn remove(&mut self, code: &str) -> bool {
+        let code = code.trim().to_ascii_uppercase();
+        let len = self.cheats.len();
+        self.cheats.retain(|c| c.code != code);
+        self.cheats.len() != len
+    }
+
+    // Returns false if there is no such cheat
+    pub fn set_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        let code = code.trim().to_ascii_uppercase();
+        match self.cheats.iter_mut().find(|c| c.code == code) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Freezes RAM at `addr` at `value`, replacing any freeze there. Takes
+    // effect from the next write; Console::freeze also stores the value now.
+    pub fn freeze(&mut self, addr: u16, value: u8) -> Result<(), CheatError> {
+        if !matches!(addr, 0x0000..=0x1FFF | 0x6000..=0x7FFF) {
+            return Err(CheatError::NotRAM(addr));
+        }
+        let address = unmirror(addr);
+        self.freezes.retain(|f| f.address != address);
+        self.freezes.push(Freeze { address, value });
+        Ok(())
+    }
+
+    // Returns false if `addr` wasn't frozen
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        let address = unmirror(addr);
+        let len = self.freezes.len();
+        self.freezes.retain(|f| f.address != address);
+        self.freezes.len() != len
+    }
+
+    pub fn freezes(&self) -> impl Iterator<Item = &Freeze> {
+        self.freezes.iter()
+    }
+
+    // The value RAM at `addr` is frozen at, if it is
+    pub fn frozen(&self, addr: u16) -> Option<u8> {
+        if self.freezes.is_empty() {
+            return None;
+        }
+        let address = unmirror(addr);
+        self.freezes
+            .iter()
+            .find(|f| f.address == address)
+            .map(|f| f.value)
+    }
+
+    // Clears cheats and freezes alike
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.freezes.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    // What the CPU reads at `addr` when memory holds `value`
+    pub fn apply(&self, addr: u16, value: u8) -> u8 {
+        for cheat in &self.cheats {
+            if cheat.enabled
+                && cheat.address == addr
+                && cheat.compare.is_none_or(|compare| compare == value)
+            {
+                return cheat.value;
+            }
+        }
+        value
+    }
+}
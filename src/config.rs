@@ -0,0 +1,380 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    console::{Accuracy, Console},
+    controller::Button,
+    memory::MemoryInit,
+    palette::{Palette, PaletteError, Preset},
+    ppu::{PixelFormat, Renderer},
+    region::Region,
+    video::{Filter, Overscan, VideoConfig},
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    Palette(PaletteError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "failed to access config: {}", err),
+            ConfigError::Parse(err) => write!(f, "invalid config: {}", err),
+            ConfigError::Serialize(err) => write!(f, "failed to write config: {}", err),
+            ConfigError::Palette(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(err: toml::ser::Error) -> Self {
+        ConfigError::Serialize(err)
+    }
+}
+
+impl From<PaletteError> for ConfigError {
+    fn from(err: PaletteError) -> Self {
+        ConfigError::Palette(err)
+    }
+}
+
+// Settings shared by the CLI and frontends, kept in a TOML file. Missing
+// keys take their defaults, so a file need only list what it changes:
+//
+//     [emulation]
+//     region = "PAL"
+//     accuracy = "CycleAccurate"
+//
+//     [video]
+//     preset = "FBX"
+//     overscan = { top = 8, bottom = 8 }
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub emulation: Emulation,
+    pub video: Video,
+    pub audio: Audio,
+    pub input: Input,
+    pub paths: Paths,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Emulation {
+    // Overrides the region taken from the ROM header
+    pub region: Option<Region>,
+    pub accuracy: Accuracy,
+    // "Scanline" draws a line at a time in Fast accuracy, for speed
+    pub renderer: Renderer,
+    pub accurate_oam: bool,
+    // Frames of input lag to hide by running ahead, 0 for none
+    pub run_ahead: u32,
+    // What each RAM holds at power-on, e.g. { cpu_ram = { Random = 1 } }
+    pub memory_init: MemoryInit,
+    // VS. System DIP switches, switch 1 in bit 0
+    pub dip_switches: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Video {
+    pub preset: Preset,
+    // A .pal file, used instead of the preset
+    pub palette: Option<PathBuf>,
+    pub filter: Filter,
+    // "Indexed" colors each frame in one pass when it completes
+    pub pixel_format: PixelFormat,
+    pub overscan: Overscan,
+    pub aspect_correction: bool,
+    // Window size as a multiple of the picture, for windowed frontends
+    pub scale: u32,
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Self {
+            preset: Preset::Default,
+            palette: None,
+            filter: Filter::None,
+            pixel_format: PixelFormat::Rgba,
+            overscan: Overscan::default(),
+            aspect_correction: false,
+            scale: 3,
+        }
+    }
+}
+
+impl Video {
+    pub fn framing(&self) -> VideoConfig {
+        VideoConfig {
+            overscan: self.overscan,
+            aspect_correction: self.aspect_correction,
+        }
+    }
+
+    pub fn palette(&self) -> Result<Palette, PaletteError> {
+        match &self.palette {
+            Some(path) => Palette::from_path(path),
+            None => Ok(Palette::preset(self.preset)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Audio {
+    pub sample_rate: u32,
+    // Scales what frontends play, 0 for silence; captures are unaffected
+    pub volume: f32,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            volume: 1.0,
+        }
+    }
+}
+
+// Keyboard bindings for the two joypads. Key names are the frontend's, e.g.
+// SDL's "Right Shift" or "Return".
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Input {
+    pub player1: Bindings,
+    pub player2: Bindings,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect();
+        Self {
+            player1: Bindings {
+                a: keys(&["X"]),
+                b: keys(&["Z"]),
+                select: keys(&["Right Shift", "Backspace"]),
+                start: keys(&["Return"]),
+                up: keys(&["Up"]),
+                down: keys(&["Down"]),
+                left: keys(&["Left"]),
+                right: keys(&["Right"]),
+                turbo_a: 0,
+                turbo_b: 0,
+            },
+            player2: Bindings::default(),
+        }
+    }
+}
+
+impl Input {
+    pub fn players(&self) -> [&Bindings; 2] {
+        [&self.player1, &self.player2]
+    }
+
+    // The player and button `key` is bound to, player 1's bindings first
+    pub fn button(&self, key: &str) -> Option<(usize, Button)> {
+        self.players()
+            .iter()
+            .enumerate()
+            .find_map(|(player, bindings)| Some((player, bindings.button(key)?)))
+    }
+}
+
+// The keys held for each button of a joypad. A player's table replaces the
+// defaults whole, so list every button bound.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub a: Vec<String>,
+    pub b: Vec<String>,
+    pub select: Vec<String>,
+    pub start: Vec<String>,
+    pub up: Vec<String>,
+    pub down: Vec<String>,
+    pub left: Vec<String>,
+    pub right: Vec<String>,
+    // Auto-fire rates for A and B as Console::set_turbo takes them, 0 for
+    // none
+    pub turbo_a: u8,
+    pub turbo_b: u8,
+}
+
+impl Bindings {
+    pub fn keys(&self, button: Button) -> &[String] {
+        match button {
+            Button::A => &self.a,
+            Button::B => &self.b,
+            Button::Select => &self.select,
+            Button::Start => &self.start,
+            Button::Up => &self.up,
+            Button::Down => &self.down,
+            Button::Left => &self.left,
+            Button::Right => &self.right,
+        }
+    }
+
+    // The button `key` is bound to, ignoring case
+    pub fn button(&self, key: &str) -> Option<Button> {
+        Button::ALL.into_iter().find(|&button| {
+            self.keys(button)
+                .iter()
+                .any(|bound| bound.eq_ignore_ascii_case(key))
+        })
+    }
+}
+
+// Where files made for a ROM go. Unset directories keep them next to the
+// ROM, as when there is no config.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Paths {
+    pub saves: Option<PathBuf>,
+    pub states: Option<PathBuf>,
+    pub screenshots: Option<PathBuf>,
+}
+
+impl Paths {
+    // The battery save file, name.sav
+    pub fn sram_path(&self, rom: &Path) -> io::Result<PathBuf> {
+        file_for(self.saves.as_deref(), rom, "sav")
+    }
+
+    // Save state `slot`, name.state1 and so on
+    pub fn state_path(&self, rom: &Path, slot: u8) -> io::Result<PathBuf> {
+        file_for(self.states.as_deref(), rom, &format!("state{}", slot))
+    }
+
+    // The first of name_000.png, name_001.png, ... not already taken
+    pub fn screenshot_path(&self, rom: &Path) -> io::Result<PathBuf> {
+        let first = file_for(self.screenshots.as_deref(), rom, "png")?;
+        let name = first.file_stem().unwrap_or_default().to_string_lossy();
+        let path = (0..)
+            .map(|index| first.with_file_name(format!("{}_{:03}.png", name, index)))
+            .find(|path| !path.exists())
+            .unwrap();
+        Ok(path)
+    }
+}
+
+// The ROM's file name with `extension`, in `directory` if given, which is
+// created if it doesn't exist
+fn file_for(directory: Option<&Path>, rom: &Path, extension: &str) -> io::Result<PathBuf> {
+    let path = rom.with_extension(extension);
+    match directory {
+        Some(directory) => {
+            fs::create_dir_all(directory)?;
+            Ok(directory.join(path.file_name().unwrap_or_default()))
+        }
+        None => Ok(path),
+    }
+}
+
+impl Config {
+    // nesrs/config.toml in the user's config directory: $XDG_CONFIG_HOME,
+    // ~/.config or %APPDATA%
+    pub fn default_path() -> Option<PathBuf> {
+        let directory = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(directory.join("nesrs").join("config.toml"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // Like `load`, but a missing file gives the defaults
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Writes every setting, creating the file's directory if need be
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        if let Some(directory) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    // Sets up `console` as configured. Input bindings and the paths are
+    // left to the frontend, which knows its keys and the ROM's path.
+    pub fn apply(&self, console: &mut Console) -> Result<(), ConfigError> {
+        let emulation = &self.emulation;
+        if let Some(region) = emulation.region {
+            console.set_region(region);
+        }
+        console.set_accuracy(emulation.accuracy);
+        console.set_renderer(emulation.renderer);
+        console.set_accurate_oam(emulation.accurate_oam);
+        console.set_run_ahead(emulation.run_ahead);
+        // The console was switched on zeroed, so boot it again with anything
+        // else
+        if emulation.memory_init != console.memory_init() {
+            console.set_memory_init(emulation.memory_init);
+            console.power_cycle();
+        }
+
+        if let Some(vs) = console.vs_system_mut() {
+            vs.dip_switches = emulation.dip_switches;
+        }
+
+        // Arcade games keep the palette of their RGB PPU unless another is
+        // asked for
+        let video = &self.video;
+        if !console.console_type().rgb_ppu()
+            || video.palette.is_some()
+            || video.preset != Preset::Default
+        {
+            console.set_palette(video.palette()?);
+        }
+        console.set_video_filter(self.video.filter);
+        console.set_pixel_format(self.video.pixel_format);
+        console.set_video_config(self.video.framing());
+
+        console.set_sample_rate(self.audio.sample_rate as f64);
+        for (player, bindings) in self.input.players().into_iter().enumerate() {
+            console.set_turbo(player, Button::A, bindings.turbo_a);
+            console.set_turbo(player, Button::B, bindings.turbo_b);
+        }
+        Ok(())
+    }
+}
@@ -1,2 +1,1205 @@
-#[derive(Default, Debug, Clone, Copy)]
-pub struct Console {}
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use image::{ImageResult, RgbaImage};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::recorder::{RecordConfig, RecordError, Recorder};
+use crate::{
+    achievements::{AchievementCallback, AchievementMemory},
+    apu::{Channel, ChannelMix, APU},
+    audio::WavCapture,
+    cartridge::{
+        Cartridge, CartridgeError, ConsoleType, Diagnostic, Header, PlayChoice,
+        EXPANSION_FAMILY_KEYBOARD, EXPANSION_FOUR_SCORE, EXPANSION_POWER_PAD_A,
+        EXPANSION_POWER_PAD_B, EXPANSION_SNES_MOUSE, EXPANSION_VAUS_FAMICOM, EXPANSION_VAUS_NES,
+        EXPANSION_ZAPPER,
+    },
+    cheats::{CheatError, Cheats},
+    controller::{Button, Microphone, PortDevice, PowerPad, SNESMouse, Side, Zapper},
+    cpu::CPU,
+    events::{Event, EventFilter, SubscriptionId},
+    expansion::{ExpansionDevice, FamilyKeyboard, Key, Vaus},
+    input::InputSource,
+    mapper::{self, Mapper},
+    memory::{CPUMemory, MemoryInit, PPUMemory},
+    movie::{self, Frame, Movie, MovieError, COMMAND_POWER, COMMAND_RESET},
+    palette::{Palette, Preset},
+    ppu::{PixelFormat, Renderer, PPU, WIDTH},
+    profiler::Profiler,
+    region::Region,
+    rewind::RewindBuffer,
+    romdb::GameInfo,
+    savestate::{StateError, StateReader, StateWriter},
+    video::{self, Filter, ScreenshotOptions, VideoConfig},
+    vs::VSSystem,
+};
+
+// How closely the PPU is kept in step with the CPU
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Accuracy {
+    // The PPU catches up after each instruction, which is fastest
+    #[default]
+    Fast,
+    // The PPU runs three dots (3.2 on PAL) ahead of every CPU bus access,
+    // so $2002 reads, sprite zero hits and mid-frame writes land on the
+    // dot they do on hardware
+    CycleAccurate,
+}
+
+enum MovieState {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+pub struct Console {
+    pub cpu: CPU,
+    // Kept to tell whether a save state belongs to this cartridge
+    header: Header,
+    // Battery-backed RAM is loaded from and flushed to this file
+    sram_path: Option<PathBuf>,
+    rewind: Option<RewindBuffer>,
+    movie: Option<MovieState>,
+    input: Option<Box<dyn InputSource>>,
+    // COMMAND_RESET and COMMAND_POWER when given this frame, for recording
+    commands: u8,
+    // RAM contents for power cycles, and the board's state at power-on,
+    // which they restore
+    memory_init: MemoryInit,
+    power_on_state: Vec<u8>,
+    // The renderer asked for, which the PPU uses unless cycle-accurate
+    renderer: Renderer,
+    // Frames emulated ahead of the one shown, see `set_run_ahead`
+    run_ahead: u32,
+    // Set while running those frames, whose effects beyond the machine
+    // itself are skipped
+    running_ahead: bool,
+    // The file stop_wav_capture finishes
+    wav_path: Option<PathBuf>,
+    video: VideoConfig,
+    achievement_callback: Option<AchievementCallback>,
+    #[cfg(not(target_arch = "wasm32"))]
+    recorder: Option<Recorder>,
+}
+
+// Every part of a console is owned by it alone, so it can be handed to
+// another thread; this keeps it that way
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<Console>()
+};
+
+impl Console {
+    pub fn new(cartridge: Cartridge) -> Result<Self, CartridgeError> {
+        let sram_path = cartridge.sav_path();
+        let mut console = Self::with_mapper(mapper::new(cartridge)?);
+        // Plug in the controllers the header says the game expects
+        match console.header.expansion_device {
+            EXPANSION_FOUR_SCORE => console.set_four_score(true),
+            EXPANSION_ZAPPER => console.cpu.memory.zapper = Some(Zapper::new()),
+            EXPANSION_VAUS_NES | EXPANSION_VAUS_FAMICOM => {
+                let famicom = console.header.expansion_device == EXPANSION_VAUS_FAMICOM;
+                console.set_expansion_device(Some(ExpansionDevice::Vaus(Vaus::new(famicom))));
+            }
+            EXPANSION_FAMILY_KEYBOARD => {
+                let keyboard = FamilyKeyboard::new();
+                console.set_expansion_device(Some(ExpansionDevice::FamilyKeyboard(keyboard)));
+            }
+            EXPANSION_POWER_PAD_A | EXPANSION_POWER_PAD_B => {
+                let side = if console.header.expansion_device == EXPANSION_POWER_PAD_B {
+                    Side::B
+                } else {
+                    Side::A
+                };
+                console.set_port_device(1, Some(PortDevice::PowerPad(PowerPad::new(side))));
+            }
+            EXPANSION_SNES_MOUSE => {
+                console.set_port_device(1, Some(PortDevice::Mouse(SNESMouse::new())));
+            }
+            _ => {}
+        }
+        // Arcade boards have an RGB PPU, and VS. System ones a cabinet the
+        // game reads
+        if let ConsoleType::VS(ppu, protection) = console.header.console_type {
+            console.cpu.memory.vs = Some(VSSystem::new(protection));
+            console.ppu_mut().set_vs_ppu(Some(ppu));
+        }
+        if console.header.console_type.rgb_ppu() {
+            console.set_palette(Palette::preset(Preset::RGB));
+        }
+        if let Some(path) = sram_path {
+            console.set_sram_path(path)?;
+        }
+        Ok(console)
+    }
+
+    // Builds a console around a board that didn't come from a ROM image,
+    // like the NSF player's
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Self {
+        let header = mapper.cartridge().header.clone();
+        let region = header.region;
+        let mut power_on_state = StateWriter::new();
+        mapper.save(&mut power_on_state);
+        let ppu = PPU::new(PPUMemory::new(mapper));
+        let cpu = CPU::new(CPUMemory::new(ppu));
+        let mut console = Self {
+            cpu,
+            header,
+            sram_path: None,
+            rewind: None,
+            movie: None,
+            input: None,
+            commands: 0,
+            memory_init: MemoryInit::default(),
+            power_on_state: power_on_state.finish(),
+            renderer: Renderer::Dot,
+            run_ahead: 0,
+            running_ahead: false,
+            wav_path: None,
+            video: VideoConfig::default(),
+            achievement_callback: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            recorder: None,
+        };
+        console.set_region(region);
+        console
+    }
+
+    // Moves the battery save file, loading it if it already exists. Has no
+    // effect for cartridges without a battery. Call before running so the
+    // loaded RAM is what the game boots with.
+    pub fn set_sram_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if !self.header.battery {
+            return Ok(());
+        }
+        let path = path.as_ref().to_path_buf();
+        match fs::read(&path) {
+            Ok(data) => self.mapper_mut().load_battery_data(&data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        self.sram_path = Some(path);
+        Ok(())
+    }
+
+    // Writes battery-backed RAM, or flash on boards that save to it, to its
+    // save file. Also done on drop.
+    pub fn flush_sram(&self) -> io::Result<()> {
+        match &self.sram_path {
+            Some(path) => fs::write(path, self.mapper().battery_data()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.ppu().region()
+    }
+
+    // Overrides the region taken from the ROM header. Best done before
+    // running: a switch mid-frame leaves that frame with the old layout.
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu_mut().set_region(region);
+        self.apu_mut().set_region(region);
+        self.cpu.memory.ppu_clock_ratio = region.ppu_clock_ratio();
+        self.cpu.memory.ppu_dots = 0;
+    }
+
+    pub fn accuracy(&self) -> Accuracy {
+        if self.cpu.memory.cycle_accurate {
+            Accuracy::CycleAccurate
+        } else {
+            Accuracy::Fast
+        }
+    }
+
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.cpu.memory.cycle_accurate = accuracy == Accuracy::CycleAccurate;
+        self.cpu.memory.clocked_cycles = 0;
+        self.update_renderer();
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn run_ahead(&self) -> u32 {
+        self.run_ahead
+    }
+
+    // Hides `frames` frames of the game's input lag: each step_frame runs
+    // that many frames past the real one, holding the input of the real one,
+    // shows the last, and then restores the real one's state. Audio, movies,
+    // rewind and recordings follow the real frames only. It costs that many
+    // extra frames of emulation and a save state load each frame. Games
+    // whose reactions take longer than `frames` to show are unaffected
+    // beyond them; 1 or 2 is typical.
+    pub fn set_run_ahead(&mut self, frames: u32) {
+        self.run_ahead = frames;
+    }
+
+    // Chooses how the PPU draws in Fast accuracy. Cycle-accurate emulation
+    // always draws a dot at a time, since that's what it's for.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+        self.update_renderer();
+    }
+
+    fn update_renderer(&mut self) {
+        let renderer = match self.accuracy() {
+            Accuracy::Fast => self.renderer,
+            Accuracy::CycleAccurate => Renderer::Dot,
+        };
+        self.ppu_mut().set_renderer(renderer);
+    }
+
+    // Presses the reset button. The CPU restarts through the reset vector
+    // and the APU falls silent, but RAM, VRAM and the board keep what they
+    // held, so games can tell a reset from a cold start.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.ppu_mut().reset();
+        self.apu_mut().reset();
+        self.commands |= COMMAND_RESET;
+    }
+
+    // Switches the console off and on again. Everything starts over as at
+    // power-on, RAM filled as `set_memory_init` says, except what survives
+    // being unplugged: battery-backed RAM and disk contents. Settings made
+    // through the console, like the palette and mixer, are kept.
+    pub fn power_cycle(&mut self) {
+        self.restore_board();
+        let init = self.memory_init;
+        self.cpu.memory.power_on(&init);
+        self.cpu.power_on();
+        self.commands |= COMMAND_POWER;
+    }
+
+    pub fn memory_init(&self) -> MemoryInit {
+        self.memory_init
+    }
+
+    // Sets what power_cycle fills each RAM with. A console starts out with
+    // everything zeroed; power cycle it after this to boot with the new
+    // contents instead.
+    pub fn set_memory_init(&mut self, init: MemoryInit) {
+        self.memory_init = init;
+    }
+
+    // Puts the board back in its power-on state, keeping its battery RAM and
+    // disk, with CHR RAM filled as the memory init says
+    fn restore_board(&mut self) {
+        {
+            let mapper = self.cpu.memory.mapper_mut();
+            let battery = self.header.battery.then(|| mapper.battery_data().to_vec());
+            let disk = mapper.disk().cloned();
+            let mut state =
+                StateReader::new(&self.power_on_state).expect("power-on state has a header");
+            mapper
+                .load(&mut state)
+                .expect("a board loads its own state");
+            if let Some(battery) = battery {
+                mapper.load_battery_data(&battery);
+            }
+            if let (Some(disk), Some(inserted)) = (disk, mapper.disk_mut()) {
+                *inserted = disk;
+            }
+            // Battery-backed CHR RAM keeps its contents too
+            let cartridge = mapper.cartridge_mut();
+            if cartridge.chr_ram
+                && !(cartridge.header.battery && cartridge.header.chr_nvram_size > 0)
+            {
+                self.memory_init.chr_ram.fill(&mut cartridge.chr);
+            }
+        }
+        self.push_expansion_gains();
+    }
+
+    // The machine the game was made for
+    pub fn console_type(&self) -> ConsoleType {
+        self.header.console_type
+    }
+
+    // The instructions screens of a PlayChoice-10 game, if its dump has them
+    pub fn playchoice(&self) -> Option<&PlayChoice> {
+        self.mapper().cartridge().playchoice.as_ref()
+    }
+
+    // The game as the ROM database lists it, if it does
+    pub fn game(&self) -> Option<GameInfo> {
+        self.mapper().cartridge().game.clone()
+    }
+
+    // Faults found in the ROM as it loaded and worked around, for warning
+    // about
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.mapper().cartridge().diagnostics.clone()
+    }
+
+    // Runs one CPU instruction along with the PPU dots and APU cycles that
+    // elapse during it, returning the CPU cycles taken
+    pub fn step(&mut self) -> u64 {
+        let frame = self.ppu().frame();
+        let scanline = self.ppu().scanline();
+        let cpu_cycles = self.cpu.step();
+        // Bus accesses may already have run the PPU through some of the
+        // cycles; any beyond the instruction's, as in OAM DMA, are taken off
+        // the stall cycles that follow
+        let memory = &mut self.cpu.memory;
+        let clocked = memory.clocked_cycles.min(cpu_cycles);
+        memory.clocked_cycles -= clocked;
+        memory.clock_ppu(cpu_cycles - clocked);
+        // Otherwise the PPU was in step at every cycle the CPU polled
+        if !memory.cycle_accurate {
+            self.cpu.poll_interrupts();
+        }
+        if self.cpu.memory.events.scanlines {
+            self.emit_scanlines(scanline);
+        }
+        if self.ppu().frame() != frame {
+            for controller in &mut self.cpu.memory.controllers {
+                controller.step_frame();
+            }
+            // Frames run ahead are undone, and see the input held now
+            if self.running_ahead {
+                return cpu_cycles;
+            }
+            if let Some(vs) = &mut self.cpu.memory.vs {
+                vs.step_frame();
+            }
+            self.cpu.memory.events.emit(Event::Frame(frame));
+            if let Some(profiler) = self.cpu.profiler_mut() {
+                profiler.end_frame();
+            }
+            if let Some(callback) = &mut self.achievement_callback {
+                callback(&AchievementMemory::new(&self.cpu.memory));
+            }
+            self.advance_movie();
+            self.poll_input();
+            self.capture_rewind();
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(recorder) = &mut self.recorder {
+                recorder.frame(self.cpu.memory.ppu.front());
+            }
+        }
+        cpu_cycles
+    }
+
+    // Runs until the PPU starts a new frame, returning the CPU cycles taken.
+    // With run-ahead, the frame shown is then the one that many frames on.
+    pub fn step_frame(&mut self) -> u64 {
+        if self.run_ahead == 0 {
+            return self.run_frame();
+        }
+        // Only the frame shown reaches the frame callback, and only the
+        // real one is heard
+        let callback = self.ppu_mut().take_frame_callback();
+        let cpu_cycles = self.run_frame();
+        let state = self.save_state();
+        // Nor do subscribers or the profiler see frames that are undone
+        let events = std::mem::take(&mut self.cpu.memory.events);
+        let profiler = self.cpu.take_profiler();
+        self.running_ahead = true;
+        self.apu_mut().set_silent(true);
+        for _ in 1..self.run_ahead {
+            self.run_frame();
+        }
+        if let Some(callback) = callback {
+            self.ppu_mut().set_frame_callback(callback);
+        }
+        self.run_frame();
+        self.running_ahead = false;
+        self.apu_mut().set_silent(false);
+        // The front buffer isn't part of the state, so keeps the future frame
+        self.load_state(&state)
+            .expect("a console loads its own save state");
+        self.cpu.memory.events = events;
+        if let Some(profiler) = profiler {
+            self.cpu.set_profiler(profiler);
+        }
+        cpu_cycles
+    }
+
+    // Emits each scanline the PPU has started since it was on `from`.
+    // Only OAM DMA runs long enough to pass more than one.
+    fn emit_scanlines(&mut self, from: i32) {
+        let scanlines = self.region().scanlines();
+        let to = self.ppu().scanline();
+        let mut scanline = from;
+        while scanline != to {
+            scanline = (scanline + 1) % scanlines;
+            self.cpu.memory.events.emit(Event::Scanline(scanline));
+        }
+    }
+
+    // Calls `handler` with each event `filter` matches until unsubscribed.
+    // Handlers run mid-step and only see the event itself.
+    pub fn subscribe<F>(&mut self, filter: EventFilter, handler: F) -> SubscriptionId
+    where
+        F: FnMut(&Event) + Send + 'static,
+    {
+        self.cpu.memory.events.subscribe(filter, Box::new(handler))
+    }
+
+    // Returns whether `id` was subscribed
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.cpu.memory.events.unsubscribe(id)
+    }
+
+    // Counts the cycles of each routine from now on; see Profiler
+    pub fn start_profiling(&mut self, profiler: Profiler) {
+        self.cpu.set_profiler(profiler);
+    }
+
+    // The profiler, with everything it counted
+    pub fn stop_profiling(&mut self) -> Option<Profiler> {
+        self.cpu.take_profiler()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.cpu.profiler()
+    }
+
+    fn run_frame(&mut self) -> u64 {
+        let mut cpu_cycles = 0;
+        let frame = self.ppu().frame();
+        while frame == self.ppu().frame() {
+            cpu_cycles += self.step();
+        }
+        cpu_cycles
+    }
+
+    // Runs for `seconds` of emulated time, returning the CPU cycles taken
+    pub fn step_seconds(&mut self, seconds: f64) -> u64 {
+        let target = (self.region().cpu_frequency() as f64 * seconds) as u64;
+        let mut cpu_cycles = 0;
+        while cpu_cycles < target {
+            cpu_cycles += self.step();
+        }
+        cpu_cycles
+    }
+
+    // Snapshots the whole machine into a versioned binary save state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.write(&self.header.mapper);
+        state.write(&self.header.prg_rom_size);
+        state.write(&self.header.chr_rom_size);
+        state.write(&self.region());
+        state.write(&self.cpu.memory.ppu_dots);
+        self.cpu.save(&mut state);
+        state.finish()
+    }
+
+    // Restores a state from `save_state`. States from another version or
+    // cartridge are refused, and a state that fails partway through leaves
+    // the machine as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data)?;
+        self.check_cartridge(&mut state)?;
+
+        let backup = self.save_state();
+        if let Err(err) = self.load_machine(&mut state) {
+            let mut state = StateReader::new(&backup)?;
+            self.check_cartridge(&mut state)?;
+            self.load_machine(&mut state)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn load_machine(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let region: Region = state.read()?;
+        if region != self.region() {
+            self.set_region(region);
+        }
+        self.cpu.memory.ppu_dots = state.read()?;
+        self.cpu.load(state)
+    }
+
+    // Starts recording joypad input, one sample per frame, so call it
+    // between frames. Unless nothing has run yet, the movie is anchored with
+    // a save state of the current machine.
+    pub fn record_movie(&mut self) {
+        let mut movie = Movie::new();
+        {
+            let mapper = self.mapper();
+            let cartridge = mapper.cartridge();
+            if let Some(name) = cartridge.path.as_ref().and_then(|path| path.file_stem()) {
+                movie.rom_filename = name.to_string_lossy().into_owned();
+            }
+            movie.rom_checksum = Some(movie::rom_checksum(cartridge));
+        }
+        movie.pal = self.region() == Region::PAL;
+        if self.cpu.cycles != 0 {
+            movie.savestate = Some(self.save_state());
+        }
+        self.movie = Some(MovieState::Recording(movie));
+    }
+
+    // Replays `movie` from its save state or, if it has none, from the
+    // current state, which should be a freshly created Console. Its input
+    // replaces the frontend's until it runs out.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        if !movie.matches(self.mapper().cartridge()) {
+            return Err(MovieError::WrongRom);
+        }
+        if let Some(state) = &movie.savestate {
+            self.load_state(state)?;
+        }
+        if movie.pal != (self.region() == Region::PAL) {
+            self.set_region(if movie.pal { Region::PAL } else { Region::NTSC });
+        }
+        self.movie = Some(MovieState::Playing { movie, frame: 0 });
+        self.apply_movie_frame();
+        Ok(())
+    }
+
+    // Ends recording or playback, returning the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match self.movie.take()? {
+            MovieState::Recording(movie) | MovieState::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    pub fn recording_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieState::Recording(_)))
+    }
+
+    pub fn playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieState::Playing { .. }))
+    }
+
+    fn advance_movie(&mut self) {
+        match &mut self.movie {
+            Some(MovieState::Recording(movie)) => {
+                let controllers = &self.cpu.memory.controllers;
+                movie.frames.push(Frame {
+                    commands: self.commands,
+                    ports: [controllers[0].buttons(), controllers[1].buttons()],
+                });
+            }
+            Some(MovieState::Playing { frame, .. }) => {
+                *frame += 1;
+                self.apply_movie_frame();
+            }
+            None => {}
+        }
+    }
+
+    fn apply_movie_frame(&mut self) {
+        let Some(MovieState::Playing { movie, frame }) = &self.movie else {
+            return;
+        };
+        let Some(input) = movie.frames.get(*frame).copied() else {
+            self.movie = None;
+            return;
+        };
+        for (controller, buttons) in self.cpu.memory.controllers.iter_mut().zip(input.ports) {
+            controller.set_buttons(buttons);
+        }
+        if input.commands & COMMAND_POWER != 0 {
+            self.power_cycle();
+        }
+        if input.commands & COMMAND_RESET != 0 {
+            self.reset();
+        }
+    }
+
+    // Keeps roughly `seconds` of history for `rewind`, snapshotting every
+    // `interval` frames. Any existing history is dropped.
+    pub fn enable_rewind(&mut self, interval: u64, seconds: f64) {
+        let snapshots = seconds * self.region().frame_rate() / interval.max(1) as f64;
+        self.rewind = Some(RewindBuffer::new(interval, snapshots.ceil() as usize));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    // Steps back at least `frames` frames, to the snapshot at or before
+    // that point (or the oldest one held), returning how many frames were
+    // actually rewound. Does nothing while rewind is disabled.
+    pub fn rewind(&mut self, frames: u64) -> Result<u64, StateError> {
+        let Some(mut rewind) = self.rewind.take() else {
+            return Ok(0);
+        };
+        let current = self.ppu().frame();
+        let result = match rewind.rewind_to(current.saturating_sub(frames)) {
+            Some((frame, state)) => self
+                .load_state(state)
+                .map(|()| current.saturating_sub(frame)),
+            None => Ok(0),
+        };
+        self.rewind = Some(rewind);
+        result
+    }
+
+    fn capture_rewind(&mut self) {
+        if let Some(mut rewind) = self.rewind.take() {
+            let frame = self.ppu().frame();
+            if rewind.due(frame) {
+                rewind.push(frame, self.save_state());
+            }
+            self.rewind = Some(rewind);
+        }
+    }
+
+    fn check_cartridge(&self, state: &mut StateReader) -> Result<(), StateError> {
+        let mapper: u16 = state.read()?;
+        let prg_rom_size: usize = state.read()?;
+        let chr_rom_size: usize = state.read()?;
+        if mapper != self.header.mapper
+            || prg_rom_size != self.header.prg_rom_size
+            || chr_rom_size != self.header.chr_rom_size
+        {
+            return Err(StateError::WrongCartridge);
+        }
+        Ok(())
+    }
+
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.cpu.memory.mapper()
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.cpu.memory.mapper_mut()
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.cpu.memory.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        &mut self.cpu.memory.ppu
+    }
+
+    pub fn apu(&self) -> &APU {
+        &self.cpu.memory.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.cpu.memory.apu
+    }
+
+    // Sets the rate, in Hz, the APU produces mixed samples at
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    // Names of the cartridge's expansion sound channels, if it has any
+    pub fn expansion_channels(&self) -> Vec<&'static str> {
+        self.mapper().expansion_channels()
+    }
+
+    // Sets the volume of the expansion channel at `channel` in
+    // `expansion_channels`, 1.0 being its normal level
+    pub fn set_expansion_volume(&mut self, channel: usize, volume: f32) {
+        self.set_channel_volume(Channel::Expansion(channel), volume);
+    }
+
+    // Sets a channel's mixer volume, 1.0 being its normal level
+    pub fn set_channel_volume(&mut self, channel: Channel, volume: f32) {
+        let mix = self.apu().channel_mix(channel);
+        self.set_channel_mix(channel, ChannelMix { volume, ..mix });
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        let mix = self.apu().channel_mix(channel);
+        self.set_channel_mix(channel, ChannelMix { muted, ..mix });
+    }
+
+    // While any channel is soloed, only soloed channels are heard
+    pub fn set_channel_solo(&mut self, channel: Channel, solo: bool) {
+        let mix = self.apu().channel_mix(channel);
+        self.set_channel_mix(channel, ChannelMix { solo, ..mix });
+    }
+
+    // Soloing one channel silences all the others, so every expansion
+    // channel's gain is passed on to the board again
+    fn set_channel_mix(&mut self, channel: Channel, mix: ChannelMix) {
+        self.apu_mut().set_channel_mix(channel, mix);
+        self.push_expansion_gains();
+    }
+
+    fn push_expansion_gains(&mut self) {
+        let channels = self.expansion_channels().len();
+        for index in 0..channels {
+            let gain = self.apu().channel_gain(Channel::Expansion(index));
+            self.mapper_mut().set_expansion_volume(index, gain);
+        }
+    }
+
+    // Starts or stops keeping each channel's output apart as well, for
+    // drain_channel_samples
+    pub fn set_channel_taps(&mut self, enabled: bool) {
+        self.apu_mut().set_channel_taps(enabled);
+    }
+
+    // Drains `channel`'s own samples, at the output rate, into `out`,
+    // returning the number written. Expansion channels come as one stream.
+    pub fn drain_channel_samples(&mut self, channel: Channel, out: &mut [f32]) -> usize {
+        self.apu_mut().drain_channel_samples(channel, out)
+    }
+
+    // Caps how many expansion channels boards that play them in turn cycle
+    // through. The N163 whines at the rate it switches channels when all
+    // eight are on; fewer channels raise the whine and drop the channels
+    // played last.
+    pub fn set_expansion_channel_limit(&mut self, limit: usize) {
+        self.mapper_mut().set_expansion_channel_limit(limit);
+    }
+
+    // Number of disk sides a Disk System game has, 0 for cartridges
+    pub fn disk_sides(&self) -> usize {
+        self.mapper().disk_sides()
+    }
+
+    pub fn disk_side(&self) -> Option<usize> {
+        self.mapper().disk_side()
+    }
+
+    // Swaps the disk in the drive for `side`, or ejects it for `None`.
+    // Games expect an empty drive for a moment between sides, so frontends
+    // should eject and insert a second or so apart.
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.mapper_mut().insert_disk(side);
+    }
+
+    // Records the audio to a 16-bit WAV file at the output sample rate, in
+    // step with emulated time, until stop_wav_capture. Finishes any WAV
+    // capture already running first.
+    pub fn start_wav_capture<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.start_capture(path.as_ref(), false)
+    }
+
+    // As start_wav_capture, also writing each channel to a stem file beside
+    // it: name.pulse1.wav, name.pulse2.wav, name.triangle.wav,
+    // name.noise.wav, name.dmc.wav and name.expansion.wav
+    pub fn start_wav_capture_with_stems<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.start_capture(path.as_ref(), true)
+    }
+
+    fn start_capture(&mut self, path: &Path, stems: bool) -> io::Result<()> {
+        self.stop_wav_capture()?;
+        let capture = self.wav_capture(path, stems)?;
+        self.apu_mut().start_capture(capture);
+        self.wav_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn wav_capture(&self, path: &Path, stems: bool) -> io::Result<WavCapture> {
+        let sample_rate = self.apu().sample_rate() as u32;
+        let cpu_frequency = self.region().cpu_frequency();
+        WavCapture::create(path, sample_rate, cpu_frequency, stems)
+    }
+
+    // Finishes the WAV files being captured to, if any
+    pub fn stop_wav_capture(&mut self) -> io::Result<()> {
+        let Some(path) = self.wav_path.take() else {
+            return Ok(());
+        };
+        match self.apu_mut().stop_capture(&path) {
+            Some(capture) => capture.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // Records video and audio to `path` through ffmpeg until
+    // stop_recording, starting with the next frame, so call it between
+    // frames. Finishes any recording already running first.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start_recording<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        config: RecordConfig,
+    ) -> Result<(), RecordError> {
+        self.stop_recording()?;
+        let recorder = Recorder::start(path, self.region(), self.video, config)?;
+        let capture = self.wav_capture(recorder.audio_path(), false)?;
+        self.apu_mut().start_capture(capture);
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    // Ends the recording, if any, and writes out the file, which can take
+    // ffmpeg a moment
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn stop_recording(&mut self) -> Result<(), RecordError> {
+        let Some(recorder) = self.recorder.take() else {
+            return Ok(());
+        };
+        if let Some(capture) = self.apu_mut().stop_capture(recorder.audio_path()) {
+            capture.finish()?;
+        }
+        recorder.finish()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Drains buffered audio into `out`, returning the number of samples written
+    pub fn drain_samples(&mut self, out: &mut [f32]) -> usize {
+        self.apu_mut().drain_samples(out)
+    }
+
+    // Adds a Game Genie code ("SXIOPO") or raw cheat ("0075:09", or
+    // "0075:09:03" to apply only while $0075 holds $03), enabled
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), CheatError> {
+        self.cpu.memory.cheats.add(code)
+    }
+
+    pub fn remove_cheat(&mut self, code: &str) -> bool {
+        self.cpu.memory.cheats.remove(code)
+    }
+
+    pub fn set_cheat_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        self.cpu.memory.cheats.set_enabled(code, enabled)
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cpu.memory.cheats
+    }
+
+    // Freezes RAM at `addr` ($0000-$1FFF or $6000-$7FFF) at `value`, stored
+    // now and held against the game's writes, as for infinite lives. Work
+    // RAM is written directly rather than through the board, which may
+    // decode the address as a register or have its RAM write-protected, in
+    // whichever bank is mapped there; it fails if no RAM is.
+    pub fn freeze(&mut self, addr: u16, value: u8) -> Result<(), CheatError> {
+        let memory = &mut self.cpu.memory;
+        let sram_offset = match addr {
+            0x6000..=0x7FFF => {
+                let offset = memory.mapper().sram_offset(addr);
+                Some(offset.ok_or(CheatError::NotRAM(addr))?)
+            }
+            _ => None,
+        };
+        memory.cheats.freeze(addr, value)?;
+        match sram_offset {
+            Some(offset) => memory
+                .mapper_mut()
+                .cartridge_mut()
+                .write_sram(offset, value),
+            None => memory.ram[addr as usize % 0x0800] = value,
+        }
+        Ok(())
+    }
+
+    // Lets the game write RAM at `addr` again, returning false if it wasn't
+    // frozen
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        self.cpu.memory.cheats.unfreeze(addr)
+    }
+
+    // Makes `source` supply the joypads from now on, starting with the
+    // current frame, in place of the button setters below. A movie being
+    // played still takes precedence.
+    pub fn set_input_source(&mut self, source: impl InputSource + 'static) {
+        self.input = Some(Box::new(source));
+        self.poll_input();
+    }
+
+    // Hands input back to the button setters, returning the source
+    pub fn clear_input_source(&mut self) -> Option<Box<dyn InputSource>> {
+        self.input.take()
+    }
+
+    fn poll_input(&mut self) {
+        self.commands = 0;
+        if self.playing_movie() {
+            return;
+        }
+        let frame = self.ppu().frame();
+        let Some(source) = &mut self.input else {
+            return;
+        };
+        let input = source.next_frame(frame);
+        for (controller, buttons) in self.cpu.memory.controllers.iter_mut().zip(input.buttons) {
+            controller.set_buttons(buttons);
+        }
+        if input.power {
+            self.power_cycle();
+        }
+        if input.reset {
+            self.reset();
+        }
+    }
+
+    // Presses or releases a button on joypad `player`: 0 and 1 are the
+    // ports, 2 and 3 the Four Score's extra pads. An input source overrides
+    // these at the start of each frame.
+    pub fn set_button(&mut self, player: usize, button: Button, pressed: bool) {
+        if let Some(controller) = self.cpu.memory.controllers.get_mut(player) {
+            controller.set_button(button, pressed);
+        }
+    }
+
+    // Sets every button on joypad `player` at once, bit n for Button n
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        if let Some(controller) = self.cpu.memory.controllers.get_mut(player) {
+            controller.set_buttons(buttons);
+        }
+    }
+
+    // Makes `button` on joypad `player` auto-fire while held, `rate` frames
+    // pressed then `rate` released; 0 turns turbo off
+    pub fn set_turbo(&mut self, player: usize, button: Button, rate: u8) {
+        if let Some(controller) = self.cpu.memory.controllers.get_mut(player) {
+            controller.set_turbo(button, rate);
+        }
+    }
+
+    // Switches the Four Score multitap on or off, for 4-player games
+    pub fn set_four_score(&mut self, enabled: bool) {
+        self.cpu.memory.four_score.enabled = enabled;
+    }
+
+    // Plugs a Zapper into port 2, if there isn't one, and aims it at screen
+    // position (x, y); negative or off-screen coordinates point it away
+    pub fn set_zapper(&mut self, x: i32, y: i32, trigger: bool) {
+        let zapper = self.cpu.memory.zapper.get_or_insert_with(Zapper::new);
+        zapper.x = x;
+        zapper.y = y;
+        zapper.trigger = trigger;
+    }
+
+    // The coin slots, service button and DIP switches of a VS. System
+    // game's cabinet
+    pub fn vs_system(&self) -> Option<&VSSystem> {
+        self.cpu.memory.vs.as_ref()
+    }
+
+    pub fn vs_system_mut(&mut self) -> Option<&mut VSSystem> {
+        self.cpu.memory.vs.as_mut()
+    }
+
+    // Drops a coin into slot 0 or 1 of a VS. System cabinet. Does nothing
+    // for other games.
+    pub fn insert_coin(&mut self, slot: usize) {
+        if let Some(vs) = self.vs_system_mut() {
+            vs.insert_coin(slot);
+        }
+    }
+
+    // Unplugs the Zapper, reconnecting the second joypad
+    pub fn remove_zapper(&mut self) {
+        self.cpu.memory.zapper = None;
+    }
+
+    pub fn port_device(&self, port: usize) -> Option<&PortDevice> {
+        self.cpu.memory.ports.get(port)?.as_ref()
+    }
+
+    pub fn port_device_mut(&mut self, port: usize) -> Option<&mut PortDevice> {
+        self.cpu.memory.ports.get_mut(port)?.as_mut()
+    }
+
+    // Plugs `device` into port 0 or 1 in place of its joypad, or puts the
+    // joypad back with `None`. Other ports are ignored.
+    pub fn set_port_device(&mut self, port: usize, device: Option<PortDevice>) {
+        if let Some(slot) = self.cpu.memory.ports.get_mut(port) {
+            *slot = device;
+        }
+    }
+
+    // Blows into the Famicom microphone, or stops
+    pub fn set_microphone(&mut self, blowing: bool) {
+        self.cpu.memory.microphone.blowing = blowing;
+    }
+
+    // Feeds the Famicom microphone a stretch of host microphone input,
+    // -1.0 to 1.0, taking anything louder than its threshold for blowing.
+    // Called once a frame with that frame's samples.
+    pub fn listen_microphone(&mut self, samples: &[f32]) {
+        self.cpu.memory.microphone.listen(samples);
+    }
+
+    pub fn microphone_mut(&mut self) -> &mut Microphone {
+        &mut self.cpu.memory.microphone
+    }
+
+    // Steps on or off a Power Pad button, numbered as printed on the side
+    // facing up, in whichever port the mat is plugged into
+    pub fn set_power_pad(&mut self, button: u8, pressed: bool) {
+        for device in self.cpu.memory.ports.iter_mut().flatten() {
+            if let PortDevice::PowerPad(pad) = device {
+                pad.set_button(button, pressed);
+            }
+        }
+    }
+
+    // Moves the SNES mouse by (dx, dy), right and down positive, and holds
+    // or releases its buttons, in whichever port it is plugged into
+    pub fn set_mouse(&mut self, dx: i32, dy: i32, left: bool, right: bool) {
+        for device in self.cpu.memory.ports.iter_mut().flatten() {
+            if let PortDevice::Mouse(mouse) = device {
+                mouse.move_by(dx, dy);
+                mouse.left = left;
+                mouse.right = right;
+            }
+        }
+    }
+
+    pub fn expansion_device(&self) -> Option<&ExpansionDevice> {
+        self.cpu.memory.expansion.as_ref()
+    }
+
+    // What's held on the expansion device, e.g. the keyboard's keys, is set
+    // through this
+    pub fn expansion_device_mut(&mut self) -> Option<&mut ExpansionDevice> {
+        self.cpu.memory.expansion.as_mut()
+    }
+
+    // Plugs `device` into the Famicom expansion port, or unplugs what's
+    // there with `None`. The Arkanoid paddle for the NES, which goes in
+    // port 2, is plugged in here too.
+    pub fn set_expansion_device(&mut self, device: Option<ExpansionDevice>) {
+        self.cpu.memory.expansion = device;
+    }
+
+    // Presses or releases a key on the Family BASIC keyboard, if plugged in
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        if let Some(ExpansionDevice::FamilyKeyboard(keyboard)) = self.expansion_device_mut() {
+            keyboard.set_key(key, pressed);
+        }
+    }
+
+    // Turns the Arkanoid paddle's knob to `position` and holds or releases
+    // its button, if plugged in
+    pub fn set_vaus(&mut self, position: u8, fire: bool) {
+        if let Some(ExpansionDevice::Vaus(vaus)) = self.expansion_device_mut() {
+            vaus.position = position;
+            vaus.fire = fire;
+        }
+    }
+
+    // The last completed 256x240 frame; `as_raw()` gives the RGBA bytes
+    pub fn framebuffer(&self) -> &RgbaImage {
+        self.ppu().front()
+    }
+
+    // Hash of the last completed frame's palette indexes and emphasis bits,
+    // so it doesn't depend on the palette or video filter. Two consoles fed
+    // the same input should agree frame by frame.
+    pub fn frame_hash(&self) -> u64 {
+        // A line at a time, little-endian, without copying the frame
+        let mut hasher = Xxh3::new();
+        let mut bytes = [0u8; WIDTH as usize * 2];
+        for line in self.ppu().front_pixels().chunks(WIDTH as usize) {
+            for (pair, pixel) in bytes.chunks_exact_mut(2).zip(line) {
+                pair.copy_from_slice(&pixel.to_le_bytes());
+            }
+            hasher.update(&bytes[..line.len() * 2]);
+        }
+        hasher.digest()
+    }
+
+    // Hash of everything a save state holds, for spotting netplay desyncs
+    // without sending whole states
+    pub fn state_hash(&self) -> u64 {
+        xxh3_64(&self.save_state())
+    }
+
+    // Draws frames with `palette`, e.g. Palette::preset(Preset::FBX) or a
+    // .pal file from Palette::from_path
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.ppu_mut().set_palette(palette);
+    }
+
+    pub fn video_filter(&self) -> Filter {
+        self.ppu().video_filter()
+    }
+
+    pub fn video_config(&self) -> VideoConfig {
+        self.video
+    }
+
+    // Sets how screenshots and recordings frame the picture, for frontends
+    // to match. A recording already running keeps the framing it started
+    // with.
+    pub fn set_video_config(&mut self, video: VideoConfig) {
+        self.video = video;
+    }
+
+    // A copy of the last completed frame, framed by the video config and
+    // drawn as `options` say
+    pub fn screenshot(&self, options: ScreenshotOptions) -> RgbaImage {
+        video::screenshot(self.ppu(), &self.video, options)
+    }
+
+    // Saves a screenshot, in the format the extension names (.png, ...)
+    pub fn screenshot_to_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: ScreenshotOptions,
+    ) -> ImageResult<()> {
+        self.screenshot(options).save(path)
+    }
+
+    // Emulates OAM decaying while rendering is off and the OAMADDR quirks of
+    // sprite evaluation, which a few games and test ROMs depend on
+    pub fn set_accurate_oam(&mut self, accurate: bool) {
+        self.ppu_mut().set_accurate_oam(accurate);
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.ppu().pixel_format()
+    }
+
+    // Chooses when pixels become colors, see PixelFormat. With IndexedOnly,
+    // framebuffer() stops updating and ppu().front_pixels() is the frame.
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.ppu_mut().set_pixel_format(format);
+    }
+
+    // Sets the post-processing applied to frames, e.g. Filter::Ntsc for
+    // composite video artifacts. Takes effect from the next frame.
+    pub fn set_video_filter(&mut self, filter: Filter) {
+        self.ppu_mut().set_video_filter(filter);
+    }
+
+    // Memory as RetroAchievements addresses it
+    pub fn achievement_memory(&self) -> AchievementMemory<'_> {
+        AchievementMemory::new(&self.cpu.memory)
+    }
+
+    // Calls `callback` with the memory at the end of every frame shown,
+    // where rcheevos's rc_runtime_do_frame or another evaluator belongs.
+    // Frames run ahead are skipped, so each is seen once.
+    pub fn set_achievement_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&AchievementMemory) + Send + 'static,
+    {
+        self.achievement_callback = Some(Box::new(callback));
+    }
+
+    pub fn clear_achievement_callback(&mut self) {
+        self.achievement_callback = None;
+    }
+
+    // Calls `callback` with every frame the PPU completes
+    pub fn set_frame_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&RgbaImage) + Send + 'static,
+    {
+        self.ppu_mut().set_frame_callback(Box::new(callback));
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_sram() {
+            eprintln!("failed to write battery save: {}", err);
+        }
+    }
+}
@@ -0,0 +1,494 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ppu::{HEIGHT, PPU, WIDTH},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Scanlines the Zapper's photodiode keeps reporting light for after the
+// beam has passed the spot it is aimed at
+const ZAPPER_LIGHT_LINES: i32 = 20;
+// Channel sum (of 765) above which a pixel is bright enough to register
+const ZAPPER_BRIGHTNESS: u32 = 0x180;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    // Every button, indexed by its bit in `Controller::buttons`
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+}
+
+// Standard joypad: a 4021 shift register that latches the buttons while
+// strobe is high and shifts them out one bit per read, in Button order
+#[derive(Default)]
+pub struct Controller {
+    buttons: [bool; 8],
+    index: u8,
+    strobe: u8,
+    // Turbo rate per button in frames, 0 when off: a held turbo button
+    // reads pressed for that many frames, then released for as many
+    turbo: [u8; 8],
+    turbo_frame: u32,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.buttons[button as usize] = pressed;
+    }
+
+    pub fn pressed(&self, button: Button) -> bool {
+        self.buttons[button as usize]
+    }
+
+    // All eight buttons as a byte, bit n set when Button n is held and, for
+    // a turbo button, in the pressed half of its cycle
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, &pressed)| {
+                bits | ((pressed && self.turbo_on(i)) as u8) << i
+            })
+    }
+
+    // Makes `button` auto-fire while held, `rate` frames pressed then as
+    // many released; a rate of 0 turns turbo off
+    pub fn set_turbo(&mut self, button: Button, rate: u8) {
+        self.turbo[button as usize] = rate;
+    }
+
+    pub fn turbo(&self, button: Button) -> u8 {
+        self.turbo[button as usize]
+    }
+
+    // Moves turbo buttons on a frame, once per frame before the game reads
+    // the pad
+    pub fn step_frame(&mut self) {
+        self.turbo_frame = self.turbo_frame.wrapping_add(1);
+    }
+
+    fn turbo_on(&self, i: usize) -> bool {
+        let rate = self.turbo[i] as u32;
+        rate == 0 || (self.turbo_frame / rate).is_multiple_of(2)
+    }
+
+    pub fn set_buttons(&mut self, bits: u8) {
+        for (i, pressed) in self.buttons.iter_mut().enumerate() {
+            *pressed = bits >> i & 1 == 1;
+        }
+    }
+
+    // $4016/$4017 (read)
+    pub fn read(&mut self) -> u8 {
+        // Official pads shift in 1s once all eight buttons have been read
+        let value = match self.index {
+            0..=7 => self.buttons() >> self.index & 1,
+            _ => 1,
+        };
+        if self.strobe & 1 == 1 {
+            self.index = 0;
+        } else if self.index < 8 {
+            self.index += 1;
+        }
+        value
+    }
+
+    // $4016 (write)
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value;
+        if self.strobe & 1 == 1 {
+            self.index = 0;
+        }
+    }
+
+    // Only the shift register is saved; held buttons belong to the frontend
+    pub fn save(&self, state: &mut StateWriter) {
+        state.write(&self.index);
+        state.write(&self.strobe);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.index = state.read()?;
+        self.strobe = state.read()?;
+        Ok(())
+    }
+}
+
+// Four Score multitap. With it switched on, each port shifts out 24 bits:
+// its own joypad, the joypad plugged in behind it (3 or 4), then a
+// signature games check for: a 1 on the 20th read of $4016 and the 19th of
+// $4017.
+#[derive(Default)]
+pub struct FourScore {
+    pub enabled: bool,
+    index: [u8; 2],
+    strobe: u8,
+}
+
+impl FourScore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // $4016/$4017 (read), `port` 0 or 1
+    pub fn read(&mut self, port: usize, controllers: &[Controller; 4]) -> u8 {
+        let index = self.index[port];
+        let value = match index {
+            0..=7 => controllers[port].buttons() >> index & 1,
+            8..=15 => controllers[port + 2].buttons() >> (index - 8) & 1,
+            _ => (index as usize == 19 - port) as u8,
+        };
+        if self.strobe & 1 == 1 {
+            self.index[port] = 0;
+        } else if index < 24 {
+            self.index[port] += 1;
+        }
+        value
+    }
+
+    // $4016 (write)
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value;
+        if self.strobe & 1 == 1 {
+            self.index = [0; 2];
+        }
+    }
+
+    // Whether it's switched on is a setting, so only the shift state is saved
+    pub fn save(&self, state: &mut StateWriter) {
+        state.write(&self.index);
+        state.write(&self.strobe);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.index = state.read()?;
+        self.strobe = state.read()?;
+        Ok(())
+    }
+}
+
+// Light gun. Reads report the trigger in bit 4 and, in bit 3, a 0 while the
+// photodiode sees a bright pixel that the beam has just drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct Zapper {
+    // Screen position aimed at, off-screen when pointed away from the TV
+    pub x: i32,
+    pub y: i32,
+    pub trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Self {
+            x: -1,
+            y: -1,
+            trigger: false,
+        }
+    }
+
+    // $4017 (read)
+    pub fn read(&self, ppu: &PPU) -> u8 {
+        let mut value = 0;
+        if self.trigger {
+            value |= 0x10;
+        }
+        if !self.detects_light(ppu) {
+            value |= 0x08;
+        }
+        value
+    }
+
+    fn detects_light(&self, ppu: &PPU) -> bool {
+        if self.x < 0 || self.y < 0 || self.x >= WIDTH as i32 || self.y >= HEIGHT as i32 {
+            return false;
+        }
+        let lines = ppu.scanline() - self.y;
+        let drawn = match lines {
+            0 => ppu.cycle() > self.x,
+            _ => lines > 0 && lines < ZAPPER_LIGHT_LINES,
+        };
+        if !drawn {
+            return false;
+        }
+        let [r, g, b, _] = ppu.beam_color(self.x as u32, self.y as u32).0;
+        r as u32 + g as u32 + b as u32 >= ZAPPER_BRIGHTNESS
+    }
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const MICROPHONE_THRESHOLD: f32 = 0.25;
+
+// The microphone built into the Famicom's second controller, heard in bit 2
+// of $4016: 1 while the player blows or shouts into it. Zelda's Pols Voice
+// and Kid Icarus's price haggling listen for it.
+pub struct Microphone {
+    pub blowing: bool,
+    // Loudest sample, as a fraction of full scale, that `listen` counts as
+    // quiet
+    pub threshold: f32,
+}
+
+impl Microphone {
+    pub fn new() -> Self {
+        Self {
+            blowing: false,
+            threshold: MICROPHONE_THRESHOLD,
+        }
+    }
+
+    // Hears a stretch of host microphone input, -1.0 to 1.0, blowing if any
+    // sample is louder than the threshold
+    pub fn listen(&mut self, samples: &[f32]) {
+        self.blowing = samples.iter().any(|sample| sample.abs() > self.threshold);
+    }
+
+    // $4016 (read)
+    pub fn read(&self) -> u8 {
+        (self.blowing as u8) << 2
+    }
+}
+
+impl Default for Microphone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Something plugged into a controller port in place of a joypad, strobed
+// through $4016 like one
+pub enum PortDevice {
+    PowerPad(PowerPad),
+    Mouse(SNESMouse),
+}
+
+impl PortDevice {
+    // $4016/$4017 (read), for the port the device is in
+    pub fn read(&mut self) -> u8 {
+        match self {
+            PortDevice::PowerPad(pad) => pad.read(),
+            PortDevice::Mouse(mouse) => mouse.read(),
+        }
+    }
+
+    // $4016 (write)
+    pub fn write(&mut self, value: u8) {
+        match self {
+            PortDevice::PowerPad(pad) => pad.write(value),
+            PortDevice::Mouse(mouse) => mouse.write(value),
+        }
+    }
+
+    // Saved ahead of the device's state, 0 standing for an empty port, so a
+    // state is only loaded into the same devices it was saved from
+    pub fn state_tag(device: Option<&PortDevice>) -> u8 {
+        match device {
+            None => 0,
+            Some(PortDevice::PowerPad(_)) => 1,
+            Some(PortDevice::Mouse(_)) => 2,
+        }
+    }
+
+    // Which device is plugged in is a setting and what's held on it input,
+    // so only the shift registers are saved
+    pub fn save(&self, state: &mut StateWriter) {
+        match self {
+            PortDevice::PowerPad(pad) => state.write(&pad.shift),
+            PortDevice::Mouse(mouse) => state.write(&mouse.shift),
+        }
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        match self {
+            PortDevice::PowerPad(pad) => pad.shift = state.read()?,
+            PortDevice::Mouse(mouse) => mouse.shift = state.read()?,
+        }
+        Ok(())
+    }
+}
+
+// Which way up the Power Pad lies. Side A has twelve buttons in three rows
+// of four; side B, the mat turned over, eight of the same switches under
+// different numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Side {
+    #[default]
+    A,
+    B,
+}
+
+// Side A's number for each of side B's buttons 1-8
+const POWER_PAD_SIDE_B: [u8; 8] = [3, 2, 8, 7, 6, 5, 11, 10];
+// The order side A's buttons shift out on bit 3 and bit 4 of reads
+const POWER_PAD_LOW: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const POWER_PAD_HIGH: [u8; 4] = [4, 3, 12, 8];
+
+// Bandai's floor mat, for World Class Track Meet and the other Family Fun
+// Fitness games. A strobe latches its buttons into two shift registers,
+// read a bit at a time from bits 3 and 4, 1 for pressed and 1s once empty.
+#[derive(Default)]
+pub struct PowerPad {
+    pub side: Side,
+    // Side A's buttons 1-12 in bits 0-11
+    buttons: u16,
+    shift: PowerPadShift,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PowerPadShift {
+    low: u8,
+    high: u8,
+    strobe: bool,
+}
+
+impl PowerPad {
+    pub fn new(side: Side) -> Self {
+        Self {
+            side,
+            ..Self::default()
+        }
+    }
+
+    // Steps on or off `button`, numbered as printed on the side facing up
+    pub fn set_button(&mut self, button: u8, pressed: bool) {
+        let button = match self.side {
+            Side::A if (1..=12).contains(&button) => button,
+            Side::B if (1..=8).contains(&button) => POWER_PAD_SIDE_B[button as usize - 1],
+            _ => return,
+        };
+        let bit = 1 << (button - 1);
+        if pressed {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+    }
+
+    fn latch(&mut self) {
+        let pressed = |order: &[u8]| {
+            order.iter().enumerate().fold(0, |bits, (index, &button)| {
+                bits | ((self.buttons >> (button - 1)) as u8 & 1) << index
+            })
+        };
+        self.shift.low = pressed(&POWER_PAD_LOW);
+        self.shift.high = pressed(&POWER_PAD_HIGH) | 0xF0;
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.shift.strobe {
+            self.latch();
+        }
+        let shift = &mut self.shift;
+        let value = (shift.low & 1) << 3 | (shift.high & 1) << 4;
+        shift.low = shift.low >> 1 | 0x80;
+        shift.high = shift.high >> 1 | 0x80;
+        value
+    }
+
+    fn write(&mut self, value: u8) {
+        self.shift.strobe = value & 0x01 != 0;
+        if self.shift.strobe {
+            self.latch();
+        }
+    }
+}
+
+// The Super NES mouse, used by homebrew through an adapter. Strobing it
+// latches a 32-bit report, shifted out of bit 0 most significant bit first:
+// a zero byte; the right and left buttons, the sensitivity and the
+// signature %0001; then the vertical and horizontal motion since the last
+// report, each a direction bit (up, left) over a 7-bit distance.
+// Reading while strobed steps the sensitivity through its three settings.
+#[derive(Default)]
+pub struct SNESMouse {
+    pub left: bool,
+    pub right: bool,
+    // Motion not yet reported, in mouse counts: right and down positive
+    dx: i32,
+    dy: i32,
+    shift: MouseShift,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct MouseShift {
+    report: u32,
+    strobe: bool,
+    sensitivity: u8,
+}
+
+impl SNESMouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Moves the mouse by (dx, dy), right and down positive
+    pub fn move_by(&mut self, dx: i32, dy: i32) {
+        self.dx = self.dx.saturating_add(dx);
+        self.dy = self.dy.saturating_add(dy);
+    }
+
+    pub fn sensitivity(&self) -> u8 {
+        self.shift.sensitivity
+    }
+
+    fn latch(&mut self) {
+        let axis =
+            |delta: i32, negative: bool| (negative as u32) << 7 | delta.unsigned_abs().min(0x7F);
+        let status = (self.right as u32) << 7
+            | (self.left as u32) << 6
+            | (self.shift.sensitivity as u32) << 4
+            | 0x01;
+        self.shift.report =
+            status << 16 | axis(self.dy, self.dy < 0) << 8 | axis(self.dx, self.dx < 0);
+        self.dx = 0;
+        self.dy = 0;
+    }
+
+    fn read(&mut self) -> u8 {
+        let shift = &mut self.shift;
+        if shift.strobe {
+            // The report's first bit, always 0
+            shift.sensitivity = (shift.sensitivity + 1) % 3;
+            return 0;
+        }
+        let value = (shift.report >> 31) as u8;
+        shift.report = shift.report << 1 | 1;
+        value
+    }
+
+    fn write(&mut self, value: u8) {
+        let strobe = value & 0x01 != 0;
+        // The report is taken as the strobe falls
+        if self.shift.strobe && !strobe {
+            self.latch();
+        }
+        self.shift.strobe = strobe;
+    }
+}
@@ -1,11 +1,24 @@
-pub static CPUFREQ: usize = 1789773;
+use std::io::{self, Write as _};
 
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::Event,
+    memory::{CPUMemory, Memory},
+    profiler::Profiler,
+    savestate::{StateError, StateReader, StateWriter},
+    symbols::Symbols,
+    trace,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IRQ {
     Normal,
     NMI,
     RESET,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AddressMode {
     Absolute,
     AbsoluteX,
@@ -22,4 +35,1686 @@ pub enum AddressMode {
     ZeroPageY,
 }
 
-pub struct CPU {}
+impl AddressMode {
+    // Number of bytes taken by an instruction using this mode, opcode included
+    pub fn size(self) -> u16 {
+        match self {
+            AddressMode::Accumulator | AddressMode::Implied => 1,
+            AddressMode::Absolute
+            | AddressMode::AbsoluteX
+            | AddressMode::AbsoluteY
+            | AddressMode::Indirect => 3,
+            _ => 2,
+        }
+    }
+}
+
+// What an instruction does with its operand. Together with the address mode
+// this decides the bus cycles it runs.
+#[derive(Clone, Copy)]
+enum Operation {
+    // Reads the operand: loads, arithmetic and compares
+    Read(fn(&mut CPU, u8)),
+    // Writes the value returned: stores
+    Write(fn(&mut CPU) -> u8),
+    // Reads the operand, writes it back unchanged while working on it, then
+    // writes the result: shifts, INC and DEC. On the accumulator, just the
+    // result.
+    Modify(fn(&mut CPU, u8) -> u8),
+    // Works on registers alone
+    Internal(fn(&mut CPU)),
+    // Branches when the condition holds
+    Branch(fn(&CPU) -> bool),
+    Push(fn(&CPU) -> u8),
+    Pull(fn(&mut CPU, u8)),
+    Jmp,
+    Jsr,
+    Rts,
+    Rti,
+    Brk,
+}
+
+// One cycle of an instruction, each making exactly one bus access. Reads
+// the CPU doesn't need are still made, as the hardware makes them, since
+// registers like $2002, $2007 and $4016 act on reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Micro {
+    // address = byte at pc
+    FetchLow,
+    // Completes address with the byte at pc
+    FetchHigh,
+    // Completes the base address with the byte at pc, then adds X or Y
+    FetchHighX,
+    FetchHighY,
+    // pointer = byte at pc
+    FetchPointer,
+    // Dummy read through the pointer while adding X, within the zero page
+    IndexPointer,
+    // Dummy read at address while adding X or Y, within the zero page
+    IndexZeroPageX,
+    IndexZeroPageY,
+    // Read the address through the pointer, wrapping in the zero page
+    PointerLow,
+    PointerHigh,
+    // As PointerHigh, then adds Y
+    PointerHighY,
+    // Read of the indexed address before any page carry is added. A read
+    // instruction that didn't cross a page is done here.
+    ReadIndexed,
+    // As ReadIndexed for writes and read-modify-writes, which always take
+    // the carry cycle
+    DummyIndexed,
+    ReadOperand,
+    WriteOperand,
+    // The three cycles of read-modify-write: read, write back the old value
+    // while modifying, write the new one
+    ReadData,
+    ModifyData,
+    WriteData,
+    ReadImmediate,
+    // Dummy read of the next byte while working on registers
+    RunInternal,
+    // Reads the offset, finishing if the branch isn't taken
+    FetchOffset,
+    // Dummy read while adding the offset to PCL, finishing unless that
+    // crossed a page
+    TakeBranch,
+    // Dummy read at the uncorrected target while fixing PCH
+    FixBranchPage,
+    // pc = byte at pc : address
+    JumpHigh,
+    // JMP (indirect), whose high byte doesn't carry into the next page
+    JumpPointerLow,
+    JumpPointerHigh,
+    DummyPC,
+    DummyStack,
+    // BRK reads and skips the byte after its opcode
+    SkipPadding,
+    IncrementPC,
+    PushPCH,
+    PushPCL,
+    // Pushes the flags with B set for BRK, clear for IRQ and NMI
+    PushFlags(bool),
+    PushValue,
+    PullFlags,
+    PullPCL,
+    PullPCH,
+    PullValue,
+    VectorLow,
+    VectorHigh,
+}
+
+impl Micro {
+    // DMA can only halt the CPU on a read cycle
+    fn writes(self) -> bool {
+        matches!(
+            self,
+            Micro::WriteOperand
+                | Micro::ModifyData
+                | Micro::WriteData
+                | Micro::PushPCH
+                | Micro::PushPCL
+                | Micro::PushFlags(_)
+                | Micro::PushValue
+        )
+    }
+}
+
+use Micro::*;
+
+// The cycles after the opcode fetch for each kind of instruction
+const INTERNAL: &[Micro] = &[RunInternal];
+const IMMEDIATE: &[Micro] = &[ReadImmediate];
+const READ: &[Micro] = &[ReadOperand];
+const WRITE: &[Micro] = &[WriteOperand];
+const MODIFY: &[Micro] = &[ReadData, ModifyData, WriteData];
+const ZERO_PAGE_READ: &[Micro] = &[FetchLow, ReadOperand];
+const ZERO_PAGE_WRITE: &[Micro] = &[FetchLow, WriteOperand];
+const ZERO_PAGE_MODIFY: &[Micro] = &[FetchLow, ReadData, ModifyData, WriteData];
+const ZERO_PAGE_X_READ: &[Micro] = &[FetchLow, IndexZeroPageX, ReadOperand];
+const ZERO_PAGE_X_WRITE: &[Micro] = &[FetchLow, IndexZeroPageX, WriteOperand];
+const ZERO_PAGE_X_MODIFY: &[Micro] = &[FetchLow, IndexZeroPageX, ReadData, ModifyData, WriteData];
+const ZERO_PAGE_Y_READ: &[Micro] = &[FetchLow, IndexZeroPageY, ReadOperand];
+const ZERO_PAGE_Y_WRITE: &[Micro] = &[FetchLow, IndexZeroPageY, WriteOperand];
+const ABSOLUTE_READ: &[Micro] = &[FetchLow, FetchHigh, ReadOperand];
+const ABSOLUTE_WRITE: &[Micro] = &[FetchLow, FetchHigh, WriteOperand];
+const ABSOLUTE_MODIFY: &[Micro] = &[FetchLow, FetchHigh, ReadData, ModifyData, WriteData];
+const ABSOLUTE_X_READ: &[Micro] = &[FetchLow, FetchHighX, ReadIndexed, ReadOperand];
+const ABSOLUTE_X_WRITE: &[Micro] = &[FetchLow, FetchHighX, DummyIndexed, WriteOperand];
+const ABSOLUTE_X_MODIFY: &[Micro] = &[
+    FetchLow,
+    FetchHighX,
+    DummyIndexed,
+    ReadData,
+    ModifyData,
+    WriteData,
+];
+const ABSOLUTE_Y_READ: &[Micro] = &[FetchLow, FetchHighY, ReadIndexed, ReadOperand];
+const ABSOLUTE_Y_WRITE: &[Micro] = &[FetchLow, FetchHighY, DummyIndexed, WriteOperand];
+const ABSOLUTE_Y_MODIFY: &[Micro] = &[
+    FetchLow,
+    FetchHighY,
+    DummyIndexed,
+    ReadData,
+    ModifyData,
+    WriteData,
+];
+const INDEXED_INDIRECT_READ: &[Micro] = &[
+    FetchPointer,
+    IndexPointer,
+    PointerLow,
+    PointerHigh,
+    ReadOperand,
+];
+const INDEXED_INDIRECT_WRITE: &[Micro] = &[
+    FetchPointer,
+    IndexPointer,
+    PointerLow,
+    PointerHigh,
+    WriteOperand,
+];
+const INDEXED_INDIRECT_MODIFY: &[Micro] = &[
+    FetchPointer,
+    IndexPointer,
+    PointerLow,
+    PointerHigh,
+    ReadData,
+    ModifyData,
+    WriteData,
+];
+const INDIRECT_INDEXED_READ: &[Micro] = &[
+    FetchPointer,
+    PointerLow,
+    PointerHighY,
+    ReadIndexed,
+    ReadOperand,
+];
+const INDIRECT_INDEXED_WRITE: &[Micro] = &[
+    FetchPointer,
+    PointerLow,
+    PointerHighY,
+    DummyIndexed,
+    WriteOperand,
+];
+const INDIRECT_INDEXED_MODIFY: &[Micro] = &[
+    FetchPointer,
+    PointerLow,
+    PointerHighY,
+    DummyIndexed,
+    ReadData,
+    ModifyData,
+    WriteData,
+];
+const BRANCH: &[Micro] = &[FetchOffset, TakeBranch, FixBranchPage];
+const JMP_ABSOLUTE: &[Micro] = &[FetchLow, JumpHigh];
+const JMP_INDIRECT: &[Micro] = &[FetchLow, FetchHigh, JumpPointerLow, JumpPointerHigh];
+const JSR: &[Micro] = &[FetchLow, DummyStack, PushPCH, PushPCL, JumpHigh];
+const RTS: &[Micro] = &[DummyPC, DummyStack, PullPCL, PullPCH, IncrementPC];
+const RTI: &[Micro] = &[DummyPC, DummyStack, PullFlags, PullPCL, PullPCH];
+const BRK: &[Micro] = &[
+    SkipPadding,
+    PushPCH,
+    PushPCL,
+    PushFlags(true),
+    VectorLow,
+    VectorHigh,
+];
+const PUSH: &[Micro] = &[DummyPC, PushValue];
+const PULL: &[Micro] = &[DummyPC, DummyStack, PullValue];
+// IRQ and NMI take the place of an opcode fetch, so run all 7 cycles
+const INTERRUPT: &[Micro] = &[
+    DummyPC,
+    DummyPC,
+    PushPCH,
+    PushPCL,
+    PushFlags(false),
+    VectorLow,
+    VectorHigh,
+];
+
+const fn micro_ops(mode: AddressMode, operation: Operation) -> &'static [Micro] {
+    match (operation, mode) {
+        (Internal(_), _) | (Modify(_), Accumulator) => INTERNAL,
+        (Read(_), Immediate) => IMMEDIATE,
+        (Branch(_), _) => BRANCH,
+        (Jmp, Indirect) => JMP_INDIRECT,
+        (Jmp, _) => JMP_ABSOLUTE,
+        (Jsr, _) => JSR,
+        (Rts, _) => RTS,
+        (Rti, _) => RTI,
+        (Brk, _) => BRK,
+        (Push(_), _) => PUSH,
+        (Pull(_), _) => PULL,
+        (Read(_), ZeroPage) => ZERO_PAGE_READ,
+        (Write(_), ZeroPage) => ZERO_PAGE_WRITE,
+        (Modify(_), ZeroPage) => ZERO_PAGE_MODIFY,
+        (Read(_), ZeroPageX) => ZERO_PAGE_X_READ,
+        (Write(_), ZeroPageX) => ZERO_PAGE_X_WRITE,
+        (Modify(_), ZeroPageX) => ZERO_PAGE_X_MODIFY,
+        (Read(_), ZeroPageY) => ZERO_PAGE_Y_READ,
+        (Write(_), ZeroPageY) => ZERO_PAGE_Y_WRITE,
+        (Read(_), Absolute) => ABSOLUTE_READ,
+        (Write(_), Absolute) => ABSOLUTE_WRITE,
+        (Modify(_), Absolute) => ABSOLUTE_MODIFY,
+        (Read(_), AbsoluteX) => ABSOLUTE_X_READ,
+        (Write(_), AbsoluteX) => ABSOLUTE_X_WRITE,
+        (Modify(_), AbsoluteX) => ABSOLUTE_X_MODIFY,
+        (Read(_), AbsoluteY) => ABSOLUTE_Y_READ,
+        (Write(_), AbsoluteY) => ABSOLUTE_Y_WRITE,
+        (Modify(_), AbsoluteY) => ABSOLUTE_Y_MODIFY,
+        (Read(_), IndexedIndirect) => INDEXED_INDIRECT_READ,
+        (Write(_), IndexedIndirect) => INDEXED_INDIRECT_WRITE,
+        (Modify(_), IndexedIndirect) => INDEXED_INDIRECT_MODIFY,
+        (Read(_), IndirectIndexed) => INDIRECT_INDEXED_READ,
+        (Write(_), IndirectIndexed) => INDIRECT_INDEXED_WRITE,
+        (Modify(_), IndirectIndexed) => INDIRECT_INDEXED_MODIFY,
+        (Read(_), _) => READ,
+        (Write(_), _) => WRITE,
+        (Modify(_), _) => MODIFY,
+    }
+}
+
+pub struct Instruction {
+    pub name: &'static str,
+    pub mode: AddressMode,
+    // Documented timing, which running `micro` reproduces
+    pub cycles: u64,
+    // Extra cycles taken when the effective address crosses a page
+    pub page_cycles: u64,
+    pub official: bool,
+    operation: Operation,
+    micro: &'static [Micro],
+}
+
+const fn op(
+    name: &'static str,
+    mode: AddressMode,
+    cycles: u64,
+    page_cycles: u64,
+    operation: Operation,
+) -> Instruction {
+    Instruction {
+        name,
+        mode,
+        cycles,
+        page_cycles,
+        official: true,
+        operation,
+        micro: micro_ops(mode, operation),
+    }
+}
+
+// The stable unofficial opcodes do what they do on hardware; the unstable
+// ones use the commonly measured behaviour. KIL runs as a NOP rather than
+// jamming the CPU.
+const fn unofficial(
+    name: &'static str,
+    mode: AddressMode,
+    cycles: u64,
+    page_cycles: u64,
+    operation: Operation,
+) -> Instruction {
+    let mut instruction = op(name, mode, cycles, page_cycles, operation);
+    instruction.official = false;
+    instruction
+}
+
+use AddressMode::*;
+use Operation::*;
+
+pub static INSTRUCTIONS: [Instruction; 256] = [
+    /* 00 */ op("BRK", Implied, 7, 0, Brk),
+    /* 01 */ op("ORA", IndexedIndirect, 6, 0, Read(CPU::ora)),
+    /* 02 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 03 */ unofficial("SLO", IndexedIndirect, 8, 0, Modify(CPU::slo)),
+    /* 04 */ unofficial("NOP", ZeroPage, 3, 0, Read(CPU::nop_read)),
+    /* 05 */ op("ORA", ZeroPage, 3, 0, Read(CPU::ora)),
+    /* 06 */ op("ASL", ZeroPage, 5, 0, Modify(CPU::asl)),
+    /* 07 */ unofficial("SLO", ZeroPage, 5, 0, Modify(CPU::slo)),
+    /* 08 */ op("PHP", Implied, 3, 0, Push(CPU::php)),
+    /* 09 */ op("ORA", Immediate, 2, 0, Read(CPU::ora)),
+    /* 0A */ op("ASL", Accumulator, 2, 0, Modify(CPU::asl)),
+    /* 0B */ unofficial("ANC", Immediate, 2, 0, Read(CPU::anc)),
+    /* 0C */ unofficial("NOP", Absolute, 4, 0, Read(CPU::nop_read)),
+    /* 0D */ op("ORA", Absolute, 4, 0, Read(CPU::ora)),
+    /* 0E */ op("ASL", Absolute, 6, 0, Modify(CPU::asl)),
+    /* 0F */ unofficial("SLO", Absolute, 6, 0, Modify(CPU::slo)),
+    /* 10 */ op("BPL", Relative, 2, 1, Branch(CPU::bpl)),
+    /* 11 */ op("ORA", IndirectIndexed, 5, 1, Read(CPU::ora)),
+    /* 12 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 13 */ unofficial("SLO", IndirectIndexed, 8, 0, Modify(CPU::slo)),
+    /* 14 */ unofficial("NOP", ZeroPageX, 4, 0, Read(CPU::nop_read)),
+    /* 15 */ op("ORA", ZeroPageX, 4, 0, Read(CPU::ora)),
+    /* 16 */ op("ASL", ZeroPageX, 6, 0, Modify(CPU::asl)),
+    /* 17 */ unofficial("SLO", ZeroPageX, 6, 0, Modify(CPU::slo)),
+    /* 18 */ op("CLC", Implied, 2, 0, Internal(CPU::clc)),
+    /* 19 */ op("ORA", AbsoluteY, 4, 1, Read(CPU::ora)),
+    /* 1A */ unofficial("NOP", Implied, 2, 0, Internal(CPU::nop)),
+    /* 1B */ unofficial("SLO", AbsoluteY, 7, 0, Modify(CPU::slo)),
+    /* 1C */ unofficial("NOP", AbsoluteX, 4, 1, Read(CPU::nop_read)),
+    /* 1D */ op("ORA", AbsoluteX, 4, 1, Read(CPU::ora)),
+    /* 1E */ op("ASL", AbsoluteX, 7, 0, Modify(CPU::asl)),
+    /* 1F */ unofficial("SLO", AbsoluteX, 7, 0, Modify(CPU::slo)),
+    /* 20 */ op("JSR", Absolute, 6, 0, Jsr),
+    /* 21 */ op("AND", IndexedIndirect, 6, 0, Read(CPU::and)),
+    /* 22 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 23 */ unofficial("RLA", IndexedIndirect, 8, 0, Modify(CPU::rla)),
+    /* 24 */ op("BIT", ZeroPage, 3, 0, Read(CPU::bit)),
+    /* 25 */ op("AND", ZeroPage, 3, 0, Read(CPU::and)),
+    /* 26 */ op("ROL", ZeroPage, 5, 0, Modify(CPU::rol)),
+    /* 27 */ unofficial("RLA", ZeroPage, 5, 0, Modify(CPU::rla)),
+    /* 28 */ op("PLP", Implied, 4, 0, Pull(CPU::plp)),
+    /* 29 */ op("AND", Immediate, 2, 0, Read(CPU::and)),
+    /* 2A */ op("ROL", Accumulator, 2, 0, Modify(CPU::rol)),
+    /* 2B */ unofficial("ANC", Immediate, 2, 0, Read(CPU::anc)),
+    /* 2C */ op("BIT", Absolute, 4, 0, Read(CPU::bit)),
+    /* 2D */ op("AND", Absolute, 4, 0, Read(CPU::and)),
+    /* 2E */ op("ROL", Absolute, 6, 0, Modify(CPU::rol)),
+    /* 2F */ unofficial("RLA", Absolute, 6, 0, Modify(CPU::rla)),
+    /* 30 */ op("BMI", Relative, 2, 1, Branch(CPU::bmi)),
+    /* 31 */ op("AND", IndirectIndexed, 5, 1, Read(CPU::and)),
+    /* 32 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 33 */ unofficial("RLA", IndirectIndexed, 8, 0, Modify(CPU::rla)),
+    /* 34 */ unofficial("NOP", ZeroPageX, 4, 0, Read(CPU::nop_read)),
+    /* 35 */ op("AND", ZeroPageX, 4, 0, Read(CPU::and)),
+    /* 36 */ op("ROL", ZeroPageX, 6, 0, Modify(CPU::rol)),
+    /* 37 */ unofficial("RLA", ZeroPageX, 6, 0, Modify(CPU::rla)),
+    /* 38 */ op("SEC", Implied, 2, 0, Internal(CPU::sec)),
+    /* 39 */ op("AND", AbsoluteY, 4, 1, Read(CPU::and)),
+    /* 3A */ unofficial("NOP", Implied, 2, 0, Internal(CPU::nop)),
+    /* 3B */ unofficial("RLA", AbsoluteY, 7, 0, Modify(CPU::rla)),
+    /* 3C */ unofficial("NOP", AbsoluteX, 4, 1, Read(CPU::nop_read)),
+    /* 3D */ op("AND", AbsoluteX, 4, 1, Read(CPU::and)),
+    /* 3E */ op("ROL", AbsoluteX, 7, 0, Modify(CPU::rol)),
+    /* 3F */ unofficial("RLA", AbsoluteX, 7, 0, Modify(CPU::rla)),
+    /* 40 */ op("RTI", Implied, 6, 0, Rti),
+    /* 41 */ op("EOR", IndexedIndirect, 6, 0, Read(CPU::eor)),
+    /* 42 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 43 */ unofficial("SRE", IndexedIndirect, 8, 0, Modify(CPU::sre)),
+    /* 44 */ unofficial("NOP", ZeroPage, 3, 0, Read(CPU::nop_read)),
+    /* 45 */ op("EOR", ZeroPage, 3, 0, Read(CPU::eor)),
+    /* 46 */ op("LSR", ZeroPage, 5, 0, Modify(CPU::lsr)),
+    /* 47 */ unofficial("SRE", ZeroPage, 5, 0, Modify(CPU::sre)),
+    /* 48 */ op("PHA", Implied, 3, 0, Push(CPU::pha)),
+    /* 49 */ op("EOR", Immediate, 2, 0, Read(CPU::eor)),
+    /* 4A */ op("LSR", Accumulator, 2, 0, Modify(CPU::lsr)),
+    /* 4B */ unofficial("ALR", Immediate, 2, 0, Read(CPU::alr)),
+    /* 4C */ op("JMP", Absolute, 3, 0, Jmp),
+    /* 4D */ op("EOR", Absolute, 4, 0, Read(CPU::eor)),
+    /* 4E */ op("LSR", Absolute, 6, 0, Modify(CPU::lsr)),
+    /* 4F */ unofficial("SRE", Absolute, 6, 0, Modify(CPU::sre)),
+    /* 50 */ op("BVC", Relative, 2, 1, Branch(CPU::bvc)),
+    /* 51 */ op("EOR", IndirectIndexed, 5, 1, Read(CPU::eor)),
+    /* 52 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 53 */ unofficial("SRE", IndirectIndexed, 8, 0, Modify(CPU::sre)),
+    /* 54 */ unofficial("NOP", ZeroPageX, 4, 0, Read(CPU::nop_read)),
+    /* 55 */ op("EOR", ZeroPageX, 4, 0, Read(CPU::eor)),
+    /* 56 */ op("LSR", ZeroPageX, 6, 0, Modify(CPU::lsr)),
+    /* 57 */ unofficial("SRE", ZeroPageX, 6, 0, Modify(CPU::sre)),
+    /* 58 */ op("CLI", Implied, 2, 0, Internal(CPU::cli)),
+    /* 59 */ op("EOR", AbsoluteY, 4, 1, Read(CPU::eor)),
+    /* 5A */ unofficial("NOP", Implied, 2, 0, Internal(CPU::nop)),
+    /* 5B */ unofficial("SRE", AbsoluteY, 7, 0, Modify(CPU::sre)),
+    /* 5C */ unofficial("NOP", AbsoluteX, 4, 1, Read(CPU::nop_read)),
+    /* 5D */ op("EOR", AbsoluteX, 4, 1, Read(CPU::eor)),
+    /* 5E */ op("LSR", AbsoluteX, 7, 0, Modify(CPU::lsr)),
+    /* 5F */ unofficial("SRE", AbsoluteX, 7, 0, Modify(CPU::sre)),
+    /* 60 */ op("RTS", Implied, 6, 0, Rts),
+    /* 61 */ op("ADC", IndexedIndirect, 6, 0, Read(CPU::adc)),
+    /* 62 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 63 */ unofficial("RRA", IndexedIndirect, 8, 0, Modify(CPU::rra)),
+    /* 64 */ unofficial("NOP", ZeroPage, 3, 0, Read(CPU::nop_read)),
+    /* 65 */ op("ADC", ZeroPage, 3, 0, Read(CPU::adc)),
+    /* 66 */ op("ROR", ZeroPage, 5, 0, Modify(CPU::ror)),
+    /* 67 */ unofficial("RRA", ZeroPage, 5, 0, Modify(CPU::rra)),
+    /* 68 */ op("PLA", Implied, 4, 0, Pull(CPU::pla)),
+    /* 69 */ op("ADC", Immediate, 2, 0, Read(CPU::adc)),
+    /* 6A */ op("ROR", Accumulator, 2, 0, Modify(CPU::ror)),
+    /* 6B */ unofficial("ARR", Immediate, 2, 0, Read(CPU::arr)),
+    /* 6C */ op("JMP", Indirect, 5, 0, Jmp),
+    /* 6D */ op("ADC", Absolute, 4, 0, Read(CPU::adc)),
+    /* 6E */ op("ROR", Absolute, 6, 0, Modify(CPU::ror)),
+    /* 6F */ unofficial("RRA", Absolute, 6, 0, Modify(CPU::rra)),
+    /* 70 */ op("BVS", Relative, 2, 1, Branch(CPU::bvs)),
+    /* 71 */ op("ADC", IndirectIndexed, 5, 1, Read(CPU::adc)),
+    /* 72 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 73 */ unofficial("RRA", IndirectIndexed, 8, 0, Modify(CPU::rra)),
+    /* 74 */ unofficial("NOP", ZeroPageX, 4, 0, Read(CPU::nop_read)),
+    /* 75 */ op("ADC", ZeroPageX, 4, 0, Read(CPU::adc)),
+    /* 76 */ op("ROR", ZeroPageX, 6, 0, Modify(CPU::ror)),
+    /* 77 */ unofficial("RRA", ZeroPageX, 6, 0, Modify(CPU::rra)),
+    /* 78 */ op("SEI", Implied, 2, 0, Internal(CPU::sei)),
+    /* 79 */ op("ADC", AbsoluteY, 4, 1, Read(CPU::adc)),
+    /* 7A */ unofficial("NOP", Implied, 2, 0, Internal(CPU::nop)),
+    /* 7B */ unofficial("RRA", AbsoluteY, 7, 0, Modify(CPU::rra)),
+    /* 7C */ unofficial("NOP", AbsoluteX, 4, 1, Read(CPU::nop_read)),
+    /* 7D */ op("ADC", AbsoluteX, 4, 1, Read(CPU::adc)),
+    /* 7E */ op("ROR", AbsoluteX, 7, 0, Modify(CPU::ror)),
+    /* 7F */ unofficial("RRA", AbsoluteX, 7, 0, Modify(CPU::rra)),
+    /* 80 */ unofficial("NOP", Immediate, 2, 0, Read(CPU::nop_read)),
+    /* 81 */ op("STA", IndexedIndirect, 6, 0, Write(CPU::sta)),
+    /* 82 */ unofficial("NOP", Immediate, 2, 0, Read(CPU::nop_read)),
+    /* 83 */ unofficial("SAX", IndexedIndirect, 6, 0, Write(CPU::sax)),
+    /* 84 */ op("STY", ZeroPage, 3, 0, Write(CPU::sty)),
+    /* 85 */ op("STA", ZeroPage, 3, 0, Write(CPU::sta)),
+    /* 86 */ op("STX", ZeroPage, 3, 0, Write(CPU::stx)),
+    /* 87 */ unofficial("SAX", ZeroPage, 3, 0, Write(CPU::sax)),
+    /* 88 */ op("DEY", Implied, 2, 0, Internal(CPU::dey)),
+    /* 89 */ unofficial("NOP", Immediate, 2, 0, Read(CPU::nop_read)),
+    /* 8A */ op("TXA", Implied, 2, 0, Internal(CPU::txa)),
+    /* 8B */ unofficial("XAA", Immediate, 2, 0, Read(CPU::xaa)),
+    /* 8C */ op("STY", Absolute, 4, 0, Write(CPU::sty)),
+    /* 8D */ op("STA", Absolute, 4, 0, Write(CPU::sta)),
+    /* 8E */ op("STX", Absolute, 4, 0, Write(CPU::stx)),
+    /* 8F */ unofficial("SAX", Absolute, 4, 0, Write(CPU::sax)),
+    /* 90 */ op("BCC", Relative, 2, 1, Branch(CPU::bcc)),
+    /* 91 */ op("STA", IndirectIndexed, 6, 0, Write(CPU::sta)),
+    /* 92 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* 93 */ unofficial("AHX", IndirectIndexed, 6, 0, Write(CPU::ahx)),
+    /* 94 */ op("STY", ZeroPageX, 4, 0, Write(CPU::sty)),
+    /* 95 */ op("STA", ZeroPageX, 4, 0, Write(CPU::sta)),
+    /* 96 */ op("STX", ZeroPageY, 4, 0, Write(CPU::stx)),
+    /* 97 */ unofficial("SAX", ZeroPageY, 4, 0, Write(CPU::sax)),
+    /* 98 */ op("TYA", Implied, 2, 0, Internal(CPU::tya)),
+    /* 99 */ op("STA", AbsoluteY, 5, 0, Write(CPU::sta)),
+    /* 9A */ op("TXS", Implied, 2, 0, Internal(CPU::txs)),
+    /* 9B */ unofficial("TAS", AbsoluteY, 5, 0, Write(CPU::tas)),
+    /* 9C */ unofficial("SHY", AbsoluteX, 5, 0, Write(CPU::shy)),
+    /* 9D */ op("STA", AbsoluteX, 5, 0, Write(CPU::sta)),
+    /* 9E */ unofficial("SHX", AbsoluteY, 5, 0, Write(CPU::shx)),
+    /* 9F */ unofficial("AHX", AbsoluteY, 5, 0, Write(CPU::ahx)),
+    /* A0 */ op("LDY", Immediate, 2, 0, Read(CPU::ldy)),
+    /* A1 */ op("LDA", IndexedIndirect, 6, 0, Read(CPU::lda)),
+    /* A2 */ op("LDX", Immediate, 2, 0, Read(CPU::ldx)),
+    /* A3 */ unofficial("LAX", IndexedIndirect, 6, 0, Read(CPU::lax)),
+    /* A4 */ op("LDY", ZeroPage, 3, 0, Read(CPU::ldy)),
+    /* A5 */ op("LDA", ZeroPage, 3, 0, Read(CPU::lda)),
+    /* A6 */ op("LDX", ZeroPage, 3, 0, Read(CPU::ldx)),
+    /* A7 */ unofficial("LAX", ZeroPage, 3, 0, Read(CPU::lax)),
+    /* A8 */ op("TAY", Implied, 2, 0, Internal(CPU::tay)),
+    /* A9 */ op("LDA", Immediate, 2, 0, Read(CPU::lda)),
+    /* AA */ op("TAX", Implied, 2, 0, Internal(CPU::tax)),
+    /* AB */ unofficial("LAX", Immediate, 2, 0, Read(CPU::lax)),
+    /* AC */ op("LDY", Absolute, 4, 0, Read(CPU::ldy)),
+    /* AD */ op("LDA", Absolute, 4, 0, Read(CPU::lda)),
+    /* AE */ op("LDX", Absolute, 4, 0, Read(CPU::ldx)),
+    /* AF */ unofficial("LAX", Absolute, 4, 0, Read(CPU::lax)),
+    /* B0 */ op("BCS", Relative, 2, 1, Branch(CPU::bcs)),
+    /* B1 */ op("LDA", IndirectIndexed, 5, 1, Read(CPU::lda)),
+    /* B2 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* B3 */ unofficial("LAX", IndirectIndexed, 5, 1, Read(CPU::lax)),
+    /* B4 */ op("LDY", ZeroPageX, 4, 0, Read(CPU::ldy)),
+    /* B5 */ op("LDA", ZeroPageX, 4, 0, Read(CPU::lda)),
+    /* B6 */ op("LDX", ZeroPageY, 4, 0, Read(CPU::ldx)),
+    /* B7 */ unofficial("LAX", ZeroPageY, 4, 0, Read(CPU::lax)),
+    /* B8 */ op("CLV", Implied, 2, 0, Internal(CPU::clv)),
+    /* B9 */ op("LDA", AbsoluteY, 4, 1, Read(CPU::lda)),
+    /* BA */ op("TSX", Implied, 2, 0, Internal(CPU::tsx)),
+    /* BB */ unofficial("LAS", AbsoluteY, 4, 1, Read(CPU::las)),
+    /* BC */ op("LDY", AbsoluteX, 4, 1, Read(CPU::ldy)),
+    /* BD */ op("LDA", AbsoluteX, 4, 1, Read(CPU::lda)),
+    /* BE */ op("LDX", AbsoluteY, 4, 1, Read(CPU::ldx)),
+    /* BF */ unofficial("LAX", AbsoluteY, 4, 1, Read(CPU::lax)),
+    /* C0 */ op("CPY", Immediate, 2, 0, Read(CPU::cpy)),
+    /* C1 */ op("CMP", IndexedIndirect, 6, 0, Read(CPU::cmp)),
+    /* C2 */ unofficial("NOP", Immediate, 2, 0, Read(CPU::nop_read)),
+    /* C3 */ unofficial("DCP", IndexedIndirect, 8, 0, Modify(CPU::dcp)),
+    /* C4 */ op("CPY", ZeroPage, 3, 0, Read(CPU::cpy)),
+    /* C5 */ op("CMP", ZeroPage, 3, 0, Read(CPU::cmp)),
+    /* C6 */ op("DEC", ZeroPage, 5, 0, Modify(CPU::dec)),
+    /* C7 */ unofficial("DCP", ZeroPage, 5, 0, Modify(CPU::dcp)),
+    /* C8 */ op("INY", Implied, 2, 0, Internal(CPU::iny)),
+    /* C9 */ op("CMP", Immediate, 2, 0, Read(CPU::cmp)),
+    /* CA */ op("DEX", Implied, 2, 0, Internal(CPU::dex)),
+    /* CB */ unofficial("AXS", Immediate, 2, 0, Read(CPU::axs)),
+    /* CC */ op("CPY", Absolute, 4, 0, Read(CPU::cpy)),
+    /* CD */ op("CMP", Absolute, 4, 0, Read(CPU::cmp)),
+    /* CE */ op("DEC", Absolute, 6, 0, Modify(CPU::dec)),
+    /* CF */ unofficial("DCP", Absolute, 6, 0, Modify(CPU::dcp)),
+    /* D0 */ op("BNE", Relative, 2, 1, Branch(CPU::bne)),
+    /* D1 */ op("CMP", IndirectIndexed, 5, 1, Read(CPU::cmp)),
+    /* D2 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* D3 */ unofficial("DCP", IndirectIndexed, 8, 0, Modify(CPU::dcp)),
+    /* D4 */ unofficial("NOP", ZeroPageX, 4, 0, Read(CPU::nop_read)),
+    /* D5 */ op("CMP", ZeroPageX, 4, 0, Read(CPU::cmp)),
+    /* D6 */ op("DEC", ZeroPageX, 6, 0, Modify(CPU::dec)),
+    /* D7 */ unofficial("DCP", ZeroPageX, 6, 0, Modify(CPU::dcp)),
+    /* D8 */ op("CLD", Implied, 2, 0, Internal(CPU::cld)),
+    /* D9 */ op("CMP", AbsoluteY, 4, 1, Read(CPU::cmp)),
+    /* DA */ unofficial("NOP", Implied, 2, 0, Internal(CPU::nop)),
+    /* DB */ unofficial("DCP", AbsoluteY, 7, 0, Modify(CPU::dcp)),
+    /* DC */ unofficial("NOP", AbsoluteX, 4, 1, Read(CPU::nop_read)),
+    /* DD */ op("CMP", AbsoluteX, 4, 1, Read(CPU::cmp)),
+    /* DE */ op("DEC", AbsoluteX, 7, 0, Modify(CPU::dec)),
+    /* DF */ unofficial("DCP", AbsoluteX, 7, 0, Modify(CPU::dcp)),
+    /* E0 */ op("CPX", Immediate, 2, 0, Read(CPU::cpx)),
+    /* E1 */ op("SBC", IndexedIndirect, 6, 0, Read(CPU::sbc)),
+    /* E2 */ unofficial("NOP", Immediate, 2, 0, Read(CPU::nop_read)),
+    /* E3 */ unofficial("ISC", IndexedIndirect, 8, 0, Modify(CPU::isc)),
+    /* E4 */ op("CPX", ZeroPage, 3, 0, Read(CPU::cpx)),
+    /* E5 */ op("SBC", ZeroPage, 3, 0, Read(CPU::sbc)),
+    /* E6 */ op("INC", ZeroPage, 5, 0, Modify(CPU::inc)),
+    /* E7 */ unofficial("ISC", ZeroPage, 5, 0, Modify(CPU::isc)),
+    /* E8 */ op("INX", Implied, 2, 0, Internal(CPU::inx)),
+    /* E9 */ op("SBC", Immediate, 2, 0, Read(CPU::sbc)),
+    /* EA */ op("NOP", Implied, 2, 0, Internal(CPU::nop)),
+    /* EB */ unofficial("SBC", Immediate, 2, 0, Read(CPU::sbc)),
+    /* EC */ op("CPX", Absolute, 4, 0, Read(CPU::cpx)),
+    /* ED */ op("SBC", Absolute, 4, 0, Read(CPU::sbc)),
+    /* EE */ op("INC", Absolute, 6, 0, Modify(CPU::inc)),
+    /* EF */ unofficial("ISC", Absolute, 6, 0, Modify(CPU::isc)),
+    /* F0 */ op("BEQ", Relative, 2, 1, Branch(CPU::beq)),
+    /* F1 */ op("SBC", IndirectIndexed, 5, 1, Read(CPU::sbc)),
+    /* F2 */ unofficial("KIL", Implied, 2, 0, Internal(CPU::nop)),
+    /* F3 */ unofficial("ISC", IndirectIndexed, 8, 0, Modify(CPU::isc)),
+    /* F4 */ unofficial("NOP", ZeroPageX, 4, 0, Read(CPU::nop_read)),
+    /* F5 */ op("SBC", ZeroPageX, 4, 0, Read(CPU::sbc)),
+    /* F6 */ op("INC", ZeroPageX, 6, 0, Modify(CPU::inc)),
+    /* F7 */ unofficial("ISC", ZeroPageX, 6, 0, Modify(CPU::isc)),
+    /* F8 */ op("SED", Implied, 2, 0, Internal(CPU::sed)),
+    /* F9 */ op("SBC", AbsoluteY, 4, 1, Read(CPU::sbc)),
+    /* FA */ unofficial("NOP", Implied, 2, 0, Internal(CPU::nop)),
+    /* FB */ unofficial("ISC", AbsoluteY, 7, 0, Modify(CPU::isc)),
+    /* FC */ unofficial("NOP", AbsoluteX, 4, 1, Read(CPU::nop_read)),
+    /* FD */ op("SBC", AbsoluteX, 4, 1, Read(CPU::sbc)),
+    /* FE */ op("INC", AbsoluteX, 7, 0, Modify(CPU::inc)),
+    /* FF */ unofficial("ISC", AbsoluteX, 7, 0, Modify(CPU::isc)),
+];
+
+pub struct CPU {
+    pub memory: CPUMemory,
+    pub cycles: u64,
+
+    // Registers
+    pub pc: u16,
+    pub sp: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+
+    // Status flags
+    pub c: u8,
+    pub z: u8,
+    pub i: u8,
+    pub d: u8,
+    pub b: u8,
+    pub u: u8,
+    pub v: u8,
+    pub n: u8,
+
+    // Interrupt to service before the next instruction
+    interrupt: Option<IRQ>,
+    // An NMI edge seen and not yet serviced
+    nmi: bool,
+    // The interrupt the CPU would take, as sampled at the end of the last
+    // cycle and the one before. The decision is made on the second to last
+    // cycle of an instruction.
+    poll: Option<IRQ>,
+    previous_poll: Option<IRQ>,
+    // Cycles left to stall, e.g. during DMA
+    pub stall: u64,
+    // How many of the stall cycles belong to an in-progress OAM DMA
+    oam_dma_cycles: u64,
+    // Receives a nestest-format line per instruction while tracing
+    trace: Option<Box<dyn io::Write + Send>>,
+    // Labels and comments to show in the trace in place of addresses
+    trace_symbols: Option<Symbols>,
+    // Counts cycles against routines while profiling
+    profiler: Option<Profiler>,
+
+    // The instruction in progress: its operation, its cycles and the next
+    // one to run, and the latches those cycles work through
+    operation: Operation,
+    micro: &'static [Micro],
+    next: usize,
+    done: bool,
+    address: u16,
+    // Address before indexing, to detect page crossings
+    base: u16,
+    pointer: u8,
+    data: u8,
+    vector: u16,
+    // Poll taken before a branch's offset fetch, which a taken branch that
+    // stays on its page uses instead of its own
+    branch_poll: Option<IRQ>,
+}
+
+impl CPU {
+    pub fn new(memory: CPUMemory) -> Self {
+        let mut cpu = Self {
+            memory,
+            cycles: 0,
+            pc: 0,
+            sp: 0,
+            a: 0,
+            x: 0,
+            y: 0,
+            c: 0,
+            z: 0,
+            i: 0,
+            d: 0,
+            b: 0,
+            u: 0,
+            v: 0,
+            n: 0,
+            interrupt: None,
+            nmi: false,
+            poll: None,
+            previous_poll: None,
+            stall: 0,
+            oam_dma_cycles: 0,
+            trace: None,
+            trace_symbols: None,
+            profiler: None,
+            operation: Internal(CPU::nop),
+            micro: INTERNAL,
+            next: 0,
+            done: true,
+            address: 0,
+            base: 0,
+            pointer: 0,
+            data: 0,
+            vector: 0xFFFE,
+            branch_poll: None,
+        };
+        cpu.power_on();
+        cpu
+    }
+
+    // Power-on state: registers cleared, nothing pending, and the program
+    // started from the reset vector
+    pub fn power_on(&mut self) {
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.sp = 0xFD;
+        self.set_flags(0x24);
+        self.clear_interrupts();
+        self.pc = self.read16(0xFFFC);
+    }
+
+    // The reset button. The reset sequence is an interrupt whose pushes are
+    // turned into reads, so the stack pointer still drops by three; it sets
+    // I and jumps through the reset vector, leaving the other registers be.
+    pub fn reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        self.i = 1;
+        self.clear_interrupts();
+        self.pc = self.read16(0xFFFC);
+    }
+
+    // Forgets interrupts and DMA in flight
+    fn clear_interrupts(&mut self) {
+        self.interrupt = None;
+        self.nmi = false;
+        self.poll = None;
+        self.previous_poll = None;
+        self.branch_poll = None;
+        self.stall = 0;
+        self.oam_dma_cycles = 0;
+        self.memory.oam_dma = None;
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.memory.read(addr)
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        self.memory.write(addr, value)
+    }
+
+    pub fn read16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr) as u16;
+        let hi = self.read(addr.wrapping_add(1)) as u16;
+        hi << 8 | lo
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x100 | self.sp as u16, value);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.read(0x100 | self.sp as u16)
+    }
+
+    pub(crate) fn push16(&mut self, value: u16) {
+        self.push((value >> 8) as u8);
+        self.push(value as u8);
+    }
+
+    pub fn flags(&self) -> u8 {
+        self.c
+            | self.z << 1
+            | self.i << 2
+            | self.d << 3
+            | self.b << 4
+            | self.u << 5
+            | self.v << 6
+            | self.n << 7
+    }
+
+    pub fn set_flags(&mut self, flags: u8) {
+        self.c = flags & 1;
+        self.z = (flags >> 1) & 1;
+        self.i = (flags >> 2) & 1;
+        self.d = (flags >> 3) & 1;
+        self.b = (flags >> 4) & 1;
+        self.u = (flags >> 5) & 1;
+        self.v = (flags >> 6) & 1;
+        self.n = (flags >> 7) & 1;
+    }
+
+    fn set_z(&mut self, value: u8) {
+        self.z = (value == 0) as u8;
+    }
+
+    fn set_n(&mut self, value: u8) {
+        self.n = (value >> 7) & 1;
+    }
+
+    fn set_zn(&mut self, value: u8) {
+        self.set_z(value);
+        self.set_n(value);
+    }
+
+    // Save states are only taken between instructions, so the micro-op
+    // latches needn't be kept
+    pub fn save(&self, state: &mut StateWriter) {
+        state.write(&self.cycles);
+        state.write(&self.pc);
+        state.write(&self.sp);
+        state.write(&self.a);
+        state.write(&self.x);
+        state.write(&self.y);
+        state.write(&self.flags());
+        state.write(&self.interrupt);
+        state.write(&self.nmi);
+        state.write(&self.stall);
+        state.write(&self.oam_dma_cycles);
+        self.memory.save(state);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cycles = state.read()?;
+        self.pc = state.read()?;
+        self.sp = state.read()?;
+        self.a = state.read()?;
+        self.x = state.read()?;
+        self.y = state.read()?;
+        let flags = state.read()?;
+        self.set_flags(flags);
+        self.interrupt = state.read()?;
+        self.nmi = state.read()?;
+        self.stall = state.read()?;
+        self.oam_dma_cycles = state.read()?;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.lose_track();
+        }
+        self.memory.load(state)
+    }
+
+    // Logs every instruction executed from now on to `out`, in the format of
+    // nestest.log. Tracing stops by itself if a write fails.
+    pub fn set_trace<W: io::Write + Send + 'static>(&mut self, out: W) {
+        self.trace = Some(Box::new(out));
+    }
+
+    pub fn clear_trace(&mut self) {
+        self.trace = None;
+    }
+
+    // Names addresses in the trace from `symbols`, with a line for each
+    // label reached and comments after the instructions they annotate
+    pub fn set_trace_symbols(&mut self, symbols: Symbols) {
+        self.trace_symbols = Some(symbols);
+    }
+
+    pub fn clear_trace_symbols(&mut self) {
+        self.trace_symbols = None;
+    }
+
+    fn write_trace(&mut self) {
+        let line = match &self.trace_symbols {
+            Some(symbols) => trace::symbolic_trace_line(self, symbols),
+            None => trace::trace_line(self),
+        };
+        if let Some(out) = self.trace.as_mut() {
+            if writeln!(out, "{}", line).is_err() {
+                self.trace = None;
+            }
+        }
+    }
+
+    // Attributes the cycles of every instruction from now on to the routine
+    // running it
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    pub fn trigger_nmi(&mut self) {
+        self.interrupt = Some(IRQ::NMI);
+    }
+
+    // IRQs are masked by the I flag and never displace a pending NMI
+    pub fn trigger_irq(&mut self) {
+        if self.i == 0 && self.interrupt.is_none() {
+            self.interrupt = Some(IRQ::Normal);
+        }
+    }
+
+    // Latches interrupts raised by the PPU, APU and cartridge since the last
+    // poll so they are serviced before the next instruction. The CPU polls
+    // on its own every cycle; this is for when the PPU only catches up after
+    // each instruction.
+    pub fn poll_interrupts(&mut self) {
+        if self.memory.ppu.take_nmi() {
+            self.nmi = true;
+        }
+        if self.nmi {
+            self.trigger_nmi();
+        } else if self.memory.apu.irq() || self.memory.mapper().irq_pending() {
+            self.trigger_irq();
+        }
+    }
+
+    // The interrupt the CPU would take if it polled now
+    fn pending_interrupt(&self) -> Option<IRQ> {
+        if self.nmi {
+            Some(IRQ::NMI)
+        } else if self.i == 0 && (self.memory.apu.irq() || self.memory.mapper().irq_pending()) {
+            Some(IRQ::Normal)
+        } else {
+            None
+        }
+    }
+
+    // Clocks the cartridge and APU for one CPU cycle and services any DMC sample fetch it
+    // asks for. A fetch halts the CPU for four cycles on its own, but only
+    // two when it lands inside an OAM DMA that already holds the bus, except
+    // at the very end of the transfer where the two DMAs realign.
+    fn step_apu(&mut self) {
+        let expansion = self.memory.step_mapper();
+        self.memory.apu.step(expansion);
+        if let Some(addr) = self.memory.apu.dmc_fetch_address() {
+            let value = self.read(addr);
+            self.memory.apu.dmc_fill(value);
+            self.stall += match self.oam_dma_cycles {
+                0 => 4,
+                1 => 3,
+                2 => 1,
+                _ => 2,
+            };
+        }
+    }
+
+    // Executes a single instruction, after servicing any interrupt due, and
+    // returns the number of cycles it took. While the CPU is halted for DMA
+    // between instructions, each call runs just one of the halted cycles.
+    pub fn step(&mut self) -> u64 {
+        if self.stall > 0 {
+            self.halt();
+            return 1;
+        }
+
+        let cycles = self.cycles;
+
+        match self.interrupt.take() {
+            Some(IRQ::NMI) => {
+                self.nmi = false;
+                self.vector = 0xFFFA;
+                self.run(INTERRUPT);
+            }
+            Some(IRQ::Normal) => {
+                self.vector = 0xFFFE;
+                self.run(INTERRUPT);
+            }
+            Some(IRQ::RESET) => self.reset(),
+            None => {}
+        }
+
+        if self.trace.is_some() {
+            self.write_trace();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.instruction(self.pc, self.sp, self.cycles, &self.memory);
+        }
+
+        while self.stall > 0 {
+            self.halt();
+        }
+        let opcode = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        self.end_cycle();
+
+        let instruction = &INSTRUCTIONS[opcode as usize];
+        self.operation = instruction.operation;
+        self.vector = 0xFFFE;
+        self.run(instruction.micro);
+        if self.interrupt.is_none() {
+            self.interrupt = self.previous_poll;
+        }
+
+        if let Some(page) = self.memory.oam_dma.take() {
+            self.oam_dma(page);
+        }
+
+        self.cycles - cycles
+    }
+
+    // Runs cycles from `micro` until the instruction finishes
+    fn run(&mut self, micro: &'static [Micro]) {
+        self.micro = micro;
+        self.next = 0;
+        self.done = false;
+        while !self.done {
+            self.tick();
+        }
+    }
+
+    // Runs the next cycle of the instruction in progress, unless DMA halts
+    // the CPU on it
+    fn tick(&mut self) {
+        let micro = self.micro[self.next];
+        if self.stall > 0 && !micro.writes() {
+            self.halt();
+            return;
+        }
+        self.next += 1;
+        self.done = self.next == self.micro.len();
+        self.execute(micro);
+        self.end_cycle();
+        // A taken branch that doesn't cross a page doesn't poll on its last
+        // cycle, delaying an interrupt by an instruction
+        if micro == TakeBranch && self.done {
+            self.previous_poll = self.branch_poll;
+        }
+    }
+
+    // A cycle lost to DMA
+    fn halt(&mut self) {
+        self.stall -= 1;
+        self.oam_dma_cycles = self.oam_dma_cycles.saturating_sub(1);
+        self.end_cycle();
+    }
+
+    // Clocks what runs alongside the CPU and samples the interrupt lines
+    fn end_cycle(&mut self) {
+        self.cycles += 1;
+        self.step_apu();
+        if self.memory.ppu.take_nmi() {
+            self.nmi = true;
+        }
+        self.previous_poll = self.poll;
+        self.poll = self.pending_interrupt();
+    }
+
+    fn execute(&mut self, micro: Micro) {
+        match micro {
+            FetchLow => {
+                self.address = self.fetch() as u16;
+            }
+            FetchHigh => {
+                self.address |= (self.fetch() as u16) << 8;
+            }
+            FetchHighX => self.index_absolute(self.x),
+            FetchHighY => self.index_absolute(self.y),
+            FetchPointer => {
+                self.pointer = self.fetch();
+            }
+            IndexPointer => {
+                self.read(self.pointer as u16);
+                self.pointer = self.pointer.wrapping_add(self.x);
+            }
+            IndexZeroPageX => {
+                self.read(self.address);
+                self.address = (self.address as u8).wrapping_add(self.x) as u16;
+            }
+            IndexZeroPageY => {
+                self.read(self.address);
+                self.address = (self.address as u8).wrapping_add(self.y) as u16;
+            }
+            PointerLow => {
+                self.address = self.read(self.pointer as u16) as u16;
+            }
+            PointerHigh => {
+                let hi = self.read(self.pointer.wrapping_add(1) as u16) as u16;
+                self.address |= hi << 8;
+            }
+            PointerHighY => {
+                let hi = self.read(self.pointer.wrapping_add(1) as u16) as u16;
+                self.base = hi << 8 | self.address;
+                self.address = self.base.wrapping_add(self.y as u16);
+            }
+            ReadIndexed => {
+                let uncorrected = self.uncorrected_address();
+                let value = self.read(uncorrected);
+                if uncorrected == self.address {
+                    self.done = true;
+                    self.operate(value);
+                }
+            }
+            DummyIndexed => {
+                self.read(self.uncorrected_address());
+            }
+            ReadOperand => {
+                let value = self.read(self.address);
+                self.operate(value);
+            }
+            WriteOperand => {
+                if let Write(store) = self.operation {
+                    let value = store(self);
+                    self.write(self.address, value);
+                }
+            }
+            ReadData => {
+                self.data = self.read(self.address);
+            }
+            ModifyData => {
+                self.write(self.address, self.data);
+                if let Modify(modify) = self.operation {
+                    self.data = modify(self, self.data);
+                }
+            }
+            WriteData => self.write(self.address, self.data),
+            ReadImmediate => {
+                let value = self.fetch();
+                self.operate(value);
+            }
+            RunInternal => {
+                self.read(self.pc);
+                match self.operation {
+                    Internal(internal) => internal(self),
+                    Modify(modify) => self.a = modify(self, self.a),
+                    _ => {}
+                }
+            }
+            FetchOffset => {
+                self.data = self.fetch();
+                self.branch_poll = self.poll;
+                if let Branch(condition) = self.operation {
+                    self.done = !condition(self);
+                }
+            }
+            TakeBranch => {
+                self.read(self.pc);
+                self.address = self.pc.wrapping_add(self.data as i8 as u16);
+                self.pc = (self.pc & 0xFF00) | (self.address & 0x00FF);
+                self.done = self.pc == self.address;
+            }
+            FixBranchPage => {
+                self.read(self.pc);
+                self.pc = self.address;
+            }
+            JumpHigh => {
+                let hi = self.read(self.pc) as u16;
+                self.pc = hi << 8 | self.address;
+            }
+            JumpPointerLow => {
+                self.data = self.read(self.address);
+            }
+            JumpPointerHigh => {
+                let address = (self.address & 0xFF00) | (self.address.wrapping_add(1) & 0x00FF);
+                let hi = self.read(address) as u16;
+                self.pc = hi << 8 | self.data as u16;
+            }
+            DummyPC => {
+                self.read(self.pc);
+            }
+            DummyStack => {
+                self.read(0x100 | self.sp as u16);
+            }
+            SkipPadding | IncrementPC => {
+                self.fetch();
+            }
+            PushPCH => self.push((self.pc >> 8) as u8),
+            PushPCL => self.push(self.pc as u8),
+            PushFlags(brk) => {
+                // An NMI arriving by now hijacks the sequence, BRK included
+                if self.nmi {
+                    self.nmi = false;
+                    self.vector = 0xFFFA;
+                }
+                if self.vector == 0xFFFA {
+                    self.memory.events.emit(Event::NMI);
+                } else if !brk {
+                    self.memory.events.emit(Event::IRQ);
+                }
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.interrupt(self.vector == 0xFFFA);
+                }
+                let flags = match brk {
+                    true => self.flags() | 0x30,
+                    false => (self.flags() & 0xEF) | 0x20,
+                };
+                self.push(flags);
+            }
+            PushValue => {
+                if let Push(value) = self.operation {
+                    self.push(value(self));
+                }
+            }
+            PullFlags => {
+                let flags = self.pull();
+                self.set_flags((flags & 0xEF) | 0x20);
+            }
+            PullPCL => {
+                self.address = self.pull() as u16;
+            }
+            PullPCH => {
+                let hi = self.pull() as u16;
+                self.pc = hi << 8 | self.address;
+            }
+            PullValue => {
+                let value = self.pull();
+                if let Pull(pull) = self.operation {
+                    pull(self, value);
+                }
+            }
+            VectorLow => {
+                self.data = self.read(self.vector);
+                self.i = 1;
+            }
+            VectorHigh => {
+                let hi = self.read(self.vector.wrapping_add(1)) as u16;
+                self.pc = hi << 8 | self.data as u16;
+            }
+        }
+    }
+
+    // Reads the byte at pc and moves past it
+    fn fetch(&mut self) -> u8 {
+        let value = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn index_absolute(&mut self, index: u8) {
+        self.base = (self.fetch() as u16) << 8 | self.address;
+        self.address = self.base.wrapping_add(index as u16);
+    }
+
+    // Where an indexed access lands before the carry into the high byte
+    fn uncorrected_address(&self) -> u16 {
+        (self.base & 0xFF00) | (self.address & 0x00FF)
+    }
+
+    // Hands a read operand to the instruction
+    fn operate(&mut self, value: u8) {
+        if let Read(read) = self.operation {
+            read(self, value);
+        }
+    }
+
+    // $4014: copies a 256-byte page into OAM through $2004. The CPU is halted
+    // for 513 cycles, plus one more to align when the write lands on an odd
+    // cycle.
+    fn oam_dma(&mut self, page: u8) {
+        let base = (page as u16) << 8;
+        for i in 0..256 {
+            let value = self.read(base | i);
+            self.memory.ppu.write_register(0x2004, value);
+        }
+        let cycles = 513 + (self.cycles & 1);
+        self.stall += cycles;
+        self.oam_dma_cycles = cycles;
+    }
+
+    fn compare(&mut self, a: u8, b: u8) {
+        self.set_zn(a.wrapping_sub(b));
+        self.c = (a >= b) as u8;
+    }
+
+    // ADC - Add with Carry
+    fn adc(&mut self, value: u8) {
+        let a = self.a;
+        let sum = a as u16 + value as u16 + self.c as u16;
+        self.a = sum as u8;
+        self.set_zn(self.a);
+        self.c = (sum > 0xFF) as u8;
+        self.v = ((a ^ value) & 0x80 == 0 && (a ^ self.a) & 0x80 != 0) as u8;
+    }
+
+    // AND - Logical AND
+    fn and(&mut self, value: u8) {
+        self.a &= value;
+        self.set_zn(self.a);
+    }
+
+    // ASL - Arithmetic Shift Left
+    fn asl(&mut self, value: u8) -> u8 {
+        self.c = (value >> 7) & 1;
+        let value = value << 1;
+        self.set_zn(value);
+        value
+    }
+
+    // BCC - Branch if Carry Clear
+    fn bcc(&self) -> bool {
+        self.c == 0
+    }
+
+    // BCS - Branch if Carry Set
+    fn bcs(&self) -> bool {
+        self.c != 0
+    }
+
+    // BEQ - Branch if Equal
+    fn beq(&self) -> bool {
+        self.z != 0
+    }
+
+    // BIT - Bit Test
+    fn bit(&mut self, value: u8) {
+        self.v = (value >> 6) & 1;
+        self.set_z(value & self.a);
+        self.set_n(value);
+    }
+
+    // BMI - Branch if Minus
+    fn bmi(&self) -> bool {
+        self.n != 0
+    }
+
+    // BNE - Branch if Not Equal
+    fn bne(&self) -> bool {
+        self.z == 0
+    }
+
+    // BPL - Branch if Positive
+    fn bpl(&self) -> bool {
+        self.n == 0
+    }
+
+    // BVC - Branch if Overflow Clear
+    fn bvc(&self) -> bool {
+        self.v == 0
+    }
+
+    // BVS - Branch if Overflow Set
+    fn bvs(&self) -> bool {
+        self.v != 0
+    }
+
+    // CLC - Clear Carry Flag
+    fn clc(&mut self) {
+        self.c = 0;
+    }
+
+    // CLD - Clear Decimal Mode
+    fn cld(&mut self) {
+        self.d = 0;
+    }
+
+    // CLI - Clear Interrupt Disable
+    fn cli(&mut self) {
+        self.i = 0;
+    }
+
+    // CLV - Clear Overflow Flag
+    fn clv(&mut self) {
+        self.v = 0;
+    }
+
+    // CMP - Compare
+    fn cmp(&mut self, value: u8) {
+        self.compare(self.a, value);
+    }
+
+    // CPX - Compare X Register
+    fn cpx(&mut self, value: u8) {
+        self.compare(self.x, value);
+    }
+
+    // CPY - Compare Y Register
+    fn cpy(&mut self, value: u8) {
+        self.compare(self.y, value);
+    }
+
+    // DEC - Decrement Memory
+    fn dec(&mut self, value: u8) -> u8 {
+        let value = value.wrapping_sub(1);
+        self.set_zn(value);
+        value
+    }
+
+    // DEX - Decrement X Register
+    fn dex(&mut self) {
+        self.x = self.x.wrapping_sub(1);
+        self.set_zn(self.x);
+    }
+
+    // DEY - Decrement Y Register
+    fn dey(&mut self) {
+        self.y = self.y.wrapping_sub(1);
+        self.set_zn(self.y);
+    }
+
+    // EOR - Exclusive OR
+    fn eor(&mut self, value: u8) {
+        self.a ^= value;
+        self.set_zn(self.a);
+    }
+
+    // INC - Increment Memory
+    fn inc(&mut self, value: u8) -> u8 {
+        let value = value.wrapping_add(1);
+        self.set_zn(value);
+        value
+    }
+
+    // INX - Increment X Register
+    fn inx(&mut self) {
+        self.x = self.x.wrapping_add(1);
+        self.set_zn(self.x);
+    }
+
+    // INY - Increment Y Register
+    fn iny(&mut self) {
+        self.y = self.y.wrapping_add(1);
+        self.set_zn(self.y);
+    }
+
+    // LDA - Load Accumulator
+    fn lda(&mut self, value: u8) {
+        self.a = value;
+        self.set_zn(self.a);
+    }
+
+    // LDX - Load X Register
+    fn ldx(&mut self, value: u8) {
+        self.x = value;
+        self.set_zn(self.x);
+    }
+
+    // LDY - Load Y Register
+    fn ldy(&mut self, value: u8) {
+        self.y = value;
+        self.set_zn(self.y);
+    }
+
+    // LSR - Logical Shift Right
+    fn lsr(&mut self, value: u8) -> u8 {
+        self.c = value & 1;
+        let value = value >> 1;
+        self.set_zn(value);
+        value
+    }
+
+    // NOP - No Operation
+    fn nop(&mut self) {}
+
+    // NOP - No Operation, reading its operand all the same
+    fn nop_read(&mut self, _value: u8) {}
+
+    // ORA - Logical Inclusive OR
+    fn ora(&mut self, value: u8) {
+        self.a |= value;
+        self.set_zn(self.a);
+    }
+
+    // PHA - Push Accumulator
+    fn pha(&self) -> u8 {
+        self.a
+    }
+
+    // PHP - Push Processor Status
+    fn php(&self) -> u8 {
+        self.flags() | 0x30
+    }
+
+    // PLA - Pull Accumulator
+    fn pla(&mut self, value: u8) {
+        self.a = value;
+        self.set_zn(self.a);
+    }
+
+    // PLP - Pull Processor Status
+    fn plp(&mut self, value: u8) {
+        self.set_flags((value & 0xEF) | 0x20);
+    }
+
+    // ROL - Rotate Left
+    fn rol(&mut self, value: u8) -> u8 {
+        let carry = self.c;
+        self.c = (value >> 7) & 1;
+        let value = (value << 1) | carry;
+        self.set_zn(value);
+        value
+    }
+
+    // ROR - Rotate Right
+    fn ror(&mut self, value: u8) -> u8 {
+        let carry = self.c;
+        self.c = value & 1;
+        let value = (value >> 1) | (carry << 7);
+        self.set_zn(value);
+        value
+    }
+
+    // SBC - Subtract with Carry
+    fn sbc(&mut self, value: u8) {
+        let a = self.a;
+        let difference = a as i16 - value as i16 - (1 - self.c as i16);
+        self.a = difference as u8;
+        self.set_zn(self.a);
+        self.c = (difference >= 0) as u8;
+        self.v = ((a ^ value) & 0x80 != 0 && (a ^ self.a) & 0x80 != 0) as u8;
+    }
+
+    // SEC - Set Carry Flag
+    fn sec(&mut self) {
+        self.c = 1;
+    }
+
+    // SED - Set Decimal Flag
+    fn sed(&mut self) {
+        self.d = 1;
+    }
+
+    // SEI - Set Interrupt Disable
+    fn sei(&mut self) {
+        self.i = 1;
+    }
+
+    // STA - Store Accumulator
+    fn sta(&mut self) -> u8 {
+        self.a
+    }
+
+    // STX - Store X Register
+    fn stx(&mut self) -> u8 {
+        self.x
+    }
+
+    // STY - Store Y Register
+    fn sty(&mut self) -> u8 {
+        self.y
+    }
+
+    // TAX - Transfer Accumulator to X
+    fn tax(&mut self) {
+        self.x = self.a;
+        self.set_zn(self.x);
+    }
+
+    // TAY - Transfer Accumulator to Y
+    fn tay(&mut self) {
+        self.y = self.a;
+        self.set_zn(self.y);
+    }
+
+    // TSX - Transfer Stack Pointer to X
+    fn tsx(&mut self) {
+        self.x = self.sp;
+        self.set_zn(self.x);
+    }
+
+    // TXA - Transfer X to Accumulator
+    fn txa(&mut self) {
+        self.a = self.x;
+        self.set_zn(self.a);
+    }
+
+    // TXS - Transfer X to Stack Pointer
+    fn txs(&mut self) {
+        self.sp = self.x;
+    }
+
+    // TYA - Transfer Y to Accumulator
+    fn tya(&mut self) {
+        self.a = self.y;
+        self.set_zn(self.a);
+    }
+
+    // Unofficial opcodes
+
+    // ALR - AND, then LSR the accumulator
+    fn alr(&mut self, value: u8) {
+        self.a &= value;
+        self.a = self.lsr(self.a);
+    }
+
+    // ANC - AND, copying N into C
+    fn anc(&mut self, value: u8) {
+        self.and(value);
+        self.c = self.n;
+    }
+
+    // ARR - AND, then ROR the accumulator, with C and V taken from bits 6
+    // and 5 of the result
+    fn arr(&mut self, value: u8) {
+        self.a &= value;
+        self.a = self.ror(self.a);
+        self.c = (self.a >> 6) & 1;
+        self.v = ((self.a >> 6) ^ (self.a >> 5)) & 1;
+    }
+
+    // AXS - X = (A AND X) - operand, setting flags as CMP
+    fn axs(&mut self, value: u8) {
+        let ax = self.a & self.x;
+        self.compare(ax, value);
+        self.x = ax.wrapping_sub(value);
+    }
+
+    // DCP - DEC, then CMP
+    fn dcp(&mut self, value: u8) -> u8 {
+        let value = value.wrapping_sub(1);
+        self.compare(self.a, value);
+        value
+    }
+
+    // ISC - INC, then SBC
+    fn isc(&mut self, value: u8) -> u8 {
+        let value = value.wrapping_add(1);
+        self.sbc(value);
+        value
+    }
+
+    // LAS - A, X and SP all get the operand AND SP
+    fn las(&mut self, value: u8) {
+        self.sp &= value;
+        self.a = self.sp;
+        self.x = self.sp;
+        self.set_zn(self.sp);
+    }
+
+    // LAX - LDA and LDX at once. The immediate form mixes in the
+    // accumulator through an unstable constant.
+    fn lax(&mut self, value: u8) {
+        let value = match self.micro == IMMEDIATE {
+            true => (self.a | 0xEE) & value,
+            false => value,
+        };
+        self.lda(value);
+        self.x = value;
+    }
+
+    // RLA - ROL, then AND
+    fn rla(&mut self, value: u8) -> u8 {
+        let value = self.rol(value);
+        self.and(value);
+        value
+    }
+
+    // RRA - ROR, then ADC
+    fn rra(&mut self, value: u8) -> u8 {
+        let value = self.ror(value);
+        self.adc(value);
+        value
+    }
+
+    // SAX - Store A AND X
+    fn sax(&mut self) -> u8 {
+        self.a & self.x
+    }
+
+    // SLO - ASL, then ORA
+    fn slo(&mut self, value: u8) -> u8 {
+        let value = self.asl(value);
+        self.ora(value);
+        value
+    }
+
+    // SRE - LSR, then EOR
+    fn sre(&mut self, value: u8) -> u8 {
+        let value = self.lsr(value);
+        self.eor(value);
+        value
+    }
+
+    // XAA - A = X AND operand, mixed with an unstable constant
+    fn xaa(&mut self, value: u8) {
+        self.a = (self.a | 0xEE) & self.x & value;
+        self.set_zn(self.a);
+    }
+
+    // AHX, SHX, SHY and TAS store a register ANDed with the high byte of the
+    // base address plus one. When indexing crosses a page, that value also
+    // replaces the high byte of the address written.
+    fn store_high(&mut self, value: u8) -> u8 {
+        let value = value & ((self.base >> 8) as u8).wrapping_add(1);
+        if pages_differ(self.base, self.address) {
+            self.address = (value as u16) << 8 | (self.address & 0x00FF);
+        }
+        value
+    }
+
+    // AHX - Store A AND X AND (high byte + 1)
+    fn ahx(&mut self) -> u8 {
+        self.store_high(self.a & self.x)
+    }
+
+    // SHX - Store X AND (high byte + 1)
+    fn shx(&mut self) -> u8 {
+        self.store_high(self.x)
+    }
+
+    // SHY - Store Y AND (high byte + 1)
+    fn shy(&mut self) -> u8 {
+        self.store_high(self.y)
+    }
+
+    // TAS - SP = A AND X, then store SP AND (high byte + 1)
+    fn tas(&mut self) -> u8 {
+        self.sp = self.a & self.x;
+        self.store_high(self.sp)
+    }
+}
+
+fn pages_differ(a: u16, b: u16) -> bool {
+    a & 0xFF00 != b & 0xFF00
+}
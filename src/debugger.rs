@@ -0,0 +1,414 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    console::Console,
+    cpu::CPU,
+    disasm::{self, Listing},
+    expr::{Expr, ExprError},
+    memory::Access,
+    memory_diff::{Change, MemoryDiff},
+    symbols::Symbols,
+};
+
+// Address spaces a watchpoint can observe. PPU watchpoints see accesses made
+// through $2007.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Space {
+    CPU,
+    PPU,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+// Why execution stopped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Hit {
+    // About to execute the instruction at `pc`
+    Breakpoint {
+        id: usize,
+        pc: u16,
+    },
+    // The instruction just executed touched a watched address
+    Watchpoint {
+        id: usize,
+        space: Space,
+        addr: u16,
+        access: Access,
+    },
+    // An expression watched every instruction changed value
+    Expression {
+        id: usize,
+        value: i64,
+    },
+}
+
+// How often a watch expression is evaluated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Every {
+    // As each frame the debugger runs ends
+    Frame,
+    // After each instruction, stopping execution when the value changes
+    Instruction,
+}
+
+pub type Condition = Box<dyn Fn(&CPU) -> bool>;
+pub type HitCallback = Box<dyn FnMut(&Hit, &mut Console)>;
+
+struct Breakpoint {
+    id: usize,
+    addr: u16,
+    condition: Option<Condition>,
+}
+
+struct ExprWatch {
+    id: usize,
+    expr: Expr,
+    every: Every,
+    value: Option<i64>,
+}
+
+struct Watchpoint {
+    id: usize,
+    space: Space,
+    addrs: RangeInclusive<u16>,
+    kind: WatchKind,
+}
+
+// Runs a Console under breakpoints and watchpoints. Stepping methods return
+// the hit that stopped them, after passing it to the callback if one is set.
+#[derive(Default)]
+pub struct Debugger {
+    next_id: usize,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    expressions: Vec<ExprWatch>,
+    // Diffs taken as each frame ends, with the changes they found
+    diffs: Vec<(usize, MemoryDiff, Vec<Change>)>,
+    callback: Option<HitCallback>,
+    symbols: Symbols,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) -> usize {
+        self.push_breakpoint(addr, None)
+    }
+
+    // Breaks at `addr` only when `condition` holds, e.g. `|cpu| cpu.a == 0x30`
+    pub fn add_conditional_breakpoint<F>(&mut self, addr: u16, condition: F) -> usize
+    where
+        F: Fn(&CPU) -> bool + 'static,
+    {
+        self.push_breakpoint(addr, Some(Box::new(condition)))
+    }
+
+    // Breaks at `addr` only when `condition` is true, e.g. `[$00A2] > 3`
+    pub fn add_breakpoint_if(&mut self, addr: u16, condition: Expr) -> usize {
+        self.add_conditional_breakpoint(addr, move |cpu| condition.is_true(cpu))
+    }
+
+    fn push_breakpoint(&mut self, addr: u16, condition: Option<Condition>) -> usize {
+        let id = self.next_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr,
+            condition,
+        });
+        id
+    }
+
+    pub fn add_watchpoint(
+        &mut self,
+        space: Space,
+        addrs: RangeInclusive<u16>,
+        kind: WatchKind,
+    ) -> usize {
+        let id = self.next_id();
+        self.watchpoints.push(Watchpoint {
+            id,
+            space,
+            addrs,
+            kind,
+        });
+        id
+    }
+
+    // Watches the value of `expr`, evaluated as often as `every` says
+    pub fn add_watch_expression(&mut self, expr: Expr, every: Every) -> usize {
+        let id = self.next_id();
+        self.expressions.push(ExprWatch {
+            id,
+            expr,
+            every,
+            value: None,
+        });
+        id
+    }
+
+    // The value a watch expression had when last evaluated, or None if it
+    // hasn't been yet
+    pub fn watch_value(&self, id: usize) -> Option<i64> {
+        self.expressions
+            .iter()
+            .find(|e| e.id == id)
+            .and_then(|e| e.value)
+    }
+
+    // Every watch expression by id, with its last value
+    pub fn watch_expressions(&self) -> impl Iterator<Item = (usize, &Expr, Option<i64>)> {
+        self.expressions.iter().map(|e| (e.id, &e.expr, e.value))
+    }
+
+    // Diffs `ranges` of CPU memory from frame to frame as step_frame runs
+    // them, starting from what they hold now
+    pub fn add_memory_diff<I>(&mut self, console: &Console, ranges: I) -> usize
+    where
+        I: IntoIterator<Item = RangeInclusive<u16>>,
+    {
+        let id = self.next_id();
+        self.diffs
+            .push((id, MemoryDiff::new(console, ranges), Vec::new()));
+        id
+    }
+
+    // What changed in a memory diff's ranges over the last frame
+    pub fn memory_changes(&self, id: usize) -> Option<&[Change]> {
+        self.diffs
+            .iter()
+            .find(|(diff_id, ..)| *diff_id == id)
+            .map(|(_, _, changes)| changes.as_slice())
+    }
+
+    // Parses `text` as an expression, with labels from the symbols
+    pub fn parse_expression(&self, text: &str) -> Result<Expr, ExprError> {
+        Expr::parse_with(text, Some(&self.symbols))
+    }
+
+    // Removes the breakpoint, watchpoint, watch expression or memory diff
+    // with this id
+    pub fn remove(&mut self, id: usize) {
+        self.breakpoints.retain(|b| b.id != id);
+        self.watchpoints.retain(|w| w.id != id);
+        self.expressions.retain(|e| e.id != id);
+        self.diffs.retain(|(diff_id, ..)| *diff_id != id);
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.expressions.clear();
+        self.diffs.clear();
+    }
+
+    pub fn set_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Hit, &mut Console) + 'static,
+    {
+        self.callback = Some(Box::new(callback));
+    }
+
+    // Labels and comments for the program being debugged, for breaking on
+    // labels and describing where things are
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    // Breaks at the label `name`, or returns None if no symbol has that name
+    pub fn add_breakpoint_at(&mut self, name: &str) -> Option<usize> {
+        let addr = self.symbols.address(name)?;
+        Some(self.add_breakpoint(addr))
+    }
+
+    // `hit` in words, with addresses named where the symbols can:
+    // "breakpoint 1 at reset ($C000)"
+    pub fn describe(&self, hit: &Hit, console: &Console) -> String {
+        let at = |addr: u16| match self.symbols.name(addr, &console.cpu.memory) {
+            Some(name) => format!("{} (${:04X})", name, addr),
+            None => format!("${:04X}", addr),
+        };
+        match *hit {
+            Hit::Breakpoint { id, pc } => format!("breakpoint {} at {}", id, at(pc)),
+            Hit::Watchpoint {
+                id,
+                space: Space::CPU,
+                addr,
+                access,
+            } => format!("watchpoint {}: {:?} of {}", id, access, at(addr)),
+            Hit::Watchpoint {
+                id,
+                space: Space::PPU,
+                addr,
+                access,
+            } => format!("watchpoint {}: {:?} of PPU ${:04X}", id, access, addr),
+            Hit::Expression { id, value } => {
+                let expr = self.expressions.iter().find(|e| e.id == id);
+                match expr {
+                    Some(e) => format!("watch {}: {} = {}", id, e.expr, value),
+                    None => format!("watch {} = {}", id, value),
+                }
+            }
+        }
+    }
+
+    // Disassembles `start..=end` from the CPU bus under the symbols' labels
+    // and comments
+    pub fn disassemble(&self, console: &Console, start: u16, end: u16) -> Listing {
+        let memory = &console.cpu.memory;
+        let mut listing = disasm::disassemble(|addr| memory.peek(addr), start, end);
+        listing.apply_symbols(&self.symbols, memory);
+        listing
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    // Executes exactly one instruction, reporting any watchpoint it hits.
+    // Breakpoints are not checked, so this also steps off a breakpoint.
+    pub fn step_instruction(&mut self, console: &mut Console) -> Option<Hit> {
+        self.attach(console);
+        console.step();
+        let watched = self.check_watchpoints(console);
+        let hit = watched.or(self.check_expressions(console, Every::Instruction));
+        detach(console);
+        self.report(hit, console)
+    }
+
+    // Runs until the PPU starts a new frame or a breakpoint or watchpoint
+    // fires. A breakpoint at the current pc is stepped over first.
+    pub fn step_frame(&mut self, console: &mut Console) -> Option<Hit> {
+        self.attach(console);
+        let hit = self.run_frame(console);
+        self.check_expressions(console, Every::Frame);
+        // A frame cut short by a hit is diffed as far as it got
+        for (_, diff, changes) in &mut self.diffs {
+            *changes = diff.diff(console);
+        }
+        detach(console);
+        self.report(hit, console)
+    }
+
+    fn run_frame(&mut self, console: &mut Console) -> Option<Hit> {
+        let frame = console.ppu().frame();
+        let mut first = true;
+        while frame == console.ppu().frame() {
+            if !first {
+                if let Some(hit) = self.check_breakpoints(&console.cpu) {
+                    return Some(hit);
+                }
+            }
+            first = false;
+
+            console.step();
+            let watched = self.check_watchpoints(console);
+            if let Some(hit) = watched.or(self.check_expressions(console, Every::Instruction)) {
+                return Some(hit);
+            }
+        }
+        None
+    }
+
+    // Access logging costs time, so it is only on while the debugger is
+    // stepping and has watchpoints in that space
+    fn attach(&self, console: &mut Console) {
+        let memory = &mut console.cpu.memory;
+        if self.watchpoints.iter().any(|w| w.space == Space::CPU) {
+            memory.access_log = Some(Vec::new());
+        }
+        if self.watchpoints.iter().any(|w| w.space == Space::PPU) {
+            memory.ppu.access_log = Some(Vec::new());
+        }
+    }
+
+    fn check_breakpoints(&self, cpu: &CPU) -> Option<Hit> {
+        self.breakpoints
+            .iter()
+            .find(|b| b.addr == cpu.pc && b.condition.as_ref().is_none_or(|c| c(cpu)))
+            .map(|b| Hit::Breakpoint {
+                id: b.id,
+                pc: cpu.pc,
+            })
+    }
+
+    fn check_watchpoints(&self, console: &mut Console) -> Option<Hit> {
+        let memory = &mut console.cpu.memory;
+        let cpu_log = memory.access_log.as_mut().map(std::mem::take);
+        let ppu_log = memory.ppu.access_log.as_mut().map(std::mem::take);
+        let accesses = cpu_log
+            .into_iter()
+            .flatten()
+            .map(|(addr, access)| (Space::CPU, addr, access))
+            .chain(
+                ppu_log
+                    .into_iter()
+                    .flatten()
+                    .map(|(addr, access)| (Space::PPU, addr, access)),
+            );
+
+        for (space, addr, access) in accesses {
+            let watchpoint = self
+                .watchpoints
+                .iter()
+                .find(|w| w.space == space && w.addrs.contains(&addr) && w.kind.matches(access));
+            if let Some(w) = watchpoint {
+                return Some(Hit::Watchpoint {
+                    id: w.id,
+                    space,
+                    addr,
+                    access,
+                });
+            }
+        }
+        None
+    }
+
+    // Updates the watch expressions evaluated `every` so often, returning a
+    // hit for the first to change if that stops execution
+    fn check_expressions(&mut self, console: &Console, every: Every) -> Option<Hit> {
+        let mut hit = None;
+        for e in self.expressions.iter_mut().filter(|e| e.every == every) {
+            let value = e.expr.eval(&console.cpu);
+            let changed = e.value.is_some_and(|old| old != value);
+            e.value = Some(value);
+            if changed && every == Every::Instruction && hit.is_none() {
+                hit = Some(Hit::Expression { id: e.id, value });
+            }
+        }
+        hit
+    }
+
+    fn report(&mut self, hit: Option<Hit>, console: &mut Console) -> Option<Hit> {
+        if let (Some(hit), Some(callback)) = (&hit, self.callback.as_mut()) {
+            callback(hit, console);
+        }
+        hit
+    }
+}
+
+fn detach(console: &mut Console) {
+    console.cpu.memory.access_log = None;
+    console.cpu.memory.ppu.access_log = None;
+}
@@ -0,0 +1,159 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    cpu::{AddressMode::*, INSTRUCTIONS},
+    memory::CPUMemory,
+    symbols::Symbols,
+};
+
+// One decoded instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub official: bool,
+    // Operand in assembler syntax, e.g. "#$10", "($80),Y" or "$C5F5"
+    pub operand: String,
+    // Where a branch, JMP or JSR transfers control to, if known statically
+    pub target: Option<u16>,
+}
+
+// Decodes the instruction at `addr`, fetching bytes with `read`
+pub fn decode<F: Fn(u16) -> u8>(read: F, addr: u16) -> Line {
+    let opcode = read(addr);
+    let instruction = &INSTRUCTIONS[opcode as usize];
+    let bytes: Vec<u8> = (0..instruction.mode.size())
+        .map(|i| read(addr.wrapping_add(i)))
+        .collect();
+    let lo = bytes.get(1).copied().unwrap_or(0);
+    let hi = bytes.get(2).copied().unwrap_or(0);
+    let word = (hi as u16) << 8 | lo as u16;
+
+    let mut target = None;
+    let operand = match instruction.mode {
+        Implied => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate => format!("#${:02X}", lo),
+        ZeroPage => format!("${:02X}", lo),
+        ZeroPageX => format!("${:02X},X", lo),
+        ZeroPageY => format!("${:02X},Y", lo),
+        Absolute => {
+            // JSR and JMP
+            if opcode == 0x20 || opcode == 0x4C {
+                target = Some(word);
+            }
+            format!("${:04X}", word)
+        }
+        AbsoluteX => format!("${:04X},X", word),
+        AbsoluteY => format!("${:04X},Y", word),
+        Indirect => format!("(${:04X})", word),
+        IndexedIndirect => format!("(${:02X},X)", lo),
+        IndirectIndexed => format!("(${:02X}),Y", lo),
+        Relative => {
+            let addr = addr.wrapping_add(2).wrapping_add(lo as i8 as u16);
+            target = Some(addr);
+            format!("${:04X}", addr)
+        }
+    };
+
+    Line {
+        addr,
+        bytes,
+        mnemonic: instruction.name,
+        official: instruction.official,
+        operand,
+        target,
+    }
+}
+
+// A linear disassembly with labels for every branch and jump target
+pub struct Listing {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<u16, String>,
+    // Notes shown after the instructions at their addresses
+    pub comments: BTreeMap<u16, String>,
+}
+
+// Disassembles `start..=end` linearly, reading bytes with `read`. Pass
+// `|addr| memory.peek(addr)` to disassemble from the CPU bus.
+pub fn disassemble<F: Fn(u16) -> u8>(read: F, start: u16, end: u16) -> Listing {
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let line = decode(&read, addr as u16);
+        addr += line.bytes.len() as u32;
+        lines.push(line);
+    }
+
+    let mut labels = BTreeMap::new();
+    for line in &lines {
+        if let Some(target) = line.target {
+            // JSR targets are subroutines; everything else is a plain label
+            let name = if line.bytes[0] == 0x20 {
+                format!("sub_{:04X}", target)
+            } else {
+                format!("L{:04X}", target)
+            };
+            labels.entry(target).or_insert(name);
+        }
+    }
+
+    Listing {
+        lines,
+        labels,
+        comments: BTreeMap::new(),
+    }
+}
+
+impl Listing {
+    // Names the listing's addresses and jump targets from `symbols` in place
+    // of the generated labels, and adds their comments
+    pub fn apply_symbols(&mut self, symbols: &Symbols, memory: &CPUMemory) {
+        let addrs = self
+            .lines
+            .iter()
+            .flat_map(|line| std::iter::once(line.addr).chain(line.target));
+        for addr in addrs {
+            let Some(symbol) = symbols.get(addr, memory) else {
+                continue;
+            };
+            if !symbol.name.is_empty() {
+                self.labels.insert(addr, symbol.name.clone());
+            }
+            if let Some(comment) = &symbol.comment {
+                self.comments.insert(addr, comment.replace('\n', " "));
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            let hex: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let operand = match line.target.and_then(|target| self.labels.get(&target)) {
+                Some(label) => label.as_str(),
+                None => line.operand.as_str(),
+            };
+            let prefix = if line.official { ' ' } else { '*' };
+            let text = format!("{} {}", line.mnemonic, operand);
+            write!(
+                f,
+                "  {:04X}  {:<8} {}{}",
+                line.addr,
+                hex.join(" "),
+                prefix,
+                text.trim_end()
+            )?;
+            match self.comments.get(&line.addr) {
+                Some(comment) => writeln!(f, "  ; {}", comment)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{console::Console, utils::splitmix64};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Observation {
+    // The frame as RGB bytes, row by row
+    #[default]
+    Rgb,
+    // The 2KB of internal RAM
+    Ram,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EnvConfig {
+    // Frames each action is held for; rewards over them are summed
+    pub frame_skip: u32,
+    pub observation: Observation,
+    // Most frames without input run after a reset, the count drawn from the
+    // seed, so episodes don't all start on the same frame
+    pub noop_max: u32,
+    // Steps after which an episode ends regardless, or 0 for no limit
+    pub max_steps: u64,
+    pub seed: u64,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            frame_skip: 4,
+            observation: Observation::Rgb,
+            noop_max: 30,
+            max_steps: 0,
+            seed: 0,
+        }
+    }
+}
+
+// Scores a frame. Closures taking the console work as rewards directly.
+pub trait Reward {
+    // Called once each episode, before its first step
+    fn reset(&mut self, _console: &Console) {}
+
+    // Reward for the frame just run
+    fn reward(&mut self, console: &Console) -> f32;
+}
+
+impl<F: FnMut(&Console) -> f32> Reward for F {
+    fn reward(&mut self, console: &Console) -> f32 {
+        self(console)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    // Little-endian binary
+    Binary,
+    // One decimal digit per byte, most significant first, as Super Mario
+    // Bros. keeps its score
+    Digits,
+    // Two decimal digits per byte, most significant first
+    PackedBCD,
+}
+
+// Rewards a number held in RAM for going up, e.g. a score or a scroll
+// position: each frame's reward is the change since the last, times `scale`.
+pub struct RamCounter {
+    addr: u16,
+    len: u16,
+    encoding: Encoding,
+    scale: f32,
+    last: i64,
+}
+
+impl RamCounter {
+    // The `len` bytes from `addr`, read as `encoding`
+    pub fn new(addr: u16, len: u16, encoding: Encoding) -> Self {
+        Self {
+            addr,
+            len,
+            encoding,
+            scale: 1.0,
+            last: 0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn value(&self, console: &Console) -> i64 {
+        let memory = &console.cpu.memory;
+        let bytes = (0..self.len).map(|i| memory.peek(self.addr.wrapping_add(i)));
+        match self.encoding {
+            Encoding::Binary => bytes.rev().fold(0, |value, byte| value << 8 | byte as i64),
+            Encoding::Digits => bytes.fold(0, |value, byte| value * 10 + (byte % 10) as i64),
+            Encoding::PackedBCD => bytes.fold(0, |value, byte| {
+                value * 100 + (byte >> 4) as i64 * 10 + (byte & 0x0F) as i64
+            }),
+        }
+    }
+}
+
+impl Reward for RamCounter {
+    fn reset(&mut self, console: &Console) {
+        self.last = self.value(console);
+    }
+
+    fn reward(&mut self, console: &Console) -> f32 {
+        let value = self.value(console);
+        let change = value - self.last;
+        self.last = value;
+        change as f32 * self.scale
+    }
+}
+
+// An episode end condition that holds when the byte at `addr` is `value`,
+// e.g. a lives counter reaching zero
+pub fn ram_equals(addr: u16, value: u8) -> impl FnMut(&Console) -> bool {
+    move |console: &Console| console.cpu.memory.peek(addr) == value
+}
+
+// Ends the episode once it returns true after a frame
+type Done = Box<dyn FnMut(&Console) -> bool>;
+
+// A Gym-style environment around a console, for reinforcement learning.
+// Actions are joypad 1's buttons, bit n for Button n. Episodes start from
+// the console's state when the environment was made, so load a save state
+// first to skip title screens. Runs are deterministic for a given seed.
+pub struct Env {
+    console: Console,
+    config: EnvConfig,
+    start: Vec<u8>,
+    rewards: Vec<Box<dyn Reward>>,
+    done: Vec<Done>,
+    rng: u64,
+    steps: u64,
+}
+
+impl Env {
+    pub fn new(console: Console, config: EnvConfig) -> Self {
+        Self {
+            start: console.save_state(),
+            console,
+            config,
+            rewards: Vec::new(),
+            done: Vec::new(),
+            rng: config.seed,
+            steps: 0,
+        }
+    }
+
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
+
+    pub fn console_mut(&mut self) -> &mut Console {
+        &mut self.console
+    }
+
+    pub fn config(&self) -> &EnvConfig {
+        &self.config
+    }
+
+    // Adds to what each step's reward is the sum of
+    pub fn add_reward(&mut self, reward: impl Reward + 'static) {
+        self.rewards.push(Box::new(reward));
+    }
+
+    // Adds a condition that ends the episode once it holds after a frame
+    pub fn add_done(&mut self, done: impl FnMut(&Console) -> bool + 'static) {
+        self.done.push(Box::new(done));
+    }
+
+    // Restarts the random sequence, as if the environment were made with
+    // `seed`
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = seed;
+    }
+
+    // Steps taken this episode
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Starts a new episode, returning its first observation
+    pub fn reset(&mut self) -> Vec<u8> {
+        self.console
+            .load_state(&self.start)
+            .expect("a console loads its own save state");
+        self.steps = 0;
+
+        let noops = self.next_random() % (self.config.noop_max as u64 + 1);
+        self.console.set_buttons(0, 0);
+        for _ in 0..noops {
+            self.console.step_frame();
+        }
+        for reward in &mut self.rewards {
+            reward.reset(&self.console);
+        }
+        self.observation()
+    }
+
+    // Holds `action` for the configured frame skip, returning the
+    // observation after, the reward earned and whether the episode is over.
+    // Stops early on the frame it ends.
+    pub fn step(&mut self, action: u8) -> (Vec<u8>, f32, bool) {
+        self.console.set_buttons(0, action);
+        let mut total = 0.0;
+        let mut done = false;
+        for _ in 0..self.config.frame_skip.max(1) {
+            self.console.step_frame();
+            for reward in &mut self.rewards {
+                total += reward.reward(&self.console);
+            }
+            done = self.done.iter_mut().any(|done| done(&self.console));
+            if done {
+                break;
+            }
+        }
+        self.steps += 1;
+        done |= self.config.max_steps > 0 && self.steps >= self.config.max_steps;
+        (self.observation(), total, done)
+    }
+
+    pub fn observation(&self) -> Vec<u8> {
+        match self.config.observation {
+            Observation::Rgb => self
+                .console
+                .framebuffer()
+                .pixels()
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect(),
+            Observation::Ram => self.console.cpu.memory.ram.to_vec(),
+        }
+    }
+
+    // SplitMix64, enough to spread the no-op counts
+    fn next_random(&mut self) -> u64 {
+        splitmix64(&mut self.rng)
+    }
+}
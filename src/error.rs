@@ -0,0 +1,282 @@
+use std::{fmt, io};
+
+use image::ImageError;
+
+#[cfg(feature = "audio-cpal")]
+use crate::audio::AudioError;
+#[cfg(all(feature = "gdb", not(target_arch = "wasm32")))]
+use crate::gdb::GdbError;
+#[cfg(feature = "scripting")]
+use crate::script::ScriptError;
+use crate::{
+    capture::CaptureError,
+    cartridge::CartridgeError,
+    cheats::CheatError,
+    config::ConfigError,
+    expr::ExprError,
+    movie::MovieError,
+    nsf::NSFError,
+    palette::PaletteError,
+    romdb::RomDbError,
+    savestate::{StateError, STATE_VERSION},
+    splits::SplitError,
+    symbols::SymbolError,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{netplay::NetplayError, recorder::RecordError};
+
+// Any error the library returns. Each module keeps its own error type, which
+// converts into this one with `?`; the cases callers most often handle,
+// like a ROM for an unsupported board, are lifted out of their module's
+// error so they can be matched without digging.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // A ROM or disk image that couldn't be read or isn't valid
+    InvalidRom(CartridgeError),
+    UnsupportedMapper(u16),
+    StateVersionMismatch {
+        expected: u32,
+        found: u32,
+    },
+    InvalidState(StateError),
+    Config(ConfigError),
+    Palette(PaletteError),
+    RomDb(RomDbError),
+    Movie(MovieError),
+    Cheat(CheatError),
+    NSF(NSFError),
+    Capture(CaptureError),
+    Image(ImageError),
+    Splits(SplitError),
+    Symbols(SymbolError),
+    Expr(ExprError),
+    #[cfg(not(target_arch = "wasm32"))]
+    Record(RecordError),
+    #[cfg(not(target_arch = "wasm32"))]
+    Netplay(NetplayError),
+    #[cfg(feature = "audio-cpal")]
+    Audio(AudioError),
+    #[cfg(feature = "scripting")]
+    Script(ScriptError),
+    #[cfg(all(feature = "gdb", not(target_arch = "wasm32")))]
+    Gdb(GdbError),
+    // Something this build left out, by the name of the cargo feature
+    MissingFeature(&'static str),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::InvalidRom(err) => write!(f, "{}", err),
+            Error::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            Error::StateVersionMismatch { expected, found } => write!(
+                f,
+                "save state version {} is not supported (expected {})",
+                found, expected
+            ),
+            Error::InvalidState(err) => write!(f, "{}", err),
+            Error::Config(err) => write!(f, "{}", err),
+            Error::Palette(err) => write!(f, "{}", err),
+            Error::RomDb(err) => write!(f, "{}", err),
+            Error::Movie(err) => write!(f, "{}", err),
+            Error::Cheat(err) => write!(f, "{}", err),
+            Error::NSF(err) => write!(f, "{}", err),
+            Error::Capture(err) => write!(f, "{}", err),
+            Error::Image(err) => write!(f, "failed to write image: {}", err),
+            Error::Splits(err) => write!(f, "{}", err),
+            Error::Symbols(err) => write!(f, "{}", err),
+            Error::Expr(err) => write!(f, "{}", err),
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Record(err) => write!(f, "{}", err),
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Netplay(err) => write!(f, "{}", err),
+            #[cfg(feature = "audio-cpal")]
+            Error::Audio(err) => write!(f, "{}", err),
+            #[cfg(feature = "scripting")]
+            Error::Script(err) => write!(f, "{}", err),
+            #[cfg(all(feature = "gdb", not(target_arch = "wasm32")))]
+            Error::Gdb(err) => write!(f, "{}", err),
+            Error::MissingFeature(feature) => {
+                write!(f, "nesrs was built without the {} feature", feature)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::InvalidRom(err) => Some(err),
+            Error::InvalidState(err) => Some(err),
+            Error::Config(err) => Some(err),
+            Error::Palette(err) => Some(err),
+            Error::RomDb(err) => Some(err),
+            Error::Movie(err) => Some(err),
+            Error::Cheat(err) => Some(err),
+            Error::NSF(err) => Some(err),
+            Error::Capture(err) => Some(err),
+            Error::Image(err) => Some(err),
+            Error::Splits(err) => Some(err),
+            Error::Symbols(err) => Some(err),
+            Error::Expr(err) => Some(err),
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Record(err) => Some(err),
+            #[cfg(not(target_arch = "wasm32"))]
+            Error::Netplay(err) => Some(err),
+            #[cfg(feature = "audio-cpal")]
+            Error::Audio(err) => Some(err),
+            #[cfg(feature = "scripting")]
+            Error::Script(err) => Some(err),
+            #[cfg(all(feature = "gdb", not(target_arch = "wasm32")))]
+            Error::Gdb(err) => Some(err),
+            Error::UnsupportedMapper(_)
+            | Error::StateVersionMismatch { .. }
+            | Error::MissingFeature(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<CartridgeError> for Error {
+    fn from(err: CartridgeError) -> Self {
+        match err {
+            CartridgeError::UnsupportedMapper(mapper) => Error::UnsupportedMapper(mapper),
+            err => Error::InvalidRom(err),
+        }
+    }
+}
+
+impl From<StateError> for Error {
+    fn from(err: StateError) -> Self {
+        match err {
+            StateError::UnsupportedVersion(found) => Error::StateVersionMismatch {
+                expected: STATE_VERSION,
+                found,
+            },
+            err => Error::InvalidState(err),
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        match err {
+            ConfigError::Palette(err) => Error::Palette(err),
+            err => Error::Config(err),
+        }
+    }
+}
+
+impl From<PaletteError> for Error {
+    fn from(err: PaletteError) -> Self {
+        Error::Palette(err)
+    }
+}
+
+impl From<RomDbError> for Error {
+    fn from(err: RomDbError) -> Self {
+        Error::RomDb(err)
+    }
+}
+
+impl From<MovieError> for Error {
+    fn from(err: MovieError) -> Self {
+        match err {
+            MovieError::State(err) => err.into(),
+            err => Error::Movie(err),
+        }
+    }
+}
+
+impl From<CheatError> for Error {
+    fn from(err: CheatError) -> Self {
+        Error::Cheat(err)
+    }
+}
+
+impl From<NSFError> for Error {
+    fn from(err: NSFError) -> Self {
+        Error::NSF(err)
+    }
+}
+
+impl From<CaptureError> for Error {
+    fn from(err: CaptureError) -> Self {
+        Error::Capture(err)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(err: ImageError) -> Self {
+        Error::Image(err)
+    }
+}
+
+impl From<SplitError> for Error {
+    fn from(err: SplitError) -> Self {
+        Error::Splits(err)
+    }
+}
+
+impl From<SymbolError> for Error {
+    fn from(err: SymbolError) -> Self {
+        Error::Symbols(err)
+    }
+}
+
+impl From<ExprError> for Error {
+    fn from(err: ExprError) -> Self {
+        Error::Expr(err)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<RecordError> for Error {
+    fn from(err: RecordError) -> Self {
+        Error::Record(err)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<NetplayError> for Error {
+    fn from(err: NetplayError) -> Self {
+        match err {
+            NetplayError::State(err) => err.into(),
+            err => Error::Netplay(err),
+        }
+    }
+}
+
+#[cfg(feature = "audio-cpal")]
+impl From<AudioError> for Error {
+    fn from(err: AudioError) -> Self {
+        Error::Audio(err)
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl From<ScriptError> for Error {
+    fn from(err: ScriptError) -> Self {
+        match err {
+            ScriptError::State(err) => err.into(),
+            err => Error::Script(err),
+        }
+    }
+}
+
+#[cfg(all(feature = "gdb", not(target_arch = "wasm32")))]
+impl From<GdbError> for Error {
+    fn from(err: GdbError) -> Self {
+        Error::Gdb(err)
+    }
+}
@@ -0,0 +1,116 @@
+use std::ops::RangeInclusive;
+
+// Something the console did, delivered to the handlers subscribed to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    // A frame finished, numbered as PPU::frame counts them
+    Frame(u64),
+    // The PPU started a scanline, numbered as PPU::scanline counts them
+    Scanline(i32),
+    // The CPU began servicing an interrupt
+    NMI,
+    IRQ,
+    // A CPU bus access, with the value read or written
+    Read { addr: u16, value: u8 },
+    Write { addr: u16, value: u8 },
+}
+
+// Which events a subscription hears
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventFilter {
+    Frame,
+    // One scanline, or every scanline with `None`
+    Scanline(Option<i32>),
+    NMI,
+    IRQ,
+    Read(RangeInclusive<u16>),
+    Write(RangeInclusive<u16>),
+}
+
+impl EventFilter {
+    fn matches(&self, event: &Event) -> bool {
+        match (self, event) {
+            (EventFilter::Frame, Event::Frame(_))
+            | (EventFilter::NMI, Event::NMI)
+            | (EventFilter::IRQ, Event::IRQ) => true,
+            (EventFilter::Scanline(line), Event::Scanline(scanline)) => {
+                line.is_none_or(|line| line == *scanline)
+            }
+            (EventFilter::Read(addrs), Event::Read { addr, .. })
+            | (EventFilter::Write(addrs), Event::Write { addr, .. }) => addrs.contains(addr),
+            _ => false,
+        }
+    }
+}
+
+// Called with each event its subscription matches. Handlers run in the
+// middle of emulation, so they only see the event; anything more, like
+// reading memory, waits until the console's step returns.
+pub type Handler = Box<dyn FnMut(&Event) + Send>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u32);
+
+struct Subscription {
+    id: SubscriptionId,
+    filter: EventFilter,
+    handler: Handler,
+}
+
+// Hands what the console does to subscribed handlers, for debuggers,
+// auto-splitters and achievement systems. Kinds of event no one has
+// subscribed to aren't looked for, so an empty bus costs nothing but a
+// flag check at each place one could be emitted.
+#[derive(Default)]
+pub struct EventBus {
+    subscriptions: Vec<Subscription>,
+    next_id: u32,
+    // Whether any subscription hears bus accesses, or scanlines
+    pub(crate) memory: bool,
+    pub(crate) scanlines: bool,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&mut self, filter: EventFilter, handler: Handler) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscriptions.push(Subscription {
+            id,
+            filter,
+            handler,
+        });
+        self.update_flags();
+        id
+    }
+
+    // Returns whether `id` was subscribed
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.subscriptions.len();
+        self.subscriptions
+            .retain(|subscription| subscription.id != id);
+        self.update_flags();
+        self.subscriptions.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        for subscription in &mut self.subscriptions {
+            if subscription.filter.matches(&event) {
+                (subscription.handler)(&event);
+            }
+        }
+    }
+
+    fn update_flags(&mut self) {
+        let filters = || self.subscriptions.iter().map(|s| &s.filter);
+        self.memory = filters().any(|f| matches!(f, EventFilter::Read(_) | EventFilter::Write(_)));
+        self.scanlines = filters().any(|f| matches!(f, EventFilter::Scanline(_)));
+    }
+}
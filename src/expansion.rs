@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+// A device plugged into the Famicom's expansion port. Every $4016 write
+// reaches it, and it answers in bits of $4016 and $4017 the joypads leave
+// alone, so it is read alongside them.
+pub enum ExpansionDevice {
+    FamilyKeyboard(FamilyKeyboard),
+    Vaus(Vaus),
+}
+
+impl ExpansionDevice {
+    // Bits the device drives on a read of $4016 (`port` 0) or $4017 (1)
+    pub fn read(&mut self, port: usize) -> u8 {
+        match self {
+            ExpansionDevice::FamilyKeyboard(keyboard) => keyboard.read(port),
+            ExpansionDevice::Vaus(vaus) => vaus.read(port),
+        }
+    }
+
+    // $4016 (write)
+    pub fn write(&mut self, value: u8) {
+        match self {
+            ExpansionDevice::FamilyKeyboard(keyboard) => keyboard.write(value),
+            ExpansionDevice::Vaus(vaus) => vaus.write(value),
+        }
+    }
+
+    // Saved ahead of the device's state, 0 standing for an empty port
+    pub fn state_tag(device: Option<&ExpansionDevice>) -> u8 {
+        match device {
+            None => 0,
+            Some(ExpansionDevice::FamilyKeyboard(_)) => 1,
+            Some(ExpansionDevice::Vaus(_)) => 2,
+        }
+    }
+
+    // Which device is plugged in is a setting, and what's held on it input,
+    // so only the scanning state is saved
+    pub fn save(&self, state: &mut StateWriter) {
+        match self {
+            ExpansionDevice::FamilyKeyboard(keyboard) => state.write(&keyboard.scan),
+            ExpansionDevice::Vaus(vaus) => state.write(&vaus.shift),
+        }
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        match self {
+            ExpansionDevice::FamilyKeyboard(keyboard) => keyboard.scan = state.read()?,
+            ExpansionDevice::Vaus(vaus) => vaus.shift = state.read()?,
+        }
+        Ok(())
+    }
+}
+
+// Keys of the Family BASIC keyboard, in the order its matrix scans them:
+// nine rows of two four-key columns, each column's keys in bits 1-4 of
+// $4017
+#[rustfmt::skip]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Key {
+    F8, Return, LeftBracket, RightBracket, Kana, RightShift, Yen, Stop,
+    F7, At, Colon, Semicolon, Underscore, Slash, Minus, Caret,
+    F6, O, L, K, Period, Comma, P, Num0,
+    F5, I, U, J, M, N, Num9, Num8,
+    F4, Y, G, H, B, V, Num7, Num6,
+    F3, T, R, D, F, C, Num5, Num4,
+    F2, W, S, A, X, Z, E, Num3,
+    F1, Escape, Q, Ctr, LeftShift, Grph, Num1, Num2,
+    ClrHome, Up, Right, Left, Down, Space, Del, Ins,
+}
+
+const KEYBOARD_ROWS: usize = 9;
+
+// The Family BASIC keyboard. Games enable it with bit 2 of $4016 writes,
+// go back to the first row with bit 0, and pick a column with bit 1, the
+// row moving on each time it falls from 1 to 0. Reads of $4017 return the
+// four keys of the current row and column, 0 for pressed.
+#[derive(Default)]
+pub struct FamilyKeyboard {
+    // Each row's keys, column 0 in bits 0-3 and column 1 in bits 4-7
+    keys: [u8; KEYBOARD_ROWS],
+    scan: KeyboardScan,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct KeyboardScan {
+    row: u8,
+    column: u8,
+    enabled: bool,
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_key(&mut self, key: Key, pressed: bool) {
+        let (row, bit) = (key as usize / 8, key as usize % 8);
+        if pressed {
+            self.keys[row] |= 1 << bit;
+        } else {
+            self.keys[row] &= !(1 << bit);
+        }
+    }
+
+    pub fn pressed(&self, key: Key) -> bool {
+        self.keys[key as usize / 8] >> (key as usize % 8) & 1 == 1
+    }
+
+    fn read(&self, port: usize) -> u8 {
+        let scan = &self.scan;
+        match (port, scan.enabled) {
+            (1, true) => match self.keys.get(scan.row as usize) {
+                Some(keys) => (!(keys >> (scan.column * 4)) << 1) & 0x1E,
+                // Past the last row nothing is pressed
+                None => 0x1E,
+            },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        let scan = &mut self.scan;
+        scan.enabled = value & 0x04 != 0;
+        if !scan.enabled {
+            return;
+        }
+        let column = (value >> 1) & 0x01;
+        if value & 0x01 != 0 {
+            scan.row = 0;
+        } else if scan.column == 1 && column == 0 {
+            scan.row = scan.row.saturating_add(1);
+        }
+        scan.column = column;
+    }
+}
+
+// The Vaus paddle packed with Arkanoid. Its knob's position is latched
+// by a strobe through bit 0 of $4016 and shifted out a bit a read, most
+// significant first and inverted. The Famicom version answers on the
+// expansion port in bit 1 of $4017, its button in bit 1 of $4016; the NES
+// version, in port 2, in bit 4 of $4017 with its button in bit 3.
+pub struct Vaus {
+    // Knob position, about 98 (left) to 242 (right) on real paddles
+    pub position: u8,
+    pub fire: bool,
+    famicom: bool,
+    shift: VausShift,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct VausShift {
+    latch: u8,
+    strobe: bool,
+}
+
+impl Vaus {
+    pub fn new(famicom: bool) -> Self {
+        Self {
+            position: 170,
+            fire: false,
+            famicom,
+            shift: VausShift::default(),
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        if port == 0 {
+            // Only the Famicom version answers here, with its button
+            return if self.famicom {
+                (self.fire as u8) << 1
+            } else {
+                0
+            };
+        }
+        let shift = &mut self.shift;
+        if shift.strobe {
+            shift.latch = self.position;
+        }
+        let data = !shift.latch >> 7;
+        shift.latch <<= 1;
+        if self.famicom {
+            data << 1
+        } else {
+            data << 4 | (self.fire as u8) << 3
+        }
+    }
+
+    fn write(&mut self, value: u8) {
+        self.shift.strobe = value & 0x01 != 0;
+        if self.shift.strobe {
+            self.shift.latch = self.position;
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use std::fmt;
+
+use crate::{cpu::CPU, symbols::Symbols};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExprError {
+    // Byte offset into the expression where it went wrong
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid expression at column {}: {}",
+            self.position + 1,
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    SP,
+    PC,
+    P,
+    // Single flags of P, 0 or 1
+    C,
+    Z,
+    I,
+    D,
+    V,
+    N,
+}
+
+impl Register {
+    fn from_name(name: &str) -> Option<Self> {
+        let register = match name.to_ascii_uppercase().as_str() {
+            "A" => Register::A,
+            "X" => Register::X,
+            "Y" => Register::Y,
+            "SP" | "S" => Register::SP,
+            "PC" => Register::PC,
+            "P" => Register::P,
+            "C" => Register::C,
+            "Z" => Register::Z,
+            "I" => Register::I,
+            "D" => Register::D,
+            "V" => Register::V,
+            "N" => Register::N,
+            _ => return None,
+        };
+        Some(register)
+    }
+
+    fn read(self, cpu: &CPU) -> i64 {
+        let value = match self {
+            Register::A => cpu.a as u16,
+            Register::X => cpu.x as u16,
+            Register::Y => cpu.y as u16,
+            Register::SP => cpu.sp as u16,
+            Register::PC => cpu.pc,
+            Register::P => cpu.flags() as u16,
+            Register::C => cpu.c as u16,
+            Register::Z => cpu.z as u16,
+            Register::I => cpu.i as u16,
+            Register::D => cpu.d as u16,
+            Register::V => cpu.v as u16,
+            Register::N => cpu.n as u16,
+        };
+        value as i64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    // Operators from the loosest binding to the tightest, as in C
+    const LEVELS: [&'static [(&'static str, BinaryOp)]; 10] = [
+        &[("||", BinaryOp::Or)],
+        &[("&&", BinaryOp::And)],
+        &[("|", BinaryOp::BitOr)],
+        &[("^", BinaryOp::BitXor)],
+        &[("&", BinaryOp::BitAnd)],
+        &[("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual)],
+        &[
+            ("<=", BinaryOp::LessEqual),
+            (">=", BinaryOp::GreaterEqual),
+            ("<", BinaryOp::Less),
+            (">", BinaryOp::Greater),
+        ],
+        &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+        &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+        &[
+            ("*", BinaryOp::Mul),
+            ("/", BinaryOp::Div),
+            ("%", BinaryOp::Rem),
+        ],
+    ];
+
+    fn apply(self, left: i64, right: i64) -> i64 {
+        match self {
+            BinaryOp::Mul => left.wrapping_mul(right),
+            // Dividing by zero gives 0 rather than stopping the game
+            BinaryOp::Div => left.checked_div(right).unwrap_or(0),
+            BinaryOp::Rem => left.checked_rem(right).unwrap_or(0),
+            BinaryOp::Add => left.wrapping_add(right),
+            BinaryOp::Sub => left.wrapping_sub(right),
+            BinaryOp::Shl => left.wrapping_shl(right as u32),
+            BinaryOp::Shr => left.wrapping_shr(right as u32),
+            BinaryOp::Less => (left < right) as i64,
+            BinaryOp::LessEqual => (left <= right) as i64,
+            BinaryOp::Greater => (left > right) as i64,
+            BinaryOp::GreaterEqual => (left >= right) as i64,
+            BinaryOp::Equal => (left == right) as i64,
+            BinaryOp::NotEqual => (left != right) as i64,
+            BinaryOp::BitAnd => left & right,
+            BinaryOp::BitXor => left ^ right,
+            BinaryOp::BitOr => left | right,
+            BinaryOp::And => (left != 0 && right != 0) as i64,
+            BinaryOp::Or => (left != 0 || right != 0) as i64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Number(i64),
+    Register(Register),
+    // The byte at an address, or the little-endian word there
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, cpu: &CPU) -> i64 {
+        match self {
+            Node::Number(value) => *value,
+            Node::Register(register) => register.read(cpu),
+            Node::Byte(addr) => cpu.memory.peek(addr.eval(cpu) as u16) as i64,
+            Node::Word(addr) => {
+                let addr = addr.eval(cpu) as u16;
+                let lo = cpu.memory.peek(addr) as i64;
+                let hi = cpu.memory.peek(addr.wrapping_add(1)) as i64;
+                hi << 8 | lo
+            }
+            Node::Unary(op, operand) => {
+                let value = operand.eval(cpu);
+                match op {
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Complement => !value,
+                }
+            }
+            // && and || don't read what they don't need
+            Node::Binary(BinaryOp::And, left, right) => {
+                (left.eval(cpu) != 0 && right.eval(cpu) != 0) as i64
+            }
+            Node::Binary(BinaryOp::Or, left, right) => {
+                (left.eval(cpu) != 0 || right.eval(cpu) != 0) as i64
+            }
+            Node::Binary(op, left, right) => op.apply(left.eval(cpu), right.eval(cpu)),
+        }
+    }
+}
+
+// An expression over the machine's state, for watches and breakpoint
+// conditions, e.g. `[$00A2] + [$00A3]*256 > 1000`:
+//
+// - numbers in decimal, or hex with $ or 0x, or binary with %
+// - [addr] for the byte at an address and {addr} for the word there, with
+//   any expression for the address; reads have no side effects
+// - registers A, X, Y, SP, PC and P, and flags C, Z, I, D, V and N
+// - labels, with symbols to look them up in
+// - C's operators and precedence, with comparisons and logic giving 0 or 1
+//
+// Values are 64-bit and wrap; anything nonzero counts as true.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expr {
+    text: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Self, ExprError> {
+        Self::parse_with(text, None)
+    }
+
+    // Parses with labels from `symbols` standing for their addresses
+    pub fn parse_with(text: &str, symbols: Option<&Symbols>) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            text,
+            position: 0,
+            symbols,
+            depth: 0,
+        };
+        let root = parser.expression(0)?;
+        parser.skip_space();
+        if parser.position < text.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(Self {
+            text: text.to_string(),
+            root,
+        })
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        self.root.eval(cpu)
+    }
+
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+// Shows the expression as it was written
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+// How deep the tree of an expression may grow, counting each paren, bracket
+// and unary operator it nests in and each binary operator chained before it,
+// which keeps the recursive parse, evaluation and drop well within the stack
+const MAX_DEPTH: usize = 128;
+
+// Precedence climbing over the text directly; the grammar is small enough
+// not to need tokens
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    symbols: Option<&'a Symbols>,
+    // How deep in the tree the node being parsed will sit
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ExprError {
+        ExprError {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.position..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected {}", token))),
+        }
+    }
+
+    // Binary operators of `level` and tighter
+    fn expression(&mut self, level: usize) -> Result<Node, ExprError> {
+        let Some(operators) = BinaryOp::LEVELS.get(level) else {
+            return self.unary();
+        };
+        let depth = self.depth;
+        let mut left = self.expression(level + 1)?;
+        'operators: loop {
+            self.skip_space();
+            for &(token, op) in operators.iter() {
+                // "|" isn't "||", nor "&" "&&", nor "<" "<<"
+                let rest = self.rest();
+                let longer = BinaryOp::LEVELS
+                    .iter()
+                    .flat_map(|level| level.iter())
+                    .any(|&(other, _)| other.len() > token.len() && rest.starts_with(other));
+                if rest.starts_with(token) && !longer {
+                    // A chain builds leftwards, one level deeper per operator
+                    self.deepen()?;
+                    self.position += token.len();
+                    let right = self.expression(level + 1)?;
+                    left = Node::Binary(op, Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            self.depth = depth;
+            return Ok(left);
+        }
+    }
+
+    fn deepen(&mut self) -> Result<(), ExprError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("expression nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Every nested paren, bracket and unary operator comes back through
+    // here, so this is where their depth is counted
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        self.deepen()?;
+        let node = self.operand();
+        self.depth -= 1;
+        node
+    }
+
+    fn operand(&mut self) -> Result<Node, ExprError> {
+        let op = if self.eat("-") {
+            UnaryOp::Negate
+        } else if self.eat("!") {
+            UnaryOp::Not
+        } else if self.eat("~") {
+            UnaryOp::Complement
+        } else {
+            return self.primary();
+        };
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        if self.eat("(") {
+            let node = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if self.eat("[") {
+            let addr = self.expression(0)?;
+            self.expect("]")?;
+            return Ok(Node::Byte(Box::new(addr)));
+        }
+        if self.eat("{") {
+            let addr = self.expression(0)?;
+            self.expect("}")?;
+            return Ok(Node::Word(Box::new(addr)));
+        }
+        self.skip_space();
+        let start = self.position;
+        if self.eat("$") {
+            return self.number(16, start);
+        }
+        if self.eat("%") {
+            return self.number(2, start);
+        }
+        if self.eat("0x") || self.eat("0X") {
+            return self.number(16, start);
+        }
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some(c) if c.is_ascii_digit() => self.number(10, start),
+            Some(c) if c.is_alphabetic() || c == '_' || c == '@' => self.name(),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of expression")),
+        }
+    }
+
+    fn number(&mut self, radix: u32, start: usize) -> Result<Node, ExprError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(rest.len());
+        let value = i64::from_str_radix(&rest[..len], radix).map_err(|_| ExprError {
+            position: start,
+            message: "invalid number".to_string(),
+        })?;
+        self.position += len;
+        Ok(Node::Number(value))
+    }
+
+    // A register, or a label from the symbols
+    fn name(&mut self) -> Result<Node, ExprError> {
+        let start = self.position;
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '@'))
+            .unwrap_or(rest.len());
+        let name = &rest[..len];
+        if let Some(register) = Register::from_name(name) {
+            self.position += len;
+            return Ok(Node::Register(register));
+        }
+        match self.symbols.and_then(|symbols| symbols.address(name)) {
+            Some(addr) => {
+                self.position += len;
+                Ok(Node::Number(addr as i64))
+            }
+            None => Err(ExprError {
+                position: start,
+                message: format!("unknown name {}", name),
+            }),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::{fs, path::Path};
+
+use crate::cartridge::CartridgeError;
+
+const FDS_MAGIC: [u8; 4] = *b"FDS\x1A";
+const HEADER_SIZE: usize = 16;
+// Bytes of block data per side in a .fds image
+const SIDE_SIZE: usize = 65500;
+// Zero bytes before the first block and between blocks, as the drive sees
+// them (28300 and 976 bits)
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+// Length of a side as the drive streams it, a little over what its blocks
+// and gaps need
+const RAW_SIDE_SIZE: usize = 0x12000;
+// First byte of block 1, the disk info block
+const DISK_INFO_BLOCK: u8 = 0x01;
+
+// A Famicom Disk System disk image. The .fds format keeps only block data,
+// so each side is rebuilt as the stream the drive's head reads: gaps of
+// zeros, a $80 start mark before each block and two CRC bytes after it.
+// The CRC bytes are left as zeros since the drive here never fails a check.
+#[derive(Clone, Debug)]
+pub struct Disk {
+    pub sides: Vec<Vec<u8>>,
+}
+
+impl Disk {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CartridgeError> {
+        // fwNES headers are optional and only add a side count
+        let data = if bytes.starts_with(&FDS_MAGIC) {
+            let sides = bytes.get(4).copied().unwrap_or(0) as usize;
+            let expected = HEADER_SIZE + sides * SIDE_SIZE;
+            if bytes.len() < expected {
+                return Err(CartridgeError::Truncated {
+                    expected,
+                    actual: bytes.len(),
+                });
+            }
+            &bytes[HEADER_SIZE..expected]
+        } else {
+            bytes
+        };
+
+        if data.len() < SIDE_SIZE {
+            return Err(CartridgeError::Truncated {
+                expected: SIDE_SIZE,
+                actual: data.len(),
+            });
+        }
+        let sides = data
+            .chunks_exact(SIDE_SIZE)
+            .map(|side| match side[0] {
+                DISK_INFO_BLOCK => Ok(raw_side(side)),
+                _ => Err(CartridgeError::InvalidDisk),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { sides })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, CartridgeError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+// Lays out a side's blocks: the disk info block (1), the file count (2),
+// then a header (3) and data (4) block per file. Disks often hide files
+// past the count, so headers are followed for as long as they appear.
+fn raw_side(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEADING_GAP];
+    let mut pos = 0;
+    let mut file_size = 0;
+    while pos < side.len() {
+        let len = match side[pos] {
+            1 => 56,
+            2 => 2,
+            3 if pos + 16 <= side.len() => {
+                file_size = u16::from_le_bytes([side[pos + 13], side[pos + 14]]) as usize;
+                16
+            }
+            4 => 1 + file_size,
+            _ => break,
+        };
+        let block = &side[pos..(pos + len).min(side.len())];
+        raw.push(0x80);
+        raw.extend_from_slice(block);
+        raw.extend_from_slice(&[0, 0]);
+        raw.resize(raw.len() + BLOCK_GAP, 0);
+        pos += len;
+    }
+    raw.resize(raw.len().max(RAW_SIDE_SIZE), 0);
+    raw
+}
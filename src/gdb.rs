@@ -0,0 +1,464 @@
+use std::{
+    fmt, io,
+    marker::PhantomData,
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    num::NonZeroUsize,
+};
+
+use gdbstub::{
+    arch::{Arch, RegId, Registers},
+    common::Signal,
+    conn::ConnectionExt,
+    stub::{run_blocking, GdbStub, SingleThreadStopReason},
+    target::{
+        ext::{
+            base::{
+                single_register_access::{SingleRegisterAccess, SingleRegisterAccessOps},
+                singlethread::{
+                    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps,
+                    SingleThreadSingleStep, SingleThreadSingleStepOps,
+                },
+                BaseOps,
+            },
+            breakpoints::{
+                Breakpoints, BreakpointsOps, HwWatchpoint, HwWatchpointOps, SwBreakpoint,
+                SwBreakpointOps, WatchKind as GdbWatchKind,
+            },
+        },
+        Target, TargetError, TargetResult,
+    },
+};
+
+use crate::{
+    console::Console,
+    debugger::{Debugger, Hit, Space, WatchKind},
+    memory::Access,
+};
+
+#[derive(Debug)]
+pub enum GdbError {
+    Io(io::Error),
+    // The remote protocol broke down, e.g. a malformed packet
+    Protocol(String),
+}
+
+impl fmt::Display for GdbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GdbError::Io(err) => write!(f, "gdb connection failed: {}", err),
+            GdbError::Protocol(err) => write!(f, "gdb session failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for GdbError {}
+
+impl From<io::Error> for GdbError {
+    fn from(err: io::Error) -> Self {
+        GdbError::Io(err)
+    }
+}
+
+// The 6502 as gdb sees it. gdb has no 6502 of its own, so the registers are
+// described to it in a target description.
+pub enum MOS6502 {}
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nesrs.mos6502">
+    <reg name="a" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+impl Arch for MOS6502 {
+    type Usize = u16;
+    type Registers = CPURegisters;
+    // 6502 breakpoints come in one size
+    type BreakpointKind = usize;
+    type RegId = CPURegister;
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(TARGET_XML)
+    }
+}
+
+// Sent to gdb in the order of TARGET_XML, pc little-endian
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CPURegisters {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+}
+
+impl Registers for CPURegisters {
+    type ProgramCounter = u16;
+
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for byte in [self.a, self.x, self.y, self.p, self.sp] {
+            write_byte(Some(byte));
+        }
+        for byte in self.pc.to_le_bytes() {
+            write_byte(Some(byte));
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let &[a, x, y, p, sp, pc_low, pc_high] = bytes else {
+            return Err(());
+        };
+        *self = CPURegisters {
+            a,
+            x,
+            y,
+            p,
+            sp,
+            pc: u16::from_le_bytes([pc_low, pc_high]),
+        };
+        Ok(())
+    }
+}
+
+// A register by gdb's number for it, as TARGET_XML counts them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CPURegister {
+    A,
+    X,
+    Y,
+    P,
+    SP,
+    PC,
+}
+
+impl RegId for CPURegister {
+    fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+        let (register, size) = match id {
+            0 => (CPURegister::A, 1),
+            1 => (CPURegister::X, 1),
+            2 => (CPURegister::Y, 1),
+            3 => (CPURegister::P, 1),
+            4 => (CPURegister::SP, 1),
+            5 => (CPURegister::PC, 2),
+            _ => return None,
+        };
+        Some((register, NonZeroUsize::new(size)))
+    }
+}
+
+// Waits for gdb to connect at `addr`, e.g. "127.0.0.1:9001", then runs
+// `console` under it until gdb detaches or kills it. In gdb:
+//
+//     target remote 127.0.0.1:9001
+pub fn serve<A: ToSocketAddrs>(console: &mut Console, addr: A) -> Result<(), GdbError> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    serve_connection(console, stream)
+}
+
+// Runs `console` under the gdb already connected on `stream`
+pub fn serve_connection(console: &mut Console, stream: TcpStream) -> Result<(), GdbError> {
+    let mut target = GdbTarget {
+        console,
+        debugger: Debugger::new(),
+        breakpoints: Vec::new(),
+        watchpoints: Vec::new(),
+        stepping: false,
+    };
+    GdbStub::new(stream)
+        .run_blocking::<EventLoop>(&mut target)
+        .map_err(|err| GdbError::Protocol(err.to_string()))?;
+    Ok(())
+}
+
+// gdb's breakpoints and watchpoints, kept in a Debugger by their ids there
+struct GdbTarget<'a> {
+    console: &'a mut Console,
+    debugger: Debugger,
+    breakpoints: Vec<(u16, usize)>,
+    watchpoints: Vec<(u16, u16, WatchKind, usize)>,
+    // Whether the next resume runs one instruction rather than freely
+    stepping: bool,
+}
+
+impl GdbTarget<'_> {
+    fn stop_reason(hit: &Hit) -> SingleThreadStopReason<u16> {
+        match *hit {
+            Hit::Breakpoint { .. } => SingleThreadStopReason::SwBreak(()),
+            // gdb sets no watch expressions, but would take one as a trap
+            Hit::Expression { .. } => SingleThreadStopReason::Signal(Signal::SIGTRAP),
+            Hit::Watchpoint { addr, access, .. } => SingleThreadStopReason::Watch {
+                tid: (),
+                kind: match access {
+                    Access::Read => GdbWatchKind::Read,
+                    Access::Write => GdbWatchKind::Write,
+                },
+                addr,
+            },
+        }
+    }
+}
+
+impl Target for GdbTarget<'_> {
+    type Arch = MOS6502;
+    type Error = &'static str;
+
+    fn base_ops(&mut self) -> BaseOps<'_, MOS6502, Self::Error> {
+        BaseOps::SingleThread(self)
+    }
+
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for GdbTarget<'_> {
+    fn read_registers(&mut self, regs: &mut CPURegisters) -> TargetResult<(), Self> {
+        let cpu = &self.console.cpu;
+        *regs = CPURegisters {
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.flags(),
+            sp: cpu.sp,
+            pc: cpu.pc,
+        };
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &CPURegisters) -> TargetResult<(), Self> {
+        let cpu = &mut self.console.cpu;
+        cpu.a = regs.a;
+        cpu.x = regs.x;
+        cpu.y = regs.y;
+        cpu.set_flags(regs.p);
+        cpu.sp = regs.sp;
+        cpu.pc = regs.pc;
+        Ok(())
+    }
+
+    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<'_, (), Self>> {
+        Some(self)
+    }
+
+    // Without side effects, so looking at $2002 doesn't clear vblank
+    fn read_addrs(&mut self, start_addr: u16, data: &mut [u8]) -> TargetResult<usize, Self> {
+        let memory = &self.console.cpu.memory;
+        for (offset, byte) in data.iter_mut().enumerate() {
+            *byte = memory.peek(start_addr.wrapping_add(offset as u16));
+        }
+        Ok(data.len())
+    }
+
+    // RAM is written directly and cartridge space as the CPU would write
+    // it, so writes there can switch banks. Registers are refused; their
+    // writes set things in motion better left to the program.
+    fn write_addrs(&mut self, start_addr: u16, data: &[u8]) -> TargetResult<(), Self> {
+        let memory = &mut self.console.cpu.memory;
+        for (offset, &byte) in data.iter().enumerate() {
+            match start_addr.wrapping_add(offset as u16) {
+                addr @ 0x0000..=0x1FFF => memory.ram[addr as usize % 0x0800] = byte,
+                0x2000..=0x401F => return Err(TargetError::NonFatal),
+                addr @ 0x4020..=0xFFFF => memory.mapper_mut().prg_write(addr, byte),
+            }
+        }
+        Ok(())
+    }
+
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleRegisterAccess<()> for GdbTarget<'_> {
+    fn read_register(
+        &mut self,
+        _tid: (),
+        register: CPURegister,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        let cpu = &self.console.cpu;
+        let bytes = match register {
+            CPURegister::A => vec![cpu.a],
+            CPURegister::X => vec![cpu.x],
+            CPURegister::Y => vec![cpu.y],
+            CPURegister::P => vec![cpu.flags()],
+            CPURegister::SP => vec![cpu.sp],
+            CPURegister::PC => cpu.pc.to_le_bytes().to_vec(),
+        };
+        let len = bytes.len().min(buf.len());
+        buf[..len].copy_from_slice(&bytes[..len]);
+        Ok(len)
+    }
+
+    fn write_register(
+        &mut self,
+        _tid: (),
+        register: CPURegister,
+        value: &[u8],
+    ) -> TargetResult<(), Self> {
+        let cpu = &mut self.console.cpu;
+        match (register, value) {
+            (CPURegister::A, &[value]) => cpu.a = value,
+            (CPURegister::X, &[value]) => cpu.x = value,
+            (CPURegister::Y, &[value]) => cpu.y = value,
+            (CPURegister::P, &[value]) => cpu.set_flags(value),
+            (CPURegister::SP, &[value]) => cpu.sp = value,
+            (CPURegister::PC, &[low, high]) => cpu.pc = u16::from_le_bytes([low, high]),
+            _ => return Err(TargetError::NonFatal),
+        }
+        Ok(())
+    }
+}
+
+impl SingleThreadResume for GdbTarget<'_> {
+    fn resume(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+        if signal.is_some() {
+            return Err("the NES has no signals to resume with");
+        }
+        self.stepping = false;
+        Ok(())
+    }
+
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for GdbTarget<'_> {
+    fn step(&mut self, signal: Option<Signal>) -> Result<(), Self::Error> {
+        if signal.is_some() {
+            return Err("the NES has no signals to step with");
+        }
+        self.stepping = true;
+        Ok(())
+    }
+}
+
+impl Breakpoints for GdbTarget<'_> {
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+
+    fn support_hw_watchpoint(&mut self) -> Option<HwWatchpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for GdbTarget<'_> {
+    fn add_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        let id = self.debugger.add_breakpoint(addr);
+        self.breakpoints.push((addr, id));
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        let Some(index) = self.breakpoints.iter().position(|&(a, _)| a == addr) else {
+            return Ok(false);
+        };
+        let (_, id) = self.breakpoints.remove(index);
+        self.debugger.remove(id);
+        Ok(true)
+    }
+}
+
+fn watch_kind(kind: GdbWatchKind) -> WatchKind {
+    match kind {
+        GdbWatchKind::Read => WatchKind::Read,
+        GdbWatchKind::Write => WatchKind::Write,
+        GdbWatchKind::ReadWrite => WatchKind::ReadWrite,
+    }
+}
+
+impl HwWatchpoint for GdbTarget<'_> {
+    fn add_hw_watchpoint(
+        &mut self,
+        addr: u16,
+        len: u16,
+        kind: GdbWatchKind,
+    ) -> TargetResult<bool, Self> {
+        let Some(last) = addr.checked_add(len.max(1) - 1) else {
+            return Ok(false);
+        };
+        let kind = watch_kind(kind);
+        let id = self.debugger.add_watchpoint(Space::CPU, addr..=last, kind);
+        self.watchpoints.push((addr, len, kind, id));
+        Ok(true)
+    }
+
+    fn remove_hw_watchpoint(
+        &mut self,
+        addr: u16,
+        len: u16,
+        kind: GdbWatchKind,
+    ) -> TargetResult<bool, Self> {
+        let kind = watch_kind(kind);
+        let Some(index) = self
+            .watchpoints
+            .iter()
+            .position(|&(a, l, k, _)| (a, l, k) == (addr, len, kind))
+        else {
+            return Ok(false);
+        };
+        let (.., id) = self.watchpoints.remove(index);
+        self.debugger.remove(id);
+        Ok(true)
+    }
+}
+
+struct EventLoop<'a>(PhantomData<&'a mut Console>);
+
+impl<'a> run_blocking::BlockingEventLoop for EventLoop<'a> {
+    type Target = GdbTarget<'a>;
+    type Connection = TcpStream;
+    type StopReason = SingleThreadStopReason<u16>;
+
+    // Steps, or runs a frame at a time between checks for gdb interrupting
+    #[allow(clippy::type_complexity)]
+    fn wait_for_stop_reason(
+        target: &mut GdbTarget<'a>,
+        conn: &mut TcpStream,
+    ) -> Result<
+        run_blocking::Event<Self::StopReason>,
+        run_blocking::WaitForStopReasonError<&'static str, io::Error>,
+    > {
+        if target.stepping {
+            let hit = target.debugger.step_instruction(target.console);
+            let reason = hit
+                .as_ref()
+                .map_or(SingleThreadStopReason::DoneStep, GdbTarget::stop_reason);
+            return Ok(run_blocking::Event::TargetStopped(reason));
+        }
+        loop {
+            if conn.peek().map_or(true, |byte| byte.is_some()) {
+                let byte = conn
+                    .read()
+                    .map_err(run_blocking::WaitForStopReasonError::Connection)?;
+                return Ok(run_blocking::Event::IncomingData(byte));
+            }
+            if let Some(hit) = target.debugger.step_frame(target.console) {
+                return Ok(run_blocking::Event::TargetStopped(GdbTarget::stop_reason(
+                    &hit,
+                )));
+            }
+        }
+    }
+
+    fn on_interrupt(
+        _target: &mut GdbTarget<'a>,
+    ) -> Result<Option<SingleThreadStopReason<u16>>, &'static str> {
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
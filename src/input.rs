@@ -0,0 +1,61 @@
+use crate::movie::{Movie, COMMAND_POWER, COMMAND_RESET};
+
+// Input for one frame: the buttons held on each of the four joypads, as
+// Controller::buttons bits, and whether reset is pressed or the power
+// cycled as it starts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameInput {
+    pub buttons: [u8; 4],
+    pub reset: bool,
+    pub power: bool,
+}
+
+// Where a Console gets its input from, asked once at the start of every
+// frame. Keyboards, replays, scripts and bots all plug in the same way;
+// closures taking the frame number work as sources too.
+pub trait InputSource: Send {
+    fn next_frame(&mut self, frame: u64) -> FrameInput;
+}
+
+impl<F: FnMut(u64) -> FrameInput + Send> InputSource for F {
+    fn next_frame(&mut self, frame: u64) -> FrameInput {
+        self(frame)
+    }
+}
+
+// Feeds a movie's frames in order, then nothing pressed. Unlike
+// Console::play_movie it doesn't load the movie's save state or region.
+pub struct Replay {
+    movie: Movie,
+    frame: usize,
+}
+
+impl Replay {
+    pub fn new(movie: Movie) -> Self {
+        Self { movie, frame: 0 }
+    }
+
+    // Whether every frame has been fed
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    pub fn into_movie(self) -> Movie {
+        self.movie
+    }
+}
+
+impl InputSource for Replay {
+    fn next_frame(&mut self, _frame: u64) -> FrameInput {
+        let Some(input) = self.movie.frames.get(self.frame) else {
+            return FrameInput::default();
+        };
+        self.frame += 1;
+        let [port1, port2] = input.ports;
+        FrameInput {
+            buttons: [port1, port2, 0, 0],
+            reset: input.commands & COMMAND_RESET != 0,
+            power: input.commands & COMMAND_POWER != 0,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// Type names follow the hardware (CPU, PPU, NROM, ...)
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod achievements;
+pub mod apu;
+pub mod audio;
+pub mod capture;
+pub mod cartridge;
+pub mod cheats;
+pub mod config;
+pub mod console;
+pub mod controller;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod env;
+pub mod error;
+pub mod events;
+pub mod expansion;
+pub mod expr;
+pub mod fds;
+// gdb talks to the server over TCP, which browsers don't offer
+#[cfg(all(feature = "gdb", not(target_arch = "wasm32")))]
+pub mod gdb;
+pub mod input;
+pub mod mapper;
+pub mod memory;
+pub mod memory_diff;
+pub mod movie;
+// UDP sockets aren't available to browsers
+#[cfg(not(target_arch = "wasm32"))]
+pub mod netplay;
+pub mod nsf;
+pub mod palette;
+// Browsers can't spawn threads
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pool;
+pub mod ppu;
+pub mod ppu_viewer;
+pub mod profiler;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod ram_search;
+// Browsers can't run ffmpeg
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recorder;
+pub mod region;
+pub mod rewind;
+pub mod romdb;
+pub mod savestate;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod splits;
+pub mod symbols;
+pub mod test_rom;
+// Browsers pace frames themselves and have no Instant
+#[cfg(not(target_arch = "wasm32"))]
+pub mod timing;
+pub mod trace;
+pub mod utils;
+pub mod video;
+pub mod vs;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use cartridge::Cartridge;
+pub use console::Console;
+pub use controller::Button;
+pub use error::{Error, Result};
+#[cfg(not(target_arch = "wasm32"))]
+pub use pool::ConsolePool;
+pub use region::Region;
@@ -1,8 +0,0 @@
-mod console;
-mod cpu;
-mod memory;
-mod ppu;
-
-fn main() {
-    println!("Hello, world!");
-}
@@ -0,0 +1,151 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Up to 32KB of CHR RAM in 8KB banks
+const CHR_RAM_SIZE: usize = 0x8000;
+
+// Mapper 28: Action 53, the homebrew multicart board. Games built for NROM,
+// CNROM, UxROM, AxROM and BNROM run unchanged inside an outer bank set by
+// the menu. Writes to $5000-$5FFF select one of four registers (bits 7 and
+// 0), which writes to $8000-$FFFF then set:
+//   $00: CHR RAM bank (bits 0-1)
+//   $01: inner PRG bank (bits 0-3)
+//   $80: mode: mirroring (bits 0-1), PRG banking (bits 2-3) and the size
+//        of the outer bank (bits 4-5, 32KB to 256KB)
+//   $81: outer 32KB PRG bank
+// In the one-screen mirroring modes, bit 4 of writes to $00 and $01 picks
+// the screen, as an AxROM game expects.
+pub struct Action53 {
+    cartridge: Cartridge,
+    select: u8,
+    chr_bank: u8,
+    inner_bank: u8,
+    mode: u8,
+    outer_bank: u8,
+}
+
+impl Action53 {
+    pub fn new(mut cartridge: Cartridge) -> Self {
+        if cartridge.chr_ram && cartridge.chr.len() < CHR_RAM_SIZE {
+            cartridge.chr.resize(CHR_RAM_SIZE, 0);
+        }
+        Self {
+            cartridge,
+            select: 0,
+            chr_bank: 0,
+            inner_bank: 0,
+            mode: 0,
+            // The menu boots from the last 32KB
+            outer_bank: 0xFF,
+        }
+    }
+
+    // 16KB PRG bank behind `addr`
+    fn prg_bank(&self, addr: u16) -> usize {
+        let a14 = (addr as usize >> 14) & 1;
+        let outer = (self.outer_bank as usize) << 1;
+        let banking = (self.mode as usize >> 2) & 0x03;
+        // UxROM modes fix the outer bank's first half at $8000 (mode 2) or
+        // its last at $C000 (mode 3)
+        if (banking ^ a14) == 0x02 {
+            return outer | a14;
+        }
+        let inner = match banking {
+            0 | 1 => (self.inner_bank as usize) << 1 | a14,
+            _ => self.inner_bank as usize,
+        };
+        // 32KB to 256KB of the outer bank come from the inner one
+        let mask = (2 << ((self.mode >> 4) & 0x03)) - 1;
+        (inner & mask) | (outer & !mask)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let len = self.cartridge.chr.len();
+        let bank = bank_offset(len, 0x2000, (self.chr_bank & 0x03) as isize);
+        (bank + addr as usize) % len
+    }
+}
+
+impl Mapper for Action53 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let len = self.cartridge.prg.len();
+                let bank = bank_offset(len, 0x4000, self.prg_bank(addr) as isize);
+                self.cartridge.prg[bank + (addr as usize & 0x3FFF)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000..=0x5FFF => self.select = value & 0x81,
+            0x8000..=0xFFFF => {
+                match self.select {
+                    0x00 => self.chr_bank = value,
+                    0x01 => self.inner_bank = value,
+                    0x80 => self.mode = value,
+                    _ => self.outer_bank = value,
+                }
+                if self.select & 0x80 == 0 && self.mode & 0x02 == 0 {
+                    self.mode = (self.mode & !0x01) | ((value >> 4) & 0x01);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Nothing on the board answers at $6000-$7FFF
+    fn sram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[self.chr_offset(addr)]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.cartridge.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mode & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.select);
+        state.write(&self.chr_bank);
+        state.write(&self.inner_bank);
+        state.write(&self.mode);
+        state.write(&self.outer_bank);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.select = state.read()?;
+        self.chr_bank = state.read()?;
+        self.inner_bank = state.read()?;
+        self.mode = state.read()?;
+        self.outer_bank = state.read()?;
+        Ok(())
+    }
+}
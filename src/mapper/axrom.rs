@@ -0,0 +1,93 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 7: switchable 32KB PRG bank and one-screen mirroring selected by
+// the same register. Oversize boards take a fourth bank bit, for 512KB.
+pub struct AxROM {
+    cartridge: Cartridge,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl AxROM {
+    pub fn new(cartridge: Cartridge, bus_conflicts: bool) -> Self {
+        Self {
+            cartridge,
+            bus_conflicts,
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for AxROM {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => {
+                let prg = &self.cartridge.prg;
+                prg[(self.prg_bank + (addr as usize - 0x8000)) % prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            let value = if self.bus_conflicts {
+                value & self.prg_read(addr)
+            } else {
+                value
+            };
+            let len = self.cartridge.prg.len();
+            self.prg_bank = bank_offset(len, 0x8000, (value & 0x0F) as isize);
+            self.mirroring = match value & 0x10 {
+                0 => Mirroring::SingleScreenLower,
+                _ => Mirroring::SingleScreenUpper,
+            };
+        }
+    }
+
+    // Nothing on the board answers at $6000-$7FFF
+    fn sram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[addr as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.mirroring);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read()?;
+        self.mirroring = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 34 as BNROM: a switchable 32KB PRG bank, like AxROM without the
+// mirroring control, and 8KB of CHR RAM
+pub struct BNROM {
+    cartridge: Cartridge,
+    bus_conflicts: bool,
+    prg_bank: usize,
+}
+
+impl BNROM {
+    pub fn new(cartridge: Cartridge, bus_conflicts: bool) -> Self {
+        Self {
+            cartridge,
+            bus_conflicts,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for BNROM {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0x8000..=0xFFFF => {
+                let prg = &self.cartridge.prg;
+                prg[(self.prg_bank + (addr as usize - 0x8000)) % prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.write_sram(addr as usize - 0x6000, value),
+            0x8000..=0xFFFF => {
+                let value = if self.bus_conflicts {
+                    value & self.prg_read(addr)
+                } else {
+                    value
+                };
+                let len = self.cartridge.prg.len();
+                self.prg_bank = bank_offset(len, 0x8000, value as isize);
+            }
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[addr as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
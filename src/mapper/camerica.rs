@@ -0,0 +1,186 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 71: Camerica/Codemasters BF9093, a UxROM-like switchable 16KB bank
+// at $8000 selected by writes to $C000-$FFFF, and the last bank fixed at
+// $C000. The BF9097 on Fire Hawk's board adds one-screen mirroring selected
+// by bit 4 of writes to $8000-$9FFF; no other game writes there, so the
+// header's mirroring holds until one does.
+pub struct BF9093 {
+    cartridge: Cartridge,
+    prg_bank: usize,
+    prg_last: usize,
+    mirroring: Option<Mirroring>,
+}
+
+impl BF9093 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let prg_last = bank_offset(cartridge.prg.len(), 0x4000, -1);
+        Self {
+            cartridge,
+            prg_bank: 0,
+            prg_last,
+            mirroring: None,
+        }
+    }
+}
+
+impl Mapper for BF9093 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xBFFF => self.cartridge.prg[self.prg_bank + (addr as usize - 0x8000)],
+            0xC000..=0xFFFF => self.cartridge.prg[self.prg_last + (addr as usize - 0xC000)],
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => {
+                self.mirroring = Some(match value & 0x10 {
+                    0 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                });
+            }
+            0xC000..=0xFFFF => {
+                let len = self.cartridge.prg.len();
+                self.prg_bank = bank_offset(len, 0x4000, (value & 0x0F) as isize);
+            }
+            _ => {}
+        }
+    }
+
+    // Nothing on the board answers at $6000-$7FFF
+    fn sram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[addr as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.unwrap_or_else(|| self.cartridge.mirroring())
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.mirroring);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read()?;
+        self.mirroring = state.read()?;
+        Ok(())
+    }
+}
+
+// Mapper 232: Camerica BF9096, on the Quattro multicarts. Writes to
+// $8000-$BFFF pick one of four 64KB blocks, and writes to $C000-$FFFF a 16KB
+// bank at $8000 within it, with the block's last bank fixed at $C000. The
+// Aladdin Deck Enhancer (submapper 1) wires the block bits the other way
+// round.
+pub struct BF9096 {
+    cartridge: Cartridge,
+    aladdin: bool,
+    block: usize,
+    bank: usize,
+}
+
+impl BF9096 {
+    pub fn new(cartridge: Cartridge, aladdin: bool) -> Self {
+        Self {
+            cartridge,
+            aladdin,
+            block: 0,
+            bank: 0,
+        }
+    }
+
+    fn prg_offset(&self, bank: usize, addr: u16) -> usize {
+        let index = (self.block << 2 | bank) as isize;
+        bank_offset(self.cartridge.prg.len(), 0x4000, index) + (addr as usize & 0x3FFF)
+    }
+}
+
+impl Mapper for BF9096 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xBFFF => self.cartridge.prg[self.prg_offset(self.bank, addr)],
+            0xC000..=0xFFFF => self.cartridge.prg[self.prg_offset(3, addr)],
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0xBFFF => {
+                let block = (value >> 3) as usize & 0x03;
+                self.block = if self.aladdin {
+                    (block & 1) << 1 | block >> 1
+                } else {
+                    block
+                };
+            }
+            0xC000..=0xFFFF => self.bank = value as usize & 0x03,
+            _ => {}
+        }
+    }
+
+    // Nothing on the board answers at $6000-$7FFF
+    fn sram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[addr as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.block);
+        state.write(&self.bank);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.block = state.read()?;
+        self.bank = state.read()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 3: fixed PRG like NROM with a switchable 8KB CHR bank
+pub struct CNROM {
+    cartridge: Cartridge,
+    bus_conflicts: bool,
+    chr_bank: usize,
+}
+
+impl CNROM {
+    pub fn new(cartridge: Cartridge, bus_conflicts: bool) -> Self {
+        Self {
+            cartridge,
+            bus_conflicts,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for CNROM {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0x8000..=0xFFFF => {
+                let prg = &self.cartridge.prg;
+                prg[(addr as usize - 0x8000) % prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.write_sram(addr as usize - 0x6000, value),
+            0x8000..=0xFFFF => {
+                let value = if self.bus_conflicts {
+                    value & self.prg_read(addr)
+                } else {
+                    value
+                };
+                let len = self.cartridge.chr.len();
+                self.chr_bank = bank_offset(len, 0x2000, (value & 0x03) as isize);
+            }
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[self.chr_bank + addr as usize]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            self.cartridge.chr[self.chr_bank + addr as usize] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.chr_bank);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.chr_bank = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
@@ -0,0 +1,404 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    fds::Disk,
+    mapper::{fds_audio::FDSAudio, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// CPU cycles per byte at the drive's ~96.4kbit/s
+const BYTE_CYCLES: u32 = 149;
+// CPU cycles the drive takes to bring the head back to the start of the
+// disk once the motor turns on
+const REWIND_CYCLES: u32 = 50000;
+
+// The Famicom Disk System's RAM adapter: 32KB of work RAM at $6000, the
+// BIOS at $E000, 8KB of CHR RAM, a CPU cycle timer IRQ, the disk drive's
+// registers and the wavetable sound channel. The drive streams one byte of
+// the inserted side every BYTE_CYCLES, raising an IRQ per byte when asked.
+pub struct FDS {
+    cartridge: Cartridge,
+    disk: Disk,
+    // Side in the drive, if any
+    side: Option<usize>,
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+    // $4023 (bit 0: disk registers; bit 1: sound registers)
+    disk_io: bool,
+    sound_io: bool,
+    // $4025 (bit 7: IRQ per byte; bit 6: look for a block start, CRC on;
+    //        bit 4: transfer the CRC; bit 3: horizontal mirroring;
+    //        bit 2: read rather than write; bit 1: hold the transfer;
+    //        bit 0: motor on)
+    control: u8,
+    read_data: u8,
+    write_data: u8,
+    transfer_complete: bool,
+    disk_irq: bool,
+    // Byte of the side under the head and cycles until the next one
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    // Set once the start mark of a block has been read
+    gap_ended: bool,
+    audio: FDSAudio,
+    // Frontend setting, not saved
+    volume: f32,
+}
+
+impl FDS {
+    pub fn new(mut cartridge: Cartridge) -> Self {
+        let disk = cartridge.disk.take().unwrap_or(Disk { sides: Vec::new() });
+        let side = (!disk.sides.is_empty()).then_some(0);
+        Self {
+            cartridge,
+            disk,
+            side,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            disk_io: false,
+            sound_io: false,
+            control: 0,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            disk_irq: false,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            audio: FDSAudio::new(),
+            volume: 1.0,
+        }
+    }
+
+    fn motor_on(&self) -> bool {
+        self.control & 0x01 != 0
+    }
+
+    fn transfer_held(&self) -> bool {
+        self.control & 0x02 != 0
+    }
+
+    fn read_mode(&self) -> bool {
+        self.control & 0x04 != 0
+    }
+
+    fn crc_control(&self) -> bool {
+        self.control & 0x10 != 0
+    }
+
+    fn block_start(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+
+    fn transfer_irq(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    fn step_timer(&mut self) {
+        if !self.irq_enabled || !self.disk_io {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            if self.irq_repeat {
+                self.irq_counter = self.irq_reload;
+            } else {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn step_drive(&mut self) {
+        let Some(side) = self.side else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if !self.motor_on() {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.transfer_held() && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        if self.read_mode() {
+            let byte = self.disk.sides[side][self.position];
+            if !self.block_start() {
+                self.gap_ended = false;
+            } else if byte != 0 && !self.gap_ended {
+                // The start mark itself isn't handed to the CPU
+                self.gap_ended = true;
+            } else if self.gap_ended {
+                self.read_data = byte;
+                self.transfer_complete = true;
+                self.disk_irq |= self.transfer_irq();
+            }
+        } else {
+            let mut byte = 0;
+            if !self.crc_control() {
+                byte = self.write_data;
+                self.transfer_complete = true;
+                self.disk_irq |= self.transfer_irq();
+            }
+            // Gaps are written while block start is off, and CRCs are
+            // written as zeros like the ones the image was built with
+            if !self.block_start() || self.crc_control() {
+                byte = 0;
+            }
+            self.disk.sides[side][self.position] = byte;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.disk.sides[side].len() {
+            // The head reached the end of the disk and the motor stops
+            self.control &= !0x01;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for FDS {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            // Disk status (bit 6: end of disk; bit 4: CRC error, never
+            // reported; bit 1: byte transferred; bit 0: timer IRQ)
+            0x4030 if self.disk_io => {
+                self.timer_irq as u8
+                    | (self.transfer_complete as u8) << 1
+                    | (self.end_of_head as u8) << 6
+            }
+            0x4031 if self.disk_io => self.read_data,
+            // Drive status (bit 2: write protected; bit 1: not ready;
+            // bit 0: no disk)
+            0x4032 if self.disk_io => {
+                let empty = self.side.is_none() as u8;
+                let not_ready = (self.side.is_none() || !self.scanning) as u8;
+                0x40 | empty << 2 | not_ready << 1 | empty
+            }
+            // Expansion port; bit 7 reports a good battery
+            0x4033 if self.disk_io => 0x80,
+            0x4040..=0x4092 if self.sound_io => self.audio.read(addr) | 0x40,
+            0x6000..=0xDFFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0xE000..=0xFFFF => self.cartridge.prg[addr as usize - 0xE000],
+            _ => 0,
+        }
+    }
+
+    // Reading the disk status acknowledges both IRQs, and reading the
+    // data the transfer's
+    fn prg_read(&mut self, addr: u16) -> u8 {
+        let value = self.prg_peek(addr);
+        match addr {
+            0x4030 if self.disk_io => {
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+            }
+            0x4031 if self.disk_io => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+        value
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (value as u16) << 8,
+            // Timer control (bit 1: enable, reloading the counter;
+            //                bit 0: repeat)
+            0x4022 if self.disk_io => {
+                self.irq_repeat = value & 0x01 != 0;
+                self.irq_enabled = value & 0x02 != 0;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_io = value & 0x01 != 0;
+                self.sound_io = value & 0x02 != 0;
+                if !self.disk_io {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_io => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_io => {
+                self.control = value;
+                self.disk_irq = false;
+            }
+            0x4040..=0x408A if self.sound_io => self.audio.write(addr, value),
+            0x6000..=0xDFFF => self.cartridge.write_sram(addr as usize - 0x6000, value),
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4030..=0x4033 => !self.disk_io,
+            0x4040..=0x4092 => !self.sound_io,
+            0x4020..=0x5FFF => true,
+            _ => false,
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        self.cartridge.chr[addr as usize] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.control & 0x08 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.step_timer();
+        self.step_drive();
+        self.audio.step();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output() * self.volume
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        vec!["Wave"]
+    }
+
+    fn set_expansion_volume(&mut self, channel: usize, volume: f32) {
+        if channel == 0 {
+            self.volume = volume;
+        }
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.disk.sides.len()
+    }
+
+    fn disk_side(&self) -> Option<usize> {
+        self.side
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.side = side.filter(|&side| side < self.disk.sides.len());
+        self.end_of_head = true;
+        self.scanning = false;
+    }
+
+    fn disk(&self) -> Option<&Disk> {
+        Some(&self.disk)
+    }
+
+    fn disk_mut(&mut self) -> Option<&mut Disk> {
+        Some(&mut self.disk)
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.side);
+        state.write(&self.irq_reload);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_repeat);
+        state.write(&self.irq_enabled);
+        state.write(&self.timer_irq);
+        state.write(&self.disk_io);
+        state.write(&self.sound_io);
+        state.write(&self.control);
+        state.write(&self.read_data);
+        state.write(&self.write_data);
+        state.write(&self.transfer_complete);
+        state.write(&self.disk_irq);
+        state.write(&self.position);
+        state.write(&self.delay);
+        state.write(&self.end_of_head);
+        state.write(&self.scanning);
+        state.write(&self.gap_ended);
+        state.write(&self.audio);
+        state.write(&self.cartridge.sram[..]);
+        // Games save to the disk, so its contents are part of the state
+        for side in &self.disk.sides {
+            state.write(&side[..]);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.side = state.read()?;
+        self.irq_reload = state.read()?;
+        self.irq_counter = state.read()?;
+        self.irq_repeat = state.read()?;
+        self.irq_enabled = state.read()?;
+        self.timer_irq = state.read()?;
+        self.disk_io = state.read()?;
+        self.sound_io = state.read()?;
+        self.control = state.read()?;
+        self.read_data = state.read()?;
+        self.write_data = state.read()?;
+        self.transfer_complete = state.read()?;
+        self.disk_irq = state.read()?;
+        self.position = state.read()?;
+        self.delay = state.read()?;
+        self.end_of_head = state.read()?;
+        self.scanning = state.read()?;
+        self.gap_ended = state.read()?;
+        self.audio = state.read()?;
+        state.read_into(&mut self.cartridge.sram)?;
+        for side in &mut self.disk.sides {
+            state.read_into(side)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+
+// Mixer level of one step of output: a full-volume wave (63 at gain 32)
+// comes out about twice as loud as an APU pulse, as on hardware
+const LEVEL: f32 = 0.00015;
+// Output scale for each $4089 master volume setting: 2/2, 2/3, 2/4, 2/5
+const MASTER_VOLUMES: [f32; 4] = [1.0, 2.0 / 3.0, 0.5, 0.4];
+// Change to the modulation counter for each mod table entry; 4 resets it
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+// A gain that ramps up or down every 8 * (speed + 1) * master speed CPU
+// cycles, or stays where it was set directly
+#[derive(Default, Serialize, Deserialize)]
+struct Envelope {
+    // $4080/$4084 (bit 7: set the gain directly; bit 6: increase;
+    //              bits 0-5: speed, or the gain when set directly)
+    direct: bool,
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8, master_speed: u8) {
+        self.direct = value & 0x80 != 0;
+        self.increase = value & 0x40 != 0;
+        self.speed = value & 0x3F;
+        if self.direct {
+            self.gain = self.speed;
+        }
+        self.timer = self.period(master_speed);
+    }
+
+    fn period(&self, master_speed: u8) -> u32 {
+        8 * (self.speed as u32 + 1) * master_speed as u32
+    }
+
+    fn step(&mut self, master_speed: u8) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period(master_speed);
+        if !self.direct {
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+// The Disk System's sound channel: a 64-step wavetable of 6-bit samples
+// whose pitch is bent by a modulation unit stepping through a table of
+// deltas, each with its own gain envelope
+#[derive(Serialize, Deserialize)]
+pub(crate) struct FDSAudio {
+    wave: Vec<u8>,
+    // $4089 (bit 7: wave RAM writable, holding the output; bits 0-1:
+    //        master volume)
+    wave_write: bool,
+    master_volume: u8,
+    volume: Envelope,
+    frequency: u16,
+    // $4083 bits 7 and 6
+    halt_wave: bool,
+    halt_envelopes: bool,
+    wave_accumulator: u32,
+    wave_position: u8,
+    modulation: Envelope,
+    mod_frequency: u16,
+    // $4087 bit 7, which also opens the mod table to $4088 writes
+    halt_mod: bool,
+    mod_accumulator: u32,
+    // 7-bit signed
+    mod_counter: i8,
+    mod_table: Vec<u8>,
+    mod_position: u8,
+    // $408A, scaling both envelopes' periods; 0 stops them
+    envelope_speed: u8,
+    // Level held while wave RAM is being written
+    output: u32,
+}
+
+impl FDSAudio {
+    pub(crate) fn new() -> Self {
+        Self {
+            wave: vec![0; 64],
+            wave_write: false,
+            master_volume: 0,
+            volume: Envelope::default(),
+            frequency: 0,
+            halt_wave: true,
+            halt_envelopes: false,
+            wave_accumulator: 0,
+            wave_position: 0,
+            modulation: Envelope::default(),
+            mod_frequency: 0,
+            halt_mod: true,
+            mod_accumulator: 0,
+            mod_counter: 0,
+            mod_table: vec![0; 64],
+            mod_position: 0,
+            envelope_speed: 0xE8,
+            output: 0,
+        }
+    }
+
+    pub(crate) fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x4040..=0x407F => self.wave[addr as usize - 0x4040],
+            0x4090 => self.volume.gain,
+            0x4092 => self.modulation.gain,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave[addr as usize - 0x4040] = value & 0x3F,
+            0x4080 => self.volume.write(value, self.envelope_speed),
+            0x4082 => self.frequency = (self.frequency & 0x0F00) | value as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.halt_wave = value & 0x80 != 0;
+                self.halt_envelopes = value & 0x40 != 0;
+                if self.halt_wave {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+            }
+            0x4084 => self.modulation.write(value, self.envelope_speed),
+            // Sign-extends the 7-bit counter
+            0x4085 => self.mod_counter = ((value << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | value as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((value & 0x0F) as u16) << 8;
+                self.halt_mod = value & 0x80 != 0;
+                if self.halt_mod {
+                    self.mod_accumulator = 0;
+                }
+            }
+            // Each write fills two entries, the table playing every value
+            // twice
+            0x4088 if self.halt_mod => {
+                let position = self.mod_position as usize;
+                self.mod_table[position] = value & 0x07;
+                self.mod_table[position + 1] = value & 0x07;
+                self.mod_position = (self.mod_position + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.wave_write = value & 0x80 != 0;
+                self.master_volume = value & 0x03;
+            }
+            0x408A => self.envelope_speed = value,
+            _ => {}
+        }
+    }
+
+    // Called once per CPU cycle
+    pub(crate) fn step(&mut self) {
+        if !self.halt_wave && !self.halt_envelopes && self.envelope_speed != 0 {
+            self.volume.step(self.envelope_speed);
+            self.modulation.step(self.envelope_speed);
+        }
+
+        if !self.halt_mod && self.mod_frequency != 0 {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator > 0xFFFF {
+                self.mod_accumulator &= 0xFFFF;
+                let entry = self.mod_table[self.mod_position as usize] as usize;
+                self.mod_counter = match entry {
+                    4 => 0,
+                    // Wraps within 7 bits
+                    _ => ((self.mod_counter + MOD_STEPS[entry]) << 1) >> 1,
+                };
+                self.mod_position = (self.mod_position + 1) & 0x3F;
+            }
+        }
+
+        if !self.halt_wave {
+            let pitch = (self.frequency as i32 + self.mod_pitch()).max(0);
+            self.wave_accumulator += pitch as u32;
+            if self.wave_accumulator > 0xFFFF {
+                self.wave_accumulator &= 0xFFFF;
+                self.wave_position = (self.wave_position + 1) & 0x3F;
+            }
+        }
+
+        if !self.wave_write {
+            let gain = self.volume.gain.min(32) as u32;
+            self.output = self.wave[self.wave_position as usize] as u32 * gain;
+        }
+    }
+
+    // Pitch offset from the modulation unit, computed the way the chip
+    // does with its rounding quirks
+    fn mod_pitch(&self) -> i32 {
+        if self.halt_mod {
+            return 0;
+        }
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= self.frequency as i32;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        temp
+    }
+
+    pub(crate) fn output(&self) -> f32 {
+        self.output as f32 * MASTER_VOLUMES[self.master_volume as usize] * LEVEL
+    }
+}
@@ -0,0 +1,400 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mixer level of a 5B channel at full volume, a little above an APU pulse
+// at full volume
+const LEVEL: f32 = 0.2;
+// CPU cycles per tick of the 5B's tone, noise and envelope generators
+const AUDIO_DIVIDER: u8 = 8;
+
+// Mapper 69: Sunsoft FME-7 and its 5A/5B variants. Registers are reached
+// through a command written to $8000 and a parameter written to $A000:
+// eight 1KB CHR banks, four 8KB PRG banks (the first at $6000, possibly
+// RAM), mirroring and a 16-bit CPU cycle IRQ counter. The 5B adds three
+// square wave channels driven through $C000/$E000.
+pub struct FME7 {
+    cartridge: Cartridge,
+    command: u8,
+    chr_banks: [u8; 8],
+    // Command 8 (bit 7: RAM enable; bit 6: RAM rather than ROM at $6000;
+    //            bits 0-5: bank)
+    prg_ram_bank: u8,
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq_pending: bool,
+    audio: Sunsoft5B,
+    // Frontend settings for the three channels; not saved
+    volumes: [f32; 3],
+    prg_offsets: [usize; 4],
+    chr_offsets: [usize; 8],
+}
+
+impl FME7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mirroring = cartridge.mirroring();
+        let mut mapper = Self {
+            cartridge,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_ram_bank: 0,
+            prg_banks: [0; 3],
+            mirroring,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5B::default(),
+            volumes: [1.0; 3],
+            prg_offsets: [0; 4],
+            chr_offsets: [0; 8],
+        };
+        mapper.update_offsets();
+        mapper
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            command @ 0..=7 => self.chr_banks[command as usize] = value,
+            8 => self.prg_ram_bank = value,
+            command @ 9..=0x0B => self.prg_banks[command as usize - 9] = value & 0x3F,
+            0x0C => {
+                self.mirroring = match value & 3 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            // IRQ control (bit 7: count down; bit 0: raise IRQs), which also
+            // acknowledges a pending IRQ
+            0x0D => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x0E => self.counter = (self.counter & 0xFF00) | value as u16,
+            _ => self.counter = (self.counter & 0x00FF) | (value as u16) << 8,
+        }
+        self.update_offsets();
+    }
+
+    fn update_offsets(&mut self) {
+        let len = self.cartridge.prg.len();
+        self.prg_offsets = [
+            bank_offset(len, 0x2000, (self.prg_ram_bank & 0x3F) as isize),
+            bank_offset(len, 0x2000, self.prg_banks[0] as isize),
+            bank_offset(len, 0x2000, self.prg_banks[1] as isize),
+            bank_offset(len, 0x2000, self.prg_banks[2] as isize),
+        ];
+        for (i, &bank) in self.chr_banks.iter().enumerate() {
+            self.chr_offsets[i] = bank_offset(self.cartridge.chr.len(), 0x0400, bank as isize);
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_offsets[addr as usize / 0x0400] + addr as usize % 0x0400
+    }
+
+    fn prg_ram_selected(&self) -> bool {
+        self.prg_ram_bank & 0x40 != 0
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_ram_bank & 0xC0 == 0xC0
+    }
+}
+
+impl Mapper for FME7 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.cartridge.read_sram(addr - 0x6000),
+            0x6000..=0x7FFF if self.prg_ram_selected() => 0,
+            0x6000..=0x7FFF => self.cartridge.prg[self.prg_offsets[0] + addr - 0x6000],
+            0x8000..=0xDFFF => {
+                let slot = (addr - 0x6000) / 0x2000;
+                self.cartridge.prg[self.prg_offsets[slot] + addr % 0x2000]
+            }
+            0xE000..=0xFFFF => {
+                let offset = bank_offset(self.cartridge.prg.len(), 0x2000, -1);
+                self.cartridge.prg[offset + addr - 0xE000]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.cartridge.write_sram(addr as usize - 0x6000, value);
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.select(value),
+            0xE000..=0xFFFF => self.audio.write(value),
+            _ => {}
+        }
+    }
+
+    // $6000 is ROM unless RAM is selected there
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4020..=0x5FFF => true,
+            0x6000..=0x7FFF if self.prg_ram_selected() => {
+                !self.prg_ram_enabled() || self.cartridge.sram.is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    fn sram_offset(&self, addr: u16) -> Option<usize> {
+        (self.prg_ram_enabled() && !self.cartridge.sram.is_empty()).then(|| addr as usize - 0x6000)
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[self.chr_offset(addr)]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.cartridge.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // The counter decrements every CPU cycle while enabled and raises the
+    // IRQ as it wraps from $0000 to $FFFF
+    fn cpu_clock(&mut self) {
+        if self.counter_enabled {
+            if self.counter == 0 && self.irq_enabled {
+                self.irq_pending = true;
+            }
+            self.counter = self.counter.wrapping_sub(1);
+        }
+        self.audio.step();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        (0..3)
+            .map(|channel| self.audio.output(channel) * self.volumes[channel])
+            .sum::<f32>()
+            * LEVEL
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        vec!["Square A", "Square B", "Square C"]
+    }
+
+    fn set_expansion_volume(&mut self, channel: usize, volume: f32) {
+        if let Some(v) = self.volumes.get_mut(channel) {
+            *v = volume;
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.command);
+        state.write(&self.chr_banks);
+        state.write(&self.prg_ram_bank);
+        state.write(&self.prg_banks);
+        state.write(&self.mirroring);
+        state.write(&self.irq_enabled);
+        state.write(&self.counter_enabled);
+        state.write(&self.counter);
+        state.write(&self.irq_pending);
+        state.write(&self.audio);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.command = state.read()?;
+        self.chr_banks = state.read()?;
+        self.prg_ram_bank = state.read()?;
+        self.prg_banks = state.read()?;
+        self.mirroring = state.read()?;
+        self.irq_enabled = state.read()?;
+        self.counter_enabled = state.read()?;
+        self.counter = state.read()?;
+        self.irq_pending = state.read()?;
+        self.audio = state.read()?;
+        self.update_offsets();
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
+
+// The 5B's YM2149-style sound generator: three square waves that can each
+// mix in a shared noise source, with a fixed volume or a shared envelope
+#[derive(Default, Serialize, Deserialize)]
+struct Sunsoft5B {
+    register: u8,
+    // Tone periods and their timers, in generator ticks
+    tone_periods: [u16; 3],
+    tone_timers: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_period: u8,
+    noise_timer: u8,
+    // 17-bit LFSR
+    noise_shift: u32,
+    // Register 7 (bits 0-2: disable tone A-C; bits 3-5: disable noise A-C)
+    mixer: u8,
+    // Registers 8-A (bit 4: use the envelope; bits 0-3: volume)
+    volumes: [u8; 3],
+    envelope_period: u16,
+    envelope_timer: u16,
+    // Register D (bit 3: continue; bit 2: attack; bit 1: alternate;
+    //             bit 0: hold)
+    envelope_shape: u8,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+    divider: u8,
+}
+
+impl Sunsoft5B {
+    // $C000: selects the register $E000 writes to. Writes with any of the
+    // upper bits set select nothing.
+    fn select(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.register {
+            register @ 0..=5 => {
+                let period = &mut self.tone_periods[register as usize / 2];
+                *period = if register & 1 == 0 {
+                    (*period & 0x0F00) | value as u16
+                } else {
+                    (*period & 0x00FF) | ((value & 0x0F) as u16) << 8
+                };
+            }
+            6 => self.noise_period = value & 0x1F,
+            7 => self.mixer = value,
+            register @ 8..=0x0A => self.volumes[register as usize - 8] = value & 0x1F,
+            0x0B => self.envelope_period = (self.envelope_period & 0xFF00) | value as u16,
+            0x0C => self.envelope_period = (self.envelope_period & 0x00FF) | (value as u16) << 8,
+            0x0D => {
+                self.envelope_shape = value & 0x0F;
+                self.envelope_attack = value & 0x04 != 0;
+                self.envelope_step = 0;
+                self.envelope_timer = 0;
+                self.envelope_holding = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn step(&mut self) {
+        self.divider += 1;
+        if self.divider < AUDIO_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+
+        // Squares and noise flip every other tick per period, the envelope
+        // steps once per period
+        for channel in 0..3 {
+            self.tone_timers[channel] += 1;
+            if self.tone_timers[channel] >= self.tone_periods[channel].max(1) * 2 {
+                self.tone_timers[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_timer += 1;
+        if self.noise_timer >= self.noise_period.max(1) * 2 {
+            self.noise_timer = 0;
+            if self.noise_shift == 0 {
+                self.noise_shift = 1;
+            }
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+        }
+
+        self.envelope_timer += 1;
+        if self.envelope_timer >= self.envelope_period.max(1) {
+            self.envelope_timer = 0;
+            self.step_envelope();
+        }
+    }
+
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        let shape = self.envelope_shape;
+        if shape & 0x08 == 0 {
+            // One ramp, then silence
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+            return;
+        }
+        if shape & 0x02 != 0 {
+            self.envelope_attack = !self.envelope_attack;
+        }
+        if shape & 0x01 != 0 {
+            self.envelope_holding = true;
+        } else {
+            self.envelope_step = 0;
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_holding && self.envelope_shape & 0x08 == 0 {
+            0
+        } else if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    // Level of `channel` from 0.0 to 1.0
+    fn output(&self, channel: usize) -> f32 {
+        let tone = self.tone_outputs[channel] || self.mixer & (1 << channel) != 0;
+        let noise = self.noise_shift & 1 != 0 || self.mixer & (8 << channel) != 0;
+        if !(tone && noise) {
+            return 0.0;
+        }
+        let volume = self.volumes[channel];
+        let level = if volume & 0x10 != 0 {
+            self.envelope_level()
+        } else {
+            match volume & 0x0F {
+                0 => 0,
+                volume => volume * 2 + 1,
+            }
+        };
+        // Levels are 1.5dB apart
+        match level {
+            0 => 0.0,
+            level => 10f32.powf((level as f32 - 31.0) * 1.5 / 20.0),
+        }
+    }
+}
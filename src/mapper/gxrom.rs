@@ -0,0 +1,101 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 66 (GxROM) and mapper 11 (Color Dreams): one register selecting a
+// 32KB PRG bank and an 8KB CHR bank, with the fields the other way round on
+// Color Dreams boards
+pub struct GxROM {
+    cartridge: Cartridge,
+    color_dreams: bool,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl GxROM {
+    pub fn new(cartridge: Cartridge, color_dreams: bool, bus_conflicts: bool) -> Self {
+        Self {
+            cartridge,
+            color_dreams,
+            bus_conflicts,
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for GxROM {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0x8000..=0xFFFF => {
+                let prg = &self.cartridge.prg;
+                prg[(self.prg_bank + (addr as usize - 0x8000)) % prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.write_sram(addr as usize - 0x6000, value),
+            0x8000..=0xFFFF => {
+                let value = if self.bus_conflicts {
+                    value & self.prg_read(addr)
+                } else {
+                    value
+                };
+                // GxROM: CHR in bits 0-1, PRG in bits 4-5. Color Dreams: PRG
+                // in bits 0-1, CHR in bits 4-7.
+                let (prg, chr) = if self.color_dreams {
+                    (value & 0x03, value >> 4)
+                } else {
+                    ((value >> 4) & 0x03, value & 0x03)
+                };
+                let cartridge = &self.cartridge;
+                self.prg_bank = bank_offset(cartridge.prg.len(), 0x8000, prg as isize);
+                self.chr_bank = bank_offset(cartridge.chr.len(), 0x2000, chr as isize);
+            }
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        let chr = &self.cartridge.chr;
+        chr[(self.chr_bank + addr as usize) % chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[(self.chr_bank + addr as usize) % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.chr_bank);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read()?;
+        self.chr_bank = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 1: registers are loaded serially, one bit per write, through a
+// 5-bit shift register at $8000-$FFFF. Boards with more than 256KB of PRG
+// ROM (SUROM, SXROM) or 8KB of PRG RAM (SOROM, SXROM) repurpose the upper
+// CHR bank 0 bits as extra address lines, which only matter on boards with
+// 8KB of CHR RAM.
+pub struct MMC1 {
+    cartridge: Cartridge,
+    fixed_prg: bool,
+    shift_register: u8,
+    control: u8,
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_bank: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_ram_enabled: bool,
+    prg_offsets: [usize; 2],
+    chr_offsets: [usize; 2],
+    sram_offset: usize,
+}
+
+impl MMC1 {
+    // `fixed_prg` ignores PRG banking for boards without the PRG address
+    // lines connected
+    pub fn new(cartridge: Cartridge, fixed_prg: bool) -> Self {
+        let mut mapper = Self {
+            cartridge,
+            fixed_prg,
+            shift_register: 0x10,
+            control: 0,
+            prg_mode: 0,
+            chr_mode: 0,
+            prg_bank: 0,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_ram_enabled: true,
+            prg_offsets: [0; 2],
+            chr_offsets: [0; 2],
+            sram_offset: 0,
+        };
+        // Power-on state fixes the last PRG bank at $C000
+        mapper.write_control(0x0C);
+        mapper
+    }
+
+    fn load_register(&mut self, addr: u16, value: u8) {
+        if value & 0x80 != 0 {
+            self.shift_register = 0x10;
+            self.write_control(self.control | 0x0C);
+            return;
+        }
+
+        // The initial 1 reaching bit 0 marks the fifth write
+        let complete = self.shift_register & 1 == 1;
+        self.shift_register >>= 1;
+        self.shift_register |= (value & 1) << 4;
+        if complete {
+            self.write_register(addr, self.shift_register);
+            self.shift_register = 0x10;
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.write_control(value),
+            0xA000..=0xBFFF => {
+                self.chr_bank0 = value;
+                self.update_offsets();
+            }
+            0xC000..=0xDFFF => {
+                self.chr_bank1 = value;
+                self.update_offsets();
+            }
+            _ => {
+                self.prg_bank = value & 0x0F;
+                self.prg_ram_enabled = value & 0x10 == 0;
+                self.update_offsets();
+            }
+        }
+    }
+
+    // Control (internal, $8000-$9FFF)
+    fn write_control(&mut self, value: u8) {
+        self.control = value;
+        self.chr_mode = (value >> 4) & 1;
+        self.prg_mode = (value >> 2) & 3;
+        self.update_offsets();
+    }
+
+    fn prg_bank_offset(&self, index: isize) -> usize {
+        bank_offset(self.cartridge.prg.len(), 0x4000, index)
+    }
+
+    fn chr_bank_offset(&self, index: isize) -> usize {
+        bank_offset(self.cartridge.chr.len(), 0x1000, index)
+    }
+
+    // PRG ROM bank mode (0, 1: switch 32KB at $8000, ignoring low bit of bank number;
+    //                    2: fix first bank at $8000 and switch 16KB bank at $C000;
+    //                    3: fix last bank at $C000 and switch 16KB bank at $8000)
+    // CHR ROM bank mode (0: switch 8KB at a time; 1: switch two separate 4KB banks)
+    fn update_offsets(&mut self) {
+        // SUROM/SXROM: CHR bank 0 bit 4 picks the 256KB half of PRG ROM,
+        // fixed banks included
+        let (outer, last) = if self.cartridge.prg.len() > 0x40000 {
+            let outer = (self.chr_bank0 & 0x10) as isize;
+            (outer, outer | 0x0F)
+        } else {
+            (0, -1)
+        };
+        let prg_bank = outer | self.prg_bank as isize;
+        self.prg_offsets = match self.prg_mode {
+            _ if self.fixed_prg => [self.prg_bank_offset(0), self.prg_bank_offset(1)],
+            0 | 1 => [
+                self.prg_bank_offset(prg_bank & !0x01),
+                self.prg_bank_offset(prg_bank | 0x01),
+            ],
+            2 => [self.prg_bank_offset(outer), self.prg_bank_offset(prg_bank)],
+            _ => [self.prg_bank_offset(prg_bank), self.prg_bank_offset(last)],
+        };
+
+        // SOROM: CHR bank 0 bit 3 picks one of two 8KB PRG RAM banks;
+        // SXROM: bits 2-3 pick one of four
+        self.sram_offset = match self.cartridge.sram.len() {
+            0x4000 => ((self.chr_bank0 >> 3) & 1) as usize * 0x2000,
+            0x8000 => ((self.chr_bank0 >> 2) & 3) as usize * 0x2000,
+            _ => 0,
+        };
+
+        let chr_bank0 = self.chr_bank0 as isize;
+        self.chr_offsets = match self.chr_mode {
+            0 => [
+                self.chr_bank_offset(chr_bank0 & 0x1E),
+                self.chr_bank_offset(chr_bank0 | 0x01),
+            ],
+            _ => [
+                self.chr_bank_offset(chr_bank0),
+                self.chr_bank_offset(self.chr_bank1 as isize),
+            ],
+        };
+    }
+}
+
+impl Mapper for MMC1 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                let offset = self.sram_offset + addr as usize - 0x6000;
+                self.cartridge.read_sram(offset)
+            }
+            0x8000..=0xFFFF => {
+                let addr = addr as usize - 0x8000;
+                let bank = addr / 0x4000;
+                self.cartridge.prg[self.prg_offsets[bank] + addr % 0x4000]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                let offset = self.sram_offset + addr as usize - 0x6000;
+                self.cartridge.write_sram(offset, value);
+            }
+            0x8000..=0xFFFF => self.load_register(addr, value),
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4020..=0x5FFF => true,
+            0x6000..=0x7FFF => !self.prg_ram_enabled || self.cartridge.sram.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn sram_offset(&self, addr: u16) -> Option<usize> {
+        (self.prg_ram_enabled && !self.cartridge.sram.is_empty())
+            .then(|| self.sram_offset + addr as usize - 0x6000)
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        let bank = addr as usize / 0x1000;
+        let offset = self.chr_offsets[bank] + addr as usize % 0x1000;
+        self.cartridge.chr[offset % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let bank = addr as usize / 0x1000;
+            let offset = self.chr_offsets[bank] + addr as usize % 0x1000;
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[offset % len] = value;
+        }
+    }
+
+    // Mirroring (0: one-screen, lower bank; 1: one-screen, upper bank;
+    //            2: vertical; 3: horizontal)
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 3 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.shift_register);
+        state.write(&self.control);
+        state.write(&self.prg_mode);
+        state.write(&self.chr_mode);
+        state.write(&self.prg_bank);
+        state.write(&self.chr_bank0);
+        state.write(&self.chr_bank1);
+        state.write(&self.prg_ram_enabled);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.shift_register = state.read()?;
+        self.control = state.read()?;
+        self.prg_mode = state.read()?;
+        self.chr_mode = state.read()?;
+        self.prg_bank = state.read()?;
+        self.chr_bank0 = state.read()?;
+        self.chr_bank1 = state.read()?;
+        self.prg_ram_enabled = state.read()?;
+        self.update_offsets();
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
@@ -0,0 +1,401 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, mirrored_page, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// The chips and boards built around the MMC3's bank registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    MMC3,
+    // MMC3A and older, where reloading a counter that ran down to 0 with a
+    // latch of 0 raises no further IRQs
+    MMC3A,
+    // The MMC3 but for its 1KB of RAM inside the chip
+    MMC6,
+    // Namco 108 (mapper 206), the MMC3's predecessor: the bank registers
+    // alone, in the MMC3's first modes, with no IRQ, mirroring control or
+    // work RAM
+    Namco108,
+    // NAMCOT-3446 (mapper 76): R2-R5 select four 2KB CHR banks
+    Namco3446,
+    // NAMCOT-3433 (mapper 88): CHR at $0000 comes from the first 64KB and
+    // at $1000 from the second
+    Namco3433,
+    // NAMCOT-3453 (mapper 154): the 3433, with bit 6 of any register write
+    // choosing a single-screen nametable
+    Namco3453,
+    // NAMCOT-3425 (mapper 95): bit 5 of R0 picks the nametable for $2000
+    // and $2400, and of R1 for $2800 and $2C00
+    Namco3425,
+}
+
+impl Variant {
+    fn namco(self) -> bool {
+        !matches!(self, Variant::MMC3 | Variant::MMC3A | Variant::MMC6)
+    }
+}
+
+// Mapper 4: 8KB PRG and 1KB/2KB CHR banking plus a scanline counter that
+// raises an IRQ after a programmable number of rendered lines, and the
+// boards sharing its banking (see Variant)
+pub struct MMC3 {
+    cartridge: Cartridge,
+    variant: Variant,
+    register: u8,
+    registers: [u8; 8],
+    prg_mode: u8,
+    chr_mode: u8,
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_protected: bool,
+    // The MMC6's read and write enables for each half of its RAM, $A001
+    // bits 4-7
+    mmc6_ram_access: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    prg_offsets: [usize; 4],
+    chr_offsets: [usize; 8],
+}
+
+impl MMC3 {
+    pub fn new(cartridge: Cartridge, variant: Variant) -> Self {
+        let mirroring = cartridge.mirroring();
+        let mut mapper = Self {
+            cartridge,
+            variant,
+            register: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_mode: 0,
+            chr_mode: 0,
+            mirroring,
+            // The MMC6's RAM is switched off until enabled through $8000 and
+            // $A001
+            prg_ram_enabled: variant != Variant::MMC6,
+            prg_ram_protected: false,
+            mmc6_ram_access: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            prg_offsets: [0; 4],
+            chr_offsets: [0; 8],
+        };
+        mapper.update_offsets();
+        mapper
+    }
+
+    fn mmc6(&self) -> bool {
+        self.variant == Variant::MMC6
+    }
+
+    // The MMC6 RAM half `addr` falls in, 0 for $7000-$71FF and 1 for
+    // $7200-$73FF, as the shift of its enable bits
+    fn mmc6_half(addr: u16) -> u8 {
+        (addr >> 9 & 1) as u8 * 2
+    }
+
+    fn mmc6_readable(&self, addr: u16) -> bool {
+        self.prg_ram_enabled && self.mmc6_ram_access & (0x20 << Self::mmc6_half(addr)) != 0
+    }
+
+    // Writes also need the half to be readable
+    fn mmc6_writable(&self, addr: u16) -> bool {
+        self.mmc6_readable(addr) && self.mmc6_ram_access & (0x10 << Self::mmc6_half(addr)) != 0
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        let even = addr & 1 == 0;
+        if self.variant == Variant::Namco3453 {
+            self.mirroring = match value & 0x40 {
+                0 => Mirroring::SingleScreenLower,
+                _ => Mirroring::SingleScreenUpper,
+            };
+        }
+        match addr {
+            // The Namco boards have only the bank registers
+            0xA000..=0xFFFF if self.variant.namco() => {}
+            0x8000..=0x9FFF if even => self.write_bank_select(value),
+            0x8000..=0x9FFF => self.write_bank_data(value),
+            0xA000..=0xBFFF if even => self.write_mirror(value),
+            0xA000..=0xBFFF => self.write_protect(value),
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    // Bank select ($8000-$9FFE, even)
+    fn write_bank_select(&mut self, value: u8) {
+        if self.mmc6() {
+            self.prg_ram_enabled = value & 0x20 != 0;
+        }
+        if !self.variant.namco() {
+            self.prg_mode = (value >> 6) & 1;
+            self.chr_mode = (value >> 7) & 1;
+        }
+        self.register = value & 7;
+        self.update_offsets();
+    }
+
+    // Bank data ($8001-$9FFF, odd)
+    fn write_bank_data(&mut self, value: u8) {
+        self.registers[self.register as usize] = value;
+        self.update_offsets();
+    }
+
+    // Mirroring ($A000-$BFFE, even)
+    fn write_mirror(&mut self, value: u8) {
+        // Four-screen boards hardwire their own nametable RAM
+        if self.mirroring == Mirroring::FourScreen {
+            return;
+        }
+        self.mirroring = match value & 1 {
+            0 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        };
+    }
+
+    // PRG RAM protect ($A001-$BFFF, odd). The MMC6 only takes it while its
+    // RAM is enabled.
+    fn write_protect(&mut self, value: u8) {
+        if self.mmc6() {
+            if self.prg_ram_enabled {
+                self.mmc6_ram_access = value & 0xF0;
+            }
+            return;
+        }
+        self.prg_ram_enabled = value & 0x80 != 0;
+        self.prg_ram_protected = value & 0x40 != 0;
+    }
+
+    fn prg_bank_offset(&self, index: isize) -> usize {
+        bank_offset(self.cartridge.prg.len(), 0x2000, index)
+    }
+
+    fn chr_bank_offset(&self, index: isize) -> usize {
+        bank_offset(self.cartridge.chr.len(), 0x0400, index)
+    }
+
+    // PRG ROM bank mode (0: $8000 swappable, $C000 fixed to second-last bank;
+    //                    1: $C000 swappable, $8000 fixed to second-last bank)
+    // CHR A12 inversion (0: two 2KB banks at $0000, four 1KB banks at $1000;
+    //                    1: two 2KB banks at $1000, four 1KB banks at $0000)
+    fn update_offsets(&mut self) {
+        let r = self.registers.map(|value| value as isize);
+
+        let switchable = self.prg_bank_offset(r[6]);
+        let second_last = self.prg_bank_offset(-2);
+        self.prg_offsets = [
+            if self.prg_mode == 0 {
+                switchable
+            } else {
+                second_last
+            },
+            self.prg_bank_offset(r[7]),
+            if self.prg_mode == 0 {
+                second_last
+            } else {
+                switchable
+            },
+            self.prg_bank_offset(-1),
+        ];
+
+        // The 1KB banks for each half of the pattern tables, the 2KB half
+        // first
+        let (low, high) = match self.variant {
+            Variant::Namco3446 => (
+                [r[2] * 2, r[2] * 2 + 1, r[3] * 2, r[3] * 2 + 1],
+                [r[4] * 2, r[4] * 2 + 1, r[5] * 2, r[5] * 2 + 1],
+            ),
+            Variant::Namco3433 | Variant::Namco3453 => {
+                let (r0, r1) = (r[0] & 0x3E, r[1] & 0x3E);
+                (
+                    [r0, r0 | 0x01, r1, r1 | 0x01],
+                    [r[2] | 0x40, r[3] | 0x40, r[4] | 0x40, r[5] | 0x40],
+                )
+            }
+            _ => {
+                // Bit 5 of R0 and R1 is the NAMCOT-3425's nametable select
+                let mask = match self.variant {
+                    Variant::Namco3425 => 0x1E,
+                    _ => 0xFE,
+                };
+                let (r0, r1) = (r[0] & mask, r[1] & mask);
+                ([r0, r0 | 0x01, r1, r1 | 0x01], [r[2], r[3], r[4], r[5]])
+            }
+        };
+        let banks = match self.chr_mode {
+            0 => [low, high],
+            _ => [high, low],
+        };
+        for (i, &bank) in banks.iter().flatten().enumerate() {
+            self.chr_offsets[i] = self.chr_bank_offset(bank);
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = addr as usize / 0x0400;
+        (self.chr_offsets[bank] + addr as usize % 0x0400) % self.cartridge.chr.len()
+    }
+}
+
+impl Mapper for MMC3 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            // A half that can't be read reads 0 while the other can
+            0x7000..=0x7FFF if self.mmc6() && self.mmc6_readable(addr) => {
+                self.cartridge.read_sram(addr as usize % 0x0400)
+            }
+            0x6000..=0x7FFF if self.mmc6() || self.variant.namco() => 0,
+            0x6000..=0x7FFF if self.prg_ram_enabled => {
+                self.cartridge.read_sram(addr as usize - 0x6000)
+            }
+            0x8000..=0xFFFF => {
+                let addr = addr as usize - 0x8000;
+                let bank = addr / 0x2000;
+                self.cartridge.prg[self.prg_offsets[bank] + addr % 0x2000]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x7000..=0x7FFF if self.mmc6() && self.mmc6_writable(addr) => {
+                self.cartridge.write_sram(addr as usize % 0x0400, value);
+            }
+            0x6000..=0x7FFF if self.mmc6() || self.variant.namco() => {}
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_protected => {
+                self.cartridge.write_sram(addr as usize - 0x6000, value);
+            }
+            0x8000..=0xFFFF => self.write_register(addr, value),
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4020..=0x7FFF if self.variant.namco() => true,
+            0x4020..=0x6FFF if self.mmc6() => true,
+            0x7000..=0x7FFF if self.mmc6() => {
+                !self.prg_ram_enabled
+                    || self.mmc6_ram_access & 0xA0 == 0
+                    || self.cartridge.sram.is_empty()
+            }
+            0x4020..=0x5FFF => true,
+            0x6000..=0x7FFF => !self.prg_ram_enabled || self.cartridge.sram.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn sram_offset(&self, addr: u16) -> Option<usize> {
+        if self.cartridge.sram.is_empty() {
+            return None;
+        }
+        match addr {
+            0x7000..=0x7FFF if self.mmc6() && self.mmc6_readable(addr) => {
+                Some(addr as usize % 0x0400)
+            }
+            _ if self.mmc6() || self.variant.namco() => None,
+            _ => self.prg_ram_enabled.then(|| addr as usize - 0x6000),
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[self.chr_offset(addr)]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.cartridge.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn name_table_page(&self, table: usize) -> usize {
+        match self.variant {
+            Variant::Namco3425 => (self.registers[table / 2] >> 5 & 1) as usize,
+            _ => mirrored_page(self.mirroring, table),
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.register);
+        state.write(&self.registers);
+        state.write(&self.prg_mode);
+        state.write(&self.chr_mode);
+        state.write(&self.mirroring);
+        state.write(&self.prg_ram_enabled);
+        state.write(&self.prg_ram_protected);
+        state.write(&self.mmc6_ram_access);
+        state.write(&self.irq_latch);
+        state.write(&self.irq_counter);
+        state.write(&self.irq_reload);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_pending);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.register = state.read()?;
+        self.registers = state.read()?;
+        self.prg_mode = state.read()?;
+        self.chr_mode = state.read()?;
+        self.mirroring = state.read()?;
+        self.prg_ram_enabled = state.read()?;
+        self.prg_ram_protected = state.read()?;
+        self.mmc6_ram_access = state.read()?;
+        self.irq_latch = state.read()?;
+        self.irq_counter = state.read()?;
+        self.irq_reload = state.read()?;
+        self.irq_enabled = state.read()?;
+        self.irq_pending = state.read()?;
+        self.update_offsets();
+        state.read_into(&mut self.cartridge.sram)
+    }
+
+    fn ppu_a12_rise(&mut self) {
+        let counted = self.irq_counter != 0 || self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        // The MMC3A only fires when the counter reaches 0 by decrementing or
+        // by a reload requested through $C001
+        if self.irq_counter == 0 && self.irq_enabled && (counted || self.variant != Variant::MMC3A)
+        {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+}
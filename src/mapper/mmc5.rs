@@ -0,0 +1,606 @@
+use crate::{
+    apu::Pulse,
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    memory::Fetch,
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// CPU cycles between clocks of the expansion pulses' envelopes and length
+// counters, a fixed 240Hz divider unlike the APU's frame counter
+const AUDIO_FRAME_PERIOD: u16 = 7457;
+
+// Mapper 5: 8KB-granular PRG ROM/RAM banking, 1KB CHR banking with separate
+// background banks for 8x16 sprites, 1KB of ExRAM usable as a nametable or
+// for per-tile attributes, a vertical split, a scanline IRQ, a multiplier
+// and two extra pulse channels plus a PCM channel.
+//
+// The board learns where the PPU is from the scanline hook and by counting
+// background nametable fetches after it: the two fetched during the end of
+// a line are columns 0 and 1 of the next one.
+pub struct MMC5 {
+    cartridge: Cartridge,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    // $5102/$5103 must hold 2 and 1 for PRG RAM writes to go through
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    name_table_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    // $5113-$5117
+    prg_registers: [u8; 5],
+    // $5120-$512B with the $5130 upper bits they were written with
+    chr_registers: [u16; 12],
+    chr_upper: u8,
+    // Whether $5128-$512B were written after $5120-$5127
+    last_chr_b: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_target: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    exram: Vec<u8>,
+
+    // What the MMC5 has seen of the PPU
+    large_sprites: bool,
+    fetch: Fetch,
+    in_frame: bool,
+    // Line the background fetches are for, and their column within it
+    line: u8,
+    tile: u8,
+    split_tile: bool,
+    // ExRAM byte of the tile being fetched in extended attribute mode
+    extended_attribute: u8,
+
+    pulse1: Pulse,
+    pulse2: Pulse,
+    audio_cycle: u16,
+    pcm: u8,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+
+    // Per 8KB window at $6000, $8000, $A000, $C000 and $E000: whether it
+    // holds RAM, and the offset into RAM or ROM
+    prg_offsets: [(bool, usize); 5],
+    // Per 1KB of pattern tables: offsets for sprites and everything in
+    // 8x8 mode (A), and for backgrounds with 8x16 sprites (B)
+    chr_offsets_a: [usize; 8],
+    chr_offsets_b: [usize; 8],
+}
+
+impl MMC5 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mut mapper = Self {
+            cartridge,
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            name_table_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_registers: [0, 0, 0, 0, 0xFF],
+            chr_registers: [0; 12],
+            chr_upper: 0,
+            last_chr_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            exram: vec![0; 0x0400],
+            large_sprites: false,
+            fetch: Fetch::Background,
+            in_frame: false,
+            line: 0,
+            tile: 0,
+            split_tile: false,
+            extended_attribute: 0,
+            pulse1: Pulse::without_sweep(),
+            pulse2: Pulse::without_sweep(),
+            audio_cycle: 0,
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            prg_offsets: [(false, 0); 5],
+            chr_offsets_a: [0; 8],
+            chr_offsets_b: [0; 8],
+        };
+        mapper.update_offsets();
+        mapper
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000 => self.pulse1.write_control(value),
+            0x5002 => self.pulse1.write_timer_low(value),
+            0x5003 => self.pulse1.write_timer_high(value),
+            0x5004 => self.pulse2.write_control(value),
+            0x5006 => self.pulse2.write_timer_low(value),
+            0x5007 => self.pulse2.write_timer_high(value),
+            0x5010 => {
+                self.pcm_read_mode = value & 0x01 != 0;
+                self.pcm_irq_enabled = value & 0x80 != 0;
+            }
+            // Writing 0 is ignored; it's what raises the IRQ in read mode
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulse1.set_enabled(value & 0x01 != 0);
+                self.pulse2.set_enabled(value & 0x02 != 0);
+            }
+            0x5100 => {
+                self.prg_mode = value & 3;
+                self.update_offsets();
+            }
+            0x5101 => {
+                self.chr_mode = value & 3;
+                self.update_offsets();
+            }
+            0x5102 => self.prg_ram_protect[0] = value & 3,
+            0x5103 => self.prg_ram_protect[1] = value & 3,
+            0x5104 => self.exram_mode = value & 3,
+            0x5105 => self.name_table_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 3,
+            0x5113..=0x5117 => {
+                self.prg_registers[addr as usize - 0x5113] = value;
+                self.update_offsets();
+            }
+            0x5120..=0x512B => {
+                let index = addr as usize - 0x5120;
+                self.chr_registers[index] = value as u16 | (self.chr_upper as u16) << 8;
+                self.last_chr_b = index >= 8;
+                self.update_offsets();
+            }
+            0x5130 => self.chr_upper = value & 3,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_target = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            // Mode 3 makes ExRAM read-only
+            0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[addr as usize - 0x5C00] = value,
+            _ => {}
+        }
+    }
+
+    // Register values, without the acknowledges reading them makes
+    fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x5010 => (self.pcm_irq as u8) << 7,
+            0x5015 => self.pulse1.active() as u8 | (self.pulse2.active() as u8) << 1,
+            0x5204 => (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6,
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[addr as usize - 0x5C00],
+            _ => 0,
+        }
+    }
+
+    // PRG mode (0: one 32KB bank, $5117;
+    //           1: two 16KB banks, $5115 and $5117;
+    //           2: 16KB at $8000 ($5115), 8KB at $C000 ($5116) and $E000 ($5117);
+    //           3: four 8KB banks, $5114-$5117)
+    // Bit 7 of $5114-$5116 selects ROM over RAM; $E000 is always ROM.
+    //
+    // CHR mode (0: 8KB pages; 1: 4KB; 2: 2KB; 3: 1KB). Each page is
+    // selected by the last register of its group, and the B set covers
+    // only $0000-$0FFF, repeated at $1000.
+    fn update_offsets(&mut self) {
+        let r = self.prg_registers;
+        self.prg_offsets[0] = (true, (r[0] & 0x07) as usize * 0x2000);
+        for window in 0..4 {
+            let (register, pages) = match (self.prg_mode, window) {
+                (0, _) => (4, 4),
+                (1, 0 | 1) | (2, 0 | 1) => (2, 2),
+                (1, _) => (4, 2),
+                // Mode 3, and the last two windows of mode 2
+                _ => (window + 1, 1),
+            };
+            let value = r[register];
+            let bank = (value & 0x7F) as usize / pages * pages + window % pages;
+            let rom = register == 4 || value & 0x80 != 0;
+            self.prg_offsets[window + 1] = if rom {
+                (
+                    false,
+                    bank_offset(self.cartridge.prg.len(), 0x2000, bank as isize),
+                )
+            } else {
+                (true, (bank & 0x07) * 0x2000)
+            };
+        }
+
+        let kb = 8 >> self.chr_mode;
+        for i in 0..8 {
+            let register = i / kb * kb + kb - 1;
+            let bank = self.chr_registers[register] as usize;
+            self.chr_offsets_a[i] = (bank * kb + i % kb) * 0x0400;
+
+            let (register, page) = match self.chr_mode {
+                0 => (11, i),
+                _ => (8 + i % 4 / kb * kb + kb - 1, i % 4 % kb),
+            };
+            let bank = self.chr_registers[register] as usize;
+            self.chr_offsets_b[i] = (bank * kb + page) * 0x0400;
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> (bool, usize) {
+        let window = (addr as usize - 0x6000) / 0x2000;
+        let (ram, offset) = self.prg_offsets[window];
+        (ram, offset + addr as usize % 0x2000)
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    // Which of nametable sources 0-3 $5105 gives `table`: console VRAM page
+    // 0 or 1, ExRAM, or fill mode
+    fn name_table_source(&self, table: usize) -> u8 {
+        (self.name_table_mapping >> (table * 2)) & 3
+    }
+
+    // Split region (bit 7: enabled; bit 6: right of the threshold rather
+    // than left; bits 0-4: threshold tile), only in ExRAM modes 0 and 1
+    fn in_split(&self, column: u8) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode >= 2 {
+            return false;
+        }
+        let threshold = self.split_control & 0x1F;
+        if self.split_control & 0x40 == 0 {
+            column < threshold
+        } else {
+            column >= threshold
+        }
+    }
+
+    // The split scrolls on its own from $5201, wrapping at 240 lines
+    fn split_y(&self) -> usize {
+        (self.split_scroll as usize + self.line as usize) % 240
+    }
+
+    fn background_name_table_read(&mut self, offset: usize) -> Option<u8> {
+        if offset < 0x03C0 {
+            let column = self.tile;
+            self.tile = self.tile.wrapping_add(1);
+            self.split_tile = self.in_split(column);
+            if self.split_tile {
+                let index = self.split_y() / 8 * 32 + (column as usize & 31);
+                return Some(self.exram[index]);
+            }
+            if self.exram_mode == 1 {
+                self.extended_attribute = self.exram[offset];
+            }
+            return None;
+        }
+
+        // Attributes made up by the MMC5 are repeated in all four quadrants
+        // so the PPU's own quadrant shift picks the right one
+        if self.split_tile {
+            let column = (self.tile.wrapping_sub(1) & 31) as usize;
+            let y = self.split_y();
+            let attribute = self.exram[0x03C0 + y / 32 * 8 + column / 4];
+            let shift = ((y / 16) & 1) * 4 + ((column / 2) & 1) * 2;
+            return Some(((attribute >> shift) & 3) * 0x55);
+        }
+        if self.exram_mode == 1 {
+            return Some((self.extended_attribute >> 6) * 0x55);
+        }
+        None
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = addr as usize / 0x0400;
+        let offset = addr as usize % 0x0400;
+        let use_b = match self.fetch {
+            Fetch::Background if self.large_sprites => true,
+            Fetch::Sprites if self.large_sprites => false,
+            _ => self.last_chr_b,
+        };
+        if self.fetch == Fetch::Background {
+            if self.split_tile {
+                // The split's own fine Y replaces the PPU's
+                let addr = (addr as usize & 0x0FF8) | (self.split_y() % 8);
+                return self.split_bank as usize * 0x1000 + addr;
+            }
+            if self.exram_mode == 1 {
+                let bank =
+                    (self.extended_attribute & 0x3F) as usize | (self.chr_upper as usize) << 6;
+                return bank * 0x1000 + (addr as usize & 0x0FFF);
+            }
+        }
+        if use_b {
+            self.chr_offsets_b[bank] + offset
+        } else {
+            self.chr_offsets_a[bank] + offset
+        }
+    }
+}
+
+impl Mapper for MMC5 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x5000..=0x5FFF => self.read_register(addr),
+            0x6000..=0xFFFF => match self.prg_offset(addr) {
+                (true, offset) => self.cartridge.read_sram(offset),
+                (false, offset) => self.cartridge.prg[offset],
+            },
+            _ => 0,
+        }
+    }
+
+    fn prg_read(&mut self, addr: u16) -> u8 {
+        let value = self.prg_peek(addr);
+        match addr {
+            // Reading an IRQ's status acknowledges it
+            0x5010 => self.pcm_irq = false,
+            0x5204 => self.irq_pending = false,
+            // In read mode the PCM channel plays back what the CPU reads
+            // from ROM at $8000-$BFFF, with a 0 raising its IRQ
+            0x8000..=0xBFFF if self.pcm_read_mode && !self.prg_offset(addr).0 => {
+                if value == 0 {
+                    self.pcm_irq = true;
+                } else {
+                    self.pcm = value;
+                }
+            }
+            _ => {}
+        }
+        value
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000..=0x5FFF => self.write_register(addr, value),
+            0x6000..=0xDFFF => {
+                let (ram, offset) = self.prg_offset(addr);
+                if ram && self.prg_ram_writable() {
+                    self.cartridge.write_sram(offset, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x5010 | 0x5015 | 0x5204..=0x5206 => false,
+            0x5C00..=0x5FFF => self.exram_mode < 2,
+            0x4020..=0x5FFF => true,
+            _ => false,
+        }
+    }
+
+    // $6000-$7FFF can have ROM switched in as well as any bank of RAM
+    fn sram_offset(&self, addr: u16) -> Option<usize> {
+        match self.prg_offset(addr) {
+            (true, offset) if !self.cartridge.sram.is_empty() => Some(offset),
+            _ => None,
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        let offset = self.chr_offset(addr);
+        self.cartridge.chr[offset % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(addr);
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[offset % len] = value;
+        }
+    }
+
+    // Only meaningful for the common $5105 layouts; the PPU goes through
+    // name_table_page and name_table_read
+    fn mirroring(&self) -> Mirroring {
+        match self.name_table_mapping {
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::FourScreen,
+        }
+    }
+
+    fn name_table_page(&self, table: usize) -> usize {
+        (self.name_table_source(table) & 1) as usize
+    }
+
+    fn name_table_read(&mut self, addr: u16) -> Option<u8> {
+        let table = (addr as usize - 0x2000) / 0x0400;
+        let offset = addr as usize % 0x0400;
+        if self.fetch == Fetch::Background && self.in_frame {
+            if let Some(value) = self.background_name_table_read(offset) {
+                return Some(value);
+            }
+        }
+        match self.name_table_source(table) {
+            2 if self.exram_mode <= 1 => Some(self.exram[offset]),
+            2 => Some(0),
+            3 if offset < 0x03C0 => Some(self.fill_tile),
+            3 => Some(self.fill_attribute * 0x55),
+            _ => None,
+        }
+    }
+
+    fn name_table_write(&mut self, addr: u16, value: u8) -> bool {
+        let table = (addr as usize - 0x2000) / 0x0400;
+        match self.name_table_source(table) {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[addr as usize % 0x0400] = value;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn ppu_fetch(&mut self, fetch: Fetch) {
+        self.fetch = fetch;
+    }
+
+    // The MMC5 watches PPUCTRL for 8x16 sprites and PPUMASK for rendering
+    // being switched off, which ends the frame as far as it is concerned
+    fn ppu_register_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x2000 => self.large_sprites = value & 0x20 != 0,
+            0x2001 if value & 0x18 == 0 => self.in_frame = false,
+            _ => {}
+        }
+    }
+
+    // Runs at the end of each rendered line, after which the fetches are
+    // for the next one. The first line seen outside a frame, normally the
+    // pre-render line, starts one at line 0; the IRQ fires as line
+    // `irq_target` begins.
+    fn scanline(&mut self) {
+        self.tile = 0;
+        if !self.in_frame {
+            self.in_frame = true;
+            self.line = 0;
+            return;
+        }
+        self.line += 1;
+        if self.line == 240 {
+            self.in_frame = false;
+        } else if self.line == self.irq_target {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || (self.pcm_irq && self.pcm_irq_enabled)
+    }
+
+    fn cpu_clock(&mut self) {
+        if self.audio_cycle.is_multiple_of(2) {
+            self.pulse1.step_timer();
+            self.pulse2.step_timer();
+        }
+        self.audio_cycle += 1;
+        if self.audio_cycle == AUDIO_FRAME_PERIOD {
+            self.audio_cycle = 0;
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.step_envelope();
+                pulse.step_length();
+            }
+        }
+    }
+
+    // Mixed like the APU's own pulses and DMC, with the PCM's 8 bits
+    // standing in for the DMC's 7
+    fn audio_output(&self) -> f32 {
+        let pulses = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pcm = self.pcm as f32 / 2.0;
+        let mut output = 0.0;
+        if pulses > 0.0 {
+            output += 95.52 / (8128.0 / pulses + 100.0);
+        }
+        if pcm > 0.0 {
+            output += 163.67 / (24329.0 / pcm + 100.0);
+        }
+        output
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_mode);
+        state.write(&self.chr_mode);
+        state.write(&self.prg_ram_protect);
+        state.write(&self.exram_mode);
+        state.write(&self.name_table_mapping);
+        state.write(&self.fill_tile);
+        state.write(&self.fill_attribute);
+        state.write(&self.prg_registers);
+        state.write(&self.chr_registers);
+        state.write(&self.chr_upper);
+        state.write(&self.last_chr_b);
+        state.write(&self.split_control);
+        state.write(&self.split_scroll);
+        state.write(&self.split_bank);
+        state.write(&self.irq_target);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_pending);
+        state.write(&self.multiplicand);
+        state.write(&self.multiplier);
+        state.write(&self.exram[..]);
+        state.write(&self.large_sprites);
+        state.write(&self.fetch);
+        state.write(&self.in_frame);
+        state.write(&self.line);
+        state.write(&self.tile);
+        state.write(&self.split_tile);
+        state.write(&self.extended_attribute);
+        state.write(&self.pulse1);
+        state.write(&self.pulse2);
+        state.write(&self.audio_cycle);
+        state.write(&self.pcm);
+        state.write(&self.pcm_read_mode);
+        state.write(&self.pcm_irq_enabled);
+        state.write(&self.pcm_irq);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_mode = state.read()?;
+        self.chr_mode = state.read()?;
+        self.prg_ram_protect = state.read()?;
+        self.exram_mode = state.read()?;
+        self.name_table_mapping = state.read()?;
+        self.fill_tile = state.read()?;
+        self.fill_attribute = state.read()?;
+        self.prg_registers = state.read()?;
+        self.chr_registers = state.read()?;
+        self.chr_upper = state.read()?;
+        self.last_chr_b = state.read()?;
+        self.split_control = state.read()?;
+        self.split_scroll = state.read()?;
+        self.split_bank = state.read()?;
+        self.irq_target = state.read()?;
+        self.irq_enabled = state.read()?;
+        self.irq_pending = state.read()?;
+        self.multiplicand = state.read()?;
+        self.multiplier = state.read()?;
+        state.read_into(&mut self.exram)?;
+        self.large_sprites = state.read()?;
+        self.fetch = state.read()?;
+        self.in_frame = state.read()?;
+        self.line = state.read()?;
+        self.tile = state.read()?;
+        self.split_tile = state.read()?;
+        self.extended_attribute = state.read()?;
+        self.pulse1 = state.read()?;
+        self.pulse2 = state.read()?;
+        self.audio_cycle = state.read()?;
+        self.pcm = state.read()?;
+        self.pcm_read_mode = state.read()?;
+        self.pcm_irq_enabled = state.read()?;
+        self.pcm_irq = state.read()?;
+        self.update_offsets();
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
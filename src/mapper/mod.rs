@@ -0,0 +1,291 @@
+mod action53;
+mod axrom;
+mod bnrom;
+mod camerica;
+mod cnrom;
+mod fds;
+mod fds_audio;
+mod fme7;
+mod gxrom;
+mod mmc1;
+mod mmc3;
+mod mmc5;
+mod n163;
+mod nina001;
+mod nrom;
+mod nsf;
+mod unrom512;
+mod uxrom;
+mod vrc4;
+mod vrc6;
+mod vrc_irq;
+mod vs_unisystem;
+
+use crate::{
+    cartridge::{Cartridge, CartridgeError, Mirroring, MAPPER_FDS},
+    fds::Disk,
+    memory::Fetch,
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+pub use action53::Action53;
+pub use axrom::AxROM;
+pub use bnrom::BNROM;
+pub use camerica::{BF9093, BF9096};
+pub use cnrom::CNROM;
+pub use fds::FDS;
+pub use fme7::FME7;
+pub use gxrom::GxROM;
+pub use mmc1::MMC1;
+pub use mmc3::{Variant as MMC3Variant, MMC3};
+pub use mmc5::MMC5;
+pub use n163::N163;
+pub use nina001::NINA001;
+pub use nrom::NROM;
+pub use nsf::NSFMapper;
+pub(crate) use nsf::IDLE_ADDRESS;
+pub use unrom512::UNROM512;
+pub use uxrom::UxROM;
+pub use vrc4::{Wiring, VRC4};
+pub use vrc6::VRC6;
+pub use vs_unisystem::VSUniSystem;
+
+// A mapper sees the cartridge side of both buses: CPU addresses $4020-$FFFF
+// through prg_read/prg_write and PPU addresses $0000-$1FFF through
+// chr_read/chr_write. Boards with timers and IRQs are driven by the same
+// hooks whatever they count: cpu_clock for every CPU cycle, ppu_a12_rise
+// and scanline from the PPU, with the CPU polling irq_pending.
+pub trait Mapper: Send {
+    fn cartridge(&self) -> &Cartridge;
+    fn cartridge_mut(&mut self) -> &mut Cartridge;
+
+    // Reads without side effects, for debuggers
+    fn prg_peek(&self, addr: u16) -> u8;
+    // Reads by the CPU. Boards with registers that change when read, like
+    // IRQ acknowledges, override this; the rest read as they peek.
+    fn prg_read(&mut self, addr: u16) -> u8 {
+        self.prg_peek(addr)
+    }
+    fn prg_write(&mut self, addr: u16, value: u8);
+    fn chr_read(&mut self, addr: u16) -> u8;
+    fn chr_write(&mut self, addr: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    // Console VRAM page (0-3) behind nametable `table` (0-3)
+    fn name_table_page(&self, table: usize) -> usize {
+        mirrored_page(self.mirroring(), table)
+    }
+
+    // Whether nothing on the board answers a CPU read of `addr`, leaving the
+    // value last on the data bus. Boards with registers below $6000 or
+    // switchable work RAM say so here.
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4020..=0x5FFF => true,
+            0x6000..=0x7FFF => self.cartridge().sram.is_empty(),
+            _ => false,
+        }
+    }
+
+    // The offset into work RAM, as read_sram takes it, that the CPU reaches
+    // at `addr` ($6000-$7FFF) with the board set as it is now, or None if
+    // RAM doesn't answer there. Boards that bank or disable their RAM, or
+    // have none at $6000, say so here.
+    fn sram_offset(&self, addr: u16) -> Option<usize> {
+        (!self.cartridge().sram.is_empty()).then(|| addr as usize - 0x6000)
+    }
+
+    // Nametable accesses ($2000-$2FFF) the board answers from its own
+    // memory; `None` and `false` leave them to console VRAM
+    fn name_table_read(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+    fn name_table_write(&mut self, _addr: u16, _value: u8) -> bool {
+        false
+    }
+
+    // Called as the PPU switches between kinds of reads
+    fn ppu_fetch(&mut self, _fetch: Fetch) {}
+
+    // Sees CPU writes to the PPU registers ($2000-$2007), for boards that
+    // watch the CPU bus for them
+    fn ppu_register_write(&mut self, _addr: u16, _value: u8) {}
+
+    // Sees CPU writes to $4016, whose output lines VS. System boards bank
+    // with
+    fn joypad_write(&mut self, _value: u8) {}
+
+    // Called once per CPU cycle
+    fn cpu_clock(&mut self) {}
+
+    // Current level of the board's expansion audio, on the APU mixer's 0-1
+    // scale
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // Names of the board's sound channels, indexing set_expansion_volume
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    // Scales one expansion channel's output, 1.0 being its normal level
+    fn set_expansion_volume(&mut self, _channel: usize, _volume: f32) {}
+
+    // Caps how many channels a board that plays its channels in turn, like
+    // the N163, cycles through
+    fn set_expansion_channel_limit(&mut self, _limit: usize) {}
+
+    // Number of disk sides in a Disk System drive, 0 for cartridges
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    // Side in the drive, if any
+    fn disk_side(&self) -> Option<usize> {
+        None
+    }
+
+    // Ejects the disk, inserting `side` if given
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+
+    // The Disk System disk, whose contents games save to and which outlast
+    // a power cycle
+    fn disk(&self) -> Option<&Disk> {
+        None
+    }
+
+    fn disk_mut(&mut self) -> Option<&mut Disk> {
+        None
+    }
+
+    // What the battery keeps between sessions, as written to the save file:
+    // work RAM, or the PRG of boards that flash it
+    fn battery_data(&self) -> &[u8] {
+        &self.cartridge().sram
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        self.cartridge_mut().load_sram(data);
+    }
+
+    // Called as PPU A12 rises during rendering, as MMC3-style counters see
+    // it once they've filtered out rises too close together: once per line
+    // when one of the background and sprites fetch from $1000 and the other
+    // from $0000
+    fn ppu_a12_rise(&mut self) {}
+
+    // Called by the PPU near the end of each rendered line, for boards that
+    // count lines without watching A12
+    fn scanline(&mut self) {}
+
+    // State of the cartridge's IRQ output, which the CPU polls each
+    // instruction
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    // Writes the board's registers and RAM for a save state, read back in
+    // the same order by `load`
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+// Console VRAM page (0-3) `mirroring` puts behind nametable `table` (0-3)
+pub(crate) fn mirrored_page(mirroring: Mirroring, table: usize) -> usize {
+    let pages = match mirroring {
+        Mirroring::Horizontal => [0, 0, 1, 1],
+        Mirroring::Vertical => [0, 1, 0, 1],
+        Mirroring::SingleScreenLower => [0, 0, 0, 0],
+        Mirroring::SingleScreenUpper => [1, 1, 1, 1],
+        Mirroring::FourScreen => [0, 1, 2, 3],
+    };
+    pages[table]
+}
+
+// Builds the mapper for the board named by the cartridge header
+pub fn new(cartridge: Cartridge) -> Result<Box<dyn Mapper>, CartridgeError> {
+    // NES 2.0 submapper 2 marks discrete boards whose ROM fights the CPU for
+    // the data bus on register writes; submapper 1 and unknown boards don't.
+    let submapper = cartridge.header.submapper;
+    let bus_conflicts = submapper == 2;
+
+    match cartridge.mapper() {
+        0 => Ok(Box::new(NROM::new(cartridge))),
+        // Submapper 5: SEROM/SHROM boards wire 32KB of PRG ROM straight in
+        1 => Ok(Box::new(MMC1::new(cartridge, submapper == 5))),
+        2 => Ok(Box::new(UxROM::new(cartridge, bus_conflicts))),
+        3 => Ok(Box::new(CNROM::new(cartridge, bus_conflicts))),
+        4 => {
+            let variant = match submapper {
+                1 => MMC3Variant::MMC6,
+                4 => MMC3Variant::MMC3A,
+                _ => MMC3Variant::MMC3,
+            };
+            Ok(Box::new(MMC3::new(cartridge, variant)))
+        }
+        5 => Ok(Box::new(MMC5::new(cartridge))),
+        7 => Ok(Box::new(AxROM::new(cartridge, bus_conflicts))),
+        11 => Ok(Box::new(GxROM::new(cartridge, true, bus_conflicts))),
+        19 => Ok(Box::new(N163::new(cartridge))),
+        21 | 22 | 23 | 25 => {
+            let wiring = vrc_wiring(cartridge.mapper(), submapper);
+            Ok(Box::new(VRC4::new(cartridge, wiring)))
+        }
+        MAPPER_FDS if cartridge.disk.is_some() => Ok(Box::new(FDS::new(cartridge))),
+        24 => Ok(Box::new(VRC6::new(cartridge, false))),
+        26 => Ok(Box::new(VRC6::new(cartridge, true))),
+        28 => Ok(Box::new(Action53::new(cartridge))),
+        30 => Ok(Box::new(UNROM512::new(cartridge))),
+        // Two unrelated boards share mapper 34. Without a submapper, NINA-001
+        // is the one with CHR ROM to bank.
+        34 if submapper == 1 || (submapper == 0 && !cartridge.chr_ram) => {
+            Ok(Box::new(NINA001::new(cartridge)))
+        }
+        34 => Ok(Box::new(BNROM::new(cartridge, bus_conflicts))),
+        66 => Ok(Box::new(GxROM::new(cartridge, false, bus_conflicts))),
+        69 => Ok(Box::new(FME7::new(cartridge))),
+        71 => Ok(Box::new(BF9093::new(cartridge))),
+        76 => Ok(Box::new(MMC3::new(cartridge, MMC3Variant::Namco3446))),
+        88 => Ok(Box::new(MMC3::new(cartridge, MMC3Variant::Namco3433))),
+        95 => Ok(Box::new(MMC3::new(cartridge, MMC3Variant::Namco3425))),
+        99 => Ok(Box::new(VSUniSystem::new(cartridge))),
+        154 => Ok(Box::new(MMC3::new(cartridge, MMC3Variant::Namco3453))),
+        206 => Ok(Box::new(MMC3::new(cartridge, MMC3Variant::Namco108))),
+        232 => Ok(Box::new(BF9096::new(cartridge, submapper == 1))),
+        mapper => Err(CartridgeError::UnsupportedMapper(mapper)),
+    }
+}
+
+// Address bits behind the VRC2/VRC4 register select lines A0 and A1 for each
+// mapper number and submapper; without a submapper both candidates are
+// decoded at once and the chip is taken to be a VRC4, which VRC2 games run on
+fn vrc_wiring(mapper: u16, submapper: u8) -> Wiring {
+    let (a0, a1, vrc2) = match (mapper, submapper) {
+        (21, 1) => (0x02, 0x04, false), // VRC4a
+        (21, 2) => (0x40, 0x80, false), // VRC4c
+        (21, _) => (0x42, 0x84, false),
+        (22, _) => (0x02, 0x01, true),  // VRC2a
+        (23, 1) => (0x01, 0x02, false), // VRC4f
+        (23, 2) => (0x04, 0x08, false), // VRC4e
+        (23, 3) => (0x01, 0x02, true),  // VRC2b
+        (23, _) => (0x05, 0x0A, false),
+        (25, 1) => (0x02, 0x01, false), // VRC4b
+        (25, 2) => (0x08, 0x04, false), // VRC4d
+        (25, 3) => (0x02, 0x01, true),  // VRC2c
+        (_, _) => (0x0A, 0x05, false),
+    };
+    Wiring {
+        a0,
+        a1,
+        vrc2,
+        chr_shift: (mapper == 22) as u8,
+    }
+}
+
+// Byte offset of a bank, wrapping out-of-range bank numbers and counting
+// negative indexes back from the last bank
+fn bank_offset(len: usize, bank_size: usize, index: isize) -> usize {
+    let banks = (len / bank_size).max(1) as isize;
+    index.rem_euclid(banks) as usize * bank_size
+}
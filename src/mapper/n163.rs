@@ -0,0 +1,342 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mixer level of one step of a channel's output. The chip only plays one
+// channel at a time, so a lone channel at full volume (15 * 15 away from
+// the midpoint) lands near an APU pulse at full volume.
+const LEVEL: f32 = 0.0007;
+// CPU cycles the chip spends on each channel before moving to the next
+const CHANNEL_CYCLES: u8 = 15;
+
+// Mapper 19: Namco 163. Three switchable 8KB PRG banks, eight 1KB CHR banks,
+// four nametable banks that can point into CHR ROM, a 15-bit CPU cycle IRQ
+// counter and 128 bytes of internal RAM holding the registers and waveforms
+// of up to eight wavetable channels. Banks from $E0 up in the pattern tables
+// select console VRAM on the real chip, which isn't supported; they read CHR
+// ROM instead.
+pub struct N163 {
+    cartridge: Cartridge,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    // Banks from $E0 up select console VRAM page (bank & 1)
+    name_table_banks: [u8; 4],
+    // $E000 bit 6
+    sound_disabled: bool,
+    // $F800 (bit 7: increment after each data port access;
+    //        bits 0-6: internal RAM address)
+    ram_address: u8,
+    // $F800 as the work RAM write protect: writes need bits 4-7 to be
+    // 0100 and the bit for the 2KB window to be clear
+    ram_protect: u8,
+    ram: [u8; 128],
+    counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+    // Channel being played and cycles left on it
+    channel: usize,
+    channel_timer: u8,
+    // Level of the channel last played, held until the next one
+    output: f32,
+    // Frontend settings, not saved: volumes for channels 0-7 and how many
+    // of the enabled channels to actually play, since cycling through all
+    // eight makes an audible whine on some setups
+    volumes: [f32; 8],
+    channel_limit: usize,
+}
+
+impl N163 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            cartridge,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            name_table_banks: [0; 4],
+            sound_disabled: false,
+            ram_address: 0,
+            ram_protect: 0,
+            ram: [0; 128],
+            counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            channel: 7,
+            channel_timer: 0,
+            output: 0.0,
+            volumes: [1.0; 8],
+            channel_limit: 8,
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.ram[(self.ram_address & 0x7F) as usize] = value;
+        self.increment_address();
+    }
+
+    fn increment_address(&mut self) {
+        if self.ram_address & 0x80 != 0 {
+            self.ram_address = 0x80 | (self.ram_address.wrapping_add(1) & 0x7F);
+        }
+    }
+
+    fn prg_ram_writable(&self, addr: u16) -> bool {
+        let window = (addr - 0x6000) / 0x0800;
+        self.ram_protect & 0xF0 == 0x40 && self.ram_protect & (1 << window) == 0
+    }
+
+    fn chr_offset(&self, bank: u8, addr: u16) -> usize {
+        bank_offset(self.cartridge.chr.len(), 0x0400, bank as isize) + addr as usize % 0x0400
+    }
+
+    // Channels play from 7 down; $7F bits 4-6 hold how many are enabled,
+    // less one
+    fn channels(&self) -> usize {
+        (((self.ram[0x7F] >> 4) & 7) as usize + 1).min(self.channel_limit)
+    }
+
+    // Advances the current channel's phase through its waveform and
+    // returns its level. Each channel keeps eight bytes from $40 + 8n:
+    // frequency and phase (24 bits each, interleaved low to high), the
+    // waveform length (256 - 4n samples, in the top bits of the frequency's
+    // high byte), the waveform's address in 4-bit samples, and its volume.
+    fn play_channel(&mut self, channel: usize) -> f32 {
+        let base = 0x40 + channel * 8;
+        let registers = &mut self.ram[base..base + 8];
+        let frequency =
+            registers[0] as u32 | (registers[2] as u32) << 8 | ((registers[4] & 0x03) as u32) << 16;
+        let length = 256 - (registers[4] & 0xFC) as u32;
+        let mut phase =
+            registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        phase = (phase + frequency) % (length << 16);
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+        let address = (registers[6] as u32 + (phase >> 16)) & 0xFF;
+        let volume = (registers[7] & 0x0F) as i32;
+
+        let byte = self.ram[address as usize / 2];
+        let sample = if address.is_multiple_of(2) {
+            byte & 0x0F
+        } else {
+            byte >> 4
+        };
+        ((sample as i32 - 8) * volume) as f32 * self.volumes[channel]
+    }
+}
+
+impl Mapper for N163 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.ram[(self.ram_address & 0x7F) as usize],
+            0x5000..=0x57FF => self.counter as u8,
+            0x5800..=0x5FFF => (self.counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / 0x2000];
+                let offset = bank_offset(self.cartridge.prg.len(), 0x2000, bank as isize);
+                self.cartridge.prg[offset + addr as usize % 0x2000]
+            }
+            0xE000..=0xFFFF => {
+                let offset = bank_offset(self.cartridge.prg.len(), 0x2000, -1);
+                self.cartridge.prg[offset + addr as usize - 0xE000]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_read(&mut self, addr: u16) -> u8 {
+        let value = self.prg_peek(addr);
+        if let 0x4800..=0x4FFF = addr {
+            self.increment_address();
+        }
+        value
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.write_data(value),
+            0x5000..=0x57FF => {
+                self.counter = (self.counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.counter = (self.counter & 0x00FF) | ((value & 0x7F) as u16) << 8;
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable(addr) => {
+                self.cartridge.write_sram(addr as usize - 0x6000, value);
+            }
+            0x8000..=0xBFFF => self.chr_banks[(addr as usize - 0x8000) / 0x0800] = value,
+            0xC000..=0xDFFF => self.name_table_banks[(addr as usize - 0xC000) / 0x0800] = value,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = value & 0x3F;
+                self.sound_disabled = value & 0x40 != 0;
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = value & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = value & 0x3F,
+            0xF800..=0xFFFF => {
+                self.ram_address = value;
+                self.ram_protect = value;
+            }
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4020..=0x47FF => true,
+            0x6000..=0x7FFF => self.cartridge.sram.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[addr as usize / 0x0400];
+        self.cartridge.chr[self.chr_offset(bank, addr)]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let bank = self.chr_banks[addr as usize / 0x0400];
+            let offset = self.chr_offset(bank, addr);
+            self.cartridge.chr[offset] = value;
+        }
+    }
+
+    // Only meaningful while every nametable points at console VRAM
+    fn mirroring(&self) -> Mirroring {
+        match self.name_table_banks.map(|bank| bank & 1) {
+            [0, 1, 0, 1] => Mirroring::Vertical,
+            [0, 0, 0, 0] => Mirroring::SingleScreenLower,
+            [1, 1, 1, 1] => Mirroring::SingleScreenUpper,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn name_table_page(&self, table: usize) -> usize {
+        (self.name_table_banks[table] & 1) as usize
+    }
+
+    fn name_table_read(&mut self, addr: u16) -> Option<u8> {
+        let bank = self.name_table_banks[(addr as usize - 0x2000) / 0x0400];
+        if bank >= 0xE0 {
+            return None;
+        }
+        Some(self.cartridge.chr[self.chr_offset(bank, addr)])
+    }
+
+    fn name_table_write(&mut self, addr: u16, value: u8) -> bool {
+        let bank = self.name_table_banks[(addr as usize - 0x2000) / 0x0400];
+        if bank >= 0xE0 {
+            return false;
+        }
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(bank, addr);
+            self.cartridge.chr[offset] = value;
+        }
+        true
+    }
+
+    // The counter counts up every CPU cycle while enabled, stopping at
+    // $7FFF and raising the IRQ there
+    fn cpu_clock(&mut self) {
+        if self.irq_enabled && self.counter < 0x7FFF {
+            self.counter += 1;
+            if self.counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+
+        if self.channel_timer > 0 {
+            self.channel_timer -= 1;
+            return;
+        }
+        self.channel_timer = CHANNEL_CYCLES - 1;
+        let channels = self.channels();
+        self.channel = if self.channel <= 8 - channels {
+            7
+        } else {
+            self.channel - 1
+        };
+        self.output = self.play_channel(self.channel);
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_disabled {
+            0.0
+        } else {
+            self.output * LEVEL
+        }
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        vec![
+            "Channel 1",
+            "Channel 2",
+            "Channel 3",
+            "Channel 4",
+            "Channel 5",
+            "Channel 6",
+            "Channel 7",
+            "Channel 8",
+        ]
+    }
+
+    fn set_expansion_volume(&mut self, channel: usize, volume: f32) {
+        if let Some(v) = self.volumes.get_mut(channel) {
+            *v = volume;
+        }
+    }
+
+    fn set_expansion_channel_limit(&mut self, limit: usize) {
+        self.channel_limit = limit.clamp(1, 8);
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_banks);
+        state.write(&self.chr_banks);
+        state.write(&self.name_table_banks);
+        state.write(&self.sound_disabled);
+        state.write(&self.ram_address);
+        state.write(&self.ram_protect);
+        state.write(&self.ram[..]);
+        state.write(&self.counter);
+        state.write(&self.irq_enabled);
+        state.write(&self.irq_pending);
+        state.write(&self.channel);
+        state.write(&self.channel_timer);
+        state.write(&self.output);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_banks = state.read()?;
+        self.chr_banks = state.read()?;
+        self.name_table_banks = state.read()?;
+        self.sound_disabled = state.read()?;
+        self.ram_address = state.read()?;
+        self.ram_protect = state.read()?;
+        state.read_into(&mut self.ram)?;
+        self.counter = state.read()?;
+        self.irq_enabled = state.read()?;
+        self.irq_pending = state.read()?;
+        self.channel = state.read()?;
+        self.channel_timer = state.read()?;
+        self.output = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 34 as AVE NINA-001: 8KB of work RAM whose last three bytes also
+// write the registers, selecting a 32KB PRG bank ($7FFD) and two 4KB CHR
+// banks ($7FFE, $7FFF)
+pub struct NINA001 {
+    cartridge: Cartridge,
+    prg_bank: usize,
+    chr_banks: [usize; 2],
+}
+
+impl NINA001 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let chr_banks = [0, bank_offset(cartridge.chr.len(), 0x1000, 1)];
+        Self {
+            cartridge,
+            prg_bank: 0,
+            chr_banks,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / 0x1000];
+        (bank + addr as usize % 0x1000) % self.cartridge.chr.len()
+    }
+}
+
+impl Mapper for NINA001 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0x8000..=0xFFFF => {
+                let prg = &self.cartridge.prg;
+                prg[(self.prg_bank + (addr as usize - 0x8000)) % prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.cartridge.write_sram(addr as usize - 0x6000, value);
+        }
+        let (prg_len, chr_len) = (self.cartridge.prg.len(), self.cartridge.chr.len());
+        match addr {
+            0x7FFD => self.prg_bank = bank_offset(prg_len, 0x8000, (value & 0x01) as isize),
+            0x7FFE => self.chr_banks[0] = bank_offset(chr_len, 0x1000, (value & 0x0F) as isize),
+            0x7FFF => self.chr_banks[1] = bank_offset(chr_len, 0x1000, (value & 0x0F) as isize),
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[self.chr_offset(addr)]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.cartridge.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.chr_banks);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read()?;
+        self.chr_banks = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
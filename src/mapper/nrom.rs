@@ -0,0 +1,67 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::Mapper,
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 0: 16KB or 32KB of fixed PRG ROM and 8KB of fixed CHR
+pub struct NROM {
+    cartridge: Cartridge,
+}
+
+impl NROM {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self { cartridge }
+    }
+}
+
+impl Mapper for NROM {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            // A 16KB board mirrors its only bank into $C000-$FFFF
+            0x8000..=0xFFFF => {
+                let prg = &self.cartridge.prg;
+                prg[(addr as usize - 0x8000) % prg.len()]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.cartridge.write_sram(addr as usize - 0x6000, value);
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[addr as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
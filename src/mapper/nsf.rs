@@ -0,0 +1,310 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{Mapper, FDS, FME7, MMC5, N163, VRC6},
+    nsf::{
+        EXPANSION_FDS, EXPANSION_MMC5, EXPANSION_N163, EXPANSION_SUNSOFT_5B, EXPANSION_VRC6, NSF,
+    },
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Where the player parks the CPU between calls: a JMP to itself. Init and
+// play return here.
+pub(crate) const IDLE_ADDRESS: u16 = 0x4100;
+const IDLE_LOOP: [u8; 3] = [0x4C, IDLE_ADDRESS as u8, (IDLE_ADDRESS >> 8) as u8];
+
+const BANK_SIZE: usize = 0x1000;
+// 4KB windows from $6000 up; the first two are only banked on FDS tunes
+const WINDOWS: usize = 10;
+
+// An expansion sound chip and the CPU addresses routed to it. The chips are
+// the cartridge mappers, sitting on blank cartridges with only their sound
+// registers reachable.
+struct Chip {
+    mapper: Box<dyn Mapper>,
+    reads: &'static [RangeInclusive<u16>],
+    writes: &'static [RangeInclusive<u16>],
+}
+
+impl Chip {
+    fn new(
+        mapper: Box<dyn Mapper>,
+        reads: &'static [RangeInclusive<u16>],
+        writes: &'static [RangeInclusive<u16>],
+    ) -> Self {
+        Self {
+            mapper,
+            reads,
+            writes,
+        }
+    }
+
+    fn reads(&self, addr: u16) -> bool {
+        self.reads.iter().any(|range| range.contains(&addr))
+    }
+
+    fn writes(&self, addr: u16) -> bool {
+        self.writes.iter().any(|range| range.contains(&addr))
+    }
+}
+
+// The board an NSF tune plays on: its program in 4KB banks switched through
+// $5FF8-$5FFF, 8KB of work RAM at $6000 (or RAM all the way up to $FFFF
+// for FDS tunes, filled from the banks), the player's idle loop and the
+// expansion chips the tune asks for
+pub struct NSFMapper {
+    cartridge: Cartridge,
+    // Program data, padded so the load address falls at its offset within
+    // a bank
+    data: Vec<u8>,
+    banks: [Option<usize>; WINDOWS],
+    fds: bool,
+    ram: Vec<u8>,
+    chips: Vec<Chip>,
+}
+
+impl NSFMapper {
+    pub fn new(nsf: &NSF) -> Self {
+        let padding = nsf.load_address as usize % BANK_SIZE;
+        let mut data = vec![0; padding];
+        data.extend_from_slice(&nsf.data);
+
+        // Unbanked tunes sit at their load address, as if banked in order
+        let banks = if nsf.bankswitched() {
+            let mut banks = [None; WINDOWS];
+            for (window, &bank) in nsf.banks.iter().enumerate() {
+                banks[window + 2] = Some(bank as usize);
+            }
+            if nsf.expansion & EXPANSION_FDS != 0 {
+                banks[0] = banks[8];
+                banks[1] = banks[9];
+            }
+            banks
+        } else {
+            let first = (nsf.load_address as usize).saturating_sub(0x6000) / BANK_SIZE;
+            std::array::from_fn(|window| window.checked_sub(first))
+        };
+
+        let fds = nsf.expansion & EXPANSION_FDS != 0;
+        let mut chips = Vec::new();
+        if nsf.expansion & EXPANSION_VRC6 != 0 {
+            chips.push(Chip::new(
+                Box::new(VRC6::new(blank_cartridge(24), false)),
+                &[],
+                &[0x9000..=0x9003, 0xA000..=0xA002, 0xB000..=0xB002],
+            ));
+        }
+        if fds {
+            let mut mapper = FDS::new(blank_cartridge(0));
+            // Opens the sound registers
+            mapper.prg_write(0x4023, 0x02);
+            chips.push(Chip::new(
+                Box::new(mapper),
+                &[0x4040..=0x4092],
+                &[0x4040..=0x408A],
+            ));
+        }
+        if nsf.expansion & EXPANSION_MMC5 != 0 {
+            let mut mapper = MMC5::new(blank_cartridge(5));
+            // ExRAM as plain RAM
+            mapper.prg_write(0x5104, 0x02);
+            chips.push(Chip::new(
+                Box::new(mapper),
+                &[
+                    0x5010..=0x5010,
+                    0x5015..=0x5015,
+                    0x5205..=0x5206,
+                    0x5C00..=0x5FF5,
+                ],
+                &[0x5000..=0x5015, 0x5205..=0x5206, 0x5C00..=0x5FF5],
+            ));
+        }
+        if nsf.expansion & EXPANSION_N163 != 0 {
+            chips.push(Chip::new(
+                Box::new(N163::new(blank_cartridge(19))),
+                &[0x4800..=0x4FFF],
+                &[0x4800..=0x4FFF, 0xF800..=0xFFFF],
+            ));
+        }
+        if nsf.expansion & EXPANSION_SUNSOFT_5B != 0 {
+            chips.push(Chip::new(
+                Box::new(FME7::new(blank_cartridge(69))),
+                &[],
+                &[0xC000..=0xC000, 0xE000..=0xE000],
+            ));
+        }
+
+        let mut mapper = Self {
+            cartridge: blank_cartridge(0),
+            data,
+            banks,
+            fds,
+            ram: vec![0; if fds { WINDOWS * BANK_SIZE } else { 0x2000 }],
+            chips,
+        };
+        if fds {
+            for window in 0..WINDOWS {
+                mapper.load_window(window);
+            }
+        }
+        mapper
+    }
+
+    fn bank_read(&self, window: usize, offset: usize) -> u8 {
+        match self.banks[window] {
+            Some(bank) => self
+                .data
+                .get(bank * BANK_SIZE + offset)
+                .copied()
+                .unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    // FDS tunes run from RAM, so switching a bank copies it in
+    fn load_window(&mut self, window: usize) {
+        for offset in 0..BANK_SIZE {
+            self.ram[window * BANK_SIZE + offset] = self.bank_read(window, offset);
+        }
+    }
+}
+
+// A cartridge with nothing on it, for the boards whose mapper only needs
+// one to exist
+fn blank_cartridge(mapper: u16) -> Cartridge {
+    let mut image = b"NES\x1A\x01\x00".to_vec();
+    image.push(((mapper & 0x0F) as u8) << 4);
+    image.push((mapper & 0xF0) as u8);
+    image.resize(16 + 0x4000, 0);
+    Cartridge::from_bytes(&image).expect("blank cartridge is a valid image")
+}
+
+impl Mapper for NSFMapper {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        if let Some(chip) = self.chips.iter().find(|chip| chip.reads(addr)) {
+            return chip.mapper.prg_peek(addr);
+        }
+        let offset = addr as usize % BANK_SIZE;
+        match addr {
+            IDLE_ADDRESS..=0x4102 => IDLE_LOOP[(addr - IDLE_ADDRESS) as usize],
+            0x6000..=0xFFFF if self.fds => self.ram[addr as usize - 0x6000],
+            0x6000..=0x7FFF => self.ram[addr as usize - 0x6000],
+            0x8000..=0xFFFF => self.bank_read((addr as usize - 0x6000) / BANK_SIZE, offset),
+            _ => 0,
+        }
+    }
+
+    fn prg_read(&mut self, addr: u16) -> u8 {
+        match self.chips.iter_mut().find(|chip| chip.reads(addr)) {
+            Some(chip) => chip.mapper.prg_read(addr),
+            None => self.prg_peek(addr),
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        for chip in self.chips.iter_mut().filter(|chip| chip.writes(addr)) {
+            chip.mapper.prg_write(addr, value);
+        }
+        match addr {
+            0x5FF6..=0x5FF7 if self.fds => {
+                let window = (addr - 0x5FF6) as usize;
+                self.banks[window] = Some(value as usize);
+                self.load_window(window);
+            }
+            0x5FF8..=0x5FFF => {
+                let window = (addr - 0x5FF8) as usize + 2;
+                self.banks[window] = Some(value as usize);
+                if self.fds {
+                    self.load_window(window);
+                }
+            }
+            0x6000..=0xFFFF if self.fds => self.ram[addr as usize - 0x6000] = value,
+            0x6000..=0x7FFF => self.ram[addr as usize - 0x6000] = value,
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            IDLE_ADDRESS..=0x4102 => false,
+            0x4020..=0x5FFF => !self.chips.iter().any(|chip| chip.reads(addr)),
+            _ => false,
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        self.cartridge.chr[addr as usize] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn cpu_clock(&mut self) {
+        for chip in &mut self.chips {
+            chip.mapper.cpu_clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.chips
+            .iter()
+            .map(|chip| chip.mapper.audio_output())
+            .sum()
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        self.chips
+            .iter()
+            .flat_map(|chip| chip.mapper.expansion_channels())
+            .collect()
+    }
+
+    // Channels are numbered across the chips in the order listed
+    fn set_expansion_volume(&mut self, mut channel: usize, volume: f32) {
+        for chip in &mut self.chips {
+            let channels = chip.mapper.expansion_channels().len();
+            if channel < channels {
+                chip.mapper.set_expansion_volume(channel, volume);
+                return;
+            }
+            channel -= channels;
+        }
+    }
+
+    fn set_expansion_channel_limit(&mut self, limit: usize) {
+        for chip in &mut self.chips {
+            chip.mapper.set_expansion_channel_limit(limit);
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.banks);
+        state.write(&self.ram[..]);
+        for chip in &self.chips {
+            chip.mapper.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.banks = state.read()?;
+        state.read_into(&mut self.ram)?;
+        for chip in &mut self.chips {
+            chip.mapper.load(state)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Up to 32KB of CHR RAM in 8KB banks
+const CHR_RAM_SIZE: usize = 0x8000;
+
+// Mapper 30: UNROM 512, a homebrew UxROM with 16KB PRG banks at $8000 (bits
+// 0-4), the last bank fixed at $C000, 8KB CHR RAM banks (bits 5-6) and
+// one-screen mirroring (bit 7). A header with the four-screen bit set asks
+// for the one-screen mirroring; the rare boards with real four-screen VRAM
+// aren't supported.
+//
+// With the battery bit set the PRG ROM is an SST39SF040 flash chip the game
+// can rewrite, to save progress: registers move to $C000-$FFFF and writes to
+// $8000-$BFFF become flash commands. The flash is what the battery save
+// keeps. Boards without it have bus conflicts.
+pub struct UNROM512 {
+    cartridge: Cartridge,
+    flashable: bool,
+    prg_bank: usize,
+    prg_last: usize,
+    chr_bank: usize,
+    mirroring: Mirroring,
+    flash: Flash,
+}
+
+// How far the chip is through a command's unlock sequence: $AA to $5555, $55
+// to $2AAA, then the command to $5555
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Flash {
+    Idle,
+    Unlock1,
+    Unlock2,
+    // $A0: the next write programs a byte
+    Program,
+    // $80: an erase, which takes the unlock sequence again
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+    // $90: reads return the chip's IDs until $F0 is written
+    SoftwareID,
+}
+
+impl UNROM512 {
+    pub fn new(mut cartridge: Cartridge) -> Self {
+        if cartridge.chr_ram && cartridge.chr.len() < CHR_RAM_SIZE {
+            cartridge.chr.resize(CHR_RAM_SIZE, 0);
+        }
+        let flashable = cartridge.header.battery;
+        let prg_last = bank_offset(cartridge.prg.len(), 0x4000, -1);
+        let mirroring = match cartridge.mirroring() {
+            Mirroring::FourScreen => Mirroring::SingleScreenLower,
+            mirroring => mirroring,
+        };
+        Self {
+            cartridge,
+            flashable,
+            prg_bank: 0,
+            prg_last,
+            chr_bank: 0,
+            mirroring,
+            flash: Flash::Idle,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        let value = if self.flashable {
+            value
+        } else {
+            value & self.prg_read(addr)
+        };
+        let cartridge = &self.cartridge;
+        self.prg_bank = bank_offset(cartridge.prg.len(), 0x4000, (value & 0x1F) as isize);
+        self.chr_bank = bank_offset(cartridge.chr.len(), 0x2000, ((value >> 5) & 0x03) as isize);
+        if cartridge.mirroring() == Mirroring::FourScreen {
+            self.mirroring = match value & 0x80 {
+                0 => Mirroring::SingleScreenLower,
+                _ => Mirroring::SingleScreenUpper,
+            };
+        }
+    }
+
+    // A write to $8000-$BFFF, addressing the flash chip through the bank
+    // selected at $8000. Commands decode the low 15 address bits alone.
+    fn write_flash(&mut self, addr: u16, value: u8) {
+        let offset = self.prg_bank + (addr as usize - 0x8000);
+        let command = offset & 0x7FFF;
+        self.flash = match (self.flash, command, value) {
+            (Flash::Program, _, _) => {
+                // Programming can only clear bits
+                self.cartridge.prg[offset] &= value;
+                Flash::Idle
+            }
+            (Flash::Idle, 0x5555, 0xAA) => Flash::Unlock1,
+            (Flash::Unlock1, 0x2AAA, 0x55) => Flash::Unlock2,
+            (Flash::Unlock2, 0x5555, 0xA0) => Flash::Program,
+            (Flash::Unlock2, 0x5555, 0x80) => Flash::Erase,
+            (Flash::Unlock2, 0x5555, 0x90) => Flash::SoftwareID,
+            (Flash::Erase, 0x5555, 0xAA) => Flash::EraseUnlock1,
+            (Flash::EraseUnlock1, 0x2AAA, 0x55) => Flash::EraseUnlock2,
+            (Flash::EraseUnlock2, _, 0x30) => {
+                // One 4KB sector
+                let sector = offset & !0xFFF;
+                self.cartridge.prg[sector..sector + 0x1000].fill(0xFF);
+                Flash::Idle
+            }
+            (Flash::EraseUnlock2, 0x5555, 0x10) => {
+                self.cartridge.prg.fill(0xFF);
+                Flash::Idle
+            }
+            (Flash::SoftwareID, _, 0xF0) => Flash::Idle,
+            (Flash::SoftwareID, _, _) => Flash::SoftwareID,
+            _ => Flash::Idle,
+        };
+    }
+}
+
+impl Mapper for UNROM512 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            // Manufacturer and device IDs of an SST39SF040
+            0x8000..=0xFFFF if self.flash == Flash::SoftwareID => match addr & 0x01 {
+                0 => 0xBF,
+                _ => 0xB7,
+            },
+            0x8000..=0xBFFF => self.cartridge.prg[self.prg_bank + (addr as usize - 0x8000)],
+            0xC000..=0xFFFF => self.cartridge.prg[self.prg_last + (addr as usize - 0xC000)],
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0xBFFF if self.flashable => self.write_flash(addr, value),
+            0x8000..=0xFFFF => self.write_register(addr, value),
+            _ => {}
+        }
+    }
+
+    // Nothing on the board answers at $6000-$7FFF
+    fn sram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        let chr = &self.cartridge.chr;
+        chr[(self.chr_bank + addr as usize) % chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[(self.chr_bank + addr as usize) % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn battery_data(&self) -> &[u8] {
+        &self.cartridge.prg
+    }
+
+    fn load_battery_data(&mut self, data: &[u8]) {
+        // A save from a different build of the game would leave it
+        // half-overwritten
+        if data.len() == self.cartridge.prg.len() {
+            self.cartridge.prg.copy_from_slice(data);
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.chr_bank);
+        state.write(&self.mirroring);
+        state.write(&self.flash);
+        if self.flashable {
+            state.write(&self.cartridge.prg[..]);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read()?;
+        self.chr_bank = state.read()?;
+        self.mirroring = state.read()?;
+        self.flash = state.read()?;
+        if self.flashable {
+            state.read_into(&mut self.cartridge.prg)?;
+        }
+        Ok(())
+    }
+}
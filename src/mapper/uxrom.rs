@@ -0,0 +1,87 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 2: switchable 16KB bank at $8000, last bank fixed at $C000. The
+// whole register selects the bank, for oversize homebrew boards past the
+// 256KB of licensed ones.
+pub struct UxROM {
+    cartridge: Cartridge,
+    bus_conflicts: bool,
+    prg_bank: usize,
+    prg_last: usize,
+}
+
+impl UxROM {
+    pub fn new(cartridge: Cartridge, bus_conflicts: bool) -> Self {
+        let prg_last = bank_offset(cartridge.prg.len(), 0x4000, -1);
+        Self {
+            cartridge,
+            bus_conflicts,
+            prg_bank: 0,
+            prg_last,
+        }
+    }
+}
+
+impl Mapper for UxROM {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0x8000..=0xBFFF => self.cartridge.prg[self.prg_bank + (addr as usize - 0x8000)],
+            0xC000..=0xFFFF => self.cartridge.prg[self.prg_last + (addr as usize - 0xC000)],
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.write_sram(addr as usize - 0x6000, value),
+            0x8000..=0xFFFF => {
+                let value = if self.bus_conflicts {
+                    value & self.prg_read(addr)
+                } else {
+                    value
+                };
+                let len = self.cartridge.prg.len();
+                self.prg_bank = bank_offset(len, 0x4000, value as isize);
+            }
+            _ => {}
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[addr as usize % self.cartridge.chr.len()]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            self.cartridge.chr[addr as usize % len] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_bank);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_bank = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
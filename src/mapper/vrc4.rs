@@ -0,0 +1,217 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, vrc_irq::VRCIRQ, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// How a board wires up the chip. Each variant routes different CPU address
+// bits to the chip's two register select lines; when the header doesn't say
+// which variant it is, the masks hold the bits of every candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Wiring {
+    pub a0: u16,
+    pub a1: u16,
+    pub vrc2: bool,
+    // VRC2a drops the low bit of CHR bank numbers
+    pub chr_shift: u8,
+}
+
+// Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4. Two switchable 8KB PRG
+// banks, eight 1KB CHR banks each written a nibble at a time, and on the
+// VRC4 a PRG swap mode and the VRC IRQ counter.
+pub struct VRC4 {
+    cartridge: Cartridge,
+    wiring: Wiring,
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    irq: VRCIRQ,
+    // VRC2 boards without work RAM keep a single bit at $6000-$6FFF
+    latch: u8,
+    prg_offsets: [usize; 4],
+    chr_offsets: [usize; 8],
+}
+
+impl VRC4 {
+    pub fn new(cartridge: Cartridge, wiring: Wiring) -> Self {
+        let mirroring = cartridge.mirroring();
+        let mut mapper = Self {
+            cartridge,
+            wiring,
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring,
+            irq: VRCIRQ::new(),
+            latch: 0,
+            prg_offsets: [0; 4],
+            chr_offsets: [0; 8],
+        };
+        mapper.update_offsets();
+        mapper
+    }
+
+    // Folds the board's address lines down to $x000-$x003
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = (addr & self.wiring.a0 != 0) as u16;
+        let a1 = (addr & self.wiring.a1 != 0) as u16;
+        (addr & 0xF000) | a1 << 1 | a0
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        let vrc4 = !self.wiring.vrc2;
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9003 if !vrc4 => self.write_mirror(value & 1),
+            0x9000 | 0x9001 => self.write_mirror(value & 3),
+            0x9002 if vrc4 => self.prg_swap = value & 0x02 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            register @ 0xB000..=0xE003 => {
+                let index = ((register - 0xB000) >> 12) as usize * 2 + (register as usize & 2) / 2;
+                let bank = &mut self.chr_banks[index];
+                *bank = if register & 1 == 0 {
+                    (*bank & 0x1F0) | (value & 0x0F) as u16
+                } else {
+                    let mask = if vrc4 { 0x1F } else { 0x0F };
+                    (*bank & 0x0F) | ((value & mask) as u16) << 4
+                };
+            }
+            0xF000 if vrc4 => self.irq.write_latch_low(value),
+            0xF001 if vrc4 => self.irq.write_latch_high(value),
+            0xF002 if vrc4 => self.irq.write_control(value),
+            0xF003 if vrc4 => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_offsets();
+    }
+
+    // Mirroring (0: vertical; 1: horizontal; 2: one-screen, lower bank;
+    //            3: one-screen, upper bank)
+    fn write_mirror(&mut self, value: u8) {
+        self.mirroring = match value {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        };
+    }
+
+    fn prg_bank_offset(&self, index: isize) -> usize {
+        bank_offset(self.cartridge.prg.len(), 0x2000, index)
+    }
+
+    // PRG swap mode (0: $8000 swappable, $C000 fixed to second-last bank;
+    //                1: $C000 swappable, $8000 fixed to second-last bank)
+    fn update_offsets(&mut self) {
+        let switchable = self.prg_bank_offset(self.prg_banks[0] as isize);
+        let second_last = self.prg_bank_offset(-2);
+        let (first, third) = if self.prg_swap {
+            (second_last, switchable)
+        } else {
+            (switchable, second_last)
+        };
+        self.prg_offsets = [
+            first,
+            self.prg_bank_offset(self.prg_banks[1] as isize),
+            third,
+            self.prg_bank_offset(-1),
+        ];
+
+        for (i, &bank) in self.chr_banks.iter().enumerate() {
+            let bank = (bank >> self.wiring.chr_shift) as isize;
+            self.chr_offsets[i] = bank_offset(self.cartridge.chr.len(), 0x0400, bank);
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_offsets[addr as usize / 0x0400] + addr as usize % 0x0400
+    }
+}
+
+impl Mapper for VRC4 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x6FFF if self.cartridge.sram.is_empty() && self.wiring.vrc2 => self.latch,
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize - 0x6000),
+            0x8000..=0xFFFF => {
+                let addr = addr as usize - 0x8000;
+                let bank = addr / 0x2000;
+                self.cartridge.prg[self.prg_offsets[bank] + addr % 0x2000]
+            }
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x6FFF if self.cartridge.sram.is_empty() && self.wiring.vrc2 => {
+                self.latch = value & 1;
+            }
+            0x6000..=0x7FFF => self.cartridge.write_sram(addr as usize - 0x6000, value),
+            0x8000..=0xFFFF => self.write_register(addr, value),
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4020..=0x5FFF => true,
+            0x6000..=0x6FFF if self.wiring.vrc2 => false,
+            0x6000..=0x7FFF => self.cartridge.sram.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[self.chr_offset(addr)]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.cartridge.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.step();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_banks);
+        state.write(&self.prg_swap);
+        state.write(&self.chr_banks);
+        state.write(&self.mirroring);
+        state.write(&self.irq);
+        state.write(&self.latch);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_banks = state.read()?;
+        self.prg_swap = state.read()?;
+        self.chr_banks = state.read()?;
+        self.mirroring = state.read()?;
+        self.irq = state.read()?;
+        self.latch = state.read()?;
+        self.update_offsets();
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
@@ -0,0 +1,356 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, vrc_irq::VRCIRQ, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mixer level of one step of VRC6 output, putting a full-volume VRC6 pulse
+// on par with a full-volume APU pulse
+const LEVEL: f32 = 0.00996;
+
+// Mappers 24 and 26: Konami VRC6. A 16KB and an 8KB PRG bank, eight 1KB CHR
+// registers, the VRC IRQ counter and three sound channels: two pulses with
+// eight duty cycles and a sawtooth. Mapper 26 swaps the register select
+// lines.
+pub struct VRC6 {
+    cartridge: Cartridge,
+    swapped: bool,
+    prg_16k: u8,
+    prg_8k: u8,
+    chr_banks: [u8; 8],
+    // $B003: PPU banking mode, mirroring and PRG RAM enable
+    ppu_control: u8,
+    irq: VRCIRQ,
+    // $9003 (bit 0: halt all channels; bits 1-2: speed the timers up 16x
+    //        or 256x)
+    frequency_control: u8,
+    pulse1: VRC6Pulse,
+    pulse2: VRC6Pulse,
+    sawtooth: Sawtooth,
+    // Frontend settings for pulse 1, pulse 2 and the sawtooth; not saved
+    volumes: [f32; 3],
+    prg_offsets: [usize; 3],
+    chr_offsets: [usize; 8],
+}
+
+impl VRC6 {
+    pub fn new(cartridge: Cartridge, swapped: bool) -> Self {
+        let mut mapper = Self {
+            cartridge,
+            swapped,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_banks: [0; 8],
+            ppu_control: 0,
+            irq: VRCIRQ::new(),
+            frequency_control: 0,
+            pulse1: VRC6Pulse::default(),
+            pulse2: VRC6Pulse::default(),
+            sawtooth: Sawtooth::default(),
+            volumes: [1.0; 3],
+            prg_offsets: [0; 3],
+            chr_offsets: [0; 8],
+        };
+        mapper.update_offsets();
+        mapper
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        let mut register = addr & 0xF003;
+        if self.swapped {
+            register = (register & 0xF000) | (addr & 1) << 1 | (addr >> 1) & 1;
+        }
+        match register {
+            0x8000..=0x8003 => self.prg_16k = value & 0x0F,
+            0x9000 => self.pulse1.write_control(value),
+            0x9001 => self.pulse1.write_period_low(value),
+            0x9002 => self.pulse1.write_period_high(value),
+            0x9003 => self.frequency_control = value & 0x07,
+            0xA000 => self.pulse2.write_control(value),
+            0xA001 => self.pulse2.write_period_low(value),
+            0xA002 => self.pulse2.write_period_high(value),
+            0xB000 => self.sawtooth.rate = value & 0x3F,
+            0xB001 => self.sawtooth.write_period_low(value),
+            0xB002 => self.sawtooth.write_period_high(value),
+            0xB003 => self.ppu_control = value,
+            0xC000..=0xC003 => self.prg_8k = value & 0x1F,
+            0xD000..=0xD003 => self.chr_banks[register as usize & 3] = value,
+            0xE000..=0xE003 => self.chr_banks[4 + (register as usize & 3)] = value,
+            0xF000 => self.irq.write_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+        self.update_offsets();
+    }
+
+    // PPU banking mode (0: eight 1KB banks;
+    //                   1: R0-R3 as 2KB banks;
+    //                   2, 3: R0-R3 as 1KB banks at $0000, R4-R5 as 2KB
+    //                         banks at $1000)
+    // A 2KB bank takes its low bit from PPU A10. The modes that put CHR
+    // ROM in the nametables aren't supported.
+    fn update_offsets(&mut self) {
+        let len = self.cartridge.prg.len();
+        self.prg_offsets = [
+            bank_offset(len, 0x4000, self.prg_16k as isize),
+            bank_offset(len, 0x2000, self.prg_8k as isize),
+            bank_offset(len, 0x2000, -1),
+        ];
+
+        let r = self.chr_banks.map(|bank| bank as isize);
+        let two_kb = |bank: isize, slot: usize| (bank & !1) | (slot & 1) as isize;
+        let banks: [isize; 8] = std::array::from_fn(|slot| match self.ppu_control & 3 {
+            0 => r[slot],
+            1 => two_kb(r[slot / 2], slot),
+            _ if slot < 4 => r[slot],
+            _ => two_kb(r[4 + (slot - 4) / 2], slot),
+        });
+        for (i, &bank) in banks.iter().enumerate() {
+            self.chr_offsets[i] = bank_offset(self.cartridge.chr.len(), 0x0400, bank);
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.chr_offsets[addr as usize / 0x0400] + addr as usize % 0x0400
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.ppu_control & 0x80 != 0
+    }
+}
+
+impl Mapper for VRC6 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.cartridge.read_sram(addr - 0x6000),
+            0x8000..=0xBFFF => self.cartridge.prg[self.prg_offsets[0] + addr - 0x8000],
+            0xC000..=0xDFFF => self.cartridge.prg[self.prg_offsets[1] + addr - 0xC000],
+            0xE000..=0xFFFF => self.cartridge.prg[self.prg_offsets[2] + addr - 0xE000],
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.cartridge.write_sram(addr as usize - 0x6000, value);
+            }
+            0x8000..=0xFFFF => self.write_register(addr, value),
+            _ => {}
+        }
+    }
+
+    fn prg_open_bus(&self, addr: u16) -> bool {
+        match addr {
+            0x4020..=0x5FFF => true,
+            0x6000..=0x7FFF => !self.prg_ram_enabled() || self.cartridge.sram.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn sram_offset(&self, addr: u16) -> Option<usize> {
+        (self.prg_ram_enabled() && !self.cartridge.sram.is_empty()).then(|| addr as usize - 0x6000)
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        self.cartridge.chr[self.chr_offset(addr)]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.cartridge.chr[offset] = value;
+        }
+    }
+
+    // Mirroring (0: vertical; 1: horizontal; 2: one-screen, lower bank;
+    //            3: one-screen, upper bank)
+    fn mirroring(&self) -> Mirroring {
+        match (self.ppu_control >> 2) & 3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.step();
+        if self.frequency_control & 1 == 0 {
+            let shift = match self.frequency_control {
+                control if control & 4 != 0 => 8,
+                control if control & 2 != 0 => 4,
+                _ => 0,
+            };
+            self.pulse1.step(shift);
+            self.pulse2.step(shift);
+            self.sawtooth.step(shift);
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio_output(&self) -> f32 {
+        let [pulse1, pulse2, sawtooth] = self.volumes;
+        (self.pulse1.output() as f32 * pulse1
+            + self.pulse2.output() as f32 * pulse2
+            + self.sawtooth.output() as f32 * sawtooth)
+            * LEVEL
+    }
+
+    fn expansion_channels(&self) -> Vec<&'static str> {
+        vec!["Pulse 1", "Pulse 2", "Sawtooth"]
+    }
+
+    fn set_expansion_volume(&mut self, channel: usize, volume: f32) {
+        if let Some(v) = self.volumes.get_mut(channel) {
+            *v = volume;
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.prg_16k);
+        state.write(&self.prg_8k);
+        state.write(&self.chr_banks);
+        state.write(&self.ppu_control);
+        state.write(&self.irq);
+        state.write(&self.frequency_control);
+        state.write(&self.pulse1);
+        state.write(&self.pulse2);
+        state.write(&self.sawtooth);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.prg_16k = state.read()?;
+        self.prg_8k = state.read()?;
+        self.chr_banks = state.read()?;
+        self.ppu_control = state.read()?;
+        self.irq = state.read()?;
+        self.frequency_control = state.read()?;
+        self.pulse1 = state.read()?;
+        self.pulse2 = state.read()?;
+        self.sawtooth = state.read()?;
+        self.update_offsets();
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
+
+// Pulse with a 16-step sequence, high for the first `duty` + 1 steps, or
+// always high in digitized mode
+#[derive(Default, Serialize, Deserialize)]
+struct VRC6Pulse {
+    enabled: bool,
+    mode: bool,
+    duty: u8,
+    volume: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl VRC6Pulse {
+    // $9000/$A000 (bit 7: digitized mode; bits 4-6: duty; bits 0-3: volume)
+    fn write_control(&mut self, value: u8) {
+        self.mode = value & 0x80 != 0;
+        self.duty = (value >> 4) & 7;
+        self.volume = value & 0x0F;
+    }
+
+    fn write_period_low(&mut self, value: u8) {
+        self.period = (self.period & 0x0F00) | value as u16;
+    }
+
+    // $9002/$A002 (bit 7: enable; bits 0-3: period high)
+    fn write_period_high(&mut self, value: u8) {
+        self.period = (self.period & 0x00FF) | ((value & 0x0F) as u16) << 8;
+        self.enabled = value & 0x80 != 0;
+        if !self.enabled {
+            self.step = 15;
+        }
+    }
+
+    fn step(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 15;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.mode || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+// Adds `rate` to an accumulator every other timer clock and clears it on the
+// fourteenth, outputting its top five bits
+#[derive(Default, Serialize, Deserialize)]
+struct Sawtooth {
+    enabled: bool,
+    rate: u8,
+    period: u16,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write_period_low(&mut self, value: u8) {
+        self.period = (self.period & 0x0F00) | value as u16;
+    }
+
+    // $B002 (bit 7: enable; bits 0-3: period high)
+    fn write_period_high(&mut self, value: u8) {
+        self.period = (self.period & 0x00FF) | ((value & 0x0F) as u16) << 8;
+        self.enabled = value & 0x80 != 0;
+        if !self.enabled {
+            self.step = 0;
+            self.accumulator = 0;
+        }
+    }
+
+    fn step(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step.is_multiple_of(2) {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
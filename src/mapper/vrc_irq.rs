@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+// PPU dots per scanline; the prescaler counts them off three per CPU cycle
+const PRESCALER_PERIOD: i16 = 341;
+
+// IRQ counter shared by Konami's VRC chips: an 8-bit up counter that raises
+// the IRQ and reloads from the latch when it overflows. It counts CPU cycles
+// or, in scanline mode, lines as measured by a prescaler, without watching
+// the PPU at all.
+#[derive(Default, Serialize, Deserialize)]
+pub struct VRCIRQ {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enabled_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VRCIRQ {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // VRC4 splits the latch across two registers
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xF0) | (value & 0x0F);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0F) | (value & 0x0F) << 4;
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    // IRQ control (bit 0: enable again on acknowledge; bit 1: enable;
+    //              bit 2: count CPU cycles rather than scanlines)
+    pub fn write_control(&mut self, value: u8) {
+        self.enabled_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enabled_after_ack;
+    }
+
+    // Called once per CPU cycle
+    pub fn step(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+            return;
+        }
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += PRESCALER_PERIOD;
+            self.clock();
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{
+    cartridge::{Cartridge, Mirroring},
+    mapper::{bank_offset, Mapper},
+    savestate::{StateError, StateReader, StateWriter},
+};
+
+// Mapper 99: the VS. UniSystem's own cartridge wiring. Bit 2 of $4016
+// writes selects one of two 8KB CHR banks and, on 40KB boards, which 8KB
+// PRG bank sits at $8000 ahead of the other four. 2KB of work RAM at $6000
+// is mirrored through $7FFF.
+pub struct VSUniSystem {
+    cartridge: Cartridge,
+    bank_select: bool,
+}
+
+impl VSUniSystem {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            cartridge,
+            bank_select: false,
+        }
+    }
+}
+
+impl Mapper for VSUniSystem {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        let prg = &self.cartridge.prg;
+        match addr {
+            0x6000..=0x7FFF => self.cartridge.read_sram(addr as usize % 0x0800),
+            // The 40KB boards' extra bank is the last 8KB of the dump
+            0x8000..=0x9FFF if prg.len() > 0x8000 && self.bank_select => {
+                prg[0x8000 + (addr as usize - 0x8000)]
+            }
+            0x8000..=0xFFFF => prg[(addr as usize - 0x8000) % prg.len()],
+            _ => 0,
+        }
+    }
+
+    fn prg_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.cartridge.write_sram(addr as usize % 0x0800, value);
+        }
+    }
+
+    fn sram_offset(&self, addr: u16) -> Option<usize> {
+        (!self.cartridge.sram.is_empty()).then_some(addr as usize % 0x0800)
+    }
+
+    fn joypad_write(&mut self, value: u8) {
+        self.bank_select = value & 0x04 != 0;
+    }
+
+    fn chr_read(&mut self, addr: u16) -> u8 {
+        let chr = &self.cartridge.chr;
+        let bank = bank_offset(chr.len(), 0x2000, self.bank_select as isize);
+        chr[bank + addr as usize]
+    }
+
+    fn chr_write(&mut self, addr: u16, value: u8) {
+        if self.cartridge.chr_ram {
+            let len = self.cartridge.chr.len();
+            let bank = bank_offset(len, 0x2000, self.bank_select as isize);
+            self.cartridge.chr[bank + addr as usize] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cartridge.mirroring()
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.bank_select);
+        state.write(&self.cartridge.sram[..]);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.bank_select = state.read()?;
+        state.read_into(&mut self.cartridge.sram)
+    }
+}
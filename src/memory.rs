@@ -1,25 +1,548 @@
-use crate::console::Console;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    apu::APU,
+    cheats::Cheats,
+    controller::{Controller, FourScore, Microphone, PortDevice, Zapper},
+    events::{Event, EventBus},
+    expansion::ExpansionDevice,
+    mapper::Mapper,
+    ppu::PPU,
+    region::Region,
+    savestate::{StateError, StateReader, StateWriter},
+    utils::splitmix64,
+    vs::VSSystem,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// What RAM holds when the console is switched on. Real RAM comes up with
+// whatever its cells settle to, which most games clear and a few don't;
+// random contents shake out code that reads memory before writing it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RamInit {
+    #[default]
+    Zeros,
+    // Every byte $FF
+    Ones,
+    // 256-byte pages of $00 and $FF in turn
+    Alternating,
+    // Noise from the seed, the same every time for the same seed
+    Random(u64),
+}
+
+impl RamInit {
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zeros => ram.fill(0),
+            RamInit::Ones => ram.fill(0xFF),
+            RamInit::Alternating => {
+                for (index, page) in ram.chunks_mut(0x100).enumerate() {
+                    page.fill(if index & 1 == 0 { 0x00 } else { 0xFF });
+                }
+            }
+            RamInit::Random(mut seed) => {
+                for chunk in ram.chunks_mut(8) {
+                    let bytes = splitmix64(&mut seed).to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+// Power-on contents for each RAM in the console
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryInit {
+    // The 2KB of work RAM at $0000
+    pub cpu_ram: RamInit,
+    // The PPU's 2KB of nametable RAM, and any four-screen RAM
+    pub nametables: RamInit,
+    // Pattern table RAM on boards without CHR ROM
+    pub chr_ram: RamInit,
+    // Palette RAM, of which only the low 6 bits of each byte exist
+    pub palette: RamInit,
+}
+
+impl MemoryInit {
+    // Every RAM the same way
+    pub fn all(init: RamInit) -> Self {
+        Self {
+            cpu_ram: init,
+            nametables: init,
+            chr_ram: init,
+            palette: init,
+        }
+    }
+}
+
+// Addresses touched since the log was last cleared, for watchpoints
+pub type AccessLog = Vec<(u16, Access)>;
+
+// What the PPU's upcoming reads are for. Boards like the MMC5 bank CHR and
+// nametables differently for each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fetch {
+    Background,
+    Sprites,
+    // $2007 accesses
+    Cpu,
+}
 
 pub trait Memory {
-    fn read(&self, addr: u16) -> u8;
+    fn read(&mut self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, value: u8);
+
+    // Called by the PPU before each run of reads of one kind
+    fn set_fetch(&mut self, _fetch: Fetch) {}
+
+    // Fills any RAM the bus owns as it is at power-on
+    fn power_on(&mut self, _init: &MemoryInit) {}
+
+    // Save state hooks for any RAM the bus owns
+    fn save(&self, _state: &mut StateWriter) {}
+    fn load(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
 }
 
-#[derive(Copy, Clone)]
 pub struct CPUMemory {
-    console: Console,
+    pub ram: [u8; 2048],
+    pub ppu: PPU,
+    pub apu: APU,
+    // Joypads 1 and 2, and 3 and 4 when the Four Score is on
+    pub controllers: [Controller; 4],
+    pub four_score: FourScore,
+    // On the Famicom's hardwired second controller
+    pub microphone: Microphone,
+    // Plugged into port 2 in place of the second joypad
+    pub zapper: Option<Zapper>,
+    // Plugged into ports 1 and 2 in place of their joypads, ahead of the
+    // Zapper and Four Score
+    pub ports: [Option<PortDevice>; 2],
+    // Plugged into the Famicom expansion port, read along with the joypads
+    pub expansion: Option<ExpansionDevice>,
+    // The cabinet's coins, switches and copy protection on VS. System
+    // boards
+    pub vs: Option<VSSystem>,
+    // Page written to $4014, transferred by the CPU once the write retires
+    pub oam_dma: Option<u8>,
+    // Bus accesses are recorded here while a debugger needs them
+    pub access_log: Option<AccessLog>,
+    // Handlers subscribed to what the console does
+    pub events: EventBus,
+    // Game Genie and raw cheats, substituted into CPU reads, and freezes
+    // that drop writes to RAM
+    pub cheats: Cheats,
+    // Last value on the data bus, which reads nothing answers return. The
+    // CPU drives the bus every cycle, so it never has time to decay.
+    pub open_bus: u8,
+    // PPU dots not yet run, scaled by the region's CPU cycle count so PAL's
+    // 16 dots per 5 cycles come out even
+    pub ppu_dots: u64,
+    // The region's PPU dots per CPU cycles
+    pub ppu_clock_ratio: (u64, u64),
+    // Whether each bus access first runs the PPU through its cycle, so the
+    // access sees the PPU mid-instruction
+    pub cycle_accurate: bool,
+    // Cycles bus accesses have already run the PPU for, still to be taken
+    // off the CPU's cycle count
+    pub clocked_cycles: u64,
 }
 
 impl CPUMemory {
-    fn new(console: Console) -> Self {
-        Self { console }
+    pub fn new(ppu: PPU) -> Self {
+        Self {
+            ram: [0; 2048],
+            ppu,
+            apu: APU::new(),
+            controllers: [
+                Controller::new(),
+                Controller::new(),
+                Controller::new(),
+                Controller::new(),
+            ],
+            four_score: FourScore::new(),
+            microphone: Microphone::new(),
+            zapper: None,
+            ports: [None, None],
+            expansion: None,
+            vs: None,
+            oam_dma: None,
+            access_log: None,
+            events: EventBus::new(),
+            cheats: Cheats::new(),
+            open_bus: 0,
+            ppu_dots: 0,
+            ppu_clock_ratio: Region::NTSC.ppu_clock_ratio(),
+            cycle_accurate: false,
+            clocked_cycles: 0,
+        }
+    }
+
+    // The cartridge is wired to both buses. The PPU's side owns it, since
+    // the PPU fetches from it several times a scanline, and the CPU reaches
+    // it through the PPU; nothing is shared, so a console is a plain tree
+    // of owned parts that can move between threads.
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.ppu.mapper()
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.ppu.mapper_mut()
+    }
+
+    // Power-on state for the bus and everything on it but the board: RAM
+    // filled as `init` says, and the PPU and APU started over
+    pub fn power_on(&mut self, init: &MemoryInit) {
+        init.cpu_ram.fill(&mut self.ram);
+        self.ppu.power_on(init);
+        self.apu.power_on();
+        self.oam_dma = None;
+        self.open_bus = 0;
+        self.ppu_dots = 0;
+        self.clocked_cycles = 0;
+    }
+
+    // Reads without side effects, for debugging tools. Registers whose reads
+    // have side effects read back as $FF, like open bus in nestest.log.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % 0x0800],
+            0x2000..=0x401F => 0xFF,
+            0x4020..=0xFFFF => self.mapper().prg_peek(addr),
+        }
+    }
+
+    fn read_port(&mut self, addr: u16) -> u8 {
+        let port = (addr - 0x4016) as usize;
+        let value = if let Some(device) = &mut self.ports[port] {
+            device.read()
+        } else {
+            match addr {
+                0x4016 if self.four_score.enabled => {
+                    self.four_score.read(0, &self.controllers) | self.microphone.read()
+                }
+                0x4016 => self.controllers[0].read() | self.microphone.read(),
+                _ => match &self.zapper {
+                    Some(zapper) => zapper.read(&self.ppu),
+                    None if self.four_score.enabled => self.four_score.read(1, &self.controllers),
+                    None => self.controllers[1].read(),
+                },
+            }
+        };
+        match &mut self.expansion {
+            Some(device) => value | device.read(port),
+            None => value,
+        }
+    }
+
+    // Advances the PPU one dot, clocking the cartridge's line counters
+    pub fn step_ppu(&mut self) {
+        self.ppu.step();
+        if self.ppu.a12_rise() {
+            self.mapper_mut().ppu_a12_rise();
+        }
+        if self.ppu.scanline_clock() {
+            self.mapper_mut().scanline();
+        }
+    }
+
+    // Runs the PPU for `cpu_cycles` CPU cycles' worth of dots, passing over
+    // any the scanline renderer has nothing to do in
+    pub fn clock_ppu(&mut self, cpu_cycles: u64) {
+        let (dots, cycles) = self.ppu_clock_ratio;
+        self.ppu_dots += cpu_cycles * dots;
+        let mut remaining = self.ppu_dots / cycles;
+        while remaining > 0 {
+            let idle = self.ppu.idle_dots().min(remaining - 1);
+            self.ppu.skip_dots(idle);
+            self.step_ppu();
+            remaining -= idle + 1;
+        }
+        self.ppu_dots %= cycles;
+    }
+
+    // In cycle-accurate mode, brings the PPU up to the cycle of a bus access
+    fn clock_access(&mut self) {
+        if self.cycle_accurate {
+            self.clock_ppu(1);
+            self.clocked_cycles += 1;
+        }
+    }
+
+    // Clocks the cartridge for one CPU cycle, returning the level of its
+    // expansion audio
+    pub fn step_mapper(&mut self) -> f32 {
+        let mapper = self.mapper_mut();
+        mapper.cpu_clock();
+        mapper.audio_output()
     }
 }
 
 impl Memory for CPUMemory {
-    fn read(&self, addr: u16) -> u8 {
-        0 as u8
+    fn read(&mut self, addr: u16) -> u8 {
+        if let Some(log) = self.access_log.as_mut() {
+            log.push((addr, Access::Read));
+        }
+        self.clock_access();
+        let value = match addr {
+            // 2KB internal RAM, mirrored every $0800
+            0x0000..=0x1FFF => self.ram[addr as usize % 0x0800],
+            // PPU registers, mirrored every 8 bytes
+            0x2000..=0x3FFF => self.ppu.read_register(0x2000 + addr % 8),
+            // APU status, with bit 5 left open. The read is internal to the
+            // CPU, so the bus keeps its value.
+            0x4015 => {
+                let value = (self.apu.read_register(addr) & !0x20) | (self.open_bus & 0x20);
+                let value = self.cheats.apply(addr, value);
+                if self.events.memory {
+                    self.events.emit(Event::Read { addr, value });
+                }
+                return value;
+            }
+            // The VS. System drives the bits joypads leave open
+            0x4016 | 0x4017 if self.vs.is_some() => {
+                let port = (addr - 0x4016) as usize;
+                let cabinet = self.vs.as_ref().map_or(0, |vs| vs.read(port));
+                (self.read_port(addr) & 0x03) | cabinet
+            }
+            // Joypads, which only drive bits 4-0
+            0x4016 | 0x4017 => self.read_port(addr) | (self.open_bus & 0xE0),
+            // Remaining APU and I/O registers are write-only
+            0x4000..=0x401F => self.open_bus,
+            // Cartridge space
+            0x4020..=0xFFFF => {
+                if let Some(value) = self.vs.as_mut().and_then(|vs| vs.protection_read(addr)) {
+                    value
+                } else if self.mapper().prg_open_bus(addr) {
+                    self.open_bus
+                } else {
+                    self.mapper_mut().prg_read(addr)
+                }
+            }
+        };
+        let value = self.cheats.apply(addr, value);
+        self.open_bus = value;
+        if self.events.memory {
+            self.events.emit(Event::Read { addr, value });
+        }
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if let Some(log) = self.access_log.as_mut() {
+            log.push((addr, Access::Write));
+        }
+        self.clock_access();
+        self.open_bus = value;
+        if self.events.memory {
+            self.events.emit(Event::Write { addr, value });
+        }
+        match addr {
+            0x0000..=0x1FFF => {
+                let value = self.cheats.frozen(addr).unwrap_or(value);
+                self.ram[addr as usize % 0x0800] = value;
+            }
+            0x2000..=0x3FFF => {
+                let addr = 0x2000 + addr % 8;
+                self.ppu.write_register(addr, value);
+                self.mapper_mut().ppu_register_write(addr, value);
+            }
+            0x4014 => self.oam_dma = Some(value),
+            0x4016 => {
+                for controller in &mut self.controllers {
+                    controller.write(value);
+                }
+                self.four_score.write(value);
+                for device in self.ports.iter_mut().flatten() {
+                    device.write(value);
+                }
+                if let Some(device) = &mut self.expansion {
+                    device.write(value);
+                }
+                self.mapper_mut().joypad_write(value);
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, value),
+            0x4000..=0x401F => {}
+            0x4020..=0xFFFF => {
+                // The board sees the write as the game made it, in case it
+                // decodes the address as a register too, and a frozen byte of
+                // work RAM is put back after, in whatever bank is mapped
+                self.mapper_mut().prg_write(addr, value);
+                if let Some(value) = self.cheats.frozen(addr) {
+                    if let Some(offset) = self.mapper().sram_offset(addr) {
+                        self.mapper_mut().cartridge_mut().write_sram(offset, value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.ram[..]);
+        state.write(&self.oam_dma);
+        state.write(&self.open_bus);
+        self.ppu.save(state);
+        self.apu.save(state);
+        for controller in &self.controllers {
+            controller.save(state);
+        }
+        self.four_score.save(state);
+        for device in &self.ports {
+            state.write(&PortDevice::state_tag(device.as_ref()));
+            if let Some(device) = device {
+                device.save(state);
+            }
+        }
+        state.write(&ExpansionDevice::state_tag(self.expansion.as_ref()));
+        if let Some(device) = &self.expansion {
+            device.save(state);
+        }
+        if let Some(vs) = &self.vs {
+            vs.save(state);
+        }
+        self.mapper().save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_into(&mut self.ram)?;
+        self.oam_dma = state.read()?;
+        self.open_bus = state.read()?;
+        self.ppu.load(state)?;
+        self.apu.load(state)?;
+        for controller in &mut self.controllers {
+            controller.load(state)?;
+        }
+        self.four_score.load(state)?;
+        // What follows each device is laid out by its kind, so a state made
+        // with other devices plugged in can't be read past them
+        for device in &mut self.ports {
+            let tag: u8 = state.read()?;
+            if tag != PortDevice::state_tag(device.as_ref()) {
+                return Err(StateError::WrongDevices);
+            }
+            if let Some(device) = device {
+                device.load(state)?;
+            }
+        }
+        let tag: u8 = state.read()?;
+        if tag != ExpansionDevice::state_tag(self.expansion.as_ref()) {
+            return Err(StateError::WrongDevices);
+        }
+        if let Some(device) = &mut self.expansion {
+            device.load(state)?;
+        }
+        if let Some(vs) = &mut self.vs {
+            vs.load(state)?;
+        }
+        self.mapper_mut().load(state)
+    }
+}
+
+pub struct PPUMemory {
+    pub mapper: Box<dyn Mapper>,
+    // 2KB of console VRAM plus the extra 2KB four-screen boards carry
+    pub name_table_data: [u8; 4096],
+    pub palette_data: [u8; 32],
+}
+
+impl PPUMemory {
+    pub fn new(mapper: Box<dyn Mapper>) -> Self {
+        Self {
+            mapper,
+            name_table_data: [0; 4096],
+            palette_data: [0; 32],
+        }
     }
 
-    fn write(&mut self, addr: u16, value: u8) {}
+    // Maps $2000-$2FFF onto physical nametable RAM according to the board's
+    // current mirroring
+    fn name_table_address(mapper: &dyn Mapper, addr: u16) -> usize {
+        let addr = addr - 0x2000;
+        let page = mapper.name_table_page((addr / 0x0400) as usize);
+        page * 0x0400 + (addr % 0x0400) as usize
+    }
+
+    // $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
+    fn palette_address(addr: u16) -> usize {
+        let addr = addr % 32;
+        if addr >= 16 && addr & 0x03 == 0 {
+            (addr - 16) as usize
+        } else {
+            addr as usize
+        }
+    }
+}
+
+impl Memory for PPUMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        let addr = addr % 0x4000;
+        match addr {
+            // Pattern tables
+            0x0000..=0x1FFF => self.mapper.chr_read(addr),
+            // Nametables, with $3000-$3EFF mirroring $2000-$2EFF
+            0x2000..=0x3EFF => {
+                let addr = 0x2000 + (addr - 0x2000) % 0x1000;
+                match self.mapper.name_table_read(addr) {
+                    Some(value) => value,
+                    None => self.name_table_data[Self::name_table_address(&*self.mapper, addr)],
+                }
+            }
+            // Palette RAM, mirrored every 32 bytes
+            _ => self.palette_data[Self::palette_address(addr)],
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let addr = addr % 0x4000;
+        match addr {
+            0x0000..=0x1FFF => self.mapper.chr_write(addr, value),
+            0x2000..=0x3EFF => {
+                let addr = 0x2000 + (addr - 0x2000) % 0x1000;
+                if !self.mapper.name_table_write(addr, value) {
+                    self.name_table_data[Self::name_table_address(&*self.mapper, addr)] = value;
+                }
+            }
+            _ => self.palette_data[Self::palette_address(addr)] = value,
+        }
+    }
+
+    fn set_fetch(&mut self, fetch: Fetch) {
+        self.mapper.ppu_fetch(fetch);
+    }
+
+    fn power_on(&mut self, init: &MemoryInit) {
+        init.nametables.fill(&mut self.name_table_data);
+        init.palette.fill(&mut self.palette_data);
+        for entry in &mut self.palette_data {
+            *entry &= 0x3F;
+        }
+    }
+
+    // CHR RAM is the cartridge's, but saved here with the rest of the
+    // PPU's memory so every board gets it without saving it itself
+    fn save(&self, state: &mut StateWriter) {
+        state.write(&self.name_table_data[..]);
+        state.write(&self.palette_data[..]);
+        let cartridge = self.mapper.cartridge();
+        if cartridge.chr_ram {
+            state.write(&cartridge.chr[..]);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_into(&mut self.name_table_data)?;
+        state.read_into(&mut self.palette_data)?;
+        let cartridge = self.mapper.cartridge_mut();
+        if cartridge.chr_ram {
+            state.read_into(&mut cartridge.chr)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,80 @@
+use std::ops::RangeInclusive;
+
+use crate::console::Console;
+
+// A byte that differs from the last snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+// Snapshots ranges of the CPU address space and reports what changed since
+// the last one, e.g. called once a frame to see which bytes a frame of play
+// touches. Bytes are read with peek, so diffing registers or mapper space
+// has no side effects.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryDiff {
+    ranges: Vec<RangeInclusive<u16>>,
+    // The bytes of each range in turn, as of the last snapshot
+    previous: Vec<u8>,
+}
+
+impl MemoryDiff {
+    // Diffs `ranges`, snapshotting them from `console` now
+    pub fn new<I>(console: &Console, ranges: I) -> Self
+    where
+        I: IntoIterator<Item = RangeInclusive<u16>>,
+    {
+        let mut diff = Self::default();
+        for range in ranges {
+            diff.add_range(console, range);
+        }
+        diff
+    }
+
+    // Adds `range`, snapshotting it now
+    pub fn add_range(&mut self, console: &Console, range: RangeInclusive<u16>) {
+        let memory = &console.cpu.memory;
+        self.previous
+            .extend(range.clone().map(|addr| memory.peek(addr)));
+        self.ranges.push(range);
+    }
+
+    pub fn ranges(&self) -> &[RangeInclusive<u16>] {
+        &self.ranges
+    }
+
+    // What changed since the last snapshot, in the order of the ranges,
+    // without taking a new one
+    pub fn changes(&self, console: &Console) -> Vec<Change> {
+        let memory = &console.cpu.memory;
+        self.addrs()
+            .zip(&self.previous)
+            .filter_map(|(addr, &old)| {
+                let new = memory.peek(addr);
+                (new != old).then_some(Change { addr, old, new })
+            })
+            .collect()
+    }
+
+    // What changed since the last snapshot, then snapshots
+    pub fn diff(&mut self, console: &Console) -> Vec<Change> {
+        let changes = self.changes(console);
+        self.snapshot(console);
+        changes
+    }
+
+    pub fn snapshot(&mut self, console: &Console) {
+        let memory = &console.cpu.memory;
+        let addrs = self.ranges.iter().flat_map(|range| range.clone());
+        for (byte, addr) in self.previous.iter_mut().zip(addrs) {
+            *byte = memory.peek(addr);
+        }
+    }
+
+    fn addrs(&self) -> impl Iterator<Item = u16> + '_ {
+        self.ranges.iter().flat_map(|range| range.clone())
+    }
+}
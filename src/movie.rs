@@ -0,0 +1,260 @@
+use std::{fmt, fs, io, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+
+use crate::{cartridge::Cartridge, savestate::StateError};
+
+// Frame commands
+pub const COMMAND_RESET: u8 = 1;
+pub const COMMAND_POWER: u8 = 2;
+
+// Button letters of an FM2 gamepad field, leftmost for bit 7
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+// FM2 port types
+const PORT_NONE: u32 = 0;
+const PORT_GAMEPAD: u32 = 1;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Unsupported(String),
+    WrongRom,
+    State(StateError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(err) => write!(f, "failed to read movie: {}", err),
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::Unsupported(feature) => write!(f, "movie uses {}", feature),
+            MovieError::WrongRom => write!(f, "movie was recorded with a different ROM"),
+            MovieError::State(err) => write!(f, "movie savestate: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> Self {
+        MovieError::State(err)
+    }
+}
+
+// Input for one frame: commands such as COMMAND_RESET, and the buttons held
+// on each joypad as Controller::buttons bits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Frame {
+    pub commands: u8,
+    pub ports: [u8; 2],
+}
+
+// An input movie in FCEUX's text FM2 format. Movies start from power-on,
+// or from `savestate` when set; that state is in this emulator's own
+// format, so anchored movies don't carry over to FCEUX.
+#[derive(Clone, Debug, Default)]
+pub struct Movie {
+    pub rom_filename: String,
+    // MD5 of the PRG and CHR ROM
+    pub rom_checksum: Option<[u8; 16]>,
+    pub pal: bool,
+    pub rerecord_count: u32,
+    pub comments: Vec<String>,
+    pub savestate: Option<Vec<u8>>,
+    pub frames: Vec<Frame>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut movie = Self::new();
+        let mut ports = [PORT_GAMEPAD, PORT_GAMEPAD];
+
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let error = |message: String| MovieError::Parse {
+                line: number,
+                message,
+            };
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('|') {
+                movie.frames.push(parse_frame(line, &ports).map_err(error)?);
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let number_value = || {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| error(format!("{} is not a number: {}", key, value)))
+            };
+            match key {
+                "version" if value != "3" => {
+                    return Err(MovieError::Unsupported(format!("FM2 version {}", value)))
+                }
+                "binary" if value != "0" => {
+                    return Err(MovieError::Unsupported("binary input".to_string()))
+                }
+                "fourscore" if value != "0" => {
+                    return Err(MovieError::Unsupported("the Four Score".to_string()))
+                }
+                "FDS" if value != "0" => {
+                    return Err(MovieError::Unsupported(
+                        "the Famicom Disk System".to_string(),
+                    ))
+                }
+                "port0" | "port1" => {
+                    let port = number_value()?;
+                    if port != PORT_NONE && port != PORT_GAMEPAD {
+                        return Err(MovieError::Unsupported(format!(
+                            "device {} in {}",
+                            port, key
+                        )));
+                    }
+                    ports[(key == "port1") as usize] = port;
+                }
+                "palFlag" => movie.pal = value == "1",
+                "rerecordCount" => movie.rerecord_count = number_value()?,
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => movie.rom_checksum = Some(parse_checksum(value).map_err(error)?),
+                "comment" => movie.comments.push(value.to_string()),
+                "savestate" => {
+                    let data = value.strip_prefix("base64:").unwrap_or(value);
+                    let state = STANDARD
+                        .decode(data)
+                        .map_err(|err| error(format!("bad savestate: {}", err)))?;
+                    movie.savestate = Some(state);
+                }
+                // Settings this emulator has no equivalent for
+                _ => {}
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    // Whether the movie was recorded with `cartridge`. Movies without a
+    // checksum are assumed to match.
+    pub fn matches(&self, cartridge: &Cartridge) -> bool {
+        self.rom_checksum
+            .is_none_or(|checksum| checksum == rom_checksum(cartridge))
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version 3")?;
+        writeln!(f, "rerecordCount {}", self.rerecord_count)?;
+        writeln!(f, "palFlag {}", self.pal as u8)?;
+        writeln!(f, "romFilename {}", self.rom_filename)?;
+        if let Some(checksum) = &self.rom_checksum {
+            writeln!(f, "romChecksum base64:{}", STANDARD.encode(checksum))?;
+        }
+        writeln!(f, "guid 00000000-0000-0000-0000-000000000000")?;
+        writeln!(f, "fourscore 0")?;
+        writeln!(f, "microphone 0")?;
+        writeln!(f, "port0 {}", PORT_GAMEPAD)?;
+        writeln!(f, "port1 {}", PORT_GAMEPAD)?;
+        writeln!(f, "port2 0")?;
+        writeln!(f, "FDS 0")?;
+        writeln!(f, "NewPPU 0")?;
+        for comment in &self.comments {
+            writeln!(f, "comment {}", comment)?;
+        }
+        if let Some(state) = &self.savestate {
+            writeln!(f, "savestate base64:{}", STANDARD.encode(state))?;
+        }
+        for frame in &self.frames {
+            write!(f, "|{}", frame.commands)?;
+            for &buttons in &frame.ports {
+                let field: String = BUTTONS
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &letter)| {
+                        if buttons >> (7 - i) & 1 == 1 {
+                            letter as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                write!(f, "|{}", field)?;
+            }
+            writeln!(f, "||")?;
+        }
+        Ok(())
+    }
+}
+
+// The checksum FCEUX identifies ROMs by: MD5 over PRG and CHR ROM
+pub fn rom_checksum(cartridge: &Cartridge) -> [u8; 16] {
+    let mut md5 = Md5::new();
+    md5.update(&cartridge.prg);
+    if !cartridge.chr_ram {
+        md5.update(&cartridge.chr);
+    }
+    md5.finalize().into()
+}
+
+// "|commands|RLDUTSBA|RLDUTSBA||", where any character but '.' or ' ' is a
+// held button and ports without a gamepad have empty fields
+fn parse_frame(line: &str, ports: &[u32; 2]) -> Result<Frame, String> {
+    let mut fields = line[1..].split('|');
+    let commands = fields.next().unwrap_or("");
+    let mut frame = Frame {
+        commands: commands
+            .trim()
+            .parse()
+            .map_err(|_| format!("bad frame commands {:?}", commands))?,
+        ports: [0; 2],
+    };
+    for (port, &kind) in ports.iter().enumerate() {
+        let field = fields.next().unwrap_or("");
+        if kind != PORT_GAMEPAD {
+            continue;
+        }
+        if field.len() != BUTTONS.len() {
+            return Err(format!("bad gamepad input {:?}", field));
+        }
+        for (i, c) in field.bytes().enumerate() {
+            if c != b'.' && c != b' ' {
+                frame.ports[port] |= 1 << (7 - i);
+            }
+        }
+    }
+    Ok(frame)
+}
+
+fn parse_checksum(value: &str) -> Result<[u8; 16], String> {
+    let data = value
+        .strip_prefix("base64:")
+        .ok_or(format!("unknown checksum format {:?}", value))?;
+    STANDARD
+        .decode(data)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(format!("bad checksum {:?}", value))
+}
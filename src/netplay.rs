@@ -0,0 +1,389 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt, io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{console::Console, savestate::StateError};
+
+// Largest datagram read; an input packet is a few dozen bytes
+const MAX_PACKET_SIZE: usize = 2048;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    State(StateError),
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetplayError::Io(err) => write!(f, "netplay connection: {}", err),
+            NetplayError::State(err) => write!(f, "netplay rollback: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {}
+
+impl From<io::Error> for NetplayError {
+    fn from(err: io::Error) -> Self {
+        NetplayError::Io(err)
+    }
+}
+
+impl From<StateError> for NetplayError {
+    fn from(err: StateError) -> Self {
+        NetplayError::State(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct NetplayConfig {
+    // Joypad (0 or 1) this side drives; the peer drives the other
+    pub local_player: usize,
+    // Frames local input is held back before it takes effect, giving the
+    // peer's input time to arrive so fewer frames run on a guess
+    pub input_delay: u64,
+    // Most frames this side may run past the peer's last received input
+    // before it waits for more
+    pub max_rollback: u64,
+    // Frames between the state checksums compared to catch desyncs
+    pub checksum_interval: u64,
+    // Silence after which the peer is reported gone
+    pub timeout: Duration,
+}
+
+impl Default for NetplayConfig {
+    fn default() -> Self {
+        Self {
+            local_player: 0,
+            input_delay: 2,
+            max_rollback: 8,
+            checksum_interval: 60,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetplayEvent {
+    // The peer's input differed from the guess, so `frames` frames from
+    // `frame` on were run again
+    Rollback { frame: u64, frames: u64 },
+    // The two consoles' states at the start of `frame` don't match
+    Desync { frame: u64 },
+    // Nothing has been heard from the peer for the configured timeout
+    Disconnected,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Packet {
+    // The sender's input for frames `start` onwards, and the first frame of
+    // the receiver's input it has yet to get
+    Input {
+        start: u64,
+        inputs: Vec<u8>,
+        ack: u64,
+    },
+    // Hash of the sender's save state at the start of `frame`, taken once
+    // every input before it was known
+    Checksum {
+        frame: u64,
+        hash: u64,
+    },
+}
+
+// A two-player session over UDP. Each frame runs straight away on the
+// peer's last known input; when their real input turns out different, the
+// console is rolled back to a save state from the first wrong frame and
+// run forward again. Input is resent until acknowledged, but checksums go
+// out once, so a lost one only means that frame goes unchecked.
+pub struct Session {
+    socket: UdpSocket,
+    config: NetplayConfig,
+    // Next frame to run
+    frame: u64,
+    // Local input by frame, from the oldest one still needed
+    local_inputs: BTreeMap<u64, u8>,
+    // First frame of local input the peer hasn't acknowledged
+    peer_ack: u64,
+    // The peer's input by frame
+    remote_inputs: BTreeMap<u64, u8>,
+    // First frame of the peer's input not yet received
+    remote_frame: u64,
+    // The guess each frame past `remote_frame` was run with
+    predictions: BTreeMap<u64, u8>,
+    // Save states from the start of each frame that may be run again
+    states: VecDeque<(u64, Vec<u8>)>,
+    checksums: BTreeMap<u64, u64>,
+    remote_checksums: BTreeMap<u64, u64>,
+    last_received: Instant,
+    disconnected: bool,
+    events: Vec<NetplayEvent>,
+}
+
+impl Session {
+    // Binds `local` and plays against `peer`. Both sides must use the same
+    // input delay, with opposite local players.
+    pub fn new(
+        local: impl ToSocketAddrs,
+        peer: impl ToSocketAddrs,
+        config: NetplayConfig,
+    ) -> Result<Self, NetplayError> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+
+        // Nobody presses anything during the first delayed frames
+        let delay = config.input_delay;
+        Ok(Self {
+            socket,
+            config,
+            frame: 0,
+            local_inputs: (0..delay).map(|frame| (frame, 0)).collect(),
+            peer_ack: delay,
+            remote_inputs: (0..delay).map(|frame| (frame, 0)).collect(),
+            remote_frame: delay,
+            predictions: BTreeMap::new(),
+            states: VecDeque::new(),
+            checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            last_received: Instant::now(),
+            disconnected: false,
+            events: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Next frame `advance_frame` will run
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Frames before this one have had the peer's real input
+    pub fn confirmed_frame(&self) -> u64 {
+        self.remote_frame.min(self.frame)
+    }
+
+    // Events since the last call
+    pub fn take_events(&mut self) -> Vec<NetplayEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Runs the next frame with `buttons` (bit n for Button n) as this
+    // side's input, first rolling back for any of the peer's input that
+    // has arrived. Returns false without running anything when the peer
+    // has fallen too far behind; call again next frame.
+    pub fn advance_frame(
+        &mut self,
+        console: &mut Console,
+        buttons: u8,
+    ) -> Result<bool, NetplayError> {
+        self.receive()?;
+        self.correct(console)?;
+
+        if self.frame >= self.remote_frame + self.config.max_rollback {
+            self.send_input()?;
+            return Ok(false);
+        }
+
+        self.local_inputs
+            .insert(self.frame + self.config.input_delay, buttons);
+        self.send_input()?;
+        self.run_frame(console, self.frame);
+        self.frame += 1;
+        Ok(true)
+    }
+
+    fn receive(&mut self) -> Result<(), NetplayError> {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let len = match self.socket.recv(&mut buffer) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                // An ICMP error from a send, while the peer isn't up yet
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(err) => return Err(err.into()),
+            };
+            // Stray datagrams are dropped
+            let Ok(packet) = bincode::deserialize(&buffer[..len]) else {
+                continue;
+            };
+            self.last_received = Instant::now();
+            self.disconnected = false;
+            match packet {
+                Packet::Input { start, inputs, ack } => {
+                    self.peer_ack = self.peer_ack.max(ack);
+                    for (frame, input) in (start..).zip(inputs) {
+                        // Input only arrives in order, every packet resending
+                        // from the first frame acknowledged missing
+                        if frame == self.remote_frame {
+                            self.remote_inputs.insert(frame, input);
+                            self.remote_frame += 1;
+                        }
+                    }
+                }
+                Packet::Checksum { frame, hash } => {
+                    self.remote_checksums.insert(frame, hash);
+                }
+            }
+        }
+
+        if !self.disconnected && self.last_received.elapsed() >= self.config.timeout {
+            self.disconnected = true;
+            self.events.push(NetplayEvent::Disconnected);
+        }
+        Ok(())
+    }
+
+    // Reruns frames that guessed the peer's input wrong, then checks and
+    // drops what newly confirmed frames no longer need
+    fn correct(&mut self, console: &mut Console) -> Result<(), NetplayError> {
+        let wrong = self
+            .predictions
+            .range(..self.remote_frame)
+            .find(|&(frame, &guess)| self.remote_inputs.get(frame) != Some(&guess))
+            .map(|(&frame, _)| frame);
+
+        if let Some(first) = wrong {
+            let index = self
+                .states
+                .iter()
+                .position(|&(frame, _)| frame == first)
+                .expect("a state is kept for every unconfirmed frame");
+            console.load_state(&self.states[index].1)?;
+            self.states.truncate(index);
+
+            // Audio from the frames being rerun has already been queued, so
+            // as much is dropped again to keep the stream from running ahead
+            let samples = console.apu().sample_count();
+            for frame in first..self.frame {
+                self.run_frame(console, frame);
+            }
+            let extra = console.apu().sample_count().saturating_sub(samples);
+            console.drain_samples(&mut vec![0.0; extra]);
+
+            self.events.push(NetplayEvent::Rollback {
+                frame: first,
+                frames: self.frame - first,
+            });
+        }
+
+        let confirmed = self.confirmed_frame();
+        self.send_checksums(confirmed)?;
+
+        self.predictions = self.predictions.split_off(&confirmed);
+        while self
+            .states
+            .front()
+            .is_some_and(|&(frame, _)| frame < confirmed)
+        {
+            self.states.pop_front();
+        }
+        // The last confirmed input stays around as the next guess
+        let oldest = confirmed.min(self.peer_ack).saturating_sub(1);
+        self.local_inputs = self.local_inputs.split_off(&oldest);
+        self.remote_inputs = self.remote_inputs.split_off(&oldest);
+        Ok(())
+    }
+
+    // Sends a checksum of each kept state that has become final and is due
+    // one, and compares any the peer has sent
+    fn send_checksums(&mut self, confirmed: u64) -> Result<(), NetplayError> {
+        let interval = self.config.checksum_interval.max(1);
+        let due: Vec<(u64, u64)> = self
+            .states
+            .iter()
+            .filter(|&&(frame, _)| frame <= confirmed && frame.is_multiple_of(interval))
+            .filter(|(frame, _)| !self.checksums.contains_key(frame))
+            .map(|(frame, state)| (*frame, xxh3_64(state)))
+            .collect();
+        for (frame, hash) in due {
+            self.checksums.insert(frame, hash);
+            self.send(&Packet::Checksum { frame, hash })?;
+        }
+
+        let compared: Vec<u64> = self
+            .remote_checksums
+            .keys()
+            .filter(|frame| self.checksums.contains_key(frame))
+            .copied()
+            .collect();
+        for frame in compared {
+            if self.checksums.remove(&frame) != self.remote_checksums.remove(&frame) {
+                self.events.push(NetplayEvent::Desync { frame });
+            }
+        }
+
+        // Forget checksums whose counterpart was lost
+        let horizon = confirmed.saturating_sub(interval * 8);
+        self.checksums = self.checksums.split_off(&horizon);
+        self.remote_checksums = self.remote_checksums.split_off(&horizon);
+        Ok(())
+    }
+
+    fn run_frame(&mut self, console: &mut Console, frame: u64) {
+        self.states.push_back((frame, console.save_state()));
+
+        let local = self.local_inputs.get(&frame).copied().unwrap_or(0);
+        let remote = match self.remote_inputs.get(&frame) {
+            Some(&input) => {
+                self.predictions.remove(&frame);
+                input
+            }
+            // Guess the peer is still holding what they last held
+            None => {
+                let guess = self
+                    .remote_inputs
+                    .range(..frame)
+                    .next_back()
+                    .map_or(0, |(_, &input)| input);
+                self.predictions.insert(frame, guess);
+                guess
+            }
+        };
+
+        let player = self.config.local_player;
+        console.set_buttons(player, local);
+        console.set_buttons(player ^ 1, remote);
+        console.step_frame();
+    }
+
+    fn send_input(&mut self) -> Result<(), NetplayError> {
+        let inputs = self
+            .local_inputs
+            .range(self.peer_ack..)
+            .map(|(_, &input)| input)
+            .collect();
+        self.send(&Packet::Input {
+            start: self.peer_ack,
+            inputs,
+            ack: self.remote_frame,
+        })
+    }
+
+    fn send(&self, packet: &Packet) -> Result<(), NetplayError> {
+        let data = bincode::serialize(packet).expect("packets always serialize");
+        match self.socket.send(&data) {
+            Ok(_) => Ok(()),
+            // Dropped like any lost datagram; it's resent or not needed
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
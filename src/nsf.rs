@@ -0,0 +1,457 @@
+use std::{fmt, fs, io, path::Path, time::Duration};
+
+use crate::{
+    console::Console,
+    mapper::{NSFMapper, IDLE_ADDRESS},
+    region::Region,
+};
+
+const NSF_MAGIC: [u8; 5] = *b"NESM\x1A";
+const NSFE_MAGIC: [u8; 4] = *b"NSFE";
+const HEADER_SIZE: usize = 0x80;
+// Play rates for files that don't give one, in microseconds per call
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+// How long tracks without a length play, and how long they fade out
+const DEFAULT_LENGTH: Duration = Duration::from_secs(150);
+const DEFAULT_FADE: Duration = Duration::from_secs(8);
+
+// Expansion sound chips a tune uses, as flagged in the header. The VRC7 is
+// not emulated and plays silent.
+pub const EXPANSION_VRC6: u8 = 0x01;
+pub const EXPANSION_VRC7: u8 = 0x02;
+pub const EXPANSION_FDS: u8 = 0x04;
+pub const EXPANSION_MMC5: u8 = 0x08;
+pub const EXPANSION_N163: u8 = 0x10;
+pub const EXPANSION_SUNSOFT_5B: u8 = 0x20;
+
+#[derive(Debug)]
+pub enum NSFError {
+    Io(io::Error),
+    InvalidMagic,
+    Truncated { expected: usize, actual: usize },
+    MissingChunk(&'static str),
+    UnsupportedChunk(String),
+    InvalidTrack(usize),
+}
+
+impl fmt::Display for NSFError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NSFError::Io(err) => write!(f, "failed to read NSF: {}", err),
+            NSFError::InvalidMagic => write!(f, "not an NSF or NSFe file"),
+            NSFError::Truncated { expected, actual } => write!(
+                f,
+                "NSF is truncated: needs {} bytes, file has {}",
+                expected, actual
+            ),
+            NSFError::MissingChunk(chunk) => write!(f, "NSFe has no {} chunk", chunk),
+            NSFError::UnsupportedChunk(chunk) => {
+                write!(f, "NSFe needs unsupported chunk {}", chunk)
+            }
+            NSFError::InvalidTrack(track) => write!(f, "track {} does not exist", track + 1),
+        }
+    }
+}
+
+impl std::error::Error for NSFError {}
+
+impl From<io::Error> for NSFError {
+    fn from(err: io::Error) -> Self {
+        NSFError::Io(err)
+    }
+}
+
+// A tune loaded from an NSF or NSFe file. Tracks are numbered from 0.
+#[derive(Clone, Debug)]
+pub struct NSF {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub songs: usize,
+    pub starting_song: usize,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    // Microseconds between play calls
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    // Banks initially at $8000-$FFFF; all zero for tunes that don't switch
+    pub banks: [u8; 8],
+    pub region: Region,
+    // EXPANSION_* flags
+    pub expansion: u8,
+    // From NSFe only: per-track lengths, fade outs and names
+    pub lengths: Vec<Option<Duration>>,
+    pub fades: Vec<Option<Duration>>,
+    pub labels: Vec<String>,
+    pub data: Vec<u8>,
+}
+
+impl NSF {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NSFError> {
+        if bytes.starts_with(&NSF_MAGIC) {
+            Self::parse_nsf(bytes)
+        } else if bytes.starts_with(&NSFE_MAGIC) {
+            Self::parse_nsfe(bytes)
+        } else {
+            Err(NSFError::InvalidMagic)
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, NSFError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    fn parse_nsf(bytes: &[u8]) -> Result<Self, NSFError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(NSFError::Truncated {
+                expected: HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&bytes[0x70..0x78]);
+        Ok(Self {
+            title: string(&bytes[0x0E..0x2E]),
+            artist: string(&bytes[0x2E..0x4E]),
+            copyright: string(&bytes[0x4E..0x6E]),
+            songs: bytes[6].max(1) as usize,
+            starting_song: bytes[7].saturating_sub(1) as usize,
+            load_address: word(8),
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            banks,
+            region: region(bytes[0x7A]),
+            expansion: bytes[0x7B],
+            lengths: Vec::new(),
+            fades: Vec::new(),
+            labels: Vec::new(),
+            data: bytes[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    // NSFe files are a list of chunks: a 32-bit length, a four letter ID
+    // and the data. Chunks whose ID starts with a capital letter must be
+    // understood to play the file; the rest are optional.
+    fn parse_nsfe(bytes: &[u8]) -> Result<Self, NSFError> {
+        let mut nsf = Self {
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            songs: 1,
+            starting_song: 0,
+            load_address: 0,
+            init_address: 0,
+            play_address: 0,
+            ntsc_speed: NTSC_SPEED,
+            pal_speed: PAL_SPEED,
+            banks: [0; 8],
+            region: Region::NTSC,
+            expansion: 0,
+            lengths: Vec::new(),
+            fades: Vec::new(),
+            labels: Vec::new(),
+            data: Vec::new(),
+        };
+        let mut info = false;
+        let mut data = false;
+
+        let mut pos = NSFE_MAGIC.len();
+        while pos + 8 <= bytes.len() {
+            let len =
+                u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+                    as usize;
+            let id = &bytes[pos + 4..pos + 8];
+            let start = pos + 8;
+            // The length is the file's say, and may not even fit in a usize
+            // once added, as on 32-bit targets
+            let end = match start.checked_add(len) {
+                Some(end) if end <= bytes.len() => end,
+                end => {
+                    return Err(NSFError::Truncated {
+                        expected: end.unwrap_or(usize::MAX),
+                        actual: bytes.len(),
+                    })
+                }
+            };
+            let chunk = &bytes[start..end];
+            pos = end;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(NSFError::Truncated {
+                            expected: 8,
+                            actual: chunk.len(),
+                        });
+                    }
+                    let word =
+                        |offset: usize| u16::from_le_bytes([chunk[offset], chunk[offset + 1]]);
+                    nsf.load_address = word(0);
+                    nsf.init_address = word(2);
+                    nsf.play_address = word(4);
+                    nsf.region = region(chunk[6]);
+                    nsf.expansion = chunk[7];
+                    nsf.songs = chunk.get(8).map_or(1, |&songs| songs.max(1) as usize);
+                    nsf.starting_song = chunk.get(9).map_or(0, |&song| song as usize);
+                    info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    data = true;
+                }
+                b"BANK" => {
+                    for (bank, &value) in nsf.banks.iter_mut().zip(chunk) {
+                        *bank = value;
+                    }
+                }
+                b"RATE" => {
+                    let word = |offset: usize| {
+                        chunk
+                            .get(offset..offset + 2)
+                            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    };
+                    nsf.ntsc_speed = word(0).unwrap_or(NTSC_SPEED);
+                    nsf.pal_speed = word(2).unwrap_or(PAL_SPEED);
+                }
+                b"auth" => {
+                    let mut strings = chunk.split(|&b| b == 0).map(string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                }
+                // Milliseconds per track; negative for unknown
+                b"time" | b"fade" => {
+                    let times = chunk
+                        .chunks_exact(4)
+                        .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                        .map(|ms| (ms >= 0).then(|| Duration::from_millis(ms as u64)))
+                        .collect();
+                    if id == b"time" {
+                        nsf.lengths = times;
+                    } else {
+                        nsf.fades = times;
+                    }
+                }
+                b"tlbl" => {
+                    nsf.labels = chunk.split(|&b| b == 0).map(string).collect();
+                    nsf.labels.truncate(nsf.songs);
+                }
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(NSFError::UnsupportedChunk(string(id)));
+                }
+                _ => {}
+            }
+        }
+
+        if !info {
+            return Err(NSFError::MissingChunk("INFO"));
+        }
+        if !data {
+            return Err(NSFError::MissingChunk("DATA"));
+        }
+        Ok(nsf)
+    }
+
+    pub fn bankswitched(&self) -> bool {
+        self.banks.iter().any(|&bank| bank != 0)
+    }
+
+    pub fn track_length(&self, track: usize) -> Option<Duration> {
+        self.lengths.get(track).copied().flatten()
+    }
+
+    pub fn track_fade(&self, track: usize) -> Option<Duration> {
+        self.fades.get(track).copied().flatten()
+    }
+
+    pub fn track_label(&self, track: usize) -> Option<&str> {
+        self.labels.get(track).map(String::as_str)
+    }
+}
+
+// Text fields are NUL-padded, and usually ASCII
+fn string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+// Bit 0 marks PAL tunes, bit 1 tunes that play on either, which run as NTSC
+fn region(flags: u8) -> Region {
+    if flags & 0x03 == 0x01 {
+        Region::PAL
+    } else {
+        Region::NTSC
+    }
+}
+
+// Plays an NSF on an emulated console. The CPU runs the tune's init routine
+// once per track, then its play routine at the tune's rate, idling in a
+// loop on the board in between. Audio is pulled with `render`.
+pub struct NSFPlayer {
+    nsf: NSF,
+    console: Console,
+    track: usize,
+    sample_rate: f64,
+    // CPU cycles run on this track, and when play is next due
+    cycles: u64,
+    next_play: u64,
+    play_period: u64,
+    // Samples rendered on this track
+    samples: u64,
+}
+
+impl NSFPlayer {
+    pub fn new(nsf: NSF) -> Self {
+        let track = nsf.starting_song.min(nsf.songs - 1);
+        let console = Self::start(&nsf, track);
+        let mut player = Self {
+            nsf,
+            console,
+            track,
+            sample_rate: 44100.0,
+            cycles: 0,
+            next_play: 0,
+            play_period: 0,
+            samples: 0,
+        };
+        player.reset_timing();
+        player
+    }
+
+    // Powers up a fresh console and calls init for `track`, following the
+    // NSF calling convention: the track in A, PAL in X, the APU silenced
+    // and the frame counter IRQ off
+    fn start(nsf: &NSF, track: usize) -> Console {
+        let mut console = Console::with_mapper(Box::new(NSFMapper::new(nsf)));
+        console.set_region(nsf.region);
+        let cpu = &mut console.cpu;
+        for addr in 0x4000..=0x4013 {
+            cpu.write(addr, 0);
+        }
+        cpu.write(0x4015, 0x0F);
+        cpu.write(0x4017, 0x40);
+        cpu.a = track as u8;
+        cpu.x = (nsf.region == Region::PAL) as u8;
+        cpu.y = 0;
+        cpu.sp = 0xFD;
+        cpu.i = 1;
+        Self::call(&mut console, nsf.init_address);
+        console
+    }
+
+    // Runs `addr` as a subroutine that returns to the idle loop
+    fn call(console: &mut Console, addr: u16) {
+        console.cpu.push16(IDLE_ADDRESS - 1);
+        console.cpu.pc = addr;
+    }
+
+    fn reset_timing(&mut self) {
+        let speed = match self.nsf.region {
+            Region::PAL => self.nsf.pal_speed,
+            _ => self.nsf.ntsc_speed,
+        };
+        let frequency = self.console.region().cpu_frequency();
+        self.play_period = (speed as u64 * frequency / 1_000_000).max(1);
+        self.cycles = 0;
+        self.next_play = 0;
+        self.samples = 0;
+        self.console.set_sample_rate(self.sample_rate);
+    }
+
+    pub fn nsf(&self) -> &NSF {
+        &self.nsf
+    }
+
+    // The console playing the tune, e.g. for expansion channel volumes
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
+
+    pub fn console_mut(&mut self) -> &mut Console {
+        &mut self.console
+    }
+
+    pub fn track(&self) -> usize {
+        self.track
+    }
+
+    // Restarts playback on `track`
+    pub fn select_track(&mut self, track: usize) -> Result<(), NSFError> {
+        if track >= self.nsf.songs {
+            return Err(NSFError::InvalidTrack(track));
+        }
+        self.console = Self::start(&self.nsf, track);
+        self.track = track;
+        self.reset_timing();
+        Ok(())
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.console.set_sample_rate(sample_rate);
+    }
+
+    // How long the current track plays before fading, and the fade's
+    // length, from the file where it gives them
+    pub fn length(&self) -> Duration {
+        self.nsf.track_length(self.track).unwrap_or(DEFAULT_LENGTH)
+    }
+
+    pub fn fade(&self) -> Duration {
+        self.nsf.track_fade(self.track).unwrap_or(DEFAULT_FADE)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.samples as f64 / self.sample_rate)
+    }
+
+    // Whether the track has played out, fade included; `render` produces
+    // silence from then on
+    pub fn finished(&self) -> bool {
+        self.elapsed() >= self.length() + self.fade()
+    }
+
+    // Fills `out` with the next samples of the track
+    pub fn render(&mut self, out: &mut [f32]) {
+        let mut written = 0;
+        while written < out.len() {
+            written += self.console.drain_samples(&mut out[written..]);
+            if written < out.len() {
+                self.step();
+            }
+        }
+
+        let length = self.length().as_secs_f64();
+        let fade = self.fade().as_secs_f64();
+        for sample in out.iter_mut() {
+            let time = self.samples as f64 / self.sample_rate;
+            if time > length {
+                let gain = if fade > 0.0 {
+                    (1.0 - (time - length) / fade).max(0.0)
+                } else {
+                    0.0
+                };
+                *sample *= gain as f32;
+            }
+            self.samples += 1;
+        }
+    }
+
+    // Runs one instruction, calling play once it's due and the last call
+    // has returned. Play routines that overrun their period delay the next
+    // call rather than stacking up.
+    fn step(&mut self) {
+        self.cycles += self.console.step();
+        let idle = (IDLE_ADDRESS..IDLE_ADDRESS + 3).contains(&self.console.cpu.pc);
+        if idle && self.cycles >= self.next_play {
+            self.next_play = (self.next_play + self.play_period).max(self.cycles);
+            Self::call(&mut self.console, self.nsf.play_address);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::{fmt, fs, io, path::Path};
+
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+
+const fn rgb(color: u32) -> Rgba<u8> {
+    Rgba([(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xFF])
+}
+
+// The 64 colors the 2C02 can output, indexed by palette RAM value
+#[rustfmt::skip]
+pub static PALETTE: [Rgba<u8>; 64] = [
+    rgb(0x666666), rgb(0x002A88), rgb(0x1412A7), rgb(0x3B00A4), rgb(0x5C007E), rgb(0x6E0040), rgb(0x6C0600), rgb(0x561D00),
+    rgb(0x333500), rgb(0x0B4800), rgb(0x005200), rgb(0x004F08), rgb(0x00404D), rgb(0x000000), rgb(0x000000), rgb(0x000000),
+    rgb(0xADADAD), rgb(0x155FD9), rgb(0x4240FF), rgb(0x7527FE), rgb(0xA01ACC), rgb(0xB71E7B), rgb(0xB53120), rgb(0x994E00),
+    rgb(0x6B6D00), rgb(0x388700), rgb(0x0C9300), rgb(0x008F32), rgb(0x007C8D), rgb(0x000000), rgb(0x000000), rgb(0x000000),
+    rgb(0xFFFEFF), rgb(0x64B0FF), rgb(0x9290FF), rgb(0xC676FF), rgb(0xF36AFF), rgb(0xFE6ECC), rgb(0xFE8170), rgb(0xEA9E22),
+    rgb(0xBCBE00), rgb(0x88D800), rgb(0x5CE430), rgb(0x45E082), rgb(0x48CDDE), rgb(0x4F4F4F), rgb(0x000000), rgb(0x000000),
+    rgb(0xFFFEFF), rgb(0xC0DFFF), rgb(0xD3D2FF), rgb(0xE8C8FF), rgb(0xFBC2FF), rgb(0xFEC4EA), rgb(0xFECCC5), rgb(0xF7D8A5),
+    rgb(0xE4E594), rgb(0xCFEF96), rgb(0xBDF4AB), rgb(0xB3F3CC), rgb(0xB5EBF2), rgb(0xB8B8B8), rgb(0x000000), rgb(0x000000),
+];
+
+// FirebrandX's "Smooth" palette, measured from a 2C02 over composite
+#[rustfmt::skip]
+static FBX_PALETTE: [Rgba<u8>; 64] = [
+    rgb(0x6A6D6A), rgb(0x001380), rgb(0x1E008A), rgb(0x39007A), rgb(0x550056), rgb(0x5A0018), rgb(0x4F1000), rgb(0x3D1C00),
+    rgb(0x253200), rgb(0x003D00), rgb(0x004000), rgb(0x003924), rgb(0x002E55), rgb(0x000000), rgb(0x000000), rgb(0x000000),
+    rgb(0xB9BCB9), rgb(0x1850C7), rgb(0x4B30E3), rgb(0x7322D6), rgb(0x951FA9), rgb(0x9D285C), rgb(0x983700), rgb(0x7F4C00),
+    rgb(0x5E6400), rgb(0x227700), rgb(0x027E02), rgb(0x007645), rgb(0x006E8A), rgb(0x000000), rgb(0x000000), rgb(0x000000),
+    rgb(0xFFFFFF), rgb(0x68A6FF), rgb(0x8C9CFF), rgb(0xB586FF), rgb(0xD975FD), rgb(0xE377B9), rgb(0xE58D68), rgb(0xD49D29),
+    rgb(0xB3AF0C), rgb(0x7BC211), rgb(0x55CA47), rgb(0x46CB81), rgb(0x47C1C5), rgb(0x4A4D4A), rgb(0x000000), rgb(0x000000),
+    rgb(0xFFFFFF), rgb(0xCCEAFF), rgb(0xDDDEFF), rgb(0xECDAFF), rgb(0xF8D7FE), rgb(0xFCD6F5), rgb(0xFDDBCF), rgb(0xF9E7B5),
+    rgb(0xF1F0AA), rgb(0xDAFAA9), rgb(0xC9FFBC), rgb(0xC3FBD7), rgb(0xC4F6F6), rgb(0xBEC1BE), rgb(0x000000), rgb(0x000000),
+];
+
+// The colors a TV with Sony's CXA2025AS decoder shows, as used by many US
+// sets of the era
+#[rustfmt::skip]
+static SONY_CXA_PALETTE: [Rgba<u8>; 64] = [
+    rgb(0x585858), rgb(0x00238C), rgb(0x00139B), rgb(0x2D0585), rgb(0x5D0052), rgb(0x7A0017), rgb(0x7A0800), rgb(0x5F1800),
+    rgb(0x352A00), rgb(0x093900), rgb(0x003F00), rgb(0x003C22), rgb(0x00325D), rgb(0x000000), rgb(0x000000), rgb(0x000000),
+    rgb(0xA1A1A1), rgb(0x0053EE), rgb(0x153CFE), rgb(0x6028E4), rgb(0xA91D98), rgb(0xD41E41), rgb(0xD22C00), rgb(0xAA4400),
+    rgb(0x6C5E00), rgb(0x2D7300), rgb(0x007D06), rgb(0x007852), rgb(0x0069A9), rgb(0x000000), rgb(0x000000), rgb(0x000000),
+    rgb(0xFFFFFF), rgb(0x1FA5FE), rgb(0x5E89FE), rgb(0xB572FE), rgb(0xFE65F6), rgb(0xFE6790), rgb(0xFE773C), rgb(0xFE9308),
+    rgb(0xC4B200), rgb(0x79CA10), rgb(0x3AD54A), rgb(0x11D1A4), rgb(0x06BFFE), rgb(0x424242), rgb(0x000000), rgb(0x000000),
+    rgb(0xFFFFFF), rgb(0xA0D9FE), rgb(0xBDCCFE), rgb(0xE1C2FE), rgb(0xFEBCFB), rgb(0xFEBDD0), rgb(0xFEC5A9), rgb(0xFED18E),
+    rgb(0xE9DE86), rgb(0xC7E992), rgb(0xA8EEB0), rgb(0x95ECD9), rgb(0x91E4FE), rgb(0xACACAC), rgb(0x000000), rgb(0x000000),
+];
+
+// The RGB PPUs' colors (2C03, 2C05 and the VS. System's 2C04s in their own
+// order), as octal 3-bit levels of red, green and blue
+#[rustfmt::skip]
+const RGB_LEVELS: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+// Each emphasis bit darkens the two other color channels by this much
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Preset {
+    #[default]
+    Default,
+    FBX,
+    SonyCXA,
+    Grayscale,
+    // The RGB PPUs of the VS. System and PlayChoice-10, whose emphasis bits
+    // turn their channel full on instead of darkening the others
+    RGB,
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(io::Error),
+    // .pal files hold 64 or 512 RGB triples
+    InvalidSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteError::Io(err) => write!(f, "failed to read palette: {}", err),
+            PaletteError::InvalidSize(size) => write!(
+                f,
+                "palette is {} bytes, expected 192 (64 colors) or 1536 (512 colors)",
+                size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<io::Error> for PaletteError {
+    fn from(err: io::Error) -> Self {
+        PaletteError::Io(err)
+    }
+}
+
+// Maps the PPU's 9-bit pixels (palette index in bits 0-5, red/green/blue
+// emphasis in bits 6-8) to RGB
+#[derive(Clone)]
+pub struct Palette {
+    colors: [Rgba<u8>; 512],
+}
+
+impl Palette {
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::Default => Self::with_emphasis(&PALETTE),
+            Preset::FBX => Self::with_emphasis(&FBX_PALETTE),
+            Preset::SonyCXA => Self::with_emphasis(&SONY_CXA_PALETTE),
+            Preset::Grayscale => {
+                let mut colors = PALETTE;
+                for color in colors.iter_mut() {
+                    let [r, g, b, a] = color.0;
+                    let luma = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8;
+                    *color = Rgba([luma, luma, luma, a]);
+                }
+                Self::with_emphasis(&colors)
+            }
+            Preset::RGB => Self::rgb(),
+        }
+    }
+
+    fn rgb() -> Self {
+        let mut colors = [Rgba([0, 0, 0, 0xFF]); 512];
+        for (pixel, color) in colors.iter_mut().enumerate() {
+            let emphasis = pixel >> 6;
+            let levels = RGB_LEVELS[pixel & 0x3F];
+            for channel in 0..3 {
+                let level = (levels >> (6 - 3 * channel)) & 0x07;
+                color.0[channel] = if emphasis & (1 << channel) != 0 {
+                    0xFF
+                } else {
+                    (level * 0xFF / 7) as u8
+                };
+            }
+        }
+        Self { colors }
+    }
+
+    // Parses a .pal file: 64 RGB triples, or 512 where the last 448 give
+    // the colors under each combination of emphasis bits
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PaletteError> {
+        let colors: Vec<Rgba<u8>> = bytes
+            .chunks_exact(3)
+            .map(|rgb| Rgba([rgb[0], rgb[1], rgb[2], 0xFF]))
+            .collect();
+        match bytes.len() {
+            192 => Ok(Self::with_emphasis(colors[..].try_into().unwrap())),
+            1536 => Ok(Self {
+                colors: colors[..].try_into().unwrap(),
+            }),
+            size => Err(PaletteError::InvalidSize(size)),
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, PaletteError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    // Derives the emphasized colors of a 64-color palette by dimming the
+    // channels that aren't emphasized
+    fn with_emphasis(base: &[Rgba<u8>; 64]) -> Self {
+        let mut colors = [Rgba([0, 0, 0, 0xFF]); 512];
+        for (pixel, color) in colors.iter_mut().enumerate() {
+            let emphasis = pixel >> 6;
+            let mut rgb = base[pixel & 0x3F].0;
+            for (channel, value) in rgb.iter_mut().take(3).enumerate() {
+                // Every emphasis bit but this channel's own attenuates it
+                let others = emphasis & !(1 << channel);
+                for _ in 0..others.count_ones() {
+                    *value = (*value as f32 * EMPHASIS_ATTENUATION) as u8;
+                }
+            }
+            *color = Rgba(rgb);
+        }
+        Self { colors }
+    }
+
+    pub fn color(&self, pixel: u16) -> Rgba<u8> {
+        self.colors[pixel as usize & 0x1FF]
+    }
+
+    // Colors 9-bit pixels, as PPU::front_pixels holds them, into RGBA bytes
+    pub fn convert(&self, pixels: &[u16], rgba: &mut [u8]) {
+        for (pixel, color) in pixels.iter().zip(rgba.chunks_exact_mut(4)) {
+            color.copy_from_slice(&self.color(*pixel).0);
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::preset(Preset::Default)
+    }
+}
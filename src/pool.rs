@@ -0,0 +1,126 @@
+use std::{num::NonZeroUsize, thread};
+
+use crate::console::Console;
+
+// Many consoles run side by side across threads, for reinforcement learning
+// rollouts and fuzzing. Each console is independent of the others, so they
+// can be given different games, states and input. Work is handed out in
+// batches: every call runs all the consoles to the end of the batch before
+// returning, split evenly over the pool's threads.
+pub struct ConsolePool {
+    consoles: Vec<Console>,
+    threads: usize,
+}
+
+impl ConsolePool {
+    // Runs on as many threads as the machine has cores
+    pub fn new(consoles: Vec<Console>) -> Self {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        Self { consoles, threads }
+    }
+
+    // Most threads a batch is split over, at least 1
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn len(&self) -> usize {
+        self.consoles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.consoles.is_empty()
+    }
+
+    pub fn push(&mut self, console: Console) {
+        self.consoles.push(console);
+    }
+
+    pub fn consoles(&self) -> &[Console] {
+        &self.consoles
+    }
+
+    pub fn consoles_mut(&mut self) -> &mut [Console] {
+        &mut self.consoles
+    }
+
+    pub fn into_consoles(self) -> Vec<Console> {
+        self.consoles
+    }
+
+    // Calls `f` with each console and its index, in parallel
+    pub fn for_each<F>(&mut self, f: F)
+    where
+        F: Fn(usize, &mut Console) + Sync,
+    {
+        self.map(f);
+    }
+
+    // Runs every console for `frames` frames
+    pub fn step_frames(&mut self, frames: u32) {
+        self.for_each(|_, console| {
+            for _ in 0..frames {
+                console.step_frame();
+            }
+        });
+    }
+
+    // Holds joypad 1's buttons at `buttons[i]` on console i, as
+    // Console::set_buttons takes them, for `frames` frames. Consoles past
+    // the end of `buttons` keep what they were holding.
+    pub fn step_frames_with(&mut self, buttons: &[u8], frames: u32) {
+        self.for_each(|index, console| {
+            if let Some(&buttons) = buttons.get(index) {
+                console.set_buttons(0, buttons);
+            }
+            for _ in 0..frames {
+                console.step_frame();
+            }
+        });
+    }
+
+    // Like for_each, collecting what `f` returns for each console in order
+    pub fn map<T, F>(&mut self, f: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(usize, &mut Console) -> T + Sync,
+    {
+        let chunk = self.consoles.len().div_ceil(self.threads).max(1);
+        if chunk == self.consoles.len() {
+            // Not worth a thread
+            return self
+                .consoles
+                .iter_mut()
+                .enumerate()
+                .map(|(index, console)| f(index, console))
+                .collect();
+        }
+        let mut results: Vec<Option<T>> = (0..self.consoles.len()).map(|_| None).collect();
+        let f = &f;
+        thread::scope(|scope| {
+            for (start, (consoles, results)) in self
+                .consoles
+                .chunks_mut(chunk)
+                .zip(results.chunks_mut(chunk))
+                .enumerate()
+            {
+                scope.spawn(move || {
+                    for (offset, (console, result)) in
+                        consoles.iter_mut().zip(results.iter_mut()).enumerate()
+                    {
+                        *result = Some(f(start * chunk + offset, console));
+                    }
+                });
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| result.expect("every console ran"))
+            .collect()
+    }
+}
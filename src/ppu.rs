@@ -1,19 +1,82 @@
-use crate::{console::Console, memory::Memory};
-use image::Rgba;
+use crate::{
+    mapper::Mapper,
+    memory::{Access, AccessLog, Fetch, Memory, MemoryInit, PPUMemory},
+    palette::Palette,
+    region::Region,
+    savestate::{StateError, StateReader, StateWriter},
+    video::{Filter, NTSC},
+    vs::VSPPU,
+};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+pub const WIDTH: u32 = 256;
+pub const HEIGHT: u32 = 240;
+// Frames a bit of the I/O latch holds its charge once no longer driven,
+// about 600ms
+const LATCH_DECAY_FRAMES: u64 = 36;
+// Dots an OAM row keeps its contents without a refresh, about 3000 CPU
+// cycles, and what its bytes read as after
+const OAM_DECAY_DOTS: u64 = 9000;
+const OAM_DECAYED: u8 = 0x10;
+
+// How the PPU draws. Per-dot rendering fetches and draws as the hardware
+// does, a pixel a dot, so changes made mid-line land where they should.
+// Scanline rendering draws each visible line whole at its first dot, from
+// the same fetches in the same order, and only keeps the dots that matter
+// to the rest of the console (scrolling, sprite evaluation, sprite zero and
+// vblank) on time, passing over the dots between. That makes the PPU's
+// share of a frame a fraction of what it was, for headless runs, at the
+// cost of raster effects timed within a line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Renderer {
+    #[default]
+    Dot,
+    Scanline,
+}
+
+// What the PPU draws frames as. It always keeps each pixel's palette index
+// and emphasis bits, for front_pixels; this is when, if at all, they become
+// the colors front() holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelFormat {
+    // Each pixel's color is looked up as it's drawn, so beam-following
+    // readers see colors mid-frame
+    #[default]
+    Rgba,
+    // Only indexes are drawn, and the finished frame is converted in one
+    // pass. Less memory traffic while drawing, for the same frames.
+    Indexed,
+    // Only indexes, never converted, leaving front() as it was. For
+    // headless runs that only read front_pixels, or convert it themselves
+    // with Palette::convert.
+    IndexedOnly,
+}
+
+pub type FrameCallback = Box<dyn FnMut(&RgbaImage) + Send>;
 
 pub struct PPU {
-    memory: Box<dyn Memory>,
-    console: Console,
+    memory: PPUMemory,
+    region: Region,
 
     cycle: i32,
     scanline: i32,
     frame: u64,
 
-    palete_data: [u8; 32],
-    name_table_data: [u8; 2048],
     oam_data: [u8; 256],
-    front: Rgba<u16>,
-    back: Rgba<u16>,
+    // Last completed frame and the frame being drawn
+    front: RgbaImage,
+    back: RgbaImage,
+    frame_callback: Option<FrameCallback>,
+    // The same frames as 9-bit pixels: palette index and emphasis bits
+    front_pixels: Vec<u16>,
+    back_pixels: Vec<u16>,
+    // Dot count mod 3, the phase of the NTSC color subcarrier, and its value
+    // at the start of each visible line
+    dot_phase: u8,
+    line_phases: [u8; HEIGHT as usize],
+    palette: Box<Palette>,
+    ntsc: Option<Box<NTSC>>,
 
     // PPU Registers
     v: u16,
@@ -21,14 +84,42 @@ pub struct PPU {
     x: u8,
     w: u8,
     f: u8,
+    // Dots until the second $2006 write reaches v; the PPU copies t a few
+    // dots after the write, not on it
+    v_delay: u8,
+    // Set by the reset button until the pre-render line, during which
+    // writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR are ignored
+    resetting: bool,
 
+    // I/O latch: the last value on the PPU's CPU-facing data bus, which
+    // reads of write-only registers and unused bits return. Each bit fades
+    // to 0 a while after the frame it was last driven in.
     register: u8,
+    register_refreshed: [u64; 8],
+
+    renderer: Renderer,
+    pixel_format: PixelFormat,
+    // OAM quirks of the 2C02, off unless asked for: its DRAM decays while
+    // rendering is off, and OAMADDR steers and corrupts sprite evaluation
+    accurate_oam: bool,
+    // The VS. System PPU standing in for the 2C02, for its register quirks
+    vs_ppu: Option<VSPPU>,
+    // Dots run, the clock OAM decay is measured on
+    dots: u64,
+    // Dot each 8-byte OAM row was last refreshed by an access, and the last
+    // dot rendering refreshed them all
+    oam_refreshed: [u64; 32],
+    oam_rendered: u64,
 
     // NMI Flags
     nmi_occurred: bool,
     nmi_output: bool,
     nmi_prev: bool,
-    nmi_delay: u8,
+    // Raised on the NMI line's edge, until the CPU takes it
+    nmi_pending: bool,
+    // Set by a $2002 read on the dot before vblank starts, which keeps it
+    // from starting this frame
+    suppress_vblank: bool,
 
     // Background temp variables
     name_table_byte: u8,
@@ -65,89 +156,307 @@ pub struct PPU {
     // $2002 PPUSTATUS
     flag_sprite_zero_hit: u8,
     flag_sprite_overflow: u8,
+    // A sprite-zero hit drawn on this dot, which reaches the flag on the next
+    sprite_zero_hit_pending: bool,
+    // The dot a line drawn ahead by the scanline renderer hits sprite zero
+    // on, 0 for none
+    sprite_zero_hit_dot: i32,
 
     // $2003 OAMADDR
     oam_addr: u8,
 
     // $2007 PPUDATA
     buffer_data: u8,
-}
 
-impl Default for PPU {
-    fn default() -> Self {
-        let front: Rgba<u16> = Rgba([0, 0, 256, 240]);
-        let back: Rgba<u16> = Rgba([0, 0, 256, 240]);
-        Self {
-            console: Console::default(),
-            front,
-            back,
-            ..Default::default()
-        }
-    }
+    // PPUDATA accesses are recorded here while a debugger needs them
+    pub access_log: Option<AccessLog>,
 }
 
 impl PPU {
-    fn new(console: Console) -> Self {
-        Self {
-            console,
-            ..Default::default()
-        }
+    pub fn new(memory: PPUMemory) -> Self {
+        // Colored as the zeroed pixels would be, whatever the pixel format
+        let palette = Box::<Palette>::default();
+        let blank = RgbaImage::from_pixel(WIDTH, HEIGHT, palette.color(0));
+        let mut ppu = Self {
+            memory,
+            region: Region::NTSC,
+            cycle: 0,
+            scanline: 0,
+            frame: 0,
+            oam_data: [0; 256],
+            front: blank.clone(),
+            back: blank,
+            frame_callback: None,
+            front_pixels: vec![0; (WIDTH * HEIGHT) as usize],
+            back_pixels: vec![0; (WIDTH * HEIGHT) as usize],
+            dot_phase: 0,
+            line_phases: [0; HEIGHT as usize],
+            palette,
+            ntsc: None,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: 0,
+            f: 0,
+            v_delay: 0,
+            resetting: false,
+            register: 0,
+            register_refreshed: [0; 8],
+            renderer: Renderer::Dot,
+            pixel_format: PixelFormat::Rgba,
+            accurate_oam: false,
+            vs_ppu: None,
+            dots: 0,
+            oam_refreshed: [0; 32],
+            oam_rendered: 0,
+            nmi_occurred: false,
+            nmi_output: false,
+            nmi_prev: false,
+            nmi_pending: false,
+            suppress_vblank: false,
+            name_table_byte: 0,
+            attr_table_byte: 0,
+            low_tile_byte: 0,
+            high_tile_byte: 0,
+            tile_data: 0,
+            sprite_count: 0,
+            sprite_patterns: [0; 8],
+            sprite_position: [0; 8],
+            sprite_priorities: [0; 8],
+            sprite_indexes: [0; 8],
+            flag_name_table: 0,
+            flag_increment: 0,
+            flag_sprite_table: 0,
+            flag_background_table: 0,
+            flag_sprite_size: 0,
+            flag_master_slave: 0,
+            flag_gray_scale: 0,
+            flag_show_left_background: 0,
+            flag_show_left_sprites: 0,
+            flag_show_background: 0,
+            flag_show_sprites: 0,
+            flag_red_tint: 0,
+            flag_green_tint: 0,
+            flag_blue_tint: 0,
+            flag_sprite_zero_hit: 0,
+            flag_sprite_overflow: 0,
+            sprite_zero_hit_pending: false,
+            sprite_zero_hit_dot: 0,
+            oam_addr: 0,
+            buffer_data: 0,
+            access_log: None,
+        };
+        ppu.power_on(&MemoryInit::default());
+        ppu
     }
-    fn reset(mut self) {
+
+    // Power-on state: registers, OAM and the pipeline start over at the end
+    // of the picture, with nametable and palette RAM filled as `init` says.
+    // The settings and frame count are kept.
+    pub fn power_on(&mut self, init: &MemoryInit) {
+        self.memory.power_on(init);
         self.cycle = 340;
         self.scanline = 240;
-        self.frame = 0;
+        self.oam_data = [0; 256];
+        self.v = 0;
+        self.t = 0;
+        self.x = 0;
+        self.w = 0;
+        self.f = 0;
+        self.v_delay = 0;
+        self.resetting = false;
+        self.register = 0;
+        self.register_refreshed = [0; 8];
+        self.oam_refreshed = [self.dots; 32];
+        self.oam_rendered = self.dots;
+        self.nmi_occurred = false;
+        self.nmi_prev = false;
+        self.suppress_vblank = false;
+        self.nmi_pending = false;
+        self.tile_data = 0;
+        self.sprite_count = 0;
+        self.flag_sprite_zero_hit = 0;
+        self.flag_sprite_overflow = 0;
+        self.sprite_zero_hit_pending = false;
+        self.sprite_zero_hit_dot = 0;
+        self.buffer_data = 0;
         self.write_control(0);
         self.write_mask(0);
         self.write_oam_addr(0);
     }
 
-    fn read_palette(&mut self, mut addr: u16) -> u8 {
-        if addr >= 16 && addr % 4 == 0 {
-            addr -= 16
-        }
-        self.palete_data[addr as usize]
+    // The reset button: PPUCTRL, PPUMASK, the scroll, the write toggle and
+    // the read buffer clear, and those registers ignore writes until the
+    // pre-render line. OAM and the position on screen are kept.
+    pub fn reset(&mut self) {
+        self.write_control(0);
+        self.write_mask(0);
+        self.t = 0;
+        self.x = 0;
+        self.w = 0;
+        self.f = 0;
+        self.buffer_data = 0;
+        self.resetting = true;
     }
 
-    fn write_palette(&mut self, mut addr: u16, value: u8) {
-        if addr >= 16 && addr % 4 == 0 {
-            addr -= 16
-        }
-        self.palete_data[addr as usize] = value
+    // The framebuffers and subcarrier phase are not saved; the next frame
+    // redraws them
+    pub fn save(&self, state: &mut StateWriter) {
+        self.memory.save(state);
+        state.write(&self.cycle);
+        state.write(&self.scanline);
+        state.write(&self.frame);
+        state.write(&self.oam_data[..]);
+        state.write(&self.v);
+        state.write(&self.t);
+        state.write(&self.x);
+        state.write(&self.w);
+        state.write(&self.f);
+        state.write(&self.v_delay);
+        state.write(&self.resetting);
+        state.write(&self.register);
+        state.write(&self.register_refreshed);
+        state.write(&self.dots);
+        state.write(&self.oam_refreshed);
+        state.write(&self.oam_rendered);
+        state.write(&self.nmi_occurred);
+        state.write(&self.nmi_output);
+        state.write(&self.nmi_prev);
+        state.write(&self.nmi_pending);
+        state.write(&self.suppress_vblank);
+        state.write(&self.name_table_byte);
+        state.write(&self.attr_table_byte);
+        state.write(&self.low_tile_byte);
+        state.write(&self.high_tile_byte);
+        state.write(&self.tile_data);
+        state.write(&self.sprite_count);
+        state.write(&self.sprite_patterns);
+        state.write(&self.sprite_position);
+        state.write(&self.sprite_priorities);
+        state.write(&self.sprite_indexes);
+        state.write(&self.flag_name_table);
+        state.write(&self.flag_increment);
+        state.write(&self.flag_sprite_table);
+        state.write(&self.flag_background_table);
+        state.write(&self.flag_sprite_size);
+        state.write(&self.flag_master_slave);
+        state.write(&self.flag_gray_scale);
+        state.write(&self.flag_show_left_background);
+        state.write(&self.flag_show_left_sprites);
+        state.write(&self.flag_show_background);
+        state.write(&self.flag_show_sprites);
+        state.write(&self.flag_red_tint);
+        state.write(&self.flag_green_tint);
+        state.write(&self.flag_blue_tint);
+        state.write(&self.flag_sprite_zero_hit);
+        state.write(&self.flag_sprite_overflow);
+        state.write(&self.sprite_zero_hit_pending);
+        state.write(&self.sprite_zero_hit_dot);
+        state.write(&self.oam_addr);
+        state.write(&self.buffer_data);
     }
 
-    fn read_register(&mut self, addr: u16) -> u8 {
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load(state)?;
+        self.cycle = state.read()?;
+        self.scanline = state.read()?;
+        self.frame = state.read()?;
+        state.read_into(&mut self.oam_data)?;
+        self.v = state.read()?;
+        self.t = state.read()?;
+        self.x = state.read()?;
+        self.w = state.read()?;
+        self.f = state.read()?;
+        self.v_delay = state.read()?;
+        self.resetting = state.read()?;
+        self.register = state.read()?;
+        self.register_refreshed = state.read()?;
+        self.dots = state.read()?;
+        self.oam_refreshed = state.read()?;
+        self.oam_rendered = state.read()?;
+        self.nmi_occurred = state.read()?;
+        self.nmi_output = state.read()?;
+        self.nmi_prev = state.read()?;
+        self.nmi_pending = state.read()?;
+        self.suppress_vblank = state.read()?;
+        self.name_table_byte = state.read()?;
+        self.attr_table_byte = state.read()?;
+        self.low_tile_byte = state.read()?;
+        self.high_tile_byte = state.read()?;
+        self.tile_data = state.read()?;
+        self.sprite_count = state.read()?;
+        self.sprite_patterns = state.read()?;
+        self.sprite_position = state.read()?;
+        self.sprite_priorities = state.read()?;
+        self.sprite_indexes = state.read()?;
+        self.flag_name_table = state.read()?;
+        self.flag_increment = state.read()?;
+        self.flag_sprite_table = state.read()?;
+        self.flag_background_table = state.read()?;
+        self.flag_sprite_size = state.read()?;
+        self.flag_master_slave = state.read()?;
+        self.flag_gray_scale = state.read()?;
+        self.flag_show_left_background = state.read()?;
+        self.flag_show_left_sprites = state.read()?;
+        self.flag_show_background = state.read()?;
+        self.flag_show_sprites = state.read()?;
+        self.flag_red_tint = state.read()?;
+        self.flag_green_tint = state.read()?;
+        self.flag_blue_tint = state.read()?;
+        self.flag_sprite_zero_hit = state.read()?;
+        self.flag_sprite_overflow = state.read()?;
+        self.sprite_zero_hit_pending = state.read()?;
+        self.sprite_zero_hit_dot = state.read()?;
+        self.oam_addr = state.read()?;
+        self.buffer_data = state.read()?;
+        Ok(())
+    }
+
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let latch = self.latch();
         match addr {
+            // Only bits 7-5 are driven
             0x2002 => {
-                return self.read_status();
+                let value = match self.vs_ppu.and_then(VSPPU::status_id) {
+                    // The 2C05s drive an ID over the sprite overflow flag
+                    Some(id) => (self.read_status() & 0xC0) | id,
+                    None => self.read_status() | (latch & 0x1F),
+                };
+                self.refresh_latch(value, 0xE0);
+                value
             }
             0x2004 => {
-                return self.read_oam_data();
-            }
-            0x2007 => {
-                return self.read_data();
+                let value = self.read_oam_data();
+                self.refresh_latch(value, 0xFF);
+                value
             }
-            _ => return 0 as u8,
+            0x2007 => self.read_data(latch),
+            _ => latch,
         }
     }
 
-    fn write_register(&mut self, addr: u16, value: u8) {
-        self.register = value;
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        self.refresh_latch(value, 0xFF);
+        let addr = match self.vs_ppu {
+            Some(ppu) if ppu.swaps_control_registers() && addr <= 0x2001 => addr ^ 1,
+            _ => addr,
+        };
         match addr {
-            0x2000 => return self.write_control(value),
-            0x2001 => return self.write_mask(value),
-            0x2003 => return self.write_oam_addr(value),
-            0x2004 => return self.write_oam_data(value),
-            0x2005 => return self.write_scroll(value),
-            0x2006 => return self.write_addr(value),
-            0x2007 => return self.write_data(value),
-            0x4014 => return self.write_dma(value),
+            0x2000 | 0x2001 | 0x2005 | 0x2006 if self.resetting => {}
+            0x2000 => self.write_control(value),
+            0x2001 => self.write_mask(value),
+            0x2003 => self.write_oam_addr(value),
+            0x2004 => self.write_oam_data(value),
+            0x2005 => self.write_scroll(value),
+            0x2006 => self.write_addr(value),
+            0x2007 => self.write_data(value),
+            _ => {}
         }
     }
 
     // $2000: PPUCTRL
     fn write_control(&mut self, value: u8) {
-        self.flag_name_table = (value >> 0) & 3;
+        self.flag_name_table = value & 3;
         self.flag_increment = (value >> 2) & 1;
         self.flag_sprite_table = (value >> 3) & 1;
         self.flag_background_table = (value >> 4) & 1;
@@ -155,36 +464,52 @@ impl PPU {
         self.flag_master_slave = (value >> 6) & 1;
         self.nmi_output = (value >> 7) & 1 == 1;
         self.nmi_change();
-        self.t = (self.t & 0xF3FF) | ((value as u16 & 0x03) << 10)
+        // t: ...GH.. ........ = d: ......GH
+        self.t = (self.t & 0x73FF) | ((value as u16 & 0x03) << 10)
     }
     // $2001: PPUMASK
     fn write_mask(&mut self, value: u8) {
-        self.flag_gray_scale = (value >> 0) & 1;
+        self.flag_gray_scale = value & 1;
         self.flag_show_left_background = (value >> 1) & 1;
         self.flag_show_left_sprites = (value >> 2) & 1;
         self.flag_show_background = (value >> 3) & 1;
         self.flag_show_sprites = (value >> 4) & 1;
         self.flag_red_tint = (value >> 5) & 1;
-        self.flag_blue_tint = (value >> 6) & 1;
+        self.flag_green_tint = (value >> 6) & 1;
         self.flag_blue_tint = (value >> 7) & 1;
     }
 
+    // Bits of a palette entry that reach the screen or a $2007 read; greyscale
+    // drops the hue, leaving the column of greys
+    fn palette_mask(&self) -> u8 {
+        if self.flag_gray_scale == 1 {
+            0x30
+        } else {
+            0x3F
+        }
+    }
+
     // $2002: PPUSTATUS
     fn read_status(&mut self) -> u8 {
-        let mut result = self.register & 0x1F;
-        result |= self.flag_sprite_overflow;
-        result |= self.flag_sprite_zero_hit;
-        result |= self.flag_sprite_zero_hit;
+        let mut result = self.flag_sprite_overflow << 5;
+        result |= self.flag_sprite_zero_hit << 6;
 
         if self.nmi_occurred {
             result |= 1 << 7;
         }
+        // Read a dot before vblank starts, the flag reads clear and neither
+        // it nor the NMI come this frame. Read on the dot it starts or the
+        // next, it reads set and clearing it drops the NMI before the CPU
+        // sees it.
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 0 {
+            self.suppress_vblank = true;
+        }
 
         self.nmi_occurred = false;
         self.nmi_change();
 
         self.w = 0;
-        return result;
+        result
     }
 
     // $2003: OAMADDR
@@ -193,18 +518,20 @@ impl PPU {
     }
 
     // $2004: OAMDATA (read)
-    fn read_oam_data(&self) -> u8 {
-        let mut data = self.oam_data[self.oam_addr as usize] as u8;
+    fn read_oam_data(&mut self) -> u8 {
+        self.refresh_oam_row(self.oam_addr);
+        let mut data = self.oam_data[self.oam_addr as usize];
         if (self.oam_addr & 0x03) == 0x02 {
-            data = data & 0xE3;
+            data &= 0xE3;
         }
         data
     }
 
     // $2004: OAMDATA (write)
     fn write_oam_data(&mut self, value: u8) {
+        self.refresh_oam_row(self.oam_addr);
         self.oam_data[self.oam_addr as usize] = value;
-        self.oam_addr += 1
+        self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
     // $2005: PPUSCROLL
@@ -213,14 +540,14 @@ impl PPU {
             // t: ........ ...HGFED = d: HGFED...
             // x:               CBA = d: .....CBA
             // w:                   = 1
-            self.t = (self.t & 0x8FFE0) | (value as u16 >> 3);
+            self.t = (self.t & 0x7FE0) | (value as u16 >> 3);
             self.x = value & 0x07;
             self.w = 1;
         } else {
             // t: .CBA..HG FED..... = d: HGFEDCBA
             // w:                   = 0
-            self.t = (self.t & 0x8FFF) | ((value as u16 & 0x07) << 12);
-            self.t = (self.t & 0xFC1F) | (((value as u16) & 0xF8) << 2);
+            self.t =
+                (self.t & 0x0C1F) | ((value as u16 & 0x07) << 12) | ((value as u16 & 0xF8) << 2);
             self.w = 0;
         }
     }
@@ -231,54 +558,929 @@ impl PPU {
             // t: ..FEDCBA ........ = d: ..FEDCBA
             // t: .X...... ........ = 0
             // w:                   = 1
-            self.t = (self.t & 0x80FF) | ((value as u16 & 0x3F) << 8);
+            self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
             self.w = 1;
         } else {
             // t: ........ HGFEDCBA = d: HGFEDCBA
-            // v                    = t
+            // v                    = t, three dots later
             // w:                   = 0
-            self.t = (self.t & 0xFF00) | value as u16;
-            self.v = self.t;
+            self.t = (self.t & 0x7F00) | value as u16;
+            self.v_delay = 3;
             self.w = 0;
         }
     }
 
     // $2007: PPUDATA (read)
-    fn read_data(&mut self) -> u8 {
+    // Reads below the palette return the buffer and refill it. Palette reads
+    // come straight back in bits 5-0, greyscale applied, with the latch in
+    // bits 7-6, and refill the buffer from the nametable underneath.
+    fn read_data(&mut self, latch: u8) -> u8 {
+        if let Some(log) = self.access_log.as_mut() {
+            log.push((self.v % 0x4000, Access::Read));
+        }
+        self.memory.set_fetch(Fetch::Cpu);
         let mut value = self.memory.read(self.v);
 
         if self.v % 0x4000 < 0x3F00 {
-            let buffered = self.buffer_data;
-            self.buffer_data = value;
-            value = buffered;
+            std::mem::swap(&mut self.buffer_data, &mut value);
+            self.refresh_latch(value, 0xFF);
         } else {
             self.buffer_data = self.memory.read(self.v - 0x1000);
+            value = (value & self.palette_mask()) | (latch & 0xC0);
+            self.refresh_latch(value, 0x3F);
         }
 
-        if self.flag_increment == 0 {
-            self.v += 1;
-        } else {
-            self.v += 32;
+        self.increment_address();
+        value
+    }
+
+    // The I/O latch with decayed bits cleared
+    fn latch(&mut self) -> u8 {
+        for bit in 0..8 {
+            if self.frame.saturating_sub(self.register_refreshed[bit]) > LATCH_DECAY_FRAMES {
+                self.register &= !(1 << bit);
+            }
         }
+        self.register
+    }
 
-        value
+    // Drives the bits of `mask` onto the I/O latch
+    fn refresh_latch(&mut self, value: u8, mask: u8) {
+        self.register = (self.register & !mask) | (value & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) != 0 {
+                self.register_refreshed[bit] = self.frame;
+            }
+        }
     }
 
     // $2007: PPUDATA (write)
     fn write_data(&mut self, value: u8) {
+        if let Some(log) = self.access_log.as_mut() {
+            log.push((self.v % 0x4000, Access::Write));
+        }
+        self.memory.set_fetch(Fetch::Cpu);
         self.memory.write(self.v, value);
-        if self.flag_increment == 0 {
+        self.increment_address();
+    }
+
+    // While rendering, the PPU bumps v with its own coarse X and Y
+    // increments instead of adding 1 or 32, which some games use to scroll
+    fn increment_address(&mut self) {
+        let render_line = self.scanline < 240 || self.scanline == self.pre_render_line();
+        if self.rendering_enabled() && render_line {
+            self.increment_x();
+            self.increment_y();
+        } else if self.flag_increment == 0 {
+            self.v = (self.v + 1) & 0x7FFF;
+        } else {
+            self.v = (self.v + 32) & 0x7FFF;
+        }
+    }
+
+    fn nmi_change(&mut self) {
+        let nmi = self.nmi_output && self.nmi_occurred;
+        if nmi != self.nmi_prev {
+            // The CPU samples the line at the end of its cycle, so a drop
+            // before then, like a $2002 read as vblank starts, means it never
+            // sees the NMI
+            self.nmi_pending = nmi;
+        }
+        self.nmi_prev = nmi;
+    }
+
+    // Whether an NMI has fired since the last call
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    // True on the dot of each rendered line that boards counting lines
+    // without A12 clock on
+    pub fn scanline_clock(&self) -> bool {
+        self.cycle == 280
+            && (self.scanline < 240 || self.scanline == self.pre_render_line())
+            && self.rendering_enabled()
+    }
+
+    // True on the dot where PPU A12 rises during rendering after being low
+    // long enough for an MMC3 to count it: the first sprite fetch from $1000
+    // after backgrounds from $0000, or the first background fetch for the
+    // next line from $1000 after sprites from $0000. 8x16 sprites fetch
+    // from $1000 for empty slots, so count as sprites from $1000.
+    pub fn a12_rise(&self) -> bool {
+        let sprites_high = self.flag_sprite_size == 1 || self.flag_sprite_table == 1;
+        let dot = match (self.flag_background_table, sprites_high) {
+            (0, true) => 260,
+            (1, false) => 324,
+            _ => return false,
+        };
+        self.cycle == dot
+            && (self.scanline < 240 || self.scanline == self.pre_render_line())
+            && self.rendering_enabled()
+    }
+
+    fn pre_render_line(&self) -> i32 {
+        self.region.scanlines() - 1
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.flag_show_background != 0 || self.flag_show_sprites != 0
+    }
+
+    // NTSC odd frames skip the first idle dot of the pre-render line when
+    // rendering is enabled
+    fn tick(&mut self) {
+        self.dots += 1;
+        self.dot_phase = (self.dot_phase + 1) % 3;
+
+        if self.region.skips_odd_dot()
+            && self.rendering_enabled()
+            && self.f == 1
+            && self.scanline == self.pre_render_line()
+            && self.cycle == 339
+        {
+            self.cycle = 0;
+            self.scanline = 0;
+            self.frame += 1;
+            self.f ^= 1;
+            return;
+        }
+
+        self.cycle += 1;
+        if self.cycle > 340 {
+            self.cycle = 0;
+            self.scanline += 1;
+            if self.scanline > self.pre_render_line() {
+                self.scanline = 0;
+                self.frame += 1;
+                self.f ^= 1;
+            }
+        }
+    }
+
+    // Advances the PPU by one dot
+    pub fn step(&mut self) {
+        self.tick();
+
+        if self.v_delay > 0 {
+            self.v_delay -= 1;
+            if self.v_delay == 0 {
+                self.v = self.t;
+            }
+        }
+
+        if self.sprite_zero_hit_pending {
+            self.sprite_zero_hit_pending = false;
+            self.flag_sprite_zero_hit = 1;
+        }
+
+        if self.renderer == Renderer::Scanline {
+            self.step_scanline();
+            return;
+        }
+
+        let pre_line = self.scanline == self.pre_render_line();
+        let visible_line = self.scanline < 240;
+        let render_line = pre_line || visible_line;
+        let pre_fetch_cycle = self.cycle >= 321 && self.cycle <= 336;
+        let visible_cycle = self.cycle >= 1 && self.cycle <= 256;
+        let fetch_cycle = pre_fetch_cycle || visible_cycle;
+
+        if self.accurate_oam && self.rendering_enabled() {
+            self.refresh_oam(pre_line, render_line);
+        }
+
+        // background logic
+        if self.rendering_enabled() {
+            if visible_line && visible_cycle {
+                self.render_pixel();
+            }
+            if render_line && fetch_cycle {
+                self.tile_data <<= 4;
+                match self.cycle % 8 {
+                    1 => {
+                        self.memory.set_fetch(Fetch::Background);
+                        self.fetch_name_table_byte();
+                    }
+                    3 => self.fetch_attribute_table_byte(),
+                    5 => self.fetch_low_tile_byte(),
+                    7 => self.fetch_high_tile_byte(),
+                    0 => self.store_tile_data(),
+                    _ => {}
+                }
+            }
+            if pre_line && self.cycle >= 280 && self.cycle <= 304 {
+                self.copy_y();
+            }
+            if render_line {
+                if fetch_cycle && self.cycle % 8 == 0 {
+                    self.increment_x();
+                }
+                if self.cycle == 256 {
+                    self.increment_y();
+                }
+                if self.cycle == 257 {
+                    self.copy_x();
+                }
+            }
+        }
+
+        // sprite logic
+        if self.rendering_enabled() && self.cycle == 257 {
+            if visible_line {
+                self.evaluate_sprites();
+            } else {
+                self.sprite_count = 0;
+            }
+        }
+
+        self.step_vertical_blank(pre_line);
+    }
+
+    // Dots from now on that the scanline renderer has nothing to do in but
+    // count, which can be passed over with skip_dots. Always 0 when drawing
+    // a dot at a time.
+    pub fn idle_dots(&self) -> u64 {
+        if self.renderer != Renderer::Scanline
+            || self.accurate_oam
+            || self.v_delay > 0
+            || self.sprite_zero_hit_pending
+        {
+            return 0;
+        }
+        // The next dot anything happens on: drawing, vblank, the end of the
+        // visible part, A12 rising, the scanline clock, copy_y and the end of
+        // the line
+        let mut next = match self.cycle {
+            1..=255 => 256,
+            257..=259 => 260,
+            261..=279 => 280,
+            304..=323 => 324,
+            325..=339 => 340,
+            cycle => cycle + 1,
+        };
+        if (self.cycle + 1..next).contains(&self.sprite_zero_hit_dot) {
+            next = self.sprite_zero_hit_dot;
+        }
+        (next - self.cycle - 1) as u64
+    }
+
+    // Passes over `dots` of the dots idle_dots counts
+    pub fn skip_dots(&mut self, dots: u64) {
+        self.cycle += dots as i32;
+        self.dots += dots;
+        self.dot_phase = ((self.dot_phase as u64 + dots) % 3) as u8;
+    }
+
+    // The rest of a dot for the scanline renderer, which draws the line and
+    // fetches its tiles on the first dot of visible lines and otherwise only
+    // does what is seen outside the PPU
+    fn step_scanline(&mut self) {
+        let pre_line = self.scanline == self.pre_render_line();
+        let visible_line = self.scanline < 240;
+
+        if self.accurate_oam && self.rendering_enabled() {
+            self.refresh_oam(pre_line, pre_line || visible_line);
+        }
+
+        if self.rendering_enabled() && (pre_line || visible_line) {
+            match self.cycle {
+                1 if visible_line => self.render_scanline(),
+                256 => self.increment_y(),
+                257 => {
+                    self.copy_x();
+                    if visible_line {
+                        self.evaluate_sprites();
+                    } else {
+                        self.sprite_count = 0;
+                    }
+                }
+                280..=304 if pre_line => self.copy_y(),
+                _ => {}
+            }
+            if self.cycle == self.sprite_zero_hit_dot {
+                self.sprite_zero_hit_dot = 0;
+                self.sprite_zero_hit_pending = true;
+            }
+        }
+
+        self.step_vertical_blank(pre_line);
+    }
+
+    fn step_vertical_blank(&mut self, pre_line: bool) {
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 {
+            self.set_vertical_blank();
+        }
+        if pre_line && self.cycle == 1 {
+            self.resetting = false;
+            self.clear_vertical_blank();
+            self.flag_sprite_zero_hit = 0;
+            self.flag_sprite_overflow = 0;
+            self.sprite_zero_hit_dot = 0;
+        }
+    }
+
+    // Dot within the current scanline, 0-340
+    pub fn cycle(&self) -> i32 {
+        self.cycle
+    }
+
+    // Current scanline, 0-261 with 261 the pre-render line (0-311 and 311
+    // on PAL and Dendy)
+    pub fn scanline(&self) -> i32 {
+        self.scanline
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    // Switches the frame layout: scanline count, vblank start and whether
+    // odd frames are a dot short
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    // Number of frames rendered since power-on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // The completed frame, ready to be displayed
+    pub fn front(&self) -> &RgbaImage {
+        &self.front
+    }
+
+    // The completed frame as 9-bit pixels: palette index in bits 0-5 and the
+    // PPUMASK emphasis bits (red, green, blue) in bits 6-8
+    pub fn front_pixels(&self) -> &[u16] {
+        &self.front_pixels
+    }
+
+    // Color of the pixel at (x, y) as last drawn by the beam, whether that
+    // was in the frame in progress or the one just completed
+    pub fn beam_color(&self, x: u32, y: u32) -> Rgba<u8> {
+        let vblank = self.region.vblank_scanline();
+        let completed = self.scanline > vblank || (self.scanline == vblank && self.cycle >= 1);
+        let pixels = if completed {
+            &self.front_pixels
+        } else {
+            &self.back_pixels
+        };
+        self.palette.color(pixels[(y * WIDTH + x) as usize])
+    }
+
+    pub fn video_filter(&self) -> Filter {
+        match self.ntsc {
+            Some(_) => Filter::Ntsc,
+            None => Filter::None,
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    // Sets the colors palette indices are drawn with. The NTSC filter works
+    // from the video signal instead and ignores it.
+    pub fn set_palette(&mut self, palette: Palette) {
+        *self.palette = palette;
+    }
+
+    // Chooses how palette indices become RGB, from the next completed frame
+    pub fn set_video_filter(&mut self, filter: Filter) {
+        self.ntsc = match filter {
+            Filter::None => None,
+            Filter::Ntsc => self.ntsc.take().or_else(|| Some(Box::new(NTSC::new()))),
+        };
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    // Switches pixel format, from the next pixel drawn
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.pixel_format = format;
+    }
+
+    // Whether pixels get their colors as they're drawn. Not when the NTSC
+    // filter draws the frame's colors over them at the end anyway.
+    fn draws_rgba(&self) -> bool {
+        self.pixel_format == PixelFormat::Rgba && self.ntsc.is_none()
+    }
+
+    // Switches renderer, from the next line
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+        self.sprite_zero_hit_dot = 0;
+    }
+
+    pub fn accurate_oam(&self) -> bool {
+        self.accurate_oam
+    }
+
+    // Turns on OAM decay and the OAMADDR quirks of sprite evaluation. Off,
+    // OAM holds its contents forever and evaluation always starts at 0.
+    pub fn set_accurate_oam(&mut self, accurate: bool) {
+        self.accurate_oam = accurate;
+        self.oam_refreshed = [self.dots; 32];
+        self.oam_rendered = self.dots;
+    }
+
+    pub fn vs_ppu(&self) -> Option<VSPPU> {
+        self.vs_ppu
+    }
+
+    // Takes on the register quirks of a VS. System PPU, or the 2C02's with
+    // `None`
+    pub fn set_vs_ppu(&mut self, ppu: Option<VSPPU>) {
+        self.vs_ppu = ppu;
+    }
+
+    // The cartridge, which the PPU's bus owns
+    pub fn mapper(&self) -> &dyn Mapper {
+        &*self.memory.mapper
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        &mut *self.memory.mapper
+    }
+
+    // Registers a function called with each frame as it completes
+    pub fn set_frame_callback(&mut self, callback: FrameCallback) {
+        self.frame_callback = Some(callback);
+    }
+
+    // Removes the frame callback, returning it
+    pub fn take_frame_callback(&mut self) -> Option<FrameCallback> {
+        self.frame_callback.take()
+    }
+
+    // Reads PPU memory as a $2007 read sees it, but without the read buffer
+    // or the address increment, for debug viewers
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        self.memory.set_fetch(Fetch::Cpu);
+        self.memory.read(addr)
+    }
+
+    pub fn oam(&self) -> &[u8; 256] {
+        &self.oam_data
+    }
+
+    // Color a palette index shows as, without emphasis
+    pub fn color(&self, index: u8) -> Rgba<u8> {
+        self.palette.color(index as u16 & 0x3F)
+    }
+
+    // Pattern table (0 or 1) backgrounds are drawn from
+    pub fn background_table(&self) -> u8 {
+        self.flag_background_table
+    }
+
+    // Pattern table (0 or 1) 8x8 sprites are drawn from
+    pub fn sprite_table(&self) -> u8 {
+        self.flag_sprite_table
+    }
+
+    // Whether sprites are 8x16 rather than 8x8
+    pub fn large_sprites(&self) -> bool {
+        self.flag_sprite_size == 1
+    }
+
+    // Where the next frame starts drawing from, in the 512x480 plane of the
+    // four nametables
+    pub fn scroll(&self) -> (u32, u32) {
+        let t = self.t as u32;
+        let x = (t >> 10 & 1) * 256 + (t & 0x1F) * 8 + self.x as u32;
+        let y = (t >> 11 & 1) * 240 + (t >> 5 & 0x1F) * 8 + (t >> 12 & 7);
+        (x, y)
+    }
+
+    fn set_vertical_blank(&mut self) {
+        if self.pixel_format != PixelFormat::IndexedOnly {
+            if let Some(ntsc) = self.ntsc.as_mut() {
+                ntsc.render(&self.back_pixels, &self.line_phases, &mut self.back);
+            } else if self.pixel_format == PixelFormat::Indexed {
+                self.palette.convert(&self.back_pixels, &mut self.back);
+            }
+        }
+        std::mem::swap(&mut self.front, &mut self.back);
+        std::mem::swap(&mut self.front_pixels, &mut self.back_pixels);
+        if let Some(callback) = self.frame_callback.as_mut() {
+            callback(&self.front);
+        }
+        if !std::mem::take(&mut self.suppress_vblank) {
+            self.nmi_occurred = true;
+            self.nmi_change();
+        }
+    }
+
+    fn clear_vertical_blank(&mut self) {
+        self.nmi_occurred = false;
+        self.nmi_change();
+    }
+
+    fn fetch_name_table_byte(&mut self) {
+        let address = 0x2000 | (self.v & 0x0FFF);
+        self.name_table_byte = self.memory.read(address);
+    }
+
+    fn fetch_attribute_table_byte(&mut self) {
+        let v = self.v;
+        let address = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let shift = ((v >> 4) & 4) | (v & 2);
+        self.attr_table_byte = ((self.memory.read(address) >> shift) & 3) << 2;
+    }
+
+    fn fetch_low_tile_byte(&mut self) {
+        let fine_y = (self.v >> 12) & 7;
+        let table = self.flag_background_table as u16;
+        let tile = self.name_table_byte as u16;
+        let address = 0x1000 * table + tile * 16 + fine_y;
+        self.low_tile_byte = self.memory.read(address);
+    }
+
+    fn fetch_high_tile_byte(&mut self) {
+        let fine_y = (self.v >> 12) & 7;
+        let table = self.flag_background_table as u16;
+        let tile = self.name_table_byte as u16;
+        let address = 0x1000 * table + tile * 16 + fine_y;
+        self.high_tile_byte = self.memory.read(address + 8);
+    }
+
+    // Packs the eight fetched pixels as 4-bit palette entries into the low
+    // half of the shift register
+    fn store_tile_data(&mut self) {
+        let mut data: u32 = 0;
+        for _ in 0..8 {
+            let a = self.attr_table_byte;
+            let p1 = (self.low_tile_byte & 0x80) >> 7;
+            let p2 = (self.high_tile_byte & 0x80) >> 6;
+            self.low_tile_byte <<= 1;
+            self.high_tile_byte <<= 1;
+            data <<= 4;
+            data |= (a | p1 | p2) as u32;
+        }
+        self.tile_data |= data as u64;
+    }
+
+    fn fetch_tile_data(&self) -> u32 {
+        (self.tile_data >> 32) as u32
+    }
+
+    fn background_pixel(&self) -> u8 {
+        if self.flag_show_background == 0 {
+            return 0;
+        }
+        let data = self.fetch_tile_data() >> ((7 - self.x) * 4);
+        (data & 0x0F) as u8
+    }
+
+    // Returns the index of the first opaque sprite at the current dot and
+    // its 4-bit palette entry
+    fn sprite_pixel(&self) -> (usize, u8) {
+        if self.flag_show_sprites == 0 {
+            return (0, 0);
+        }
+        for i in 0..self.sprite_count as usize {
+            let offset = (self.cycle - 1) - self.sprite_position[i] as i32;
+            if !(0..=7).contains(&offset) {
+                continue;
+            }
+            let offset = 7 - offset;
+            let color = ((self.sprite_patterns[i] >> (offset * 4)) & 0x0F) as u8;
+            if color & 3 == 0 {
+                continue;
+            }
+            return (i, color);
+        }
+        (0, 0)
+    }
+
+    fn render_pixel(&mut self) {
+        let x = self.cycle - 1;
+        let y = self.scanline;
+        let mut background = self.background_pixel();
+        let (i, mut sprite) = self.sprite_pixel();
+        // Clipping the left column hides pixels from the sprite-zero test
+        // too, so no hit happens there unless both columns show
+        if x < 8 && self.flag_show_left_background == 0 {
+            background = 0;
+        }
+        if x < 8 && self.flag_show_left_sprites == 0 {
+            sprite = 0;
+        }
+
+        let b = background & 3 != 0;
+        let s = sprite & 3 != 0;
+        let color = match (b, s) {
+            (false, false) => 0,
+            (false, true) => sprite | 0x10,
+            (true, false) => background,
+            (true, true) => {
+                // Sprite zero is the first entry evaluated. The flag rises a
+                // dot after the pixel is drawn, so on dot 2 at the earliest,
+                // and never for x = 255
+                if self.sprite_indexes[i] == 0 && x < 255 {
+                    self.sprite_zero_hit_pending = true;
+                }
+                if self.sprite_priorities[i] == 0 {
+                    sprite | 0x10
+                } else {
+                    background
+                }
+            }
+        };
+        let index = self.memory.read(0x3F00 + color as u16) & self.palette_mask();
+        let emphasis = self.flag_red_tint | self.flag_green_tint << 1 | self.flag_blue_tint << 2;
+        if x == 0 {
+            self.line_phases[y as usize] = self.dot_phase;
+        }
+        let pixel = index as u16 | (emphasis as u16) << 6;
+        self.back_pixels[(y * WIDTH as i32 + x) as usize] = pixel;
+        if self.draws_rgba() {
+            self.back
+                .put_pixel(x as u32, y as u32, self.palette.color(pixel));
+        }
+    }
+
+    // Draws the whole of the current line for the scanline renderer. The 34
+    // tiles the per-dot renderer fetches across this line and the end of
+    // the last are fetched here in the same order, so boards watching the
+    // fetches see no difference, and v is left as those fetches leave it.
+    fn render_scanline(&mut self) {
+        let y = self.scanline as usize;
+        // Background palette entries, with the fine X scroll's worth of
+        // pixels before the first shown
+        let mut background = [0u8; 34 * 8];
+        self.memory.set_fetch(Fetch::Background);
+        for tile in background.chunks_exact_mut(8) {
+            self.fetch_name_table_byte();
+            self.fetch_attribute_table_byte();
+            self.fetch_low_tile_byte();
+            self.fetch_high_tile_byte();
+            for pixel in tile {
+                let p1 = (self.low_tile_byte & 0x80) >> 7;
+                let p2 = (self.high_tile_byte & 0x80) >> 6;
+                self.low_tile_byte <<= 1;
+                self.high_tile_byte <<= 1;
+                *pixel = self.attr_table_byte | p1 | p2;
+            }
+            self.increment_x();
+        }
+        let background = &background[self.x as usize..][..WIDTH as usize];
+
+        // The first opaque sprite at each pixel, by palette entry and slot
+        let mut sprites = [(0u8, 0u8); WIDTH as usize];
+        for i in (0..self.sprite_count as usize).rev() {
+            let position = self.sprite_position[i] as usize;
+            for offset in 0..8 {
+                let color = ((self.sprite_patterns[i] >> ((7 - offset) * 4)) & 0x0F) as u8;
+                if color & 3 != 0 && position + offset < WIDTH as usize {
+                    sprites[position + offset] = (color, i as u8);
+                }
+            }
+        }
+
+        let mut palette = [0u8; 32];
+        for (index, entry) in palette.iter_mut().enumerate() {
+            *entry = self.memory.read(0x3F00 + index as u16) & self.palette_mask();
+        }
+        let emphasis = self.flag_red_tint | self.flag_green_tint << 1 | self.flag_blue_tint << 2;
+        let emphasis = (emphasis as u16) << 6;
+        self.line_phases[y] = self.dot_phase;
+
+        let row = y * WIDTH as usize;
+        let rgba = self.draws_rgba();
+        let pixels = &mut self.back_pixels[row..row + WIDTH as usize];
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            let left = x < 8;
+            let mut b = background[x];
+            if self.flag_show_background == 0 || (left && self.flag_show_left_background == 0) {
+                b = 0;
+            }
+            let (mut s, i) = sprites[x];
+            let i = i as usize;
+            if self.flag_show_sprites == 0 || (left && self.flag_show_left_sprites == 0) {
+                s = 0;
+            }
+            let entry = match (b & 3 != 0, s & 3 != 0) {
+                (false, false) => 0,
+                (false, true) => s | 0x10,
+                (true, false) => b,
+                (true, true) => {
+                    if self.sprite_indexes[i] == 0 && x < 255 && self.sprite_zero_hit_dot == 0 {
+                        self.sprite_zero_hit_dot = x as i32 + 1;
+                    }
+                    if self.sprite_priorities[i] == 0 {
+                        s | 0x10
+                    } else {
+                        b
+                    }
+                }
+            };
+            *pixel = palette[entry as usize] as u16 | emphasis;
+        }
+        if rgba {
+            let colors = &mut self.back.as_mut()[row * 4..(row + WIDTH as usize) * 4];
+            self.palette.convert(pixels, colors);
+        }
+    }
+
+    // Pattern row `row` of the OAM entry starting at byte `entry`
+    fn fetch_sprite_pattern(&mut self, entry: usize, mut row: i32) -> u32 {
+        let mut tile = self.oam_byte(entry + 1) as u16;
+        let attributes = self.oam_byte(entry + 2);
+        let table;
+        if self.flag_sprite_size == 0 {
+            if attributes & 0x80 == 0x80 {
+                row = 7 - row;
+            }
+            table = self.flag_sprite_table as u16;
+        } else {
+            // 8x16 sprites pick their table from bit 0 of the tile number
+            if attributes & 0x80 == 0x80 {
+                row = 15 - row;
+            }
+            table = tile & 1;
+            tile &= 0xFE;
+            if row > 7 {
+                tile += 1;
+                row -= 8;
+            }
+        }
+        let address = 0x1000 * table + tile * 16 + row as u16;
+
+        let a = (attributes & 3) << 2;
+        let mut low_tile_byte = self.memory.read(address);
+        let mut high_tile_byte = self.memory.read(address + 8);
+        let mut data: u32 = 0;
+        for _ in 0..8 {
+            let (p1, p2);
+            if attributes & 0x40 == 0x40 {
+                // flipped horizontally
+                p1 = low_tile_byte & 1;
+                p2 = (high_tile_byte & 1) << 1;
+                low_tile_byte >>= 1;
+                high_tile_byte >>= 1;
+            } else {
+                p1 = (low_tile_byte & 0x80) >> 7;
+                p2 = (high_tile_byte & 0x80) >> 6;
+                low_tile_byte <<= 1;
+                high_tile_byte <<= 1;
+            }
+            data <<= 4;
+            data |= (a | p1 | p2) as u32;
+        }
+        data
+    }
+
+    // Fills the sprite slots for the next line from OAM, keeping the first
+    // eight sprites in range. Past those the hardware goes on looking for a
+    // ninth to flag overflow, but with a bug: after each sprite out of range
+    // it steps to the next byte of the next entry rather than to its Y, so it
+    // compares tiles, attributes and X positions against the scanline. That
+    // misses real overflows and reports false ones, as games expect.
+    //
+    // Evaluation starts at OAMADDR, normally 0 by then. With accurate OAM a
+    // game leaving it elsewhere has entries read from there on, misaligned
+    // if need be, the first counting as sprite zero.
+    fn evaluate_sprites(&mut self) {
+        self.memory.set_fetch(Fetch::Sprites);
+        let h = if self.flag_sprite_size == 0 { 8 } else { 16 };
+        let in_range = |y: u8, scanline: i32| (0..h).contains(&(scanline - y as i32));
+        let start = if self.accurate_oam {
+            self.oam_addr as usize
+        } else {
+            0
+        };
+        let mut count = 0;
+        let mut n = 0;
+        while start + n * 4 < 256 && count < 8 {
+            let entry = start + n * 4;
+            let y = self.oam_data[entry];
+            if in_range(y, self.scanline) {
+                let row = self.scanline - y as i32;
+                let a = self.oam_byte(entry + 2);
+                self.sprite_patterns[count] = self.fetch_sprite_pattern(entry, row);
+                self.sprite_position[count] = self.oam_byte(entry + 3) as u32;
+                self.sprite_priorities[count] = ((a >> 5) & 1) as u32;
+                self.sprite_indexes[count] = n as u32;
+                count += 1;
+            }
+            n += 1;
+        }
+        self.sprite_count = count as i32;
+
+        let mut m = 0;
+        while start + n * 4 < 256 {
+            if in_range(self.oam_byte(start + n * 4 + m), self.scanline) {
+                self.flag_sprite_overflow = 1;
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+    }
+
+    // OAM byte `addr`, wrapping past the end
+    fn oam_byte(&self, addr: usize) -> u8 {
+        self.oam_data[addr % 256]
+    }
+
+    // While rendering, the PPU keeps all of OAM refreshed. Rows left alone
+    // since it last did decay first. When rendering starts with OAMADDR at 8
+    // or more, the row it points into is copied over the first, and OAMADDR
+    // is cleared while sprite patterns are fetched.
+    fn refresh_oam(&mut self, pre_line: bool, render_line: bool) {
+        if self.dots - self.oam_rendered > 1 {
+            for row in 0..32 {
+                self.decay_oam_row(row);
+            }
+        }
+        self.oam_rendered = self.dots;
+        if pre_line && self.cycle == 1 && self.oam_addr >= 8 {
+            let row = (self.oam_addr & 0xF8) as usize;
+            self.oam_data.copy_within(row..row + 8, 0);
+        }
+        if render_line && (257..=320).contains(&self.cycle) {
+            self.oam_addr = 0;
+        }
+    }
+
+    // A CPU access to OAM refreshes the row it touches
+    fn refresh_oam_row(&mut self, addr: u8) {
+        if !self.accurate_oam {
+            return;
+        }
+        let row = addr as usize / 8;
+        self.decay_oam_row(row);
+        self.oam_refreshed[row] = self.dots;
+    }
+
+    fn decay_oam_row(&mut self, row: usize) {
+        let refreshed = self.oam_refreshed[row].max(self.oam_rendered);
+        if self.dots - refreshed > OAM_DECAY_DOTS {
+            self.oam_data[row * 8..row * 8 + 8].fill(OAM_DECAYED);
+        }
+    }
+
+    // increment hori(v)
+    fn increment_x(&mut self) {
+        // if coarse X == 31
+        if self.v & 0x001F == 31 {
+            // coarse X = 0
+            self.v &= 0x7FE0;
+            // switch horizontal nametable
+            self.v ^= 0x0400;
+        } else {
+            // increment coarse X
             self.v += 1;
+        }
+    }
+
+    // increment vert(v)
+    fn increment_y(&mut self) {
+        // if fine Y < 7
+        if self.v & 0x7000 != 0x7000 {
+            // increment fine Y
+            self.v += 0x1000;
         } else {
-            self.v += 32;
+            // fine Y = 0
+            self.v &= 0x0FFF;
+            // let y = coarse Y
+            let mut y = (self.v & 0x03E0) >> 5;
+            if y == 29 {
+                // coarse Y = 0
+                y = 0;
+                // switch vertical nametable
+                self.v ^= 0x0800;
+            } else if y == 31 {
+                // coarse Y = 0, nametable not switched
+                y = 0;
+            } else {
+                // increment coarse Y
+                y += 1;
+            }
+            // put coarse Y back into v
+            self.v = (self.v & 0xFC1F) | (y << 5);
         }
     }
 
-    // $4014: OAMDMA
-    fn write_dma(&mut self, value: u8) {
-        // TODO: Implemet CPU
-        println!("{}", value);
+    // hori(v) = hori(t)
+    fn copy_x(&mut self) {
+        // v: .....F.. ...EDCBA = t: .....F.. ...EDCBA
+        self.v = (self.v & 0x7BE0) | (self.t & 0x041F);
     }
 
-    fn nmi_change(&mut self) {}
+    // vert(v) = vert(t)
+    fn copy_y(&mut self) {
+        // v: .IHGF.ED CBA..... = t: .IHGF.ED CBA.....
+        self.v = (self.v & 0x041F) | (self.t & 0x7BE0);
+    }
 }
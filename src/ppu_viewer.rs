@@ -0,0 +1,178 @@
+use image::{Rgba, RgbaImage};
+
+use crate::{console::Console, ppu::PPU};
+
+// Marks the scroll rectangle on the nametable view
+const SCROLL_COLOR: Rgba<u8> = Rgba([0xFF, 0x00, 0xFF, 0xFF]);
+
+// Images of what the PPU holds, for building debugger windows like FCEUX's
+// PPU, nametable and OAM viewers. Everything is read without disturbing
+// emulation; call between frames for a consistent picture. Colors come from
+// the console's palette, ignoring emphasis and the NTSC filter.
+
+// A decoded OAM entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sprite {
+    pub index: u8,
+    pub x: u8,
+    // Scanline of the sprite's top row; OAM holds one less
+    pub y: u16,
+    // Tile number as stored; 8x16 sprites take their table from bit 0
+    pub tile: u8,
+    // Sprite palette, 0-3
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+// 128x128 image of pattern table `table` (0 or 1), 16x16 tiles colored with
+// `palette` (0-3 background, 4-7 sprite)
+pub fn pattern_table(console: &mut Console, table: u8, palette: u8) -> RgbaImage {
+    let ppu = console.ppu_mut();
+    let colors = palette_colors(ppu, palette);
+    let mut image = RgbaImage::new(128, 128);
+    for tile in 0..256 {
+        let address = (table as u16 & 1) * 0x1000 + tile * 16;
+        let (x, y) = ((tile % 16) as u32 * 8, (tile / 16) as u32 * 8);
+        draw_tile(ppu, &mut image, address, x, y, &colors, (false, false));
+    }
+    image
+}
+
+// 512x480 image of the four nametables as laid out at $2000, $2400, $2800
+// and $2C00 under the current mirroring, with the screen the next frame
+// shows outlined when `show_scroll` is set
+pub fn name_tables(console: &mut Console, show_scroll: bool) -> RgbaImage {
+    let ppu = console.ppu_mut();
+    let pattern_base = ppu.background_table() as u16 * 0x1000;
+    let mut image = RgbaImage::new(512, 480);
+    for table in 0..4u16 {
+        let base = 0x2000 + table * 0x0400;
+        let (left, top) = ((table % 2) as u32 * 256, (table / 2) as u32 * 240);
+        for row in 0..30u16 {
+            for column in 0..32u16 {
+                let tile = ppu.peek(base + row * 32 + column) as u16;
+                let attribute = ppu.peek(base + 0x03C0 + row / 4 * 8 + column / 4);
+                let shift = (row & 2) << 1 | (column & 2);
+                let palette = attribute >> shift & 3;
+                let colors = palette_colors(ppu, palette);
+                let (x, y) = (left + column as u32 * 8, top + row as u32 * 8);
+                let address = pattern_base + tile * 16;
+                draw_tile(ppu, &mut image, address, x, y, &colors, (false, false));
+            }
+        }
+    }
+
+    if show_scroll {
+        // The screen wraps around the edges of the plane
+        let (x, y) = ppu.scroll();
+        for i in 0..256 {
+            image.put_pixel((x + i) % 512, y, SCROLL_COLOR);
+            image.put_pixel((x + i) % 512, (y + 239) % 480, SCROLL_COLOR);
+        }
+        for j in 0..240 {
+            image.put_pixel(x, (y + j) % 480, SCROLL_COLOR);
+            image.put_pixel((x + 255) % 512, (y + j) % 480, SCROLL_COLOR);
+        }
+    }
+    image
+}
+
+// The 64 OAM entries, in order
+pub fn sprites(console: &Console) -> Vec<Sprite> {
+    console
+        .ppu()
+        .oam()
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(index, entry)| Sprite {
+            index: index as u8,
+            x: entry[3],
+            y: entry[0] as u16 + 1,
+            tile: entry[1],
+            palette: entry[2] & 3,
+            behind_background: entry[2] & 0x20 != 0,
+            flip_horizontal: entry[2] & 0x40 != 0,
+            flip_vertical: entry[2] & 0x80 != 0,
+        })
+        .collect()
+}
+
+// One sprite as it is drawn, 8x8 or 8x16 with flips applied, color 0 left
+// transparent
+pub fn sprite_image(console: &mut Console, sprite: &Sprite) -> RgbaImage {
+    let ppu = console.ppu_mut();
+    let mut colors = palette_colors(ppu, 4 + sprite.palette);
+    colors[0] = Rgba([0, 0, 0, 0]);
+    let flip = (sprite.flip_horizontal, sprite.flip_vertical);
+
+    if !ppu.large_sprites() {
+        let address = ppu.sprite_table() as u16 * 0x1000 + sprite.tile as u16 * 16;
+        let mut image = RgbaImage::new(8, 8);
+        draw_tile(ppu, &mut image, address, 0, 0, &colors, flip);
+        return image;
+    }
+
+    // Flipping an 8x16 sprite vertically also swaps its two tiles
+    let address = (sprite.tile as u16 & 1) * 0x1000 + (sprite.tile as u16 & 0xFE) * 16;
+    let (top, bottom) = match sprite.flip_vertical {
+        false => (address, address + 16),
+        true => (address + 16, address),
+    };
+    let mut image = RgbaImage::new(8, 16);
+    draw_tile(ppu, &mut image, top, 0, 0, &colors, flip);
+    draw_tile(ppu, &mut image, bottom, 0, 8, &colors, flip);
+    image
+}
+
+// The 32 palette RAM entries as a 16x2 image, background palettes on the
+// top row and sprite palettes below, one pixel each
+pub fn palettes(console: &mut Console) -> RgbaImage {
+    let ppu = console.ppu_mut();
+    let mut image = RgbaImage::new(16, 2);
+    for entry in 0..32u16 {
+        let index = ppu.peek(0x3F00 + entry);
+        let color = ppu.color(index);
+        image.put_pixel(entry as u32 % 16, entry as u32 / 16, color);
+    }
+    image
+}
+
+// The four colors of palette `palette` (0-7), entry 0 being the shared
+// backdrop
+fn palette_colors(ppu: &mut PPU, palette: u8) -> [Rgba<u8>; 4] {
+    let base = 0x3F00 + (palette as u16 & 7) * 4;
+    let mut colors = [Rgba([0, 0, 0, 0]); 4];
+    for (i, color) in colors.iter_mut().enumerate() {
+        let entry = if i == 0 { 0x3F00 } else { base + i as u16 };
+        let index = ppu.peek(entry);
+        *color = ppu.color(index);
+    }
+    colors
+}
+
+// Draws the 8x8 tile at `address` with its top left at (x, y), flipped
+// (horizontally, vertically) as `flip` says
+fn draw_tile(
+    ppu: &mut PPU,
+    image: &mut RgbaImage,
+    address: u16,
+    x: u32,
+    y: u32,
+    colors: &[Rgba<u8>; 4],
+    (flip_horizontal, flip_vertical): (bool, bool),
+) {
+    for row in 0..8 {
+        let low = ppu.peek(address + row);
+        let high = ppu.peek(address + row + 8);
+        let py = if flip_vertical { 7 - row } else { row } as u32;
+        for column in 0..8 {
+            let bit = 7 - column;
+            let color = (low >> bit & 1) | (high >> bit & 1) << 1;
+            let px = if flip_horizontal { 7 - column } else { column } as u32;
+            let pixel = colors[color as usize];
+            image.put_pixel(x + px, y + py, pixel);
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt,
+};
+
+use crate::{memory::CPUMemory, symbols::Symbols};
+
+const JSR: u8 = 0x20;
+
+// Code cycles are counted against, by how it was entered and where
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Routine {
+    // Whatever runs outside any subroutine, like the main loop
+    TopLevel,
+    // A subroutine called with JSR, by its address
+    Call(u16),
+    // An interrupt handler, by its address. BRK enters the IRQ handler.
+    NMI(u16),
+    IRQ(u16),
+}
+
+impl Routine {
+    pub fn addr(self) -> Option<u16> {
+        match self {
+            Routine::TopLevel => None,
+            Routine::Call(addr) | Routine::NMI(addr) | Routine::IRQ(addr) => Some(addr),
+        }
+    }
+}
+
+// What a routine cost over some frames
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RoutineStats {
+    pub calls: u64,
+    // Cycles spent in the routine itself, and in it and everything it
+    // called. Recursion counts once towards the total, and interrupts that
+    // cut in count towards their handlers alone.
+    pub self_cycles: u64,
+    pub total_cycles: u64,
+    // The most total cycles it took in any one frame
+    pub peak_cycles: u64,
+}
+
+impl RoutineStats {
+    fn add(&mut self, other: &RoutineStats) {
+        self.calls += other.calls;
+        self.self_cycles += other.self_cycles;
+        self.total_cycles += other.total_cycles;
+        self.peak_cycles = self.peak_cycles.max(other.peak_cycles);
+    }
+}
+
+// A routine in progress, and how deep the stack was when it was entered
+struct Frame {
+    routine: Routine,
+    stack: u8,
+}
+
+// Attributes CPU cycles to the routines that spend them. Routines are
+// entered by JSR and interrupts and left when the stack rises above where it
+// was on entry, which covers RTS and RTI, and code that drops its return
+// address to leave early. Returns faked with RTS, as in jump tables, stay
+// in the routine that made them.
+//
+// A profiler works while set on the CPU with Console::start_profiling, and
+// takes stock of each frame as the PPU completes it.
+pub struct Profiler {
+    stack: Vec<Frame>,
+    symbols: Option<Symbols>,
+    names: HashMap<u16, String>,
+    // The CPU cycle count as the last instruction started, and where it
+    // calls if it's a JSR
+    last_cycles: Option<u64>,
+    call: Option<u16>,
+    // An interrupt taken since the last instruction
+    interrupt: Option<fn(u16) -> Routine>,
+    // The frame in progress, and every frame before it
+    frame: HashMap<Routine, RoutineStats>,
+    frame_cycles: u64,
+    last_frame: HashMap<Routine, RoutineStats>,
+    last_frame_cycles: u64,
+    totals: HashMap<Routine, RoutineStats>,
+    cycles: u64,
+    frames: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            stack: vec![Frame {
+                routine: Routine::TopLevel,
+                stack: 0,
+            }],
+            symbols: None,
+            names: HashMap::new(),
+            last_cycles: None,
+            call: None,
+            interrupt: None,
+            frame: HashMap::new(),
+            frame_cycles: 0,
+            last_frame: HashMap::new(),
+            last_frame_cycles: 0,
+            totals: HashMap::new(),
+            cycles: 0,
+            frames: 0,
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Names routines in reports from `symbols`
+    pub fn with_symbols(symbols: Symbols) -> Self {
+        Self {
+            symbols: Some(symbols),
+            ..Self::default()
+        }
+    }
+
+    // Called by the CPU as its state is loaded, which leaves nothing of the
+    // calls in progress or the cycle count they were followed by
+    pub(crate) fn lose_track(&mut self) {
+        self.stack.truncate(1);
+        self.last_cycles = None;
+        self.call = None;
+        self.interrupt = None;
+    }
+
+    // Called by the CPU as it services an interrupt
+    pub(crate) fn interrupt(&mut self, nmi: bool) {
+        self.interrupt = Some(if nmi { Routine::NMI } else { Routine::IRQ });
+    }
+
+    // Called by the CPU as it starts the instruction at `pc`, with the
+    // stack pointer and cycle count then. The cycles since the last one go
+    // to the routine that ran it.
+    pub(crate) fn instruction(&mut self, pc: u16, sp: u8, cycles: u64, memory: &CPUMemory) {
+        if let Some(last) = self.last_cycles {
+            self.attribute(cycles - last);
+        }
+        self.last_cycles = Some(cycles);
+
+        // Leave the routines whose return addresses have been pulled
+        while let [_, .., frame] = self.stack.as_slice() {
+            if sp.wrapping_sub(frame.stack) as i8 <= 0 {
+                break;
+            }
+            self.stack.pop();
+        }
+        let interrupt = self.interrupt.take();
+        if let Some(target) = self.call.take() {
+            // An interrupt taken right after the JSR pushed three bytes more
+            let stack = if interrupt.is_some() {
+                sp.wrapping_add(3)
+            } else {
+                sp
+            };
+            self.enter(Routine::Call(target), stack, memory);
+        }
+        if let Some(routine) = interrupt {
+            self.enter(routine(pc), sp, memory);
+        }
+        if memory.peek(pc) == JSR {
+            let lo = memory.peek(pc.wrapping_add(1)) as u16;
+            let hi = memory.peek(pc.wrapping_add(2)) as u16;
+            self.call = Some(hi << 8 | lo);
+        }
+    }
+
+    fn enter(&mut self, routine: Routine, stack: u8, memory: &CPUMemory) {
+        self.frame.entry(routine).or_default().calls += 1;
+        self.stack.push(Frame { routine, stack });
+        let (Some(symbols), Some(addr)) = (&self.symbols, routine.addr()) else {
+            return;
+        };
+        if let Entry::Vacant(entry) = self.names.entry(addr) {
+            if let Some(name) = symbols.name(addr, memory) {
+                entry.insert(name);
+            }
+        }
+    }
+
+    fn attribute(&mut self, cycles: u64) {
+        self.frame_cycles += cycles;
+        let current = self.stack.last().unwrap().routine;
+        self.frame.entry(current).or_default().self_cycles += cycles;
+        // Up to the innermost interrupt handler, as what it cut into didn't
+        // call it
+        for (i, frame) in self.stack.iter().enumerate().rev() {
+            let routine = frame.routine;
+            if !self.stack[i + 1..]
+                .iter()
+                .any(|inner| inner.routine == routine)
+            {
+                self.frame.entry(routine).or_default().total_cycles += cycles;
+            }
+            if matches!(routine, Routine::NMI(_) | Routine::IRQ(_)) {
+                break;
+            }
+        }
+    }
+
+    // Takes stock of the frame just completed
+    pub(crate) fn end_frame(&mut self) {
+        for stats in self.frame.values_mut() {
+            stats.peak_cycles = stats.total_cycles;
+        }
+        for (routine, stats) in &self.frame {
+            self.totals.entry(*routine).or_default().add(stats);
+        }
+        self.last_frame = std::mem::take(&mut self.frame);
+        self.last_frame_cycles = std::mem::take(&mut self.frame_cycles);
+        self.cycles += self.last_frame_cycles;
+        self.frames += 1;
+    }
+
+    // Clears what has been counted, as when the part of the game worth
+    // profiling starts
+    pub fn reset(&mut self) {
+        self.frame.clear();
+        self.frame_cycles = 0;
+        self.last_frame.clear();
+        self.last_frame_cycles = 0;
+        self.totals.clear();
+        self.cycles = 0;
+        self.frames = 0;
+    }
+
+    // Every complete frame since profiling started, hottest routines first
+    pub fn report(&self) -> Report {
+        self.build_report(&self.totals, self.cycles, self.frames)
+    }
+
+    // The last complete frame alone, for looking into a slow one
+    pub fn last_frame(&self) -> Report {
+        let frames = self.frames.min(1);
+        self.build_report(&self.last_frame, self.last_frame_cycles, frames)
+    }
+
+    fn build_report(
+        &self,
+        stats: &HashMap<Routine, RoutineStats>,
+        cycles: u64,
+        frames: u64,
+    ) -> Report {
+        let mut routines: Vec<RoutineReport> = stats
+            .iter()
+            .map(|(&routine, &stats)| RoutineReport {
+                routine,
+                name: routine
+                    .addr()
+                    .and_then(|addr| self.names.get(&addr).cloned()),
+                stats,
+            })
+            .collect();
+        routines.sort_by(|a, b| {
+            b.stats
+                .self_cycles
+                .cmp(&a.stats.self_cycles)
+                .then(a.routine.cmp(&b.routine))
+        });
+        Report {
+            frames,
+            cycles,
+            routines,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutineReport {
+    pub routine: Routine,
+    // Its label, if the profiler has symbols that name it
+    pub name: Option<String>,
+    pub stats: RoutineStats,
+}
+
+impl RoutineReport {
+    fn label(&self) -> String {
+        let addr = match (&self.name, self.routine.addr()) {
+            (Some(name), _) => name.clone(),
+            (None, Some(addr)) => format!("${:04X}", addr),
+            (None, None) => return "(top level)".to_string(),
+        };
+        match self.routine {
+            Routine::NMI(_) => format!("{} (NMI)", addr),
+            Routine::IRQ(_) => format!("{} (IRQ)", addr),
+            _ => addr,
+        }
+    }
+}
+
+// Where the cycles of some frames went, hottest routines first. Displays as
+// a table of per-frame averages:
+//
+// routine            calls/frame  self/frame   self%  total/frame  total%  peak
+// update_sprites            1.00        8512   28.6%        9120   30.6%   9244
+pub struct Report {
+    pub frames: u64,
+    pub cycles: u64,
+    pub routines: Vec<RoutineReport>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frames = self.frames.max(1) as f64;
+        let cycles = self.cycles.max(1) as f64;
+        writeln!(
+            f,
+            "{} frames, {:.0} cycles/frame",
+            self.frames,
+            self.cycles as f64 / frames
+        )?;
+        writeln!(
+            f,
+            "{:<24} {:>11} {:>11} {:>7} {:>12} {:>7} {:>8}",
+            "routine", "calls/frame", "self/frame", "self%", "total/frame", "total%", "peak"
+        )?;
+        for routine in &self.routines {
+            let stats = &routine.stats;
+            writeln!(
+                f,
+                "{:<24} {:>11.2} {:>11.0} {:>6.1}% {:>12.0} {:>6.1}% {:>8}",
+                routine.label(),
+                stats.calls as f64 / frames,
+                stats.self_cycles as f64 / frames,
+                stats.self_cycles as f64 * 100.0 / cycles,
+                stats.total_cycles as f64 / frames,
+                stats.total_cycles as f64 * 100.0 / cycles,
+                stats.peak_cycles
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use numpy::{PyArray1, PyArray3, PyArrayMethods};
+use pyo3::{
+    exceptions::{PyOSError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+use crate::{
+    cartridge::{Cartridge, CartridgeError},
+    console::Console,
+    controller::Button,
+    memory::Memory,
+};
+
+fn rom_error(err: CartridgeError) -> PyErr {
+    match err {
+        CartridgeError::Io(err) => PyOSError::new_err(err.to_string()),
+        err => PyValueError::new_err(err.to_string()),
+    }
+}
+
+// A console for Python, meant as a drop-in core for nes-py style
+// reinforcement learning setups:
+//
+//     import nesrs
+//     nes = nesrs.Console("smb.nes")
+//     nes.set_buttons(0, 1 << nesrs.BUTTON_RIGHT | 1 << nesrs.BUTTON_A)
+//     nes.step_frame(4)
+//     screen = nes.framebuffer()  # (240, 256, 3) uint8
+//     lives = nes.peek(0x075A)
+//
+// Arrays handed out are copies, so they stay valid across frames. Build the
+// extension with `maturin develop --release`.
+#[pyclass(name = "Console", unsendable)]
+pub struct PyConsole {
+    console: Console,
+}
+
+#[pymethods]
+impl PyConsole {
+    #[new]
+    fn new(path: PathBuf) -> PyResult<Self> {
+        let cartridge = Cartridge::from_path(path).map_err(rom_error)?;
+        Self::with_cartridge(cartridge)
+    }
+
+    // Loads an iNES or NES 2.0 image held in memory
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        let cartridge = Cartridge::from_bytes(data).map_err(rom_error)?;
+        Self::with_cartridge(cartridge)
+    }
+
+    // Frames emulated so far
+    #[getter]
+    fn frame(&self) -> u64 {
+        self.console.ppu().frame()
+    }
+
+    // Runs `frames` whole frames, holding the current input throughout
+    #[pyo3(signature = (frames = 1))]
+    fn step_frame(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.console.step_frame();
+        }
+    }
+
+    // The last frame as a (height, width, 3) RGB array
+    fn framebuffer<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let image = self.console.framebuffer();
+        let rgb: Vec<u8> = image
+            .pixels()
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect();
+        let shape = [image.height() as usize, image.width() as usize, 3];
+        PyArray1::from_vec(py, rgb).reshape(shape)
+    }
+
+    // The 2KB of internal RAM, where games keep most of their state
+    fn ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u8>> {
+        PyArray1::from_slice(py, &self.console.cpu.memory.ram)
+    }
+
+    // Reads the CPU bus without side effects, as a debugger would
+    fn peek(&self, addr: u16) -> u8 {
+        self.console.cpu.memory.peek(addr)
+    }
+
+    // Writes the CPU bus, as the CPU would
+    fn poke(&mut self, addr: u16, value: u8) {
+        self.console.cpu.memory.write(addr, value);
+    }
+
+    // Presses or releases `button` (one of the BUTTON_ constants) on joypad
+    // `player`
+    fn set_button(&mut self, player: usize, button: usize, pressed: bool) -> PyResult<()> {
+        let button = Self::button(button)?;
+        self.console
+            .set_button(Self::player(player)?, button, pressed);
+        Ok(())
+    }
+
+    // Sets every button on joypad `player` at once, bit n for BUTTON_ n
+    fn set_buttons(&mut self, player: usize, buttons: u8) -> PyResult<()> {
+        self.console.set_buttons(Self::player(player)?, buttons);
+        Ok(())
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.console.save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.console
+            .load_state(state)
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    // Presses the console's reset button
+    fn reset(&mut self) {
+        self.console.reset();
+    }
+}
+
+impl PyConsole {
+    fn with_cartridge(cartridge: Cartridge) -> PyResult<Self> {
+        let console = Console::new(cartridge).map_err(rom_error)?;
+        Ok(Self { console })
+    }
+
+    fn player(player: usize) -> PyResult<usize> {
+        match player {
+            0..=3 => Ok(player),
+            _ => Err(PyValueError::new_err("player must be 0-3")),
+        }
+    }
+
+    fn button(button: usize) -> PyResult<Button> {
+        Button::ALL
+            .get(button)
+            .copied()
+            .ok_or_else(|| PyValueError::new_err("button must be 0-7"))
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "nesrs")]
+fn nesrs_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyConsole>()?;
+    for (bit, button) in Button::ALL.iter().enumerate() {
+        let name = format!("BUTTON_{:?}", button).to_uppercase();
+        m.add(name.as_str(), bit)?;
+    }
+    Ok(())
+}
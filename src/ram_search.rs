@@ -0,0 +1,133 @@
+use crate::{
+    cheats::Cheat,
+    console::Console,
+    debugger::{Debugger, Space, WatchKind},
+};
+
+const RAM_SIZE: usize = 2048;
+
+// A test on one byte of RAM, against a value or against what the byte held
+// at the last snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal(u8),
+    NotEqual(u8),
+    Greater(u8),
+    Less(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    // Went up by this much, wrapping, so ChangedBy(-1) finds 3 -> 2 and
+    // 0 -> 255 alike
+    ChangedBy(i8),
+}
+
+impl Comparison {
+    fn matches(self, previous: u8, current: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => current == value,
+            Comparison::NotEqual(value) => current != value,
+            Comparison::Greater(value) => current > value,
+            Comparison::Less(value) => current < value,
+            Comparison::Changed => current != previous,
+            Comparison::Unchanged => current == previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+            Comparison::ChangedBy(delta) => current == previous.wrapping_add(delta as u8),
+        }
+    }
+}
+
+// Narrows down where a game keeps something in its 2KB of RAM, the way
+// emulator RAM search windows do: start with every address, then between
+// frames keep only those whose byte passes a comparison, e.g. Decreased
+// after losing a life and Unchanged after not.
+pub struct RamSearch {
+    previous: [u8; RAM_SIZE],
+    candidates: Vec<u16>,
+}
+
+impl RamSearch {
+    pub fn new(console: &Console) -> Self {
+        let mut search = Self {
+            previous: [0; RAM_SIZE],
+            candidates: Vec::new(),
+        };
+        search.reset(console);
+        search
+    }
+
+    // Starts over with every address a candidate
+    pub fn reset(&mut self, console: &Console) {
+        self.candidates = (0..RAM_SIZE as u16).collect();
+        self.snapshot(console);
+    }
+
+    // Takes the values comparisons against the last snapshot see, without
+    // filtering
+    pub fn snapshot(&mut self, console: &Console) {
+        self.previous = console.cpu.memory.ram;
+    }
+
+    // Drops the candidates failing `comparison`, then snapshots. Returns how
+    // many are left.
+    pub fn filter(&mut self, console: &Console, comparison: Comparison) -> usize {
+        let ram = &console.cpu.memory.ram;
+        self.candidates.retain(|&addr| {
+            let addr = addr as usize;
+            comparison.matches(self.previous[addr], ram[addr])
+        });
+        self.snapshot(console);
+        self.candidates.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    // Addresses still in the running, in order
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // Each candidate with its value at the last snapshot
+    pub fn results(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.candidates
+            .iter()
+            .map(|&addr| (addr, self.previous[addr as usize]))
+    }
+
+    // Adds a CPU watchpoint on each candidate, returning their ids
+    pub fn add_watchpoints(&self, debugger: &mut Debugger, kind: WatchKind) -> Vec<usize> {
+        self.candidates
+            .iter()
+            .map(|&addr| debugger.add_watchpoint(Space::CPU, addr..=addr, kind))
+            .collect()
+    }
+
+    // Freezes each candidate at `value` on `console`
+    pub fn freeze(&self, console: &mut Console, value: u8) {
+        for &addr in &self.candidates {
+            console
+                .freeze(addr, value)
+                .expect("candidates are all in RAM");
+        }
+    }
+
+    // Raw cheats pinning each candidate to `value`, ready for
+    // `Console::add_cheat` through their codes
+    pub fn cheats(&self, value: u8) -> Vec<Cheat> {
+        self.candidates
+            .iter()
+            .map(|&addr| {
+                Cheat::raw(&format!("{:04X}:{:02X}", addr, value))
+                    .expect("formatted cheats always parse")
+            })
+            .collect()
+    }
+}
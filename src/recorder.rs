@@ -0,0 +1,214 @@
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use image::RgbaImage;
+
+use crate::{region::Region, video::VideoConfig};
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(io::Error),
+    // ffmpeg couldn't be started, usually because it isn't installed
+    Spawn(io::Error),
+    // ffmpeg exited unsuccessfully, with what it printed
+    Ffmpeg(String),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::Io(err) => write!(f, "failed to record: {}", err),
+            RecordError::Spawn(err) => write!(f, "failed to run ffmpeg: {}", err),
+            RecordError::Ffmpeg(message) => write!(f, "ffmpeg failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<io::Error> for RecordError {
+    fn from(err: io::Error) -> Self {
+        RecordError::Io(err)
+    }
+}
+
+// How recordings are encoded. The output container follows the file
+// extension, e.g. .mp4 or .mkv.
+#[derive(Clone, Debug)]
+pub struct RecordConfig {
+    pub ffmpeg: PathBuf,
+    pub video_args: Vec<String>,
+    pub audio_args: Vec<String>,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+        Self {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            // yuv420p needs even dimensions, which cropping or aspect
+            // correction may not leave
+            video_args: args(&[
+                "-vf",
+                "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+                "-c:v",
+                "libx264",
+                "-pix_fmt",
+                "yuv420p",
+                "-crf",
+                "18",
+            ]),
+            audio_args: args(&["-c:a", "aac", "-b:a", "192k"]),
+        }
+    }
+}
+
+// Records gameplay through an external ffmpeg. Frames are piped to it raw
+// at the console's exact frame rate as they finish, while the audio goes
+// to a WAV file clocked by the APU. Both are timed by emulation alone, so
+// they stay in sync however fast it runs; `finish` muxes them into the
+// output file. The intermediate files sit beside it until then.
+pub struct Recorder {
+    config: RecordConfig,
+    path: PathBuf,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    // Framing fixed at the start, since the video size can't change
+    video: VideoConfig,
+    ffmpeg: Child,
+    stdin: Option<BufWriter<ChildStdin>>,
+    // The first error writing frames, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    pub fn start<P: AsRef<Path>>(
+        path: P,
+        region: Region,
+        video: VideoConfig,
+        config: RecordConfig,
+    ) -> Result<Self, RecordError> {
+        let path = path.as_ref().to_path_buf();
+        let video_path = sibling(&path, "video.mkv");
+        let audio_path = sibling(&path, "audio.wav");
+
+        let (numerator, denominator) = region.frame_rate_fraction();
+        let (width, height) = video.display_size(1);
+        let mut ffmpeg = ffmpeg_command(&config.ffmpeg)
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .arg("-s")
+            .arg(format!("{}x{}", width, height))
+            .arg("-r")
+            .arg(format!("{}/{}", numerator, denominator))
+            .args(["-i", "-"])
+            .args(&config.video_args)
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(RecordError::Spawn)?;
+        let stdin = ffmpeg.stdin.take().map(BufWriter::new);
+        Ok(Self {
+            config,
+            path,
+            video_path,
+            audio_path,
+            video,
+            ffmpeg,
+            stdin,
+            error: None,
+        })
+    }
+
+    // Where the audio should be captured to for the recording's length
+    pub fn audio_path(&self) -> &Path {
+        &self.audio_path
+    }
+
+    // Adds a finished frame, framed as the recording started
+    pub fn frame(&mut self, frame: &RgbaImage) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(stdin) = &mut self.stdin {
+            let image = self.video.frame(frame, 1);
+            if let Err(err) = stdin.write_all(image.as_raw()) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    // Ends the video, then muxes it with the captured audio, which must be
+    // finished by now, into the output file. The intermediate files are
+    // removed either way.
+    pub fn finish(mut self) -> Result<(), RecordError> {
+        let result = self.mux();
+        let _ = fs::remove_file(&self.video_path);
+        let _ = fs::remove_file(&self.audio_path);
+        result
+    }
+
+    fn mux(&mut self) -> Result<(), RecordError> {
+        let flushed = match self.stdin.take() {
+            Some(mut stdin) => stdin.flush(),
+            None => Ok(()),
+        };
+        wait(&mut self.ffmpeg)?;
+        if let Some(err) = self.error.take().or(flushed.err()) {
+            return Err(err.into());
+        }
+
+        let mut mux = ffmpeg_command(&self.config.ffmpeg)
+            .arg("-i")
+            .arg(&self.video_path)
+            .arg("-i")
+            .arg(&self.audio_path)
+            .args(["-c:v", "copy"])
+            .args(&self.config.audio_args)
+            .arg(&self.path)
+            .spawn()
+            .map_err(RecordError::Spawn)?;
+        wait(&mut mux)
+    }
+}
+
+// ffmpeg, quiet but for errors, overwriting its output, with stderr kept
+// for error messages
+fn ffmpeg_command(ffmpeg: &Path) -> Command {
+    let mut command = Command::new(ffmpeg);
+    command
+        .args(["-y", "-nostdin", "-nostats", "-loglevel", "error"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    command
+}
+
+fn wait(child: &mut Child) -> Result<(), RecordError> {
+    let mut message = String::new();
+    if let Some(mut stderr) = child.stderr.take() {
+        stderr.read_to_string(&mut message)?;
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        let message = message.trim();
+        Err(RecordError::Ffmpeg(if message.is_empty() {
+            status.to_string()
+        } else {
+            message.to_string()
+        }))
+    }
+}
+
+// name.mp4 -> name.mp4.video.mkv
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map_or_else(OsString::new, OsString::from);
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
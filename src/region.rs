@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+// TV system the console was built for. PAL and Dendy consoles run their
+// clocks from a 26.601712 MHz crystal instead of NTSC's 21.477272 MHz and
+// draw 312 scanlines per frame; the Dendy, a famiclone, keeps NTSC's 3 PPU
+// dots per CPU cycle and APU timing, but starts vblank 50 lines late.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Region {
+    #[default]
+    NTSC,
+    PAL,
+    Dendy,
+}
+
+impl Region {
+    // CPU clock in Hz: the master clock divided by 12, 16 and 15
+    pub fn cpu_frequency(self) -> u64 {
+        match self {
+            Region::NTSC => 1789773,
+            Region::PAL => 1662607,
+            Region::Dendy => 1773448,
+        }
+    }
+
+    // PPU dots per CPU cycle as a fraction, 3/1 or PAL's 16/5
+    pub fn ppu_clock_ratio(self) -> (u64, u64) {
+        match self {
+            Region::PAL => (16, 5),
+            Region::NTSC | Region::Dendy => (3, 1),
+        }
+    }
+
+    // Frames per second
+    pub fn frame_rate(self) -> f64 {
+        let (numerator, denominator) = self.frame_rate_fraction();
+        numerator as f64 / denominator as f64
+    }
+
+    // The exact frame rate as a fraction, for video containers. Frames
+    // average 341 dots a line less the half dot NTSC skips, so counts are
+    // doubled to keep them whole.
+    pub fn frame_rate_fraction(self) -> (u64, u64) {
+        let (dots, cycles) = self.ppu_clock_ratio();
+        let mut frame_half_dots = self.scanlines() as u64 * 682;
+        if self.skips_odd_dot() {
+            frame_half_dots -= 1;
+        }
+        (self.cpu_frequency() * dots * 2, cycles * frame_half_dots)
+    }
+
+    // Scanlines per frame, including the pre-render line
+    pub fn scanlines(self) -> i32 {
+        match self {
+            Region::NTSC => 262,
+            Region::PAL | Region::Dendy => 312,
+        }
+    }
+
+    // Scanline on whose second dot vblank starts
+    pub fn vblank_scanline(self) -> i32 {
+        match self {
+            Region::NTSC | Region::PAL => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // Only NTSC PPUs shorten odd frames by a dot while rendering
+    pub fn skips_odd_dot(self) -> bool {
+        self == Region::NTSC
+    }
+
+    // CPU cycles from an APU frame counter reset to each of its steps, about
+    // 240Hz on NTSC and 200Hz on PAL. The 4-step sequence ends at the fourth
+    // and the 5-step sequence at the fifth. The Dendy counts NTSC's number of
+    // cycles at its own clock.
+    pub fn frame_counter_steps(self) -> [u64; 5] {
+        match self {
+            Region::NTSC | Region::Dendy => [7457, 14913, 22371, 29829, 37281],
+            Region::PAL => [8313, 16627, 24939, 33253, 41565],
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+// Save states taken at regular frame intervals, kept as one full state (the
+// newest) plus, for every older one, a delta that turns its successor back
+// into it. Consecutive states differ in a few hundred bytes, so the deltas
+// are XORs with the runs of unchanged (zero) bytes collapsed.
+pub struct RewindBuffer {
+    interval: u64,
+    capacity: usize,
+    newest: Option<(u64, Vec<u8>)>,
+    // (frame, delta to the state of that frame), oldest first
+    deltas: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl RewindBuffer {
+    // Snapshots every `interval` frames, keeping up to `capacity` of them
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    // Whether a snapshot should be taken at `frame`
+    pub fn due(&self, frame: u64) -> bool {
+        frame.is_multiple_of(self.interval)
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    // Bytes held, for judging how much history fits in memory
+    pub fn size(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, |(_, state)| state.len());
+        newest
+            + self
+                .deltas
+                .iter()
+                .map(|(_, delta)| delta.len())
+                .sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((previous_frame, previous)) = self.newest.take() {
+            self.deltas
+                .push_back((previous_frame, encode_delta(&state, &previous)));
+        }
+        self.newest = Some((frame, state));
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    // Drops snapshots newer than `frame` and returns the newest remaining
+    // one, or the oldest held if none is that old
+    pub fn rewind_to(&mut self, frame: u64) -> Option<(u64, &[u8])> {
+        let (mut newest_frame, mut newest) = self.newest.take()?;
+        while newest_frame > frame {
+            match self.deltas.pop_back() {
+                Some((previous_frame, delta)) => {
+                    newest = apply_delta(&newest, &delta);
+                    newest_frame = previous_frame;
+                }
+                None => break,
+            }
+        }
+        let (frame, state) = self.newest.insert((newest_frame, newest));
+        Some((*frame, state))
+    }
+}
+
+// Delta from `base` to `target`: the target's length, then pairs of
+// (unchanged byte count, changed byte count) as LEB128 varints, each
+// followed by the changed bytes XORed with the base
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let byte = |data: &[u8], i: usize| data.get(i).copied().unwrap_or(0);
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len());
+
+    let mut i = 0;
+    while i < target.len() {
+        let start = i;
+        while i < target.len() && byte(base, i) == target[i] {
+            i += 1;
+        }
+        if i == target.len() {
+            break;
+        }
+        let skip = i - start;
+        let start = i;
+        // Short runs of unchanged bytes are cheaper left in the literal
+        while i < target.len()
+            && (byte(base, i) != target[i]
+                || (i + 1 < target.len() && byte(base, i + 1) != target[i + 1]))
+        {
+            i += 1;
+        }
+        write_varint(&mut delta, skip);
+        write_varint(&mut delta, i - start);
+        delta.extend((start..i).map(|j| byte(base, j) ^ target[j]));
+    }
+    delta
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut delta = delta;
+    let len = read_varint(&mut delta);
+    let mut target: Vec<u8> = (0..len)
+        .map(|i| base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut i = 0;
+    while !delta.is_empty() {
+        i += read_varint(&mut delta);
+        let count = read_varint(&mut delta);
+        for (out, xor) in target[i..i + count].iter_mut().zip(&delta[..count]) {
+            *out ^= xor;
+        }
+        delta = &delta[count..];
+        i += count;
+    }
+    target
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some((&byte, rest)) = data.split_first() {
+        *data = rest;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
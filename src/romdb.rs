@@ -0,0 +1,262 @@
+use std::{collections::HashMap, fmt, fs, io, path::Path, sync::OnceLock};
+
+use crate::{cartridge::Mirroring, region::Region};
+
+#[derive(Debug)]
+pub enum RomDbError {
+    Io(io::Error),
+    // A line that doesn't follow the format, numbered from 1
+    Invalid { line: usize, reason: &'static str },
+}
+
+impl fmt::Display for RomDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomDbError::Io(err) => write!(f, "failed to read ROM database: {}", err),
+            RomDbError::Invalid { line, reason } => {
+                write!(f, "ROM database line {}: {}", line, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomDbError {}
+
+impl From<io::Error> for RomDbError {
+    fn from(err: io::Error) -> Self {
+        RomDbError::Io(err)
+    }
+}
+
+// Checksums identifying a ROM by its contents alone, whatever its header
+// says. CHR RAM isn't part of the ROM, so boards with it hash PRG alone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hashes {
+    pub prg_crc32: u32,
+    pub chr_crc32: u32,
+    // PRG then CHR, as NesCartDB lists games
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+impl Hashes {
+    pub fn new(prg: &[u8], chr: &[u8]) -> Self {
+        let mut rom = Vec::with_capacity(prg.len() + chr.len());
+        rom.extend_from_slice(prg);
+        rom.extend_from_slice(chr);
+        Self {
+            prg_crc32: crc32fast::hash(prg),
+            chr_crc32: crc32fast::hash(chr),
+            crc32: crc32fast::hash(&rom),
+            sha1: sha1(&rom),
+        }
+    }
+
+    pub fn sha1_hex(&self) -> String {
+        self.sha1
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+// What the database knows about a dump: the board it really is, which may
+// differ from what its header claims, and where it came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameInfo {
+    pub title: String,
+    pub region: Region,
+    pub mapper: u16,
+    pub submapper: u8,
+    // None where the board switches mirroring itself
+    pub mirroring: Option<Mirroring>,
+    pub battery: bool,
+    // Dumps known to be corrupt or modified, which may not run right
+    pub bad_dump: bool,
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+}
+
+// Games by the checksum of their ROM, in the format of romdb.txt
+#[derive(Clone, Debug, Default)]
+pub struct RomDatabase {
+    games: HashMap<u32, Vec<GameInfo>>,
+}
+
+impl RomDatabase {
+    // The database built into the library, parsed on first use
+    pub fn embedded() -> &'static RomDatabase {
+        static EMBEDDED: OnceLock<RomDatabase> = OnceLock::new();
+        EMBEDDED.get_or_init(|| {
+            RomDatabase::parse(include_str!("romdb.txt")).expect("embedded ROM database is valid")
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, RomDbError> {
+        let mut database = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let game = parse_line(line).map_err(|reason| RomDbError::Invalid {
+                line: index + 1,
+                reason,
+            })?;
+            database.insert(game);
+        }
+        Ok(database)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, RomDbError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // Adds a game, replacing any entry with the same checksums
+    pub fn insert(&mut self, game: GameInfo) {
+        let games = self.games.entry(game.crc32).or_default();
+        games.retain(|other| other.sha1 != game.sha1);
+        games.push(game);
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    // The game with these checksums. Entries listing a SHA-1 must match it
+    // too, and are preferred over those matching by CRC alone.
+    pub fn lookup(&self, hashes: &Hashes) -> Option<&GameInfo> {
+        let games = self.games.get(&hashes.crc32)?;
+        games
+            .iter()
+            .find(|game| game.sha1 == Some(hashes.sha1))
+            .or_else(|| games.iter().find(|game| game.sha1.is_none()))
+    }
+}
+
+fn parse_line(line: &str) -> Result<GameInfo, &'static str> {
+    // Six words, then the title
+    let mut rest = line;
+    let mut field = |missing| {
+        let trimmed = rest.trim_start();
+        let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        let (word, after) = trimmed.split_at(end);
+        rest = after;
+        Some(word).filter(|word| !word.is_empty()).ok_or(missing)
+    };
+    let crc32 = u32::from_str_radix(field("missing CRC32")?, 16).map_err(|_| "invalid CRC32")?;
+    let sha1 = match field("missing SHA-1")? {
+        "-" => None,
+        hex => Some(parse_sha1(hex).ok_or("invalid SHA-1")?),
+    };
+    let mapper = field("missing mapper")?;
+    let (mapper, submapper) = match mapper.split_once('.') {
+        Some((mapper, submapper)) => (mapper, submapper.parse().map_err(|_| "invalid submapper")?),
+        None => (mapper, 0),
+    };
+    let mapper = mapper.parse().map_err(|_| "invalid mapper")?;
+    let mirroring = match field("missing mirroring")? {
+        "H" => Some(Mirroring::Horizontal),
+        "V" => Some(Mirroring::Vertical),
+        "4" => Some(Mirroring::FourScreen),
+        "-" => None,
+        _ => return Err("mirroring must be H, V, 4 or -"),
+    };
+    let (mut battery, mut bad_dump) = (false, false);
+    for flag in field("missing flags")?.split(',') {
+        match flag {
+            "battery" => battery = true,
+            "bad" => bad_dump = true,
+            "-" => {}
+            _ => return Err("unknown flag"),
+        }
+    }
+    let region = match field("missing region")? {
+        "NTSC" => Region::NTSC,
+        "PAL" => Region::PAL,
+        "Dendy" => Region::Dendy,
+        _ => return Err("region must be NTSC, PAL or Dendy"),
+    };
+    let title = rest.trim().to_string();
+    if title.is_empty() {
+        return Err("missing title");
+    }
+    Ok(GameInfo {
+        title,
+        region,
+        mapper,
+        submapper,
+        mirroring,
+        battery,
+        bad_dump,
+        crc32,
+        sha1,
+    })
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut sha1 = [0; 20];
+    for (byte, pair) in sha1.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(sha1)
+}
+
+// SHA-1 (FIPS 180-4), as ROM databases list it
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // Padded with a 1 bit, zeros, then the length in bits, to whole blocks
+    let mut message = data.to_vec();
+    message.push(0x80);
+    message.resize((data.len() + 8) / 64 * 64 + 64, 0);
+    let length = message.len();
+    message[length - 8..].copy_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.into_iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+const STATE_MAGIC: [u8; 4] = *b"NESS";
+// Bump whenever any component changes what its `save` writes; states from
+// other versions are refused rather than misread
+pub const STATE_VERSION: u32 = 17;
+
+#[derive(Debug)]
+pub enum StateError {
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    WrongCartridge,
+    // Made with other devices in the controller or expansion ports
+    WrongDevices,
+    SizeMismatch { expected: usize, actual: usize },
+    Corrupt(bincode::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::InvalidMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported (expected {})",
+                version, STATE_VERSION
+            ),
+            StateError::WrongCartridge => {
+                write!(f, "save state was made with a different cartridge")
+            }
+            StateError::WrongDevices => {
+                write!(f, "save state was made with different devices plugged in")
+            }
+            StateError::SizeMismatch { expected, actual } => write!(
+                f,
+                "save state holds {} bytes where {} were expected",
+                actual, expected
+            ),
+            StateError::Corrupt(err) => write!(f, "save state is corrupt: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<bincode::Error> for StateError {
+    fn from(err: bincode::Error) -> Self {
+        StateError::Corrupt(err)
+    }
+}
+
+// Fixed-width integers keep every field at the same offset from one state
+// to the next, which lets rewind store them as small XOR deltas
+fn options() -> impl Options {
+    bincode::DefaultOptions::new().with_fixint_encoding()
+}
+
+// Components write their fields in a fixed order with `write` and read them
+// back in the same order with `StateReader::read`
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = STATE_MAGIC.to_vec();
+        data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        Self { data }
+    }
+
+    pub fn write<T: Serialize + ?Sized>(&mut self, value: &T) {
+        options()
+            .serialize_into(&mut self.data, value)
+            .expect("serializing into memory cannot fail");
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    // Checks the header of a state produced by `StateWriter`
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        if data.len() < 8 || data[0..4] != STATE_MAGIC {
+            return Err(StateError::InvalidMagic);
+        }
+        let version = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(Self { data: &data[8..] })
+    }
+
+    pub fn read<T: DeserializeOwned>(&mut self) -> Result<T, StateError> {
+        Ok(options().deserialize_from(&mut self.data)?)
+    }
+
+    // Reads a slice written with `write` back into a buffer of the same size
+    pub fn read_into(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        let bytes: Vec<u8> = self.read()?;
+        if bytes.len() != out.len() {
+            return Err(StateError::SizeMismatch {
+                expected: out.len(),
+                actual: bytes.len(),
+            });
+        }
+        out.copy_from_slice(&bytes);
+        Ok(())
+    }
+}
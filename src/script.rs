@@ -0,0 +1,350 @@
+use std::{cell::RefCell, collections::HashMap, fmt, fs, io, path::Path, rc::Rc};
+
+use image::{Rgba, RgbaImage};
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST, INT};
+
+use crate::{console::Console, memory::Memory, savestate::StateError};
+
+// Functions a script may define, called around every frame
+const FRAME_START: &str = "on_frame_start";
+const FRAME_END: &str = "on_frame_end";
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Parse(String),
+    Runtime(String),
+    State(StateError),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Io(err) => write!(f, "failed to read script: {}", err),
+            ScriptError::Parse(message) => write!(f, "script: {}", message),
+            ScriptError::Runtime(message) => write!(f, "script: {}", message),
+            ScriptError::State(err) => write!(f, "script savestate: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl From<io::Error> for ScriptError {
+    fn from(err: io::Error) -> Self {
+        ScriptError::Io(err)
+    }
+}
+
+impl From<StateError> for ScriptError {
+    fn from(err: StateError) -> Self {
+        ScriptError::State(err)
+    }
+}
+
+// Changes a callback asks for, made to the console in order once it returns
+enum Command {
+    Write(u16, u8),
+    SaveState(INT),
+    LoadState(INT),
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    Pixel(i64, i64),
+    Line(i64, i64, i64, i64),
+    Rect(i64, i64, i64, i64),
+    FillRect(i64, i64, i64, i64),
+}
+
+// What a script sees during a callback. Scripts don't hold the console, so
+// the CPU's view of memory is copied in beforehand and writes are queued,
+// showing up in the copy straight away.
+struct Context {
+    frame: u64,
+    memory: Vec<u8>,
+    buttons: [u8; 4],
+    // Input the script has set for the coming frame, by joypad
+    overrides: [Option<u8>; 4],
+    commands: Vec<Command>,
+    shapes: Vec<(Shape, Rgba<u8>)>,
+}
+
+impl Context {
+    fn new() -> Self {
+        Self {
+            frame: 0,
+            memory: vec![0; 0x10000],
+            buttons: [0; 4],
+            overrides: [None; 4],
+            commands: Vec::new(),
+            shapes: Vec::new(),
+        }
+    }
+
+    fn read(&self, addr: INT) -> INT {
+        self.memory[addr as u16 as usize] as INT
+    }
+}
+
+// A Rhai script run alongside the console, FCEUX style. Top-level code runs
+// once on load; `on_frame_start()` and `on_frame_end()`, if defined, run
+// before and after each frame. Rhai functions can't see top-level variables,
+// so callbacks keep anything they need between frames in `this`, an object
+// map that lasts as long as the script. Scripts can call:
+//
+//   frame()                          frames emulated so far
+//   read(addr), read16(addr)         CPU bus, as a debugger peeks it
+//   write(addr, value)               CPU bus
+//   buttons(player)                  buttons held, bit n for Button n
+//   set_buttons(player, bits)        input for the coming frame
+//   pixel(x, y, color)               drawing over the frame, colors 0xRRGGBB
+//   line(x1, y1, x2, y2, color)
+//   rect(x, y, w, h, color)
+//   fill_rect(x, y, w, h, color)
+//   save_state(slot), load_state(slot)
+//
+// Drawing is kept from the end of one frame until the start of the next;
+// `draw` paints it over a copy of the framebuffer.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+    context: Rc<RefCell<Context>>,
+    states: HashMap<INT, Vec<u8>>,
+}
+
+impl Script {
+    pub fn from_path(path: impl AsRef<Path>, console: &mut Console) -> Result<Self, ScriptError> {
+        let source = fs::read_to_string(path)?;
+        Self::new(&source, console)
+    }
+
+    // Compiles `source` and runs its top-level code
+    pub fn new(source: &str, console: &mut Console) -> Result<Self, ScriptError> {
+        let context = Rc::new(RefCell::new(Context::new()));
+        let engine = Self::engine(&context);
+        let ast = engine
+            .compile(source)
+            .map_err(|err| ScriptError::Parse(err.to_string()))?;
+
+        let mut script = Self {
+            engine,
+            ast,
+            scope: Scope::new(),
+            this: Dynamic::from_map(Map::new()),
+            context,
+            states: HashMap::new(),
+        };
+        script.begin(console);
+        let result = script
+            .engine
+            .run_ast_with_scope(&mut script.scope, &script.ast);
+        result.map_err(|err| ScriptError::Runtime(err.to_string()))?;
+        script.finish(console)?;
+        Ok(script)
+    }
+
+    fn engine(context: &Rc<RefCell<Context>>) -> Engine {
+        let mut engine = Engine::new();
+
+        let ctx = context.clone();
+        engine.register_fn("frame", move || ctx.borrow().frame as INT);
+        let ctx = context.clone();
+        engine.register_fn("read", move |addr: INT| ctx.borrow().read(addr));
+        let ctx = context.clone();
+        engine.register_fn("read16", move |addr: INT| {
+            let ctx = ctx.borrow();
+            ctx.read(addr) | ctx.read(addr + 1) << 8
+        });
+        let ctx = context.clone();
+        engine.register_fn("write", move |addr: INT, value: INT| {
+            let mut ctx = ctx.borrow_mut();
+            let (addr, value) = (addr as u16, value as u8);
+            ctx.memory[addr as usize] = value;
+            ctx.commands.push(Command::Write(addr, value));
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("buttons", move |player: INT| {
+            let ctx = ctx.borrow();
+            let player = player as usize % 4;
+            ctx.overrides[player].unwrap_or(ctx.buttons[player]) as INT
+        });
+        let ctx = context.clone();
+        engine.register_fn("set_buttons", move |player: INT, buttons: INT| {
+            ctx.borrow_mut().overrides[player as usize % 4] = Some(buttons as u8);
+        });
+
+        let ctx = context.clone();
+        engine.register_fn("pixel", move |x: INT, y: INT, color: INT| {
+            ctx.borrow_mut()
+                .shapes
+                .push((Shape::Pixel(x, y), rgba(color)));
+        });
+        let ctx = context.clone();
+        engine.register_fn(
+            "line",
+            move |x1: INT, y1: INT, x2: INT, y2: INT, color: INT| {
+                let shape = Shape::Line(x1, y1, x2, y2);
+                ctx.borrow_mut().shapes.push((shape, rgba(color)));
+            },
+        );
+        let ctx = context.clone();
+        engine.register_fn("rect", move |x: INT, y: INT, w: INT, h: INT, color: INT| {
+            ctx.borrow_mut()
+                .shapes
+                .push((Shape::Rect(x, y, w, h), rgba(color)));
+        });
+        let ctx = context.clone();
+        engine.register_fn(
+            "fill_rect",
+            move |x: INT, y: INT, w: INT, h: INT, color: INT| {
+                let shape = Shape::FillRect(x, y, w, h);
+                ctx.borrow_mut().shapes.push((shape, rgba(color)));
+            },
+        );
+
+        let ctx = context.clone();
+        engine.register_fn("save_state", move |slot: INT| {
+            ctx.borrow_mut().commands.push(Command::SaveState(slot));
+        });
+        let ctx = context.clone();
+        engine.register_fn("load_state", move |slot: INT| {
+            ctx.borrow_mut().commands.push(Command::LoadState(slot));
+        });
+
+        engine
+    }
+
+    // Runs `on_frame_start()` and applies its input. Drawing from the last
+    // frame is cleared first.
+    pub fn frame_start(&mut self, console: &mut Console) -> Result<(), ScriptError> {
+        self.context.borrow_mut().shapes.clear();
+        self.callback(console, FRAME_START)
+    }
+
+    // Runs `on_frame_end()`, which sees the frame just finished
+    pub fn frame_end(&mut self, console: &mut Console) -> Result<(), ScriptError> {
+        self.callback(console, FRAME_END)
+    }
+
+    // Paints the script's drawing onto `image`, normally a copy of the
+    // console's framebuffer
+    pub fn draw(&self, image: &mut RgbaImage) {
+        for &(shape, color) in &self.context.borrow().shapes {
+            match shape {
+                Shape::Pixel(x, y) => put_pixel(image, x, y, color),
+                Shape::Line(x1, y1, x2, y2) => {
+                    // Bresenham's, covering every octant
+                    let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+                    let (sx, sy) = ((x2 - x1).signum(), (y2 - y1).signum());
+                    let (mut x, mut y, mut err) = (x1, y1, dx + dy);
+                    loop {
+                        put_pixel(image, x, y, color);
+                        if x == x2 && y == y2 {
+                            break;
+                        }
+                        let e2 = 2 * err;
+                        if e2 >= dy {
+                            err += dy;
+                            x += sx;
+                        }
+                        if e2 <= dx {
+                            err += dx;
+                            y += sy;
+                        }
+                    }
+                }
+                Shape::Rect(x, y, w, h) => {
+                    for i in x..x + w {
+                        put_pixel(image, i, y, color);
+                        put_pixel(image, i, y + h - 1, color);
+                    }
+                    for j in y..y + h {
+                        put_pixel(image, x, j, color);
+                        put_pixel(image, x + w - 1, j, color);
+                    }
+                }
+                Shape::FillRect(x, y, w, h) => {
+                    for j in y..y + h {
+                        for i in x..x + w {
+                            put_pixel(image, i, j, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn callback(&mut self, console: &mut Console, name: &str) -> Result<(), ScriptError> {
+        if !self.ast.iter_functions().any(|f| f.name == name) {
+            return Ok(());
+        }
+        self.begin(console);
+        // The top-level code already ran on load
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            name,
+            (),
+        );
+        result
+            .map(drop)
+            .map_err(|err| ScriptError::Runtime(err.to_string()))?;
+        self.finish(console)
+    }
+
+    // Copies what the script can see of the console into the context
+    fn begin(&mut self, console: &Console) {
+        let mut ctx = self.context.borrow_mut();
+        ctx.frame = console.ppu().frame();
+        let memory = &console.cpu.memory;
+        for (addr, byte) in ctx.memory.iter_mut().enumerate() {
+            *byte = memory.peek(addr as u16);
+        }
+        for (player, buttons) in ctx.buttons.iter_mut().enumerate() {
+            *buttons = memory.controllers[player].buttons();
+        }
+    }
+
+    // Makes the changes the script queued
+    fn finish(&mut self, console: &mut Console) -> Result<(), ScriptError> {
+        let mut ctx = self.context.borrow_mut();
+        for command in ctx.commands.drain(..) {
+            match command {
+                Command::Write(addr, value) => console.cpu.memory.write(addr, value),
+                Command::SaveState(slot) => {
+                    self.states.insert(slot, console.save_state());
+                }
+                Command::LoadState(slot) => {
+                    if let Some(state) = self.states.get(&slot) {
+                        console.load_state(state)?;
+                    }
+                }
+            }
+        }
+        for (player, buttons) in ctx.overrides.iter_mut().enumerate() {
+            if let Some(buttons) = buttons.take() {
+                console.set_buttons(player, buttons);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn rgba(color: INT) -> Rgba<u8> {
+    let [_, r, g, b] = (color as u32).to_be_bytes();
+    Rgba([r, g, b, 0xFF])
+}
+
+fn put_pixel(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>) {
+    if (0..image.width() as i64).contains(&x) && (0..image.height() as i64).contains(&y) {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
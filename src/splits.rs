@@ -0,0 +1,231 @@
+use std::{
+    fmt, fs, io,
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    console::Console,
+    events::{Event, EventFilter, SubscriptionId},
+};
+
+#[derive(Debug)]
+pub enum SplitError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    // A watch that can't be followed, by its name
+    InvalidWatch(String),
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitError::Io(err) => write!(f, "failed to read splits: {}", err),
+            SplitError::Parse(err) => write!(f, "invalid splits: {}", err),
+            SplitError::InvalidWatch(name) => {
+                write!(
+                    f,
+                    "watch {:?} must read 1 to 4 bytes within $0000-$FFFF",
+                    name
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+impl From<io::Error> for SplitError {
+    fn from(err: io::Error) -> Self {
+        SplitError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for SplitError {
+    fn from(err: toml::de::Error) -> Self {
+        SplitError::Parse(err)
+    }
+}
+
+// When a watched value sets its event off. Each fires once as it comes
+// true, on the write that makes it so, not on every write while it holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    // Any new value
+    Changes,
+    ChangesTo(u32),
+    ChangesFrom(u32),
+    Increases,
+    Decreases,
+    Above(u32),
+    Below(u32),
+}
+
+impl Condition {
+    // Whether going from `old` to `new` sets the event off. Nothing is
+    // known about a value before its first write.
+    fn fires(self, old: Option<u32>, new: u32) -> bool {
+        if old == Some(new) {
+            return false;
+        }
+        match self {
+            Condition::Changes => true,
+            Condition::ChangesTo(value) => new == value,
+            Condition::ChangesFrom(value) => old == Some(value),
+            Condition::Increases => old.is_some_and(|old| new > old),
+            Condition::Decreases => old.is_some_and(|old| new < old),
+            Condition::Above(value) => new > value && old.is_none_or(|old| old <= value),
+            Condition::Below(value) => new < value && old.is_none_or(|old| old >= value),
+        }
+    }
+}
+
+// What a speedrun timer should do when a watch fires
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitAction {
+    Start,
+    #[default]
+    Split,
+    Reset,
+}
+
+impl SplitAction {
+    // The LiveSplit Server command for it
+    pub fn livesplit_command(self) -> &'static str {
+        match self {
+            SplitAction::Start => "starttimer",
+            SplitAction::Split => "split",
+            SplitAction::Reset => "reset",
+        }
+    }
+}
+
+// A value in CPU memory and the condition on it that names an event, e.g.
+// the level number changing to 2:
+//
+//     [[watch]]
+//     name = "World 1-2"
+//     address = 0x075C
+//     condition = { changes_to = 1 }
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watch {
+    pub name: String,
+    // Where the game writes the value, $0000-$07FF for RAM rather than its
+    // mirrors
+    pub address: u16,
+    // 1 to 4 bytes from `address`, little-endian
+    #[serde(default = "default_bytes")]
+    pub bytes: u8,
+    pub condition: Condition,
+    #[serde(default)]
+    pub action: SplitAction,
+}
+
+fn default_bytes() -> u8 {
+    1
+}
+
+// A game's watches, as loaded from a TOML file of [[watch]] tables
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Splits {
+    #[serde(default, rename = "watch")]
+    pub watches: Vec<Watch>,
+}
+
+// A watch going off
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SplitEvent {
+    pub name: String,
+    pub action: SplitAction,
+}
+
+impl Splits {
+    pub fn parse(text: &str) -> Result<Self, SplitError> {
+        let splits: Self = toml::from_str(text)?;
+        for watch in &splits.watches {
+            if !(1..=4).contains(&watch.bytes)
+                || watch.address.checked_add(watch.bytes as u16 - 1).is_none()
+            {
+                return Err(SplitError::InvalidWatch(watch.name.clone()));
+            }
+        }
+        Ok(splits)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SplitError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // Subscribes each watch to writes to its bytes. The events come out of
+    // the returned watcher's channel as the console runs.
+    pub fn attach(&self, console: &mut Console) -> SplitWatcher {
+        let (sender, events) = mpsc::channel();
+        let subscriptions = self
+            .watches
+            .iter()
+            .map(|watch| watch_writes(console, watch.clone(), sender.clone()))
+            .collect();
+        SplitWatcher {
+            events,
+            subscriptions,
+        }
+    }
+}
+
+fn watch_writes(console: &mut Console, watch: Watch, sender: Sender<SplitEvent>) -> SubscriptionId {
+    let start = watch.address;
+    let end = start + watch.bytes as u16 - 1;
+    let mut bytes = [0u8; 4];
+    let mut written = 0u8;
+    let mut value: Option<u32> = None;
+    console.subscribe(EventFilter::Write(start..=end), move |event| {
+        let Event::Write { addr, value: byte } = *event else {
+            return;
+        };
+        let offset = (addr - start) as usize;
+        bytes[offset] = byte;
+        written |= 1 << offset;
+        // A value is known once all its bytes have been written
+        if written.count_ones() < watch.bytes as u32 {
+            return;
+        }
+        let new = u32::from_le_bytes(bytes);
+        if watch.condition.fires(value, new) {
+            // Nobody listening any more is no reason to stop the game
+            let _ = sender.send(SplitEvent {
+                name: watch.name.clone(),
+                action: watch.action,
+            });
+        }
+        value = Some(new);
+    })
+}
+
+// The watches of a set of splits on a console
+pub struct SplitWatcher {
+    events: Receiver<SplitEvent>,
+    subscriptions: Vec<SubscriptionId>,
+}
+
+impl SplitWatcher {
+    // Events since the last call, oldest first
+    pub fn poll(&self) -> Vec<SplitEvent> {
+        self.events.try_iter().collect()
+    }
+
+    // The channel events arrive on, for blocking or timed waits
+    pub fn events(&self) -> &Receiver<SplitEvent> {
+        &self.events
+    }
+
+    // Unsubscribes the watches
+    pub fn detach(self, console: &mut Console) {
+        for id in self.subscriptions {
+            console.unsubscribe(id);
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::Path,
+};
+
+use crate::memory::CPUMemory;
+
+// Size of the PRG banks FCEUX numbers its .nl files by
+const NL_BANK_SIZE: usize = 0x4000;
+// iNES header ld65 writes ahead of PRG ROM in the output file
+const INES_HEADER_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    // What's wrong on which 1-based line
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolError::Io(err) => write!(f, "failed to read symbols: {}", err),
+            SymbolError::Parse { line, message } => {
+                write!(f, "invalid symbols on line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(err: io::Error) -> Self {
+        SymbolError::Io(err)
+    }
+}
+
+// A name for an address, or a run of them for arrays
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub comment: Option<String>,
+    // Bytes the symbol covers, 1 unless it names an array
+    pub size: u16,
+    // Where in PRG ROM the address is, for code and data in a switchable
+    // bank; None for RAM, registers and anything else unbanked
+    pub prg_offset: Option<usize>,
+}
+
+// Labels and comments from FCEUX .nl files or ca65 debug files, keyed by CPU
+// address. Banked ROM can give one address several names, one per bank; the
+// one whose bank is mapped in is told apart by comparing ROM with what the
+// CPU sees there.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    symbols: BTreeMap<u16, Vec<Symbol>>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn insert(&mut self, addr: u16, symbol: Symbol) {
+        self.symbols.entry(addr).or_default().push(symbol);
+    }
+
+    // Adds everything in `other`, e.g. RAM symbols to a bank's
+    pub fn extend(&mut self, other: Symbols) {
+        for (addr, symbols) in other.symbols {
+            self.symbols.entry(addr).or_default().extend(symbols);
+        }
+    }
+
+    // The symbol naming `addr` itself as the CPU sees memory now
+    pub fn get(&self, addr: u16, memory: &CPUMemory) -> Option<&Symbol> {
+        let candidates = self.symbols.get(&addr)?;
+        mapped(candidates, addr, memory)
+    }
+
+    // `addr` as a symbol name, with an offset into arrays, e.g. "oam+4", or
+    // None if nothing names it
+    pub fn name(&self, addr: u16, memory: &CPUMemory) -> Option<String> {
+        if let Some(symbol) = self.get(addr, memory).filter(|s| !s.name.is_empty()) {
+            return Some(symbol.name.clone());
+        }
+        // The nearest symbol below, if it's an array reaching this far
+        let (&base, candidates) = self.symbols.range(..addr).next_back()?;
+        let symbol = mapped(candidates, base, memory)?;
+        let offset = addr - base;
+        (offset < symbol.size && !symbol.name.is_empty())
+            .then(|| format!("{}+{}", symbol.name, offset))
+    }
+
+    // `addr` named if it can be, or else as "$XXXX" (`wide`) or "$XX"
+    pub fn label(&self, addr: u16, wide: bool, memory: &CPUMemory) -> String {
+        match self.name(addr, memory) {
+            Some(name) => name,
+            None if wide => format!("${:04X}", addr),
+            None => format!("${:02X}", addr),
+        }
+    }
+
+    // The address of the symbol called `name`, for breaking on a label
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.symbols
+            .iter()
+            .find(|(_, symbols)| symbols.iter().any(|s| s.name == name))
+            .map(|(&addr, _)| addr)
+    }
+
+    // Parses one FCEUX .nl file. Lines are "$ADDR#name#comment", with
+    // "$ADDR/SIZE" (hex) for arrays and "\" starting comment lines that
+    // continue the one before. `bank` is the 16KB PRG bank a bank file
+    // ("game.nes.2.nl") describes, None for the RAM file.
+    pub fn parse_nl(text: &str, bank: Option<usize>) -> Result<Self, SymbolError> {
+        let mut symbols = Symbols::new();
+        let mut last: Option<u16> = None;
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| SymbolError::Parse {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let line = line.trim_end_matches('\r');
+            if let Some(more) = line.strip_prefix('\\') {
+                let symbol = last
+                    .and_then(|addr| symbols.symbols.get_mut(&addr))
+                    .and_then(|symbols| symbols.last_mut())
+                    .ok_or_else(|| error("comment continues nothing"))?;
+                let comment = symbol.comment.get_or_insert_with(String::new);
+                comment.push('\n');
+                comment.push_str(more);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(3, '#');
+            let location = fields.next().unwrap_or_default();
+            let name = fields.next().ok_or_else(|| error("expected $ADDR#name#"))?;
+            let comment = fields.next().unwrap_or_default();
+
+            let location = location
+                .strip_prefix('$')
+                .ok_or_else(|| error("address must start with $"))?;
+            let (addr, size) = match location.split_once('/') {
+                Some((addr, size)) => (addr, size),
+                None => (location, "1"),
+            };
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| error("invalid address"))?;
+            let size = u16::from_str_radix(size, 16).map_err(|_| error("invalid size"))?;
+            let prg_offset = bank
+                .filter(|_| addr >= 0x8000)
+                .map(|bank| bank * NL_BANK_SIZE + addr as usize % NL_BANK_SIZE);
+            symbols.insert(
+                addr,
+                Symbol {
+                    name: name.to_string(),
+                    comment: (!comment.is_empty()).then(|| comment.to_string()),
+                    size: size.max(1),
+                    prg_offset,
+                },
+            );
+            last = Some(addr);
+        }
+        Ok(symbols)
+    }
+
+    // Loads the .nl files FCEUX keeps beside `rom`: rom.ram.nl for RAM, and
+    // rom.N.nl (N in hex) for each of its `prg_banks` 16KB PRG banks. Files
+    // that don't exist are skipped.
+    pub fn load_nl<P: AsRef<Path>>(rom: P, prg_banks: usize) -> Result<Self, SymbolError> {
+        let rom = rom.as_ref().as_os_str().to_string_lossy().into_owned();
+        let files = std::iter::once(("ram".to_string(), None))
+            .chain((0..prg_banks).map(|bank| (format!("{:X}", bank), Some(bank))));
+        let mut symbols = Symbols::new();
+        for (suffix, bank) in files {
+            match fs::read_to_string(format!("{}.{}.nl", rom, suffix)) {
+                Ok(text) => symbols.extend(Self::parse_nl(&text, bank)?),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(symbols)
+    }
+
+    // Parses the debug file ld65 writes with --dbgfile. Labels and equates
+    // become symbols; ROM labels are placed in PRG by their segment's offset
+    // in the output file, which is taken to start with an iNES header.
+    pub fn parse_dbg(text: &str) -> Result<Self, SymbolError> {
+        // Segment id to its start address and offset in the output file
+        let mut segments: HashMap<String, (u32, Option<usize>)> = HashMap::new();
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: &str| SymbolError::Parse {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let Some((kind, fields)) = line.split_once('\t') else {
+                continue;
+            };
+            let fields = dbg_fields(fields);
+            let number = |key: &str| {
+                fields
+                    .get(key)
+                    .map(|value| parse_number(value).ok_or_else(|| error("invalid number")))
+                    .transpose()
+            };
+            match kind {
+                "seg" => {
+                    let id = fields.get("id").ok_or_else(|| error("seg without id"))?;
+                    let start = number("start")?.unwrap_or(0);
+                    let offset = number("ooffs")?.map(|offset| offset as usize);
+                    segments.insert(id.to_string(), (start, offset));
+                }
+                // Imports repeat a symbol defined elsewhere
+                "sym" if fields.get("type").is_some_and(|&kind| kind != "imp") => {
+                    let name = fields
+                        .get("name")
+                        .ok_or_else(|| error("sym without name"))?;
+                    let Some(value) = number("val")? else {
+                        continue;
+                    };
+                    let size = number("size")?.unwrap_or(1);
+                    let segment = fields.get("seg").map(|seg| seg.to_string());
+                    entries.push((name.to_string(), value, size, segment));
+                }
+                _ => {}
+            }
+        }
+
+        let mut symbols = Symbols::new();
+        for (name, value, size, segment) in entries {
+            let Ok(addr) = u16::try_from(value) else {
+                continue;
+            };
+            let prg_offset = segment
+                .and_then(|segment| segments.get(&segment))
+                .filter(|_| addr >= 0x8000)
+                .and_then(|&(start, offset)| {
+                    let offset = offset? + (value.checked_sub(start)? as usize);
+                    offset.checked_sub(INES_HEADER_SIZE)
+                });
+            symbols.insert(
+                addr,
+                Symbol {
+                    name,
+                    comment: None,
+                    size: size.clamp(1, u16::MAX as u32) as u16,
+                    prg_offset,
+                },
+            );
+        }
+        Ok(symbols)
+    }
+
+    // Loads a ca65 debug file (.dbg), or a single FCEUX .nl file, taking
+    // its bank from its name
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SymbolError> {
+        let path = path.as_ref();
+        if path.extension().is_some_and(|ext| ext == "dbg") {
+            return Self::load_dbg(path);
+        }
+        // "game.nes.2.nl" is bank 2, "game.nes.ram.nl" RAM
+        let bank = Path::new(path.file_stem().unwrap_or_default())
+            .extension()
+            .and_then(|bank| usize::from_str_radix(&bank.to_string_lossy(), 16).ok());
+        Self::parse_nl(&fs::read_to_string(path)?, bank)
+    }
+
+    pub fn load_dbg<P: AsRef<Path>>(path: P) -> Result<Self, SymbolError> {
+        Self::parse_dbg(&fs::read_to_string(path)?)
+    }
+}
+
+// The one of `candidates` for `addr` in the bank mapped in now. A lone
+// candidate is taken as is; between several, the one whose ROM matches
+// what the CPU reads there.
+fn mapped<'a>(candidates: &'a [Symbol], addr: u16, memory: &CPUMemory) -> Option<&'a Symbol> {
+    if let [symbol] = candidates {
+        return Some(symbol);
+    }
+    let prg = &memory.mapper().cartridge().prg;
+    candidates.iter().find(|symbol| match symbol.prg_offset {
+        None => true,
+        Some(offset) => (0..8u16)
+            .map_while(|i| Some((addr.checked_add(i)?, prg.get(offset + i as usize)?)))
+            .all(|(addr, &byte)| memory.peek(addr) == byte),
+    })
+}
+
+// The key=value pairs of a debug file line, values unquoted
+fn dbg_fields(fields: &str) -> HashMap<&str, &str> {
+    let mut pairs = HashMap::new();
+    let mut rest = fields;
+    while let Some((key, after)) = rest.split_once('=') {
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let next = quoted[end..].trim_start_matches('"');
+                (&quoted[..end], next.strip_prefix(',').unwrap_or(next))
+            }
+            None => match after.split_once(',') {
+                Some((value, next)) => (value, next),
+                None => (after, ""),
+            },
+        };
+        pairs.insert(key, value);
+        rest = next;
+    }
+    pairs
+}
+
+// Debug files write numbers in hex with 0x, or in decimal
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
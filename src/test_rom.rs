@@ -0,0 +1,65 @@
+use crate::console::Console;
+
+// blargg's newer test ROMs report through work RAM: $6000 is the status,
+// $6001-$6003 a signature and $6004 onwards a NUL-terminated message
+pub const STATUS_RUNNING: u8 = 0x80;
+pub const STATUS_RESET: u8 = 0x81;
+pub const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+// Frames to wait before pressing reset for a ROM that asks for it
+const RESET_DELAY: u64 = 6;
+
+// Whether the ROM has written the signature, i.e. $6000 is a status
+pub fn signed(console: &Console) -> bool {
+    (0..3).all(|i| console.cpu.memory.peek(0x6001 + i) == SIGNATURE[i as usize])
+}
+
+// Text the ROM has written from $6004
+pub fn message(console: &Console) -> String {
+    let mut message = Vec::new();
+    for addr in 0x6004..0x7000 {
+        match console.cpu.memory.peek(addr) {
+            0 => break,
+            byte => message.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&message).trim().to_string()
+}
+
+// Drives a test ROM frame by frame, pressing reset when it asks
+#[derive(Default)]
+pub struct TestRun {
+    frame: u64,
+    reset_at: Option<u64>,
+}
+
+impl TestRun {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Runs one frame, returning the ROM's result code (0 = passed) once it
+    // has reported one
+    pub fn step_frame(&mut self, console: &mut Console) -> Option<u8> {
+        console.step_frame();
+        self.frame += 1;
+        if !signed(console) {
+            return None;
+        }
+        match console.cpu.memory.peek(0x6000) {
+            STATUS_RUNNING => None,
+            STATUS_RESET => {
+                match self.reset_at {
+                    None => self.reset_at = Some(self.frame + RESET_DELAY),
+                    Some(at) if self.frame >= at => {
+                        console.reset();
+                        self.reset_at = None;
+                    }
+                    Some(_) => {}
+                }
+                None
+            }
+            result => Some(result),
+        }
+    }
+}
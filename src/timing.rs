@@ -0,0 +1,164 @@
+use std::{
+    hint, thread,
+    time::{Duration, Instant},
+};
+
+use crate::region::Region;
+
+// Sleeps are only trusted up to this close to a deadline; the rest is spent
+// spinning, since schedulers often oversleep by a millisecond or more
+const DEFAULT_SPIN: Duration = Duration::from_millis(2);
+// Falling further behind than this many frames gives up on catching up and
+// restarts the schedule from now
+const MAX_LAG_FRAMES: u32 = 4;
+
+// Holds a frontend to the console's own frame rate, NTSC's 60.0988Hz or
+// PAL's 50.007Hz, rather than the display's. Call `wait` once per emulated
+// frame. A speed other than 1 scales the rate for slow motion or a capped
+// fast-forward; fast-forward runs frames back to back.
+pub struct FrameLimiter {
+    frame_rate: f64,
+    speed: f64,
+    fast_forward: bool,
+    spin: Duration,
+    start: Instant,
+    // When the next frame is due
+    deadline: Instant,
+    frames: u64,
+    // Console time run, in seconds
+    emulated: f64,
+    // Time given up on by restarting the schedule
+    lost: Duration,
+}
+
+// How emulated time has kept up with the wall clock since the limiter was
+// reset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DriftReport {
+    pub frames: u64,
+    // Console time the frames stand for, at the console's frame rate
+    pub emulated: Duration,
+    pub wall: Duration,
+    // Time skipped after falling too far behind to catch up
+    pub lost: Duration,
+}
+
+impl DriftReport {
+    // Emulated seconds per wall-clock second: 1 at full speed
+    pub fn speed(&self) -> f64 {
+        if self.wall.is_zero() {
+            return 0.0;
+        }
+        self.emulated.as_secs_f64() / self.wall.as_secs_f64()
+    }
+
+    // Seconds the emulation is ahead of the wall clock, negative when behind
+    pub fn drift(&self) -> f64 {
+        self.emulated.as_secs_f64() - self.wall.as_secs_f64()
+    }
+}
+
+impl FrameLimiter {
+    pub fn new(region: Region) -> Self {
+        let now = Instant::now();
+        Self {
+            frame_rate: region.frame_rate(),
+            speed: 1.0,
+            fast_forward: false,
+            spin: DEFAULT_SPIN,
+            start: now,
+            deadline: now,
+            frames: 0,
+            emulated: 0.0,
+            lost: Duration::ZERO,
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.frame_rate = region.frame_rate();
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.frame_rate
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // Multiplies the frame rate: 0.5 for half speed, 2 for double
+    pub fn set_speed(&mut self, speed: f64) {
+        if speed > 0.0 && speed.is_finite() {
+            self.speed = speed;
+        }
+    }
+
+    pub fn fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    // Runs frames as fast as they come while set. The schedule restarts when
+    // it is cleared so the limiter doesn't stall to make up the time.
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        if self.fast_forward && !fast_forward {
+            self.deadline = Instant::now();
+        }
+        self.fast_forward = fast_forward;
+    }
+
+    // How long before a deadline to stop sleeping and spin; zero only sleeps
+    pub fn set_spin(&mut self, spin: Duration) {
+        self.spin = spin;
+    }
+
+    // Wall-clock time one frame takes at the current speed
+    pub fn frame_time(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / (self.frame_rate * self.speed))
+    }
+
+    // Blocks until the frame just run is due to end
+    pub fn wait(&mut self) {
+        self.frames += 1;
+        self.emulated += 1.0 / self.frame_rate;
+        if self.fast_forward {
+            return;
+        }
+
+        let frame_time = self.frame_time();
+        self.deadline += frame_time;
+        let now = Instant::now();
+        if now > self.deadline + frame_time * MAX_LAG_FRAMES {
+            self.lost += now - self.deadline;
+            self.deadline = now;
+            return;
+        }
+
+        if let Some(remaining) = self.deadline.checked_duration_since(now) {
+            if remaining > self.spin {
+                thread::sleep(remaining - self.spin);
+            }
+        }
+        while Instant::now() < self.deadline {
+            hint::spin_loop();
+        }
+    }
+
+    // Starts the schedule and drift report over, e.g. after a pause
+    pub fn reset(&mut self) {
+        let now = Instant::now();
+        self.start = now;
+        self.deadline = now;
+        self.frames = 0;
+        self.emulated = 0.0;
+        self.lost = Duration::ZERO;
+    }
+
+    pub fn report(&self) -> DriftReport {
+        DriftReport {
+            frames: self.frames,
+            emulated: Duration::from_secs_f64(self.emulated),
+            wall: self.start.elapsed(),
+            lost: self.lost,
+        }
+    }
+}
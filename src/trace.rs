@@ -0,0 +1,157 @@
+use crate::{
+    cpu::{AddressMode::*, CPU, INSTRUCTIONS},
+    disasm,
+    symbols::Symbols,
+};
+
+// Formats the instruction at pc, and the machine state before it runs, as a
+// line of nestest.log:
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//
+// Operand values are read with peek so tracing never disturbs the machine.
+pub fn trace_line(cpu: &CPU) -> String {
+    format_line(cpu, None)
+}
+
+// trace_line with addresses named from `symbols`, a "label:" line ahead of
+// an instruction a label names and its comment after it:
+//
+// reset:
+// C000  78        SEI                             A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7  ; start here
+pub fn symbolic_trace_line(cpu: &CPU, symbols: &Symbols) -> String {
+    let mut text = String::new();
+    let symbol = symbols.get(cpu.pc, &cpu.memory);
+    if let Some(symbol) = symbol.filter(|symbol| !symbol.name.is_empty()) {
+        text.push_str(&symbol.name);
+        text.push_str(":\n");
+    }
+    text.push_str(&format_line(cpu, Some(symbols)));
+    if let Some(comment) = symbol.and_then(|symbol| symbol.comment.as_ref()) {
+        text.push_str("  ; ");
+        text.push_str(&comment.replace('\n', " "));
+    }
+    text
+}
+
+fn format_line(cpu: &CPU, symbols: Option<&Symbols>) -> String {
+    let line = disasm::decode(|addr| cpu.memory.peek(addr), cpu.pc);
+    let hex: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let prefix = if line.official { ' ' } else { '*' };
+    let disassembly = format!("{} {}", line.mnemonic, operand(cpu, &line.bytes, symbols));
+
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        line.addr,
+        hex.join(" "),
+        prefix,
+        disassembly.trim_end(),
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.flags(),
+        cpu.sp,
+        cpu.memory.ppu.scanline(),
+        cpu.memory.ppu.cycle(),
+        cpu.cycles
+    )
+}
+
+// Operand in nestest notation, annotated with the effective address and the
+// value currently stored there. Operand addresses are named from `symbols`
+// where they can be.
+fn operand(cpu: &CPU, bytes: &[u8], symbols: Option<&Symbols>) -> String {
+    let memory = &cpu.memory;
+    let at = |addr: u16, wide: bool| match symbols {
+        Some(symbols) => symbols.label(addr, wide, memory),
+        None if wide => format!("${:04X}", addr),
+        None => format!("${:02X}", addr),
+    };
+    let opcode = bytes[0];
+    let lo = bytes.get(1).copied().unwrap_or(0);
+    let hi = bytes.get(2).copied().unwrap_or(0);
+    let word = (hi as u16) << 8 | lo as u16;
+    let zero_page16 = |addr: u8| {
+        let lo = memory.peek(addr as u16) as u16;
+        let hi = memory.peek(addr.wrapping_add(1) as u16) as u16;
+        hi << 8 | lo
+    };
+
+    match INSTRUCTIONS[opcode as usize].mode {
+        Implied => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate => format!("#${:02X}", lo),
+        Relative => {
+            let target = cpu.pc.wrapping_add(2).wrapping_add(lo as i8 as u16);
+            at(target, true)
+        }
+        ZeroPage => format!("{} = {:02X}", at(lo as u16, false), memory.peek(lo as u16)),
+        ZeroPageX => {
+            let addr = lo.wrapping_add(cpu.x);
+            format!(
+                "{},X @ {:02X} = {:02X}",
+                at(lo as u16, false),
+                addr,
+                memory.peek(addr as u16)
+            )
+        }
+        ZeroPageY => {
+            let addr = lo.wrapping_add(cpu.y);
+            format!(
+                "{},Y @ {:02X} = {:02X}",
+                at(lo as u16, false),
+                addr,
+                memory.peek(addr as u16)
+            )
+        }
+        // JMP and JSR name their target rather than read it
+        Absolute if opcode == 0x4C || opcode == 0x20 => at(word, true),
+        Absolute => format!("{} = {:02X}", at(word, true), memory.peek(word)),
+        AbsoluteX => {
+            let addr = word.wrapping_add(cpu.x as u16);
+            format!(
+                "{},X @ {:04X} = {:02X}",
+                at(word, true),
+                addr,
+                memory.peek(addr)
+            )
+        }
+        AbsoluteY => {
+            let addr = word.wrapping_add(cpu.y as u16);
+            format!(
+                "{},Y @ {:04X} = {:02X}",
+                at(word, true),
+                addr,
+                memory.peek(addr)
+            )
+        }
+        Indirect => {
+            // Reproduces the page-wrap bug of JMP ($xxFF)
+            let hi_addr = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+            let target = (memory.peek(hi_addr) as u16) << 8 | memory.peek(word) as u16;
+            format!("({}) = {:04X}", at(word, true), target)
+        }
+        IndexedIndirect => {
+            let pointer = lo.wrapping_add(cpu.x);
+            let addr = zero_page16(pointer);
+            format!(
+                "({},X) @ {:02X} = {:04X} = {:02X}",
+                at(lo as u16, false),
+                pointer,
+                addr,
+                memory.peek(addr)
+            )
+        }
+        IndirectIndexed => {
+            let base = zero_page16(lo);
+            let addr = base.wrapping_add(cpu.y as u16);
+            format!(
+                "({}),Y = {:04X} @ {:04X} = {:02X}",
+                at(lo as u16, false),
+                base,
+                addr,
+                memory.peek(addr)
+            )
+        }
+    }
+}
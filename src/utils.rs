@@ -0,0 +1,76 @@
+// Fixed-capacity FIFO that overwrites its oldest entry when full, so a
+// producer never blocks on a consumer that has fallen behind
+pub struct RingBuffer<T> {
+    buffer: Vec<T>,
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy + Default> RingBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: vec![T::default(); capacity],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn push(&mut self, value: T) {
+        let capacity = self.capacity();
+        let tail = (self.head + self.len) % capacity;
+        self.buffer[tail] = value;
+        if self.len == capacity {
+            self.head = (self.head + 1) % capacity;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = self.buffer[self.head];
+        self.head = (self.head + 1) % self.capacity();
+        self.len -= 1;
+        Some(value)
+    }
+
+    // Moves as many entries as fit into `out`, oldest first
+    pub fn drain_into(&mut self, out: &mut [T]) -> usize {
+        let count = self.len.min(out.len());
+        for slot in out.iter_mut().take(count) {
+            *slot = self.buffer[self.head];
+            self.head = (self.head + 1) % self.capacity();
+        }
+        self.len -= count;
+        count
+    }
+}
+
+// The SplitMix64 generator: advances `state` and returns the next value.
+// Quick, and good enough for seeded noise that must repeat exactly.
+pub fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
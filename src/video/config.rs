@@ -0,0 +1,90 @@
+use image::{imageops, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::ppu::{HEIGHT, WIDTH};
+
+// Pixels hidden at each edge of the picture. TVs cut off some of the
+// border, and games left garbage there accordingly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overscan {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Overscan {
+    // The 8 lines top and bottom that NTSC TVs hid
+    pub const TV: Overscan = Overscan {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+}
+
+// How frames are framed for display, shared by frontends, screenshots and
+// recordings so they all come out the same
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VideoConfig {
+    pub overscan: Overscan,
+    // Widens the picture by 8:7, the shape of NTSC pixels on a 4:3 TV
+    pub aspect_correction: bool,
+}
+
+impl VideoConfig {
+    // The visible part of the frame as x, y, width and height, at least a
+    // pixel across however much is cropped
+    pub fn visible(&self) -> (u32, u32, u32, u32) {
+        let overscan = &self.overscan;
+        let x = overscan.left.min(WIDTH - 1);
+        let y = overscan.top.min(HEIGHT - 1);
+        let width = WIDTH.saturating_sub(x + overscan.right).max(1);
+        let height = HEIGHT.saturating_sub(y + overscan.bottom).max(1);
+        (x, y, width, height)
+    }
+
+    // Width over height of one pixel as displayed
+    pub fn pixel_aspect(&self) -> f64 {
+        if self.aspect_correction {
+            8.0 / 7.0
+        } else {
+            1.0
+        }
+    }
+
+    // Size of the framed picture at a whole-number `scale`
+    pub fn display_size(&self, scale: u32) -> (u32, u32) {
+        let (_, _, width, height) = self.visible();
+        let scale = scale.max(1);
+        let width = (width as f64 * scale as f64 * self.pixel_aspect()).round() as u32;
+        (width, height * scale)
+    }
+
+    // Crops `frame` and scales it up by `scale`, each pixel a square block,
+    // then stretches it to the corrected aspect, blending neighbours
+    pub fn frame(&self, frame: &RgbaImage, scale: u32) -> RgbaImage {
+        let (x, y, width, height) = self.visible();
+        let mut image = imageops::crop_imm(frame, x, y, width, height).to_image();
+        let scale = scale.max(1);
+        if scale > 1 {
+            image = imageops::resize(
+                &image,
+                width * scale,
+                height * scale,
+                imageops::FilterType::Nearest,
+            );
+        }
+        let (display_width, display_height) = self.display_size(scale);
+        if display_width != image.width() {
+            image = imageops::resize(
+                &image,
+                display_width,
+                display_height,
+                imageops::FilterType::Triangle,
+            );
+        }
+        image
+    }
+}
@@ -0,0 +1,19 @@
+mod config;
+mod ntsc;
+mod screenshot;
+
+use serde::{Deserialize, Serialize};
+
+pub use config::{Overscan, VideoConfig};
+pub use ntsc::NTSC;
+pub use screenshot::{screenshot, ScreenshotOptions};
+
+// Post-processing applied to each frame as the PPU completes it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Filter {
+    // Palette colors, one per pixel
+    #[default]
+    None,
+    // Composite video simulation with color fringing and dot crawl
+    Ntsc,
+}
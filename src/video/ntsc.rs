@@ -0,0 +1,146 @@
+use std::f32::consts::PI;
+
+use image::{Rgba, RgbaImage};
+
+use crate::ppu::{HEIGHT, WIDTH};
+
+// Composite signal generation and decoding after Bisqwit's algorithm on the
+// nesdev wiki ("NTSC video"). The PPU draws each pixel as 8 samples of a
+// square wave whose phase against the 12-sample color subcarrier picks the
+// hue; decoding averages a subcarrier-wide window, so neighbouring pixels
+// bleed into each other the way they do on a real TV.
+
+// Square wave voltages, relative to sync, for the four luma levels
+static LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+static HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+// Emphasis bits scale the signal by this during their third of the wave
+const ATTENUATION: f32 = 0.746;
+
+// Decoder hue offset, in samples, and color gain, calibrated so flat areas
+// come out close to the default palette
+const HUE: f32 = 4.0;
+const SATURATION: f32 = 1.5;
+
+const PHASES: usize = 12;
+const SAMPLES_PER_PIXEL: usize = 8;
+const LINE_SAMPLES: usize = WIDTH as usize * SAMPLES_PER_PIXEL;
+
+pub struct NTSC {
+    // Normalized signal level of every 9-bit pixel at every phase
+    signal: Vec<[f32; PHASES]>,
+    // Subcarrier reference for each phase
+    cos: [f32; PHASES],
+    sin: [f32; PHASES],
+    line: Vec<f32>,
+}
+
+impl NTSC {
+    pub fn new() -> Self {
+        let signal = (0..512)
+            .map(|pixel| {
+                let mut levels = [0.0; PHASES];
+                for (phase, level) in levels.iter_mut().enumerate() {
+                    *level = (signal_level(pixel, phase) - BLACK) / (WHITE - BLACK);
+                }
+                levels
+            })
+            .collect();
+        let mut cos = [0.0; PHASES];
+        let mut sin = [0.0; PHASES];
+        for phase in 0..PHASES {
+            let angle = PI * (phase as f32 + HUE) / 6.0;
+            cos[phase] = angle.cos() * SATURATION;
+            sin[phase] = angle.sin() * SATURATION;
+        }
+        Self {
+            signal,
+            cos,
+            sin,
+            line: vec![0.0; LINE_SAMPLES],
+        }
+    }
+
+    // Encodes a frame of 9-bit pixels as composite video and decodes it
+    // into `out`. `phases` holds the PPU dot count, mod 3, at the start of
+    // each line, which is what makes the artifacts crawl between frames.
+    pub fn render(&mut self, pixels: &[u16], phases: &[u8], out: &mut RgbaImage) {
+        for y in 0..HEIGHT as usize {
+            let start = phases[y] as usize * SAMPLES_PER_PIXEL;
+            let row = &pixels[y * WIDTH as usize..(y + 1) * WIDTH as usize];
+            for (x, &pixel) in row.iter().enumerate() {
+                let levels = &self.signal[pixel as usize & 0x1FF];
+                for p in 0..SAMPLES_PER_PIXEL {
+                    let sample = x * SAMPLES_PER_PIXEL + p;
+                    self.line[sample] = levels[(start + sample) % PHASES];
+                }
+            }
+
+            for x in 0..WIDTH as usize {
+                let center = x * SAMPLES_PER_PIXEL + SAMPLES_PER_PIXEL / 2;
+                let begin = center.saturating_sub(PHASES / 2);
+                let end = (center + PHASES / 2).min(LINE_SAMPLES);
+                let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+                for sample in begin..end {
+                    let level = self.line[sample] / PHASES as f32;
+                    let phase = (start + sample) % PHASES;
+                    luma += level;
+                    i += level * self.cos[phase];
+                    q += level * self.sin[phase];
+                }
+                out.put_pixel(x as u32, y as u32, yiq_to_rgb(luma, i, q));
+            }
+        }
+    }
+}
+
+impl Default for NTSC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Signal voltage for a pixel (palette index in bits 0-5, emphasis in 6-8)
+// at one of the 12 subcarrier phases
+fn signal_level(pixel: usize, phase: usize) -> f32 {
+    let color = pixel & 0x0F;
+    // Colors $xE and $xF are black, driven at level 1
+    let level = if color > 13 { 1 } else { (pixel >> 4) & 3 };
+    let emphasis = pixel >> 6;
+
+    let mut low = LOW[level];
+    let mut high = HIGH[level];
+    // Color 0 is a flat high level, colors $xD-$xF a flat low one
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    let in_phase = |color: usize| (color + phase) % PHASES < 6;
+    let mut signal = if in_phase(color) { high } else { low };
+    if color < 14
+        && ((emphasis & 1 != 0 && in_phase(0))
+            || (emphasis & 2 != 0 && in_phase(4))
+            || (emphasis & 4 != 0 && in_phase(8)))
+    {
+        signal *= ATTENUATION;
+    }
+    signal
+}
+
+fn yiq_to_rgb(y: f32, i: f32, q: f32) -> Rgba<u8> {
+    let channel = |value: f32| {
+        // Mild gamma lift, as the wiki's decoder uses
+        let value = if value <= 0.0 { 0.0 } else { value.powf(1.1) };
+        (value * 255.95).clamp(0.0, 255.0) as u8
+    };
+    Rgba([
+        channel(y + 0.946882 * i + 0.623557 * q),
+        channel(y - 0.274788 * i - 0.635691 * q),
+        channel(y - 1.108545 * i + 1.709007 * q),
+        0xFF,
+    ])
+}
@@ -0,0 +1,35 @@
+use image::RgbaImage;
+
+use super::VideoConfig;
+use crate::ppu::{HEIGHT, PPU, WIDTH};
+
+// How Console::screenshot draws the last completed frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScreenshotOptions {
+    // Whole-number enlargement, each pixel a square block
+    pub scale: u32,
+    // As displayed, through the video filter; otherwise plain palette
+    // colors, one per pixel
+    pub filtered: bool,
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1,
+            filtered: true,
+        }
+    }
+}
+
+// The last completed frame, framed by `video`
+pub fn screenshot(ppu: &PPU, video: &VideoConfig, options: ScreenshotOptions) -> RgbaImage {
+    if options.filtered {
+        return video.frame(ppu.front(), options.scale);
+    }
+    let pixels = ppu.front_pixels();
+    let image = RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        ppu.palette().color(pixels[(y * WIDTH + x) as usize])
+    });
+    video.frame(&image, options.scale)
+}
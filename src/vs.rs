@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+use crate::savestate::{StateError, StateReader, StateWriter};
+
+// Frames a coin holds its slot's switch closed, long enough for games that
+// debounce it
+const COIN_FRAMES: u8 = 4;
+
+// The PPU on a VS. System board. All of them output RGB rather than
+// composite video. The 2C04s each scramble the order of their colors in
+// their own way, so games made for one only look right with a palette
+// dumped from that chip; the 2C05s answer $2002 with an ID the game checks,
+// and swap $2000 and $2001.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VSPPU {
+    #[default]
+    RP2C03,
+    // -0001 to -0004
+    RP2C04(u8),
+    // -01 to -05
+    RC2C05(u8),
+}
+
+impl VSPPU {
+    // From the low nibble of NES 2.0 header byte 13
+    pub fn from_header(value: u8) -> Self {
+        match value & 0x0F {
+            value @ 2..=5 => VSPPU::RP2C04(value - 1),
+            value @ 8..=12 => VSPPU::RC2C05(value - 7),
+            // 2C03 variants, and the 2C02 some boards were fitted with
+            _ => VSPPU::RP2C03,
+        }
+    }
+
+    // What the 2C05s put in the low six bits of $2002
+    pub fn status_id(self) -> Option<u8> {
+        match self {
+            VSPPU::RC2C05(1 | 4) => Some(0x1B),
+            VSPPU::RC2C05(2) => Some(0x3D),
+            VSPPU::RC2C05(3) => Some(0x1C),
+            VSPPU::RC2C05(_) => Some(0x00),
+            _ => None,
+        }
+    }
+
+    pub fn swaps_control_registers(self) -> bool {
+        matches!(self, VSPPU::RC2C05(_))
+    }
+}
+
+// Copy protection on the board, from the high nibble of NES 2.0 header byte
+// 13
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VSProtection {
+    #[default]
+    None,
+    // A value read back from $5E01 after a reset through $5E00
+    RBIBaseball,
+    // Like RBI Baseball's, with a table of answers that isn't emulated
+    TKOBoxing,
+    // Fixed answers at a few addresses in $5000-$5FFF, one pair flipped by
+    // reading $5567
+    SuperXevious,
+}
+
+impl VSProtection {
+    pub fn from_header(value: u8) -> Self {
+        match value >> 4 {
+            1 => VSProtection::RBIBaseball,
+            2 => VSProtection::TKOBoxing,
+            3 => VSProtection::SuperXevious,
+            // Plain UniSystems, and DualSystems run as one of their halves
+            _ => VSProtection::None,
+        }
+    }
+}
+
+// The arcade cabinet around a VS. UniSystem game: coin slots, the service
+// button and eight DIP switches, all read through spare bits of $4016 and
+// $4017, and the board's copy protection
+pub struct VSSystem {
+    // Switch 1 in bit 0
+    pub dip_switches: u8,
+    pub service: bool,
+    // Frames each slot's coin switch stays closed
+    coins: [u8; 2],
+    protection: VSProtection,
+    protection_state: ProtectionState,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ProtectionState {
+    counter: u8,
+    flipped: bool,
+}
+
+impl VSSystem {
+    pub fn new(protection: VSProtection) -> Self {
+        Self {
+            dip_switches: 0,
+            service: false,
+            coins: [0; 2],
+            protection,
+            protection_state: ProtectionState::default(),
+        }
+    }
+
+    // Drops a coin into slot 0 or 1
+    pub fn insert_coin(&mut self, slot: usize) {
+        self.coins[slot] = COIN_FRAMES;
+    }
+
+    // Once per frame, letting go of coin switches
+    pub fn step_frame(&mut self) {
+        for coin in &mut self.coins {
+            *coin = coin.saturating_sub(1);
+        }
+    }
+
+    // Bits 2-7 of $4016 (`port` 0) and $4017 (1). $4016 has the service
+    // button in bit 2, DIP switches 1-2 in bits 3-4 and the coin slots in
+    // bits 5-6; $4017 has switches 3-8. Bit 7 of $4016 is low on the main
+    // CPU, the only one there is.
+    pub fn read(&self, port: usize) -> u8 {
+        match port {
+            0 => {
+                (self.service as u8) << 2
+                    | (self.dip_switches & 0x03) << 3
+                    | ((self.coins[0] > 0) as u8) << 5
+                    | ((self.coins[1] > 0) as u8) << 6
+            }
+            _ => self.dip_switches & 0xFC,
+        }
+    }
+
+    // CPU reads the protection answers in place of the cartridge
+    pub fn protection_read(&mut self, addr: u16) -> Option<u8> {
+        let state = &mut self.protection_state;
+        match (self.protection, addr) {
+            (VSProtection::RBIBaseball, 0x5E00) => {
+                state.counter = 0;
+                None
+            }
+            (VSProtection::RBIBaseball, 0x5E01) => {
+                state.counter = state.counter.wrapping_add(1);
+                Some(if state.counter == 10 { 0x6F } else { 0xB4 })
+            }
+            (VSProtection::SuperXevious, 0x54FF) => Some(0x05),
+            (VSProtection::SuperXevious, 0x5678) => Some(if state.flipped { 0x00 } else { 0x01 }),
+            (VSProtection::SuperXevious, 0x578F) => Some(if state.flipped { 0xD1 } else { 0x89 }),
+            (VSProtection::SuperXevious, 0x5567) => {
+                state.flipped = !state.flipped;
+                Some(if state.flipped { 0x37 } else { 0x3E })
+            }
+            _ => None,
+        }
+    }
+
+    // The coins and switches are input and settings, so only the
+    // protection's state is saved
+    pub fn save(&self, state: &mut StateWriter) {
+        state.write(&self.protection_state);
+    }
+
+    pub fn load(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.protection_state = state.read()?;
+        Ok(())
+    }
+}
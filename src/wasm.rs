@@ -0,0 +1,112 @@
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    cartridge::Cartridge,
+    console::Console,
+    controller::Button,
+    ppu::{HEIGHT, WIDTH},
+};
+
+// A console for JavaScript frontends. Frames and audio are handed over as
+// pointers into the module's memory, to be viewed without copying:
+//
+//     const frame = new Uint8ClampedArray(memory.buffer,
+//         emulator.framebuffer(), emulator.framebuffer_len());
+//
+// Views go stale when memory grows, so they are made again after each call.
+#[wasm_bindgen]
+pub struct Emulator {
+    console: Option<Console>,
+    sample_rate: f64,
+    samples: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            console: None,
+            sample_rate: 44100.0,
+            samples: vec![0.0; 8192],
+        }
+    }
+
+    // Loads an iNES or NES 2.0 image, replacing any running game
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), JsError> {
+        let cartridge =
+            Cartridge::from_bytes(data).map_err(|err| JsError::new(&err.to_string()))?;
+        let mut console = Console::new(cartridge).map_err(|err| JsError::new(&err.to_string()))?;
+        console.set_sample_rate(self.sample_rate);
+        self.console = Some(console);
+        Ok(())
+    }
+
+    pub fn step_frame(&mut self) {
+        if let Some(console) = &mut self.console {
+            console.step_frame();
+        }
+    }
+
+    // Start of the last frame's RGBA pixels, WIDTH x HEIGHT, or null before
+    // a game is loaded
+    pub fn framebuffer(&self) -> *const u8 {
+        match &self.console {
+            Some(console) => console.framebuffer().as_raw().as_ptr(),
+            None => std::ptr::null(),
+        }
+    }
+
+    pub fn framebuffer_len(&self) -> usize {
+        (WIDTH * HEIGHT * 4) as usize
+    }
+
+    pub fn width(&self) -> u32 {
+        WIDTH
+    }
+
+    pub fn height(&self) -> u32 {
+        HEIGHT
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        if let Some(console) = &mut self.console {
+            console.set_sample_rate(sample_rate);
+        }
+    }
+
+    // Moves the audio produced since the last call into the buffer at
+    // `audio_buffer`, returning how many samples it now holds
+    pub fn drain_audio(&mut self) -> usize {
+        match &mut self.console {
+            Some(console) => console.drain_samples(&mut self.samples),
+            None => 0,
+        }
+    }
+
+    // Start of the mono f32 samples from the last `drain_audio`
+    pub fn audio_buffer(&self) -> *const f32 {
+        self.samples.as_ptr()
+    }
+
+    // Presses or releases `button` (0-7: A, B, Select, Start, Up, Down,
+    // Left, Right) on joypad `player`
+    pub fn set_button(&mut self, player: usize, button: usize, pressed: bool) {
+        if let (Some(console), Some(&button)) = (&mut self.console, Button::ALL.get(button)) {
+            console.set_button(player, button, pressed);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        if let Some(console) = &mut self.console {
+            console.reset();
+        }
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
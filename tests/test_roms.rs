@@ -0,0 +1,148 @@
+// Runs every .nes file under tests/roms, or the directory $NESRS_TEST_ROMS
+// names, and fails if any of them does. Lay out blargg's cpu, ppu and apu
+// suites there as they unpack, then run
+//
+//     cargo test --features test-roms -- --nocapture
+//
+// to see the result of each. ROMs that report through $6000 are judged by
+// their result code. Older ones that only draw their result are judged by a
+// hash of the screen, listed in hashes.txt in the same directory as
+//
+//     <ROM path relative to the directory> <frames to run> <MD5 of the frame>
+//
+// Unlisted ROMs that never report print the hash of their last frame, to be
+// checked by eye and added.
+#![cfg(feature = "test-roms")]
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest, Md5};
+use nesrs::{
+    console::Accuracy,
+    test_rom::{self, TestRun},
+    Cartridge, Console,
+};
+
+// Frames to give a ROM to report through $6000 before it counts as hung
+const FRAME_LIMIT: u64 = 60 * 60;
+
+struct ScreenHash {
+    frames: u64,
+    md5: String,
+}
+
+fn rom_directory() -> PathBuf {
+    env::var_os("NESRS_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"))
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("nes"))
+        {
+            roms.push(path);
+        }
+    }
+}
+
+fn read_hashes(dir: &Path) -> HashMap<String, ScreenHash> {
+    let Ok(text) = fs::read_to_string(dir.join("hashes.txt")) else {
+        return HashMap::new();
+    };
+    let mut hashes = HashMap::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if let [path, frames, md5] = fields[..] {
+            let frames = frames.parse().expect("hashes.txt frame counts are numbers");
+            let md5 = md5.to_ascii_lowercase();
+            hashes.insert(path.to_string(), ScreenHash { frames, md5 });
+        }
+    }
+    hashes
+}
+
+fn screen_hash(console: &Console) -> String {
+    let digest = Md5::digest(console.framebuffer().as_raw());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Runs one ROM, returning why it failed
+fn run(path: &Path, hash: Option<&ScreenHash>) -> Result<(), String> {
+    let cartridge = Cartridge::from_path(path).map_err(|err| err.to_string())?;
+    let mut console = Console::new(cartridge).map_err(|err| err.to_string())?;
+    console.set_accuracy(Accuracy::CycleAccurate);
+
+    if let Some(hash) = hash {
+        for _ in 0..hash.frames {
+            console.step_frame();
+        }
+        let md5 = screen_hash(&console);
+        return match md5 == hash.md5 {
+            true => Ok(()),
+            false => Err(format!("screen hash {}, expected {}", md5, hash.md5)),
+        };
+    }
+
+    let mut test_run = TestRun::new();
+    for _ in 0..FRAME_LIMIT {
+        if let Some(result) = test_run.step_frame(&mut console) {
+            return match result {
+                0 => Ok(()),
+                _ => Err(format!(
+                    "result {}: {}",
+                    result,
+                    test_rom::message(&console)
+                )),
+            };
+        }
+    }
+    match test_rom::signed(&console) {
+        true => Err(format!("no result after {} frames", FRAME_LIMIT)),
+        false => Err(format!(
+            "no result through $6000 and no screen hash; last frame {}",
+            screen_hash(&console)
+        )),
+    }
+}
+
+#[test]
+fn test_roms() {
+    let dir = rom_directory();
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+    assert!(!roms.is_empty(), "no test ROMs found in {}", dir.display());
+
+    let hashes = read_hashes(&dir);
+    let mut failures = 0;
+    for path in &roms {
+        let name = path.strip_prefix(&dir).unwrap_or(path);
+        let name = name.to_string_lossy().replace('\\', "/");
+        match run(path, hashes.get(&name)) {
+            Ok(()) => println!("pass  {}", name),
+            Err(reason) => {
+                println!("FAIL  {}: {}", name, reason);
+                failures += 1;
+            }
+        }
+    }
+    println!(
+        "{} of {} test ROMs passed",
+        roms.len() - failures,
+        roms.len()
+    );
+    assert_eq!(failures, 0, "{} test ROMs failed", failures);
+}